@@ -0,0 +1,274 @@
+// Decode throughput on a generated trace: packet parsing, basic block
+// stepping, the whole packet decoder, the stack unwinder and the major
+// receivers, each over the entries the decoder produced. The program is a
+// loop calling a leaf function with a branch in it, built into a RISC-V ELF
+// in memory; TACIT_BENCH_ITERATIONS sets how many times the trace goes
+// around the loop (default 10000). Run with `cargo bench`.
+use std::hint::black_box;
+
+use bus::{Bus, BusReader};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use object::write::{Object, Symbol, SymbolSection};
+use object::{Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+
+use ltrace_decoder::backend::abstract_receiver::{boxed, AbstractReceiver};
+use ltrace_decoder::backend::afdo_receiver::AfdoReceiver;
+use ltrace_decoder::backend::event::{Entry, Event};
+use ltrace_decoder::backend::fxt_receiver::FxtReceiver;
+use ltrace_decoder::backend::perfetto_receiver::PerfettoReceiver;
+use ltrace_decoder::backend::speedscope_receiver::SpeedscopeReceiver;
+use ltrace_decoder::backend::stack_txt_receiver::StackTxtReceiver;
+use ltrace_decoder::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use ltrace_decoder::backend::top_receiver::TopReceiver;
+use ltrace_decoder::backend::txt_receiver::{TxtOptions, TxtReceiver};
+use ltrace_decoder::backend::vbb_receiver::VBBReceiver;
+use ltrace_decoder::backend::vpp_receiver::VPPReceiver;
+use ltrace_decoder::frontend::br_mode::BrMode;
+use ltrace_decoder::frontend::decoder::{step_bb, DecoderConfig, EntrySink, PacketDecoder};
+use ltrace_decoder::frontend::insn_map::build_insn_map;
+use ltrace_decoder::frontend::packet::{read_first_packet, read_packet};
+use tacit_wire::write_varint;
+
+const DEFAULT_ITERATIONS: u64 = 10_000;
+
+// main:  0x00 addi a0, a0, 1
+//        0x04 jal  ra, leaf
+//        0x08 bne  a0, a1, main
+//        0x0c j    0x0c
+// leaf:  0x10 addi t0, t0, 1
+//        0x14 beq  t0, t1, 0x1c
+//        0x18 addi t0, t0, 1
+//        0x1c ret
+const PROGRAM: [u32; 8] = [0x0015_0513, 0x00c0_00ef, 0xfeb5_1ce3, 0x0000_006f,
+                           0x0012_8293, 0x0062_8463, 0x0012_8293, 0x0000_8067];
+const MAIN: u64 = 0x00;
+const LEAF: u64 = 0x10;
+const CALL_RETURN: u64 = 0x08;
+const RET: u64 = 0x1c;
+const END: u64 = 0x0c;
+
+// cycles between two packets
+const PACKET_CYCLES: u8 = 3;
+// the default address scheme: shifted by 1, targets XOR the pc
+const SHIFT: u32 = 1;
+
+fn iterations() -> u64 {
+    std::env::var("TACIT_BENCH_ITERATIONS").ok().and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_ITERATIONS)
+}
+
+fn build_elf() -> Vec<u8> {
+    let mut obj = Object::new(BinaryFormat::Elf, Architecture::Riscv64, Endianness::Little);
+    let text = obj.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+    let code: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    obj.append_section_data(text, &code, 4);
+    for (name, value) in [("main", MAIN), ("leaf", LEAF)] {
+        obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value,
+            size: 0x10,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+    }
+    obj.write().unwrap()
+}
+
+fn compressed(bytes: &mut Vec<u8>, c_header: u8) {
+    bytes.push(PACKET_CYCLES << 2 | c_header);
+}
+
+fn uncompressed(bytes: &mut Vec<u8>, f_header: u8, varints: &[u64]) {
+    bytes.push(f_header << 2 | 0b10);
+    for &value in varints {
+        write_varint(bytes, value);
+    }
+}
+
+// the packets of br_mode 0 (branch targets) going around the loop
+// `iterations` times, the leaf's branch taken every other time, without
+// the sync packet ending the trace
+fn build_trace(iterations: u64) -> Vec<u8> {
+    const CTB: u8 = 0b00;
+    const CNT: u8 = 0b01;
+    const CIJ: u8 = 0b11;
+    const FUJ: u8 = 0b010;
+    const FSYNC: u8 = 0b101;
+    let mut bytes = Vec::new();
+    uncompressed(&mut bytes, FSYNC, &[MAIN >> SHIFT, 0]);
+    for i in 0..iterations {
+        compressed(&mut bytes, CIJ);
+        compressed(&mut bytes, if i % 2 == 0 { CTB } else { CNT });
+        uncompressed(&mut bytes, FUJ, &[(CALL_RETURN >> SHIFT) ^ (RET >> SHIFT), PACKET_CYCLES as u64]);
+        compressed(&mut bytes, if i + 1 < iterations { CTB } else { CNT });
+    }
+    bytes
+}
+
+fn end_packet() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    uncompressed(&mut bytes, 0b101, &[END >> SHIFT, PACKET_CYCLES as u64]);
+    bytes
+}
+
+struct CountSink(u64);
+
+impl EntrySink for CountSink {
+    fn broadcast(&mut self, entry: Entry) {
+        black_box(entry);
+        self.0 += 1;
+    }
+}
+
+struct CollectSink(Vec<Entry>);
+
+impl EntrySink for CollectSink {
+    fn broadcast(&mut self, entry: Entry) {
+        self.0.push(entry);
+    }
+}
+
+// the packet decoder over the whole trace, into sink
+fn decode_trace(elf: &[u8], trace: &[u8], sink: &mut impl EntrySink) -> u64 {
+    let mut decoder = PacketDecoder::new(elf, None, &DecoderConfig::default()).unwrap();
+    let mut stream = trace;
+    let (_, first) = read_first_packet(&mut stream).unwrap();
+    decoder.start(first, sink);
+    while let Ok(packet) = read_packet(&mut stream) {
+        decoder.step(packet, sink);
+    }
+    decoder.packet_count
+}
+
+struct Fixture {
+    elf: Vec<u8>,
+    elf_path: String,
+    trace: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+fn fixture() -> Fixture {
+    // receivers write their outputs to the working directory
+    let dir = std::env::temp_dir().join("tacit-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    let elf = build_elf();
+    let elf_path = dir.join("bench.elf");
+    std::fs::write(&elf_path, &elf).unwrap();
+    let trace = build_trace(iterations());
+    let mut full = trace.clone();
+    full.extend(end_packet());
+    let mut sink = CollectSink(Vec::new());
+    decode_trace(&elf, &full, &mut sink);
+    Fixture { elf, elf_path: elf_path.to_string_lossy().into_owned(), trace, entries: sink.0 }
+}
+
+fn bench_frontend(c: &mut Criterion, fixture: &Fixture) {
+    let packets = 1 + 4 * iterations();
+    let mut group = c.benchmark_group("frontend");
+    group.throughput(Throughput::Elements(packets));
+    group.bench_function("read_packet", |b| b.iter(|| {
+        let mut stream = fixture.trace.as_slice();
+        let mut count = 0;
+        while let Ok(packet) = read_packet(&mut stream) {
+            black_box(packet);
+            count += 1;
+        }
+        count
+    }));
+    group.bench_function("decode", |b| b.iter_batched(
+        || PacketDecoder::new(&fixture.elf, None, &DecoderConfig::default()).unwrap(),
+        |mut decoder| {
+            let mut sink = CountSink(0);
+            let mut stream = fixture.trace.as_slice();
+            let (_, first) = read_first_packet(&mut stream).unwrap();
+            decoder.start(first, &mut sink);
+            while let Ok(packet) = read_packet(&mut stream) {
+                decoder.step(packet, &mut sink);
+            }
+            sink.0
+        },
+        BatchSize::LargeInput,
+    ));
+    group.finish();
+
+    // the three blocks of an iteration, each stepped to its branch or jump
+    let insn_map = build_insn_map(&object::File::parse(fixture.elf.as_slice()).unwrap()).unwrap().insns;
+    let mut group = c.benchmark_group("frontend");
+    group.throughput(Throughput::Elements(3 * iterations()));
+    group.bench_function("step_bb", |b| b.iter(|| {
+        let mut sink = CountSink(0);
+        for _ in 0..iterations() {
+            for pc in [MAIN, LEAF, LEAF + 8] {
+                black_box(step_bb(pc, &insn_map, &mut sink, &BrMode::BrTarget));
+            }
+        }
+        sink.0
+    }));
+    group.finish();
+}
+
+fn bench_unwinder(c: &mut Criterion, fixture: &Fixture) {
+    let mut group = c.benchmark_group("backend");
+    group.throughput(Throughput::Elements(fixture.entries.len() as u64));
+    group.bench_function("stack_unwinder", |b| b.iter_batched(
+        || StackUnwinder::from_elf_bytes(&fixture.elf, &UnwinderOptions::default()).unwrap(),
+        |mut unwinder| {
+            for entry in &fixture.entries {
+                match entry.event {
+                    Event::InferrableJump => { black_box(unwinder.step_ij(entry.clone())); }
+                    Event::UninferableJump => { black_box(unwinder.step_uj(entry.clone())); }
+                    _ => {}
+                }
+            }
+            unwinder.flush()
+        },
+        BatchSize::LargeInput,
+    ));
+    group.finish();
+}
+
+type NewReceiver = fn(BusReader<Entry>, String) -> anyhow::Result<Box<dyn AbstractReceiver>>;
+
+fn bench_receivers(c: &mut Criterion, fixture: &Fixture) {
+    let receivers: [(&str, NewReceiver); 9] = [
+        ("txt", |rx, elf| boxed(TxtReceiver::new(rx, elf, &UnwinderOptions::default(), TxtOptions::default()))),
+        ("stack_txt", |rx, elf| boxed(StackTxtReceiver::new(rx, elf, &UnwinderOptions::default(), false, 0))),
+        ("afdo", |rx, elf| boxed(AfdoReceiver::new(rx, elf))),
+        ("speedscope", |rx, elf| boxed(SpeedscopeReceiver::new(rx, elf, &UnwinderOptions::default()))),
+        ("perfetto", |rx, elf| boxed(PerfettoReceiver::new(rx, elf, &UnwinderOptions::default()))),
+        ("fxt", |rx, elf| boxed(FxtReceiver::new(rx, elf, &UnwinderOptions::default()))),
+        ("vpp", |rx, elf| boxed(VPPReceiver::new(rx, elf, &UnwinderOptions::default(), true, false, false))),
+        ("vbb", |rx, elf| boxed(VBBReceiver::new(rx, elf, 0.0, false))),
+        ("top", |rx, elf| boxed(TopReceiver::new(rx, elf, &UnwinderOptions::default(), 10))),
+    ];
+    // the receivers are fed directly, nothing is sent on the bus
+    let mut bus: Bus<Entry> = Bus::new(1);
+    let mut group = c.benchmark_group("receiver");
+    group.throughput(Throughput::Elements(fixture.entries.len() as u64));
+    for (name, new) in receivers {
+        group.bench_function(name, |b| b.iter_batched(
+            || new(bus.add_rx(), fixture.elf_path.clone()).unwrap(),
+            |mut receiver| {
+                for entry in &fixture.entries {
+                    receiver._receive_entry(entry.clone()).unwrap();
+                }
+                receiver._flush().unwrap();
+            },
+            BatchSize::LargeInput,
+        ));
+    }
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    let fixture = fixture();
+    bench_frontend(c, &fixture);
+    bench_unwinder(c, &fixture);
+    bench_receivers(c, &fixture);
+}
+
+criterion_group!(decode, benches);
+criterion_main!(decode);
@@ -1,7 +1,10 @@
 use indexmap::IndexMap as OrderedMap;
-use crate::{gcno::Gcno, tag};
+use crate::{gcno::{lineno_checksum, Gcno}, tag};
+use crate::version::GcovVersion;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use anyhow::{anyhow, Result};
 use log::debug;
 // New CFG data structures
 
@@ -174,21 +177,53 @@ impl ControlFlowGraph {
     edge_map
   }
   
-  pub fn write_gcda(&self, edge_map: &OrderedMap<String, Vec<ReportedEdge>>, writer: &mut BufWriter<File>) {
+  /// Checks what gcov checks before it accepts a data file, so a mismatch
+  /// is an error here instead of a gcda silently ignored: every function
+  /// record agrees with its lineno checksum, identifiers are unique, and
+  /// the functions with counters are in the binary (`symbols`).
+  pub fn validate(&self, symbols: &HashSet<String>) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut identifiers = HashSet::new();
+    for func in self.functions.iter() {
+      let expected = lineno_checksum(&func.name, &func.source_file, func.span.start_line);
+      if func.lineno_checksum != expected {
+        problems.push(format!("{}: lineno checksum {:#010x}, its name, file and line give {:#010x}", func.name, func.lineno_checksum, expected));
+      }
+      if !identifiers.insert(func.identifier) {
+        problems.push(format!("{}: identifier {} is used twice", func.name, func.identifier));
+      }
+      if !func.report_instrumented_edges().is_empty() && !symbols.contains(&func.name) {
+        problems.push(format!("{}: not in the binary, the notes file is from another build", func.name));
+      }
+    }
+    if problems.is_empty() {
+      return Ok(());
+    }
+    let shown = problems.iter().take(10).cloned().collect::<Vec<_>>().join("\n  ");
+    Err(anyhow!("the gcno does not match the binary ({} problems):\n  {}", problems.len(), shown))
+  }
+
+  /// Writes the counters in the layout of the given gcov version; it has to
+  /// be one whose gcov reads the notes file, see `GcovVersion::same_layout`.
+  pub fn write_gcda(&self, edge_map: &OrderedMap<String, Vec<ReportedEdge>>, writer: &mut BufWriter<File>, gcov: &GcovVersion) {
+    // record lengths are in bytes from GCC 12 on, in words before
+    let unit = if gcov.byte_lengths() { 1 } else { 4 };
     writer.write_all(&GCDA_MAGIC.to_le_bytes()).unwrap();
-    writer.write_all(&self.version.to_le_bytes()).unwrap();
+    writer.write_all(&gcov.word().to_le_bytes()).unwrap();
     writer.write_all(&self.stamp.to_le_bytes()).unwrap();
-    writer.write_all(&0u32.to_le_bytes()).unwrap(); // checksum
+    if gcov.has_checksum() {
+      writer.write_all(&0u32.to_le_bytes()).unwrap(); // checksum
+    }
     // write the function summary tag
     for func in self.functions.iter() {
       writer.write_all(&tag::FUNCTION_TAG.to_le_bytes()).unwrap();
-      writer.write_all(&12u32.to_le_bytes()).unwrap();
+      writer.write_all(&(12u32 / unit).to_le_bytes()).unwrap();
       writer.write_all(&(func.identifier as u32).to_le_bytes()).unwrap();
       writer.write_all(&(func.lineno_checksum as u32).to_le_bytes()).unwrap();
       writer.write_all(&(func.cfg_checksum as u32).to_le_bytes()).unwrap();
       writer.write_all(&tag::COUNTER_BASE_TAG.to_le_bytes()).unwrap();
       let size = edge_map.get(&func.name).unwrap().len() as u32;
-      writer.write_all(&(size*8).to_le_bytes()).unwrap();
+      writer.write_all(&(size * 8 / unit).to_le_bytes()).unwrap();
       for edge in edge_map.get(&func.name).unwrap() {
         debug!("writing edge count: {:?}", edge.count);
         writer.write_all(&edge.count.to_le_bytes()).unwrap();
@@ -4,6 +4,26 @@ pub fn str_trim(s: String) -> String {
   s.split('\0').next().unwrap_or("").to_string()
 }
 
+// libiberty's crc32_string: MSB first, polynomial 0x04c11db7, over the
+// bytes of the string and its null terminator
+fn crc32_string(mut chksum: u32, s: &str) -> u32 {
+  for &byte in s.as_bytes().iter().chain(std::iter::once(&0)) {
+    let mut value = (byte as u32) << 24;
+    for _ in 0..8 {
+      let feedback = if (value ^ chksum) & 0x8000_0000 != 0 { 0x04c1_1db7 } else { 0 };
+      chksum = (chksum << 1) ^ feedback;
+      value <<= 1;
+    }
+  }
+  chksum
+}
+
+// the lineno checksum GCC gives a function, from its start line, source
+// file and assembler name
+pub fn lineno_checksum(name: &str, source: &str, line: u32) -> u32 {
+  crc32_string(crc32_string(line, source), name)
+}
+
 pub fn str_term(s: String) -> String {
   let mut s = s.clone();
   s.push('\0');
@@ -2,4 +2,5 @@ extern crate anyhow;
 pub mod reader;
 pub mod tag;
 pub mod gcno;
-pub mod cfg;
\ No newline at end of file
+pub mod cfg;
+pub mod version;
\ No newline at end of file
@@ -4,9 +4,11 @@ use anyhow::Result;
 use log::trace;
 use crate::tag::*;
 use crate::gcno::*;
+use crate::version::GcovVersion;
 pub struct GCNOReader {
   reader: BufReader<File>,
   version: u32,
+  gcov: GcovVersion,
   stamp: u32,
   cwd: String
 }
@@ -34,24 +36,25 @@ fn read_u32_from_slice(reader: &mut BufReader<&[u8]>) -> Result<u32> {
   Ok(u32::from_le_bytes(buf))
 }
 
-// string: int32:0 | int32:length char* char:0
-fn read_string(reader: &mut BufReader<File>) -> Result<String> {
+// bytes in a length field, counted in words before GCC 12
+fn length_bytes(length: u32, gcov: &GcovVersion) -> usize {
+  if gcov.byte_lengths() { length as usize } else { length as usize * 4 }
+}
+
+// string: int32:length char* char:0, padded to a word before GCC 12
+fn read_string(reader: &mut BufReader<File>, gcov: &GcovVersion) -> Result<String> {
   let pos = reader.stream_position()?;
   trace!("{:08x}: reading string", pos);
-  let string_magic = read_u32(reader)?;
-  assert!(string_magic == 0, "string magic should be 0 but got {:x}", string_magic);
   let length = read_u32(reader)?;
-  let mut buf = vec![0u8; length as usize];
+  let mut buf = vec![0u8; length_bytes(length, gcov)];
   reader.read_exact(&mut buf)?;
-  read_u32(reader)?; // I don't why but this is there somehow
   Ok(str_trim(String::from_utf8(buf)?))
 }
 
-fn read_string_from_slice(reader: &mut BufReader<&[u8]>) -> Result<String> {
+fn read_string_from_slice(reader: &mut BufReader<&[u8]>, gcov: &GcovVersion) -> Result<String> {
   let length = read_u32_from_slice(reader)?;
-  let mut buf = vec![0u8; length as usize];
+  let mut buf = vec![0u8; length_bytes(length, gcov)];
   reader.read_exact(&mut buf)?;
-  // read_u32_from_slice(reader)?; // I don't why but this is there somehow
   Ok(str_trim(String::from_utf8(buf)?))
 }
 
@@ -60,13 +63,13 @@ fn read_string_from_slice(reader: &mut BufReader<&[u8]>) -> Result<String> {
 	header: int32:tag int32:length
 	data: item* 
 */
-fn read_record(reader: &mut BufReader<File>) -> Result<(u32, u32, Vec<u8>)> {
+fn read_record(reader: &mut BufReader<File>, gcov: &GcovVersion) -> Result<(u32, u32, Vec<u8>)> {
   let pos = reader.stream_position()?;
   trace!("{:08x}: reading record", pos);
   // let magic = read_u32(reader)?;
   // assert!(magic == 1, "Invalid record magic: {:x}", magic);
   let tag = read_u32(reader)?;
-  let length = read_u32(reader)?;
+  let length = length_bytes(read_u32(reader)?, gcov) as u32;
   trace!("         tag={:08x}, length={:08x}", tag, length);
   match tag {
     FUNCTION_TAG => trace!("[RECORD]function"),
@@ -83,14 +86,14 @@ fn read_record(reader: &mut BufReader<File>) -> Result<(u32, u32, Vec<u8>)> {
   Ok((tag, length, data))
 }
 
-fn parse_function(data: Vec<u8>, _length: u32) -> Result<Function> {
+fn parse_function(data: Vec<u8>, _length: u32, gcov: &GcovVersion) -> Result<Function> {
   let mut reader = BufReader::new(data.as_slice());
   let identifier = read_u32_from_slice(&mut reader)?;
   let lineno_checksum = read_u32_from_slice(&mut reader)?;
   let cfg_checksum = read_u32_from_slice(&mut reader)?;
-  let name = read_string_from_slice(&mut reader)?;
-  read_u32_from_slice(&mut reader)?; // I don't why but this is there somehow
-  let source = read_string_from_slice(&mut reader)?;
+  let name = read_string_from_slice(&mut reader, gcov)?;
+  read_u32_from_slice(&mut reader)?; // artificial
+  let source = read_string_from_slice(&mut reader, gcov)?;
   let start_lineno = read_u32_from_slice(&mut reader)?;
   let start_colno = read_u32_from_slice(&mut reader)?;
   let end_lineno = read_u32_from_slice(&mut reader)?;
@@ -120,7 +123,7 @@ fn parse_arcs(data: Vec<u8>, length: u32) -> Result<Arcs> {
   Ok(Arcs::new(num_arcs, src_block, arcs))
 }
 
-fn parse_lines(data: Vec<u8>, _length: u32) -> Result<Lines> {
+fn parse_lines(data: Vec<u8>, _length: u32, gcov: &GcovVersion) -> Result<Lines> {
   let mut reader = BufReader::new(data.as_slice());
   let block_id = read_u32_from_slice(&mut reader)?;
   let mut sources = Vec::new();
@@ -131,7 +134,7 @@ fn parse_lines(data: Vec<u8>, _length: u32) -> Result<Lines> {
     match read_u32_from_slice(&mut reader) {
       Ok(lineno) => {
         if lineno == 0 {
-          let new_source_file = read_string_from_slice(&mut reader)?;
+          let new_source_file = read_string_from_slice(&mut reader, gcov)?;
           if !source_lineno.is_empty() {
             sources.push(Source { file_name: current_source_file.clone(), lineno: source_lineno });
           }
@@ -161,22 +164,27 @@ impl GCNOReader {
     let magic = read_u32(&mut reader)?;
     assert!(magic == 0x67636e6f); // GCNO magic number, only support little-endian 
     let version = read_u32(&mut reader)?;
+    let gcov = GcovVersion::from_word(version)?;
     let stamp = read_u32(&mut reader)?;
     trace!("version: {:x}, stamp: { }", version, stamp);
-    let cwd = read_string(&mut reader)?;
+    if gcov.has_checksum() {
+      read_u32(&mut reader)?; // checksum, only meaningful in data files
+    }
+    let cwd = read_string(&mut reader, &gcov)?;
     trace!("cwd: {:?}", cwd);
-    Ok(Self { reader, version, stamp, cwd })
+    read_u32(&mut reader)?; // has unexecuted blocks
+    Ok(Self { reader, version, gcov, stamp, cwd })
   }
 
   pub fn parse(&mut self) -> Result<Gcno> {
     let mut gcno = Gcno::new(self.version, self.stamp, self.cwd.clone());
     let mut current_function: Option<Function> = None;
     loop {
-      match read_record(&mut self.reader) {
+      match read_record(&mut self.reader, &self.gcov) {
         Ok((tag, length, data)) => {
           match tag {
             FUNCTION_TAG => {
-              let function = parse_function(data, length)?;
+              let function = parse_function(data, length, &self.gcov)?;
               if let Some(cf) = current_function {
                 gcno.add_function(cf.clone());
               }
@@ -191,7 +199,7 @@ impl GCNOReader {
               current_function.as_mut().unwrap().blocks.as_mut().unwrap().add_arcs(arcs);
             },
             LINES_TAG => {
-              let lines = parse_lines(data, length)?;
+              let lines = parse_lines(data, length, &self.gcov)?;
               current_function.as_mut().unwrap().blocks.as_mut().unwrap().add_line(lines);
             },
             _ => (),
@@ -216,6 +224,10 @@ impl GCNOReader {
     self.version
   }
 
+  pub fn gcov_version(&self) -> GcovVersion {
+    self.gcov
+  }
+
   pub fn stamp(&self) -> u32 {
     self.stamp
   }
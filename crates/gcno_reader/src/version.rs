@@ -0,0 +1,70 @@
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+
+/// The oldest and newest GCC whose notes and data files are understood.
+pub const MIN_MAJOR: u32 = 10;
+pub const MAX_MAJOR: u32 = 14;
+
+/// A gcov format version. GCC writes it as four characters, most
+/// significant first: `A` + major / 10, `0` + major % 10, `0` + minor, `*`,
+/// e.g. `B32*` for GCC 13.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcovVersion {
+  pub major: u32,
+  pub minor: u32,
+}
+
+impl GcovVersion {
+  pub fn new(major: u32, minor: u32) -> Result<Self> {
+    if !(MIN_MAJOR..=MAX_MAJOR).contains(&major) || minor > 9 {
+      return Err(anyhow!("gcov version {}.{} is not supported, only GCC {} to {}", major, minor, MIN_MAJOR, MAX_MAJOR));
+    }
+    Ok(Self { major, minor })
+  }
+
+  pub fn from_word(word: u32) -> Result<Self> {
+    let [v0, v1, v2, _] = word.to_be_bytes();
+    if !v0.is_ascii_uppercase() || !v1.is_ascii_digit() || !v2.is_ascii_digit() {
+      return Err(anyhow!("{:#010x} is not a gcov version", word));
+    }
+    Self::new((v0 - b'A') as u32 * 10 + (v1 - b'0') as u32, (v2 - b'0') as u32)
+  }
+
+  pub fn word(&self) -> u32 {
+    u32::from_be_bytes([b'A' + (self.major / 10) as u8, b'0' + (self.major % 10) as u8, b'0' + self.minor as u8, b'*'])
+  }
+
+  /// GCC 12 added a checksum to the file header.
+  pub fn has_checksum(&self) -> bool {
+    self.major >= 12
+  }
+
+  /// GCC 12 counts record and string lengths in bytes, older ones in words.
+  pub fn byte_lengths(&self) -> bool {
+    self.major >= 12
+  }
+
+  /// Whether the gcov of one version reads the files of the other.
+  pub fn same_layout(&self, other: &GcovVersion) -> bool {
+    self.byte_lengths() == other.byte_lengths() && self.has_checksum() == other.has_checksum()
+  }
+}
+
+// `13` or `13.2`
+impl FromStr for GcovVersion {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+    let major = major.parse().map_err(|_| anyhow!("gcov version `{}` is not MAJOR or MAJOR.MINOR", s))?;
+    let minor = minor.parse().map_err(|_| anyhow!("gcov version `{}` is not MAJOR or MAJOR.MINOR", s))?;
+    Self::new(major, minor)
+  }
+}
+
+impl fmt::Display for GcovVersion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}", self.major, self.minor)
+  }
+}
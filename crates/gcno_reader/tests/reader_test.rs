@@ -3,6 +3,8 @@ use gcno_reader::reader::GCNOReader;
 use env_logger;
 use gcno_reader::gcno::str_trim;
 use gcno_reader::cfg::ControlFlowGraph;
+use gcno_reader::gcno::lineno_checksum;
+use gcno_reader::version::GcovVersion;
 
 #[test]
 fn test_gcno_read_sort() -> Result<()> {
@@ -33,4 +35,21 @@ fn test_cut_string() {
     let s = String::from("hello\0");
     let cut = str_trim(s);
     assert_eq!(cut, "hello");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_gcov_version() -> Result<()> {
+    let version: GcovVersion = "12.2".parse()?;
+    assert_eq!(version.word(), u32::from_be_bytes(*b"B22*"));
+    assert_eq!(GcovVersion::from_word(version.word())?, version);
+    assert!(version.same_layout(&"14".parse()?));
+    assert!(!version.same_layout(&"11".parse()?));
+    assert!("9".parse::<GcovVersion>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_lineno_checksum() {
+    // as written by GCC 12 for main() on line 62 of sort.c
+    assert_eq!(lineno_checksum("main", "sort.c", 62), 0x1ec3b52a);
+}
@@ -0,0 +1,43 @@
+// Bit fields of packed headers and payloads.
+
+// the `width` bits of value starting at bit `offset`
+pub fn field(value: u64, offset: u32, width: u32) -> u64 {
+    let value = value.checked_shr(offset).unwrap_or(0);
+    if width >= u64::BITS { value } else { value & ((1 << width) - 1) }
+}
+
+// value, `bits` wide, as a two's complement number
+pub fn sign_extend(value: u64, bits: u32) -> i64 {
+    if bits == 0 || bits >= 64 {
+        return value as i64;
+    }
+    let unused = 64 - bits;
+    ((value << unused) as i64) >> unused
+}
+
+// LSB-first reader over a payload, sign-extending past its end: the bits
+// read beyond the last one repeat it
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn read(&mut self, bits: u32) -> u64 {
+        if self.bytes.is_empty() {
+            return 0;
+        }
+        let last = self.bytes.len() * 8 - 1;
+        let mut value = 0;
+        for i in 0..bits {
+            let pos = self.pos.min(last);
+            value |= (((self.bytes[pos / 8] >> (pos % 8)) & 1) as u64) << i;
+            self.pos += 1;
+        }
+        value
+    }
+}
@@ -0,0 +1,28 @@
+// Fixed-size little-endian integers.
+use std::io::Read;
+
+use anyhow::Result;
+
+pub fn read_u8<R: Read>(stream: &mut R) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn read_u16_le<R: Read>(stream: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    stream.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub fn read_u32_le<R: Read>(stream: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u64_le<R: Read>(stream: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
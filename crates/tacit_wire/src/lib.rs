@@ -0,0 +1,10 @@
+// Byte-level primitives of the trace formats: the varints of tacit packets
+// and replay records, little-endian fixed-size integers and bit fields.
+// Readers work over any `Read`, a byte slice included.
+pub mod bits;
+pub mod fixed;
+pub mod varint;
+
+pub use bits::{field, sign_extend, BitReader};
+pub use fixed::{read_u16_le, read_u32_le, read_u64_le, read_u8};
+pub use varint::{read_varint, read_varint_width, write_varint, VAR_BITS, VAR_LAST, VAR_MASK};
@@ -0,0 +1,46 @@
+// Varints, least significant group first, 7 payload bits per byte; the last
+// byte has its top bit set (the opposite of LEB128).
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+
+use crate::fixed::read_u8;
+
+pub const VAR_MASK: u8 = 0b1000_0000;
+pub const VAR_LAST: u8 = 0b1000_0000;
+pub const VAR_BITS: u32 = 7;
+const VAR_VAL_MASK: u8 = 0b0111_1111;
+
+pub fn read_varint<R: Read>(stream: &mut R) -> Result<u64> {
+    Ok(read_varint_width(stream)?.0)
+}
+
+// value and number of payload bits, for the sign extension of deltas
+pub fn read_varint_width<R: Read>(stream: &mut R) -> Result<(u64, u32)> {
+    let mut value = 0;
+    let mut bits = 0;
+    loop {
+        let byte = read_u8(stream)?;
+        // the tenth byte only has room for the top bit
+        if bits >= u64::BITS || (bits == u64::BITS - 1 && byte & VAR_VAL_MASK > 1) {
+            return Err(anyhow!("varint longer than 64 bits"));
+        }
+        value |= ((byte & VAR_VAL_MASK) as u64) << bits;
+        bits += VAR_BITS;
+        if byte & VAR_MASK == VAR_LAST {
+            return Ok((value, bits));
+        }
+    }
+}
+
+pub fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & VAR_VAL_MASK as u64) as u8;
+        value >>= VAR_BITS;
+        if value == 0 {
+            buf.push(byte | VAR_LAST);
+            return;
+        }
+        buf.push(byte);
+    }
+}
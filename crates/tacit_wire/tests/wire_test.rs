@@ -0,0 +1,73 @@
+use anyhow::Result;
+use tacit_wire::*;
+
+#[test]
+fn test_varint_round_trip() -> Result<()> {
+    for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, u64::MAX] {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, value);
+        assert_eq!(buf.last().unwrap() & VAR_MASK, VAR_LAST);
+        assert!(buf[..buf.len() - 1].iter().all(|byte| byte & VAR_MASK != VAR_LAST));
+        let mut stream = buf.as_slice();
+        assert_eq!(read_varint(&mut stream)?, value);
+        assert!(stream.is_empty());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_varint_width() -> Result<()> {
+    // 0x7f in two bytes, as a delta encoder pads a negative value
+    let (value, bits) = read_varint_width(&mut [0x7f, 0x80].as_slice())?;
+    assert_eq!((value, bits), (0x7f, 14));
+    assert_eq!(sign_extend(value, bits), 0x7f);
+    let (value, bits) = read_varint_width(&mut [0xff].as_slice())?;
+    assert_eq!(sign_extend(value, bits), -1);
+    Ok(())
+}
+
+#[test]
+fn test_varint_errors() {
+    assert!(read_varint(&mut [0x01, 0x02].as_slice()).is_err());
+    assert!(read_varint(&mut [0x00; 11].as_slice()).is_err());
+    let too_long = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80];
+    assert!(read_varint(&mut too_long.as_slice()).is_err());
+    // bit 64 and above in the tenth byte
+    let overflow = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0x82];
+    assert!(read_varint(&mut overflow.as_slice()).is_err());
+    let top_bit = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0x81];
+    assert_eq!(read_varint(&mut top_bit.as_slice()).unwrap(), 1 << 63);
+}
+
+#[test]
+fn test_fixed() -> Result<()> {
+    let bytes = [0x01, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01];
+    let mut stream = &bytes[..14];
+    assert_eq!(read_u8(&mut stream)?, 0x01);
+    assert_eq!(read_u16_le(&mut stream)?, 0x1234);
+    assert_eq!(read_u32_le(&mut stream)?, 0x1234_5678);
+    // one byte short
+    assert!(read_u64_le(&mut stream).is_err());
+    let mut stream = &bytes[7..];
+    assert_eq!(read_u64_le(&mut stream)?, 0x0123_4567_89ab_cdef);
+    Ok(())
+}
+
+#[test]
+fn test_field() {
+    assert_eq!(field(0b1011_0100, 2, 3), 0b101);
+    assert_eq!(field(u64::MAX, 0, 64), u64::MAX);
+    assert_eq!(field(u64::MAX, 60, 8), 0xf);
+    assert_eq!(field(1, 64, 1), 0);
+}
+
+#[test]
+fn test_bit_reader() {
+    let mut bits = BitReader::new(&[0b1010_0110, 0b1000_0001]);
+    assert_eq!(bits.read(3), 0b110);
+    assert_eq!(bits.read(5), 0b10100);
+    assert_eq!(bits.read(4), 0b0001);
+    // past the end, the last bit repeats
+    assert_eq!(bits.read(8), 0xf8);
+    assert_eq!(BitReader::new(&[]).read(8), 0);
+}
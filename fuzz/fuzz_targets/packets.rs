@@ -0,0 +1,30 @@
+// well-formed traces from the packet generator: every packet has to read
+// back as it was written, a truncated trace has to end in an error
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ltrace_decoder::frontend::packet::{read_first_packet, read_packet};
+use tacit_fuzz::Trace;
+
+fuzz_target!(|input: (Trace, usize)| {
+    let (trace, cut) = input;
+    let bytes = trace.encode();
+    let mut stream = bytes.as_slice();
+    let (header, first) = read_first_packet(&mut stream).expect("a generated trace starts with a sync packet");
+    assert_eq!(header, trace.header.as_ref().map(|header| header.expected()));
+    trace.sync().check(&first);
+    for packet in &trace.packets {
+        packet.check(&read_packet(&mut stream).expect("a generated packet reads back"));
+    }
+    assert!(stream.is_empty());
+
+    // cut within the last packet
+    if let Some(last) = trace.packets.last() {
+        let len = last.encode().len();
+        let mut stream = &bytes[..bytes.len() - 1 - cut % len];
+        let _ = read_first_packet(&mut stream);
+        for _ in 1..trace.packets.len() {
+            let _ = read_packet(&mut stream);
+        }
+        assert!(read_packet(&mut stream).is_err());
+    }
+});
@@ -0,0 +1,12 @@
+// any bytes as the start of a trace: the optional header, the sync packet
+// and the packets after it
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ltrace_decoder::frontend::packet::{read_first_packet, read_packet};
+
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+    if read_first_packet(&mut stream).is_ok() {
+        while read_packet(&mut stream).is_ok() {}
+    }
+});
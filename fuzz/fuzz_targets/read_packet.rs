@@ -0,0 +1,9 @@
+// any bytes as a packet stream: read packets until the first error
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ltrace_decoder::frontend::packet::read_packet;
+
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+    while read_packet(&mut stream).is_ok() {}
+});
@@ -0,0 +1,248 @@
+// Generator of well-formed tacit traces for the structured fuzz target: an
+// optional header, the sync packet a trace starts with and any packets
+// after it, each of which knows its encoding and checks what the parser
+// read back.
+use arbitrary::Arbitrary;
+use ltrace_decoder::frontend::address::{AddressScheme, Compression};
+use ltrace_decoder::frontend::c_header::CHeader;
+use ltrace_decoder::frontend::check_type::{CHECK_CRC, CHECK_SEQUENCE};
+use ltrace_decoder::frontend::f_header::FHeader;
+use ltrace_decoder::frontend::packet::{Packet, TraceHeader, TRACE_MAGIC};
+use ltrace_decoder::frontend::trap_type::TrapType;
+use ltrace_decoder::frontend::val_type::ValType;
+use tacit_wire::write_varint;
+
+// parameters the decoder knows, the rest are skipped
+const KNOWN_PARAMS: usize = 7;
+
+#[derive(Debug, Arbitrary)]
+pub struct Trace {
+    pub header: Option<Header>,
+    pub sync_target: u64,
+    pub sync_timestamp: u64,
+    pub packets: Vec<GenPacket>,
+}
+
+impl Trace {
+    pub fn sync(&self) -> GenPacket {
+        GenPacket::Sync { target: self.sync_target, timestamp: self.sync_timestamp }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if let Some(header) = &self.header {
+            header.encode_into(&mut bytes);
+        }
+        self.sync().encode_into(&mut bytes);
+        for packet in &self.packets {
+            packet.encode_into(&mut bytes);
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+pub struct Header {
+    pub version: u8,
+    pub shift: u8,
+    // 0 xor, 1 absolute, 2 delta, taken modulo 3
+    pub compression: u8,
+    pub br_mode: u8,
+    pub bp_entries: u32,
+    // parameters of a newer version
+    pub extra: Vec<u8>,
+}
+
+impl Header {
+    fn compression_code(&self) -> u8 {
+        self.compression % 3
+    }
+
+    fn extra(&self) -> &[u8] {
+        &self.extra[..self.extra.len().min(u8::MAX as usize - KNOWN_PARAMS)]
+    }
+
+    fn encode_into(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(TRACE_MAGIC);
+        bytes.push(self.version);
+        bytes.push((KNOWN_PARAMS + self.extra().len()) as u8);
+        bytes.extend_from_slice(&[self.shift, self.compression_code(), self.br_mode]);
+        bytes.extend_from_slice(&self.bp_entries.to_le_bytes());
+        bytes.extend_from_slice(self.extra());
+    }
+
+    pub fn expected(&self) -> TraceHeader {
+        TraceHeader {
+            version: self.version,
+            address: AddressScheme {
+                shift: self.shift as u32,
+                compression: Compression::from_code(self.compression_code()).unwrap(),
+            },
+            br_mode: self.br_mode as u64,
+            bp_entries: self.bp_entries as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Arbitrary)]
+pub enum CompressedKind {
+    Taken,
+    NotTaken,
+    InferableJump,
+}
+
+#[derive(Debug, Clone, Copy, Arbitrary)]
+pub enum TrapKind {
+    None,
+    Exception,
+    Interrupt,
+    Return,
+}
+
+impl TrapKind {
+    fn bits(self) -> u8 {
+        match self {
+            TrapKind::None => 0b000,
+            TrapKind::Exception => 0b001,
+            TrapKind::Interrupt => 0b010,
+            TrapKind::Return => 0b100,
+        }
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+pub enum GenPacket {
+    // the low 6 bits of timestamp
+    Compressed { kind: CompressedKind, timestamp: u8 },
+    Branch { taken: bool, timestamp: u64 },
+    InferableJump { timestamp: u64 },
+    UninferableJump { target: u64, timestamp: u64 },
+    Sync { target: u64, timestamp: u64 },
+    Trap { kind: TrapKind, from: u64, target: u64, timestamp: u64 },
+    // the low 3 bits of val_type, unknown ones read as VNone
+    Value { val_type: u8, regno: u16, value: u64 },
+    Check { sequence: Option<u64>, crc: Option<u64> },
+}
+
+fn uncompressed(f_header: FHeader, extra: u8) -> u8 {
+    extra << 5 | (f_header as u8) << 2 | CHeader::CNa as u8
+}
+
+impl GenPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_into(&mut bytes);
+        bytes
+    }
+
+    fn encode_into(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            GenPacket::Compressed { kind, timestamp } => {
+                let c_header = match kind {
+                    CompressedKind::Taken => CHeader::CTb,
+                    CompressedKind::NotTaken => CHeader::CNt,
+                    CompressedKind::InferableJump => CHeader::CIj,
+                };
+                bytes.push((timestamp & 0b11_1111) << 2 | c_header as u8);
+            }
+            GenPacket::Branch { taken, timestamp } => {
+                bytes.push(uncompressed(if taken { FHeader::FTb } else { FHeader::FNt }, 0));
+                write_varint(bytes, timestamp);
+            }
+            GenPacket::InferableJump { timestamp } => {
+                bytes.push(uncompressed(FHeader::FIj, 0));
+                write_varint(bytes, timestamp);
+            }
+            GenPacket::UninferableJump { target, timestamp } | GenPacket::Sync { target, timestamp } => {
+                let f_header = if matches!(self, GenPacket::Sync { .. }) { FHeader::FSync } else { FHeader::FUj };
+                bytes.push(uncompressed(f_header, 0));
+                write_varint(bytes, target);
+                write_varint(bytes, timestamp);
+            }
+            GenPacket::Trap { kind, from, target, timestamp } => {
+                bytes.push(uncompressed(FHeader::FTrap, kind.bits()));
+                write_varint(bytes, from);
+                write_varint(bytes, target);
+                write_varint(bytes, timestamp);
+            }
+            GenPacket::Value { val_type, regno, value } => {
+                let val_type = val_type & 0b111;
+                bytes.push(uncompressed(FHeader::FVal, val_type));
+                if ValType::from(val_type) == ValType::VReg {
+                    write_varint(bytes, regno as u64);
+                }
+                write_varint(bytes, value);
+            }
+            GenPacket::Check { sequence, crc } => {
+                let check_type = if sequence.is_some() { CHECK_SEQUENCE } else { 0 }
+                               | if crc.is_some() { CHECK_CRC } else { 0 };
+                bytes.push(uncompressed(FHeader::FCheck, check_type));
+                if let Some(sequence) = sequence {
+                    write_varint(bytes, sequence);
+                }
+                if let Some(crc) = crc {
+                    write_varint(bytes, crc);
+                }
+            }
+        }
+    }
+
+    // panics on any difference between this packet and the one read back
+    pub fn check(&self, packet: &Packet) {
+        match *self {
+            GenPacket::Compressed { kind, timestamp } => {
+                let f_header = match kind {
+                    CompressedKind::Taken => FHeader::FTb,
+                    CompressedKind::NotTaken => FHeader::FNt,
+                    CompressedKind::InferableJump => FHeader::FIj,
+                };
+                assert!(packet.is_compressed);
+                assert_eq!(packet.f_header, f_header);
+                assert_eq!(packet.timestamp, (timestamp & 0b11_1111) as u64);
+                return;
+            }
+            GenPacket::Branch { taken, timestamp } => {
+                assert_eq!(packet.f_header, if taken { FHeader::FTb } else { FHeader::FNt });
+                assert_eq!(packet.timestamp, timestamp);
+            }
+            GenPacket::InferableJump { timestamp } => {
+                assert_eq!(packet.f_header, FHeader::FIj);
+                assert_eq!(packet.timestamp, timestamp);
+            }
+            GenPacket::UninferableJump { target, timestamp } => {
+                assert_eq!(packet.f_header, FHeader::FUj);
+                assert_eq!(packet.target_address, target);
+                assert!(u64::BITS - target.leading_zeros() <= packet.target_bits);
+                assert_eq!(packet.timestamp, timestamp);
+            }
+            GenPacket::Sync { target, timestamp } => {
+                assert_eq!(packet.f_header, FHeader::FSync);
+                assert_eq!(packet.target_address, target);
+                assert_eq!(packet.timestamp, timestamp);
+            }
+            GenPacket::Trap { kind, from, target, timestamp } => {
+                assert_eq!(packet.f_header, FHeader::FTrap);
+                assert_eq!(std::mem::discriminant(&packet.trap_type), std::mem::discriminant(&TrapType::from(kind.bits())));
+                assert_eq!(packet.from_address, from);
+                assert_eq!(packet.target_address, target);
+                assert_eq!(packet.timestamp, timestamp);
+            }
+            GenPacket::Value { val_type, regno, value } => {
+                let val_type = ValType::from(val_type & 0b111);
+                assert_eq!(packet.f_header, FHeader::FVal);
+                if val_type == ValType::VReg {
+                    assert_eq!(packet.regno, regno);
+                }
+                assert_eq!(packet.val_type, val_type);
+                assert_eq!(packet.value, value);
+            }
+            GenPacket::Check { sequence, crc } => {
+                assert_eq!(packet.f_header, FHeader::FCheck);
+                assert_eq!(packet.sequence, sequence);
+                assert_eq!(packet.crc, crc);
+            }
+        }
+        assert!(!packet.is_compressed);
+        assert_eq!(packet.c_header, CHeader::CNa);
+    }
+}
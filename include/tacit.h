@@ -0,0 +1,128 @@
+/* Generated with cbindgen from src/ffi.rs, regenerate with
+ *   cbindgen --config cbindgen.toml --output include/tacit.h
+ * and link against libltrace_decoder (.so or .a). */
+
+#ifndef TACIT_H
+#define TACIT_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* values of TacitEvent.kind, the event codes of the replay format */
+#define TACIT_EVENT_INSN 0
+#define TACIT_EVENT_START 1
+#define TACIT_EVENT_TAKEN_BRANCH 2
+#define TACIT_EVENT_NON_TAKEN_BRANCH 3
+#define TACIT_EVENT_UNINFERABLE_JUMP 4
+#define TACIT_EVENT_INFERRABLE_JUMP 5
+#define TACIT_EVENT_END 6
+#define TACIT_EVENT_TRAP_EXCEPTION 7
+#define TACIT_EVENT_TRAP_INTERRUPT 8
+#define TACIT_EVENT_TRAP_RETURN 9
+#define TACIT_EVENT_BP_HIT 10
+#define TACIT_EVENT_BP_MISS 11
+#define TACIT_EVENT_CONTEXT_SWITCH 12
+#define TACIT_EVENT_PANIC 13
+#define TACIT_EVENT_RESYNC 14
+#define TACIT_EVENT_DATA_ACCESS 15
+#define TACIT_EVENT_REGISTER_VALUE 16
+
+/**
+ * Version of TacitPlugin, a plugin built against another one is rejected.
+ */
+#define TACIT_PLUGIN_ABI 3
+
+typedef struct TacitDecoder TacitDecoder;
+
+/**
+ * One decoded entry. `kind` is the event code of the replay format, 0 being
+ * a retired instruction at `from`.
+ */
+typedef struct TacitEvent {
+  uint8_t kind;
+  uint8_t has_timestamp;
+  uint64_t from;
+  uint64_t to;
+  uint64_t timestamp;
+  uint64_t hart;
+  uint64_t asid;
+  uint64_t address;
+  uint64_t size;
+  uint8_t is_store;
+  uint16_t regno;
+  uint64_t value;
+} TacitEvent;
+
+/**
+ * A function symbol of the binary. The strings live as long as the decoder.
+ */
+typedef struct TacitSymbol {
+  uint32_t index;
+  uint32_t line;
+  uint64_t start;
+  uint64_t end;
+  const char *name;
+  const char *file;
+} TacitSymbol;
+
+/**
+ * A receiver in a plugin library, loaded with `--plugin`. The library
+ * exports `const TacitPlugin *tacit_plugins(size_t *count)`, its receivers
+ * by name. `create` gets the binary and the encoded traces, newline
+ * separated, and returns the state handed to the other calls, NULL on
+ * failure. `receive` and `flush` return 0 on success; on failure
+ * `last_error`, if set, says why. `flush`, `last_error` and `destroy` may
+ * be NULL. Every call of one receiver comes from the same thread.
+ */
+typedef struct TacitPlugin {
+  uint32_t abi_version;
+  const char *name;
+  void *(*create)(const char *binary, const char *traces);
+  int32_t (*receive)(void *state, const TacitEvent *event);
+  int32_t (*flush)(void *state);
+  const char *(*last_error)(void *state);
+  void (*destroy)(void *state);
+} TacitPlugin;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Starts decoding `encoded_trace` against `binary`. Returns NULL on failure.
+ */
+TacitDecoder *tacit_open(const char *binary,
+                         const char *encoded_trace,
+                         uint64_t br_mode,
+                         uint64_t bp_entries);
+
+/**
+ * Blocks until the next entry is decoded. Returns 1 when `event` was filled,
+ * 0 at the end of the trace and -1 on a decoding error (see tacit_last_error).
+ */
+int32_t tacit_next_event(TacitDecoder *decoder, TacitEvent *event);
+
+/**
+ * Points `symbols` at the function symbol table and returns its length.
+ */
+size_t tacit_symbols(const TacitDecoder *decoder, const TacitSymbol **symbols);
+
+/**
+ * Message of the last decoding error, NULL if there was none.
+ */
+const char *tacit_last_error(const TacitDecoder *decoder);
+
+/**
+ * Releases the decoder. A decoder thread that is still running finishes
+ * the trace in the background and its entries are dropped.
+ */
+void tacit_close(TacitDecoder *decoder);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TACIT_H */
@@ -0,0 +1,35 @@
+# import trace.annotations.json into Binary Ninja: highlights every executed
+# instruction by its share of the cycles and sets a comment with its count,
+# cycles and flags. Run from the scripting console with the traced binary
+# open: exec(open('scripts/binja_annotate.py').read())
+
+import json
+
+from binaryninja import HighlightColor, HighlightStandardColor
+from binaryninja.interaction import get_open_filename_input
+
+
+def heat(cycles, max_cycles):
+    # from white for cold to red for the hottest
+    share = float(cycles) / max_cycles if max_cycles else 0.0
+    return HighlightColor(HighlightStandardColor.WhiteHighlightColor, HighlightStandardColor.RedHighlightColor,
+                          mix=int(255 * share ** 0.5))
+
+
+def annotate(bv, path):
+    with open(path) as f:
+        annotations = json.load(f)
+    max_cycles = annotations['max_cycles']
+    with bv.undoable_transaction():
+        for row in annotations['addresses']:
+            address = int(row['address'], 16)
+            comment = 'tacit: %d executions, %d cycles' % (row['count'], row['cycles'])
+            if row['flags']:
+                comment += ', ' + ' '.join(row['flags'])
+            bv.set_comment_at(address, comment)
+            for function in bv.get_functions_containing(address):
+                function.set_user_instr_highlight(address, heat(row['cycles'], max_cycles))
+    print('annotated %d addresses' % len(annotations['addresses']))
+
+
+annotate(bv, get_open_filename_input('trace.annotations.json', '*.json'))
@@ -0,0 +1,36 @@
+# import trace.annotations.json into Ghidra: colors every executed
+# instruction by its share of the cycles and sets a comment with its count,
+# cycles and flags. Run from the Script Manager with the traced binary open.
+#@category Tacit
+
+import json
+
+from java.awt import Color
+
+
+def heat(cycles, max_cycles):
+    # white for cold, through yellow, to red for the hottest
+    share = float(cycles) / max_cycles if max_cycles else 0.0
+    share = share ** 0.5
+    return Color(255, int(255 - 160 * share), int(200 - 200 * share))
+
+
+def annotate(path):
+    with open(path) as f:
+        annotations = json.load(f)
+    max_cycles = annotations['max_cycles']
+    transaction = currentProgram.startTransaction('tacit annotations')
+    try:
+        for row in annotations['addresses']:
+            address = toAddr(row['address'])
+            setBackgroundColor(address, heat(row['cycles'], max_cycles))
+            comment = 'tacit: %d executions, %d cycles' % (row['count'], row['cycles'])
+            if row['flags']:
+                comment += ', ' + ' '.join(row['flags'])
+            setEOLComment(address, comment)
+    finally:
+        currentProgram.endTransaction(transaction, True)
+    print('annotated %d addresses' % len(annotations['addresses']))
+
+
+annotate(str(askFile('trace.annotations.json', 'Import')))
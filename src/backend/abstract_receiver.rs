@@ -1,6 +1,12 @@
 use crate::backend::event::Entry;
+use crate::metrics::{self, ReceiverMetrics};
+use anyhow::{Context, Result};
 use bus::BusReader;
+use log::error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 pub struct BusReceiver {
     pub name: String, // name of the type of receiver
@@ -8,31 +14,122 @@ pub struct BusReceiver {
     pub checksum: usize,
 }
 
+// what is known of the run before the first entry, handed to on_start
+#[derive(Clone, Default)]
+pub struct TraceMetadata {
+    pub binary: String,
+    // the encoded traces, or the replay file when replaying
+    pub traces: Vec<String>,
+    pub replay: bool,
+    pub br_mode: u64,
+    pub bp_entries: u64,
+}
+
+// tells the watchdog the receive loop is over, also when unwinding
+struct LoopGuard(Arc<ReceiverMetrics>);
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.panicked.store(true, Ordering::Relaxed);
+        }
+        self.0.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+// the type name without its path, `TxtReceiver`
+fn short_type_name<R: ?Sized>() -> String {
+    let type_name = std::any::type_name::<R>();
+    type_name.rsplit("::").next().unwrap_or(type_name).to_string()
+}
+
+// a receiver ready for the bus, or the error of building it with its name
+pub fn boxed<R: AbstractReceiver>(receiver: Result<R>) -> Result<Box<dyn AbstractReceiver>> {
+    let receiver = receiver.with_context(|| format!("{} failed to start", short_type_name::<R>()))?;
+    Ok(Box::new(receiver))
+}
+
 pub trait AbstractReceiver: Send + 'static {
     fn bus_rx(&mut self) -> &mut BusReader<Entry>;
-    fn try_receive_loop(&mut self) {
+    // receiver name in metrics and reports, the type name by default
+    fn name(&self) -> String {
+        short_type_name::<Self>()
+    }
+    // runs the receiver to the end of the bus, returning the first error of
+    // on_start, _receive_entry or _flush; a receiver that failed keeps taking
+    // entries off the bus so the frontend is not blocked on it
+    fn try_receive_loop(&mut self, metadata: &TraceMetadata) -> Result<()> {
+        let metrics = metrics::register_receiver(&self.name());
+        let _guard = LoopGuard(metrics.clone());
+        let mut failure = self.on_start(metadata).err();
+        if let Some(e) = &failure {
+            self.on_error(e);
+        }
+        // time is only taken when switching between busy and idle, not per entry
+        let mut busy = false;
+        let mut since = Instant::now();
         loop {
             match self.bus_rx().try_recv() {
                 Ok(entry) => {
-                    self._receive_entry(entry);
-                    self._bump_checksum();
+                    if !busy {
+                        metrics.add_idle(since.elapsed());
+                        since = Instant::now();
+                        busy = true;
+                    }
+                    if failure.is_none() {
+                        match self._receive_entry(entry) {
+                            Ok(()) => self._bump_checksum(),
+                            Err(e) => {
+                                self.on_error(&e);
+                                failure = Some(e);
+                            }
+                        }
+                    }
+                    if metrics.entries.fetch_add(1, Ordering::Relaxed).is_multiple_of(4096) {
+                        metrics.sample_lag();
+                    }
                 }
                 // if the bus is disconnected, we're done!
                 Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                    self._flush();
-                    return;
+                    if !busy {
+                        metrics.add_idle(since.elapsed());
+                        since = Instant::now();
+                    }
+                    if failure.is_none() {
+                        if let Err(e) = self._flush() {
+                            self.on_error(&e);
+                            failure = Some(e);
+                        }
+                    }
+                    metrics.checksum.store(self.checksum() as u64, Ordering::Relaxed);
+                    metrics.add_busy(since.elapsed());
+                    return failure.map_or(Ok(()), Err);
                 }
                 // if the bus is empty, yield until later
                 Err(std::sync::mpsc::TryRecvError::Empty) => {
+                    if busy {
+                        metrics.add_busy(since.elapsed());
+                        since = Instant::now();
+                        busy = false;
+                    }
                     thread::yield_now();
                 }
             }
         }
     }
-    // unused
+    // before the first entry, e.g. to open outputs or check the run suits it
+    fn on_start(&mut self, _metadata: &TraceMetadata) -> Result<()> {
+        Ok(())
+    }
+    // the receiver failed and gets no more entries, nor a flush
+    fn on_error(&mut self, error: &anyhow::Error) {
+        error!("{} failed: {:#}", self.name(), error);
+    }
+    // counts the entries received, checked against the frontend at the end
     fn _bump_checksum(&mut self);
+    fn checksum(&self) -> usize;
     // step through the trace
-    fn _receive_entry(&mut self, entry: Entry);
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()>;
     // any final actions
-    fn _flush(&mut self);
+    fn _flush(&mut self) -> Result<()>;
 }
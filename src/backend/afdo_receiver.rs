@@ -1,10 +1,103 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
 use bus::BusReader;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use addr2line::{gimli, Loader};
+use anyhow::{anyhow, Result};
+use object::{Object, ObjectSection, ObjectSymbol};
+use log::debug;
 
+type LineInfo = (Vec<(u64, u64)>, HashMap<String, u64>);
+
+// the discriminator of every line table row, sorted by address, and the
+// DW_AT_decl_line of every subprogram by linkage name and name
+fn read_line_info(elf: &object::File) -> Result<LineInfo> {
+  let endian = if elf.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+  let dwarf_sections = gimli::DwarfSections::load(|id| -> Result<Cow<[u8]>, gimli::Error> {
+    Ok(elf.section_by_name(id.name()).and_then(|sec| sec.uncompressed_data().ok()).unwrap_or(Cow::Borrowed(&[])))
+  })?;
+  let dwarf = dwarf_sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+  let mut rows = Vec::new();
+  let mut decl_lines = HashMap::new();
+  let mut headers = dwarf.units();
+  while let Some(header) = headers.next()? {
+    let unit = dwarf.unit(header)?;
+    if let Some(program) = unit.line_program.clone() {
+      let mut program_rows = program.rows();
+      while let Some((_, row)) = program_rows.next_row()? {
+        if !row.end_sequence() {
+          rows.push((row.address(), row.discriminator()));
+        }
+      }
+    }
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs()? {
+      if entry.tag() != gimli::DW_TAG_subprogram {
+        continue;
+      }
+      let Some(line) = entry.attr(gimli::DW_AT_decl_line)?.and_then(|attr| attr.udata_value()) else { continue };
+      for name_attr in [gimli::DW_AT_linkage_name, gimli::DW_AT_MIPS_linkage_name, gimli::DW_AT_name] {
+        if let Some(value) = entry.attr_value(name_attr)? {
+          let name = dwarf.attr_string(&unit, value)?;
+          decl_lines.entry(name.to_string_lossy().into_owned()).or_insert(line);
+        }
+      }
+    }
+  }
+  rows.sort_by_key(|&(address, _)| address);
+  Ok((rows, decl_lines))
+}
+
+// the samples of a function, or of one inlined instance of it
+#[derive(Default)]
+struct FunctionSamples {
+  head: u64,
+  // (line offset, discriminator) to the count and the call targets
+  body: BTreeMap<(u64, u64), (u64, BTreeMap<String, u64>)>,
+  // (line offset, discriminator, callee) of the call site it was inlined at
+  inlined: BTreeMap<(u64, u64, String), FunctionSamples>,
+}
+
+impl FunctionSamples {
+  fn total(&self) -> u64 {
+    self.body.values().map(|(count, _)| count).sum::<u64>() + self.inlined.values().map(|f| f.total()).sum::<u64>()
+  }
+
+  fn write(&self, w: &mut impl Write, depth: usize) -> std::io::Result<()> {
+    let indent = " ".repeat(depth);
+    for (&(offset, discriminator), (count, targets)) in self.body.iter() {
+      write!(w, "{}{}", indent, offset)?;
+      if discriminator != 0 {
+        write!(w, ".{}", discriminator)?;
+      }
+      write!(w, ": {}", count)?;
+      for (callee, calls) in targets {
+        write!(w, " {}:{}", callee, calls)?;
+      }
+      writeln!(w)?;
+    }
+    for ((offset, discriminator, callee), samples) in self.inlined.iter() {
+      write!(w, "{}{}", indent, offset)?;
+      if *discriminator != 0 {
+        write!(w, ".{}", discriminator)?;
+      }
+      writeln!(w, ": {}:{}", callee, samples.total())?;
+      samples.write(w, depth + 1)?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes `trace_afdo.txt`, the range and branch counts AutoFDO's
+/// create_gcov reads, and `trace_afdo.prof`, an LLVM text sample profile
+/// symbolized here: per function the samples of every line offset and
+/// discriminator, the call targets of the call sites, and the inline stacks,
+/// headed by the ELF build-ID so a profile is not applied to another build.
+/// Line counts are the highest instruction count of the line, as exact as
+/// the trace is.
 pub struct AfdoReceiver {
   writer: BufWriter<File>,
   receiver: BusReceiver,
@@ -12,16 +105,107 @@ pub struct AfdoReceiver {
   branch_map: HashMap<(u64, u64), usize>,
   last_record: (u64, u64),
   elf_start: u64,
+  loader: Loader,
+  build_id: Option<Vec<u8>>,
+  func_starts: HashSet<u64>,
+  discriminators: Vec<(u64, u64)>,
+  decl_lines: HashMap<String, u64>,
+  insn_counts: HashMap<u64, u64>,
+  // call site pc to the count per callee entry
+  call_counts: HashMap<u64, HashMap<u64, u64>>,
 }
 
 impl AfdoReceiver {
-  pub fn new(bus_rx: BusReader<Entry>, elf_start: u64) -> Self {
-    Self { writer: BufWriter::new(File::create("trace_afdo.txt").unwrap()), 
+  pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Result<Self> {
+    let elf_data = std::fs::read(&elf_path)?;
+    let elf = object::File::parse(&*elf_data)?;
+    let (discriminators, decl_lines) = read_line_info(&elf)?;
+    let func_starts = elf.symbols()
+      .filter(|symbol| symbol.kind() == object::SymbolKind::Text && symbol.address() != 0)
+      .map(|symbol| symbol.address())
+      .collect();
+    Ok(Self { writer: BufWriter::new(File::create("trace_afdo.txt")?),
             receiver: BusReceiver { name: "afdo".to_string(), bus_rx: bus_rx, checksum: 0 },
             range_map: HashMap::new(),
             branch_map: HashMap::new(),
             last_record: (0, 0),
-            elf_start: elf_start }
+            elf_start: elf.entry(),
+            loader: Loader::new(&elf_path).map_err(|e| anyhow!("{}", e))?,
+            build_id: elf.build_id().ok().flatten().map(|id| id.to_vec()),
+            func_starts,
+            discriminators,
+            decl_lines,
+            insn_counts: HashMap::new(),
+            call_counts: HashMap::new() })
+  }
+
+  fn discriminator(&self, pc: u64) -> u64 {
+    match self.discriminators.partition_point(|&(address, _)| address <= pc).checked_sub(1) {
+      Some(i) => self.discriminators[i].1,
+      None => 0,
+    }
+  }
+
+  // (name, line) of each frame at pc, the innermost inlined one first
+  fn frames(&self, pc: u64) -> Vec<(String, u64)> {
+    let mut frames = Vec::new();
+    if let Ok(mut iter) = self.loader.find_frames(pc) {
+      while let Ok(Some(frame)) = iter.next() {
+        let name = frame.function.as_ref().and_then(|f| f.raw_name().ok()).map(|n| n.into_owned());
+        let line = frame.location.as_ref().and_then(|l| l.line).unwrap_or(0) as u64;
+        match name {
+          Some(name) => frames.push((name, line)),
+          None => break,
+        }
+      }
+    }
+    frames
+  }
+
+  fn line_offset(&self, name: &str, line: u64) -> u64 {
+    line.saturating_sub(*self.decl_lines.get(name).unwrap_or(&0))
+  }
+
+  fn build_profile(&self) -> BTreeMap<String, FunctionSamples> {
+    let mut profile: BTreeMap<String, FunctionSamples> = BTreeMap::new();
+    for (&pc, &count) in self.insn_counts.iter() {
+      let frames = self.frames(pc);
+      let Some((outermost, _)) = frames.last() else { continue };
+      let mut samples = profile.entry(outermost.clone()).or_default();
+      if self.func_starts.contains(&pc) {
+        samples.head += count;
+      }
+      // every caller frame is at the call site of the one it inlined
+      for i in (1..frames.len()).rev() {
+        let (caller, line) = &frames[i];
+        let key = (self.line_offset(caller, *line), 0, frames[i - 1].0.clone());
+        samples = samples.inlined.entry(key).or_default();
+      }
+      let (name, line) = &frames[0];
+      let body = samples.body.entry((self.line_offset(name, *line), self.discriminator(pc))).or_default();
+      body.0 = body.0.max(count);
+      for (callee, calls) in self.call_counts.get(&pc).into_iter().flatten() {
+        if let Some(callee) = self.loader.find_symbol(*callee) {
+          *body.1.entry(callee.to_string()).or_default() += calls;
+        }
+      }
+    }
+    profile
+  }
+
+  fn write_profile(&self) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create("trace_afdo.prof")?);
+    if let Some(build_id) = &self.build_id {
+      let hex: String = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+      writeln!(writer, "# build-id: {}", hex)?;
+    }
+    let profile = self.build_profile();
+    debug!("afdo profile of {} functions", profile.len());
+    for (name, samples) in profile.iter() {
+      writeln!(writer, "{}:{}:{}", name, samples.total(), samples.head)?;
+      samples.write(&mut writer, 1)?;
+    }
+    writer.flush()
   }
 }
 
@@ -35,8 +219,15 @@ impl AbstractReceiver for AfdoReceiver {
     self.receiver.checksum += 1;
   }
 
-  fn _receive_entry(&mut self, entry: Entry) {
+  fn checksum(&self) -> usize {
+    self.receiver.checksum
+  }
+
+  fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
     match entry.event {
+      Event::None => {
+        *self.insn_counts.entry(entry.arc.0).or_default() += 1;
+      }
       Event::Start => {
         self.last_record = (0, entry.arc.0);
       }
@@ -44,24 +235,34 @@ impl AbstractReceiver for AfdoReceiver {
           self.range_map.entry((self.last_record.1, entry.arc.0)).and_modify(|v| *v += 1).or_insert(1);
           self.branch_map.entry((entry.arc.0, entry.arc.1)).and_modify(|v| *v += 1).or_insert(1);
           self.last_record = (entry.arc.0, entry.arc.1);
+          if entry.event != Event::TakenBranch && self.func_starts.contains(&entry.arc.1) {
+            *self.call_counts.entry(entry.arc.0).or_default().entry(entry.arc.1).or_default() += 1;
+          }
       }
       _ => {}
     }
+    Ok(())
   }
 
-  fn _flush(&mut self) {
-    // write the range map 
-    self.writer.write_all(format!("{}\n", self.range_map.len()).as_bytes()).unwrap();
-    for (key, value) in self.range_map.iter() {
-      self.writer.write_all(format!("{:x}-{:x}:{}\n", key.0 - self.elf_start, key.1 - self.elf_start, value).as_bytes()).unwrap();
+  fn _flush(&mut self) -> Result<()> {
+    // write the range map
+    self.writer.write_all(format!("{}\n", self.range_map.len()).as_bytes())?;
+    let mut ranges: Vec<_> = self.range_map.iter().collect();
+    ranges.sort_unstable();
+    for (key, value) in ranges {
+      self.writer.write_all(format!("{:x}-{:x}:{}\n", key.0 - self.elf_start, key.1 - self.elf_start, value).as_bytes())?;
     }
     // write the sample record, which should always be 0
-    self.writer.write_all(b"0\n").unwrap();
-    // write the branch map 
-    self.writer.write_all(format!("{}\n", self.branch_map.len()).as_bytes()).unwrap();
-    for (key, value) in self.branch_map.iter() {
-      self.writer.write_all(format!("{:x}->{:x}:{}\n", key.0 - self.elf_start, key.1 - self.elf_start, value).as_bytes()).unwrap();
+    self.writer.write_all(b"0\n")?;
+    // write the branch map
+    self.writer.write_all(format!("{}\n", self.branch_map.len()).as_bytes())?;
+    let mut branches: Vec<_> = self.branch_map.iter().collect();
+    branches.sort_unstable();
+    for (key, value) in branches {
+      self.writer.write_all(format!("{:x}->{:x}:{}\n", key.0 - self.elf_start, key.1 - self.elf_start, value).as_bytes())?;
     }
-    self.writer.flush().unwrap();
+    self.writer.flush()?;
+    self.write_profile()?;
+    Ok(())
   }
 }
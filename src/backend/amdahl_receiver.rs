@@ -0,0 +1,122 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::diagnostics::{self, Category};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use log::debug;
+
+/// Projects the runtime if the selected functions were faster: a finite
+/// factor divides the cycles spent in the function's own code, `inf` removes
+/// its invocations altogether, callees included. Each cycle is saved at most
+/// once, by the outermost removed frame on the stack or else by the leaf, so
+/// overlapping selections do not add up to more than the trace.
+pub struct AmdahlReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    // symbol index to its position in `speedups`
+    selected: HashMap<u32, usize>,
+    // (function, factor, cycles saved)
+    speedups: Vec<(String, f64, f64)>,
+    last_ts: Option<u64>,
+    total: u64,
+}
+
+impl AmdahlReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, speedups: Vec<(String, f64)>) -> Result<Self> {
+        debug!("AmdahlReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let mut selected = HashMap::new();
+        for (i, (name, _)) in speedups.iter().enumerate() {
+            let mut found = false;
+            for info in unwinder.symbols().iter().filter(|info| info.name == *name) {
+                selected.insert(info.index, i);
+                found = true;
+            }
+            if !found {
+                diagnostics::report(Category::MissingSymbol, format!("--speedup: no function named {}, it is left as is", name));
+            }
+        }
+        Ok(AmdahlReceiver {
+            receiver: BusReceiver { name: "amdahl".into(), bus_rx, checksum: 0 },
+            unwinder,
+            selected,
+            speedups: speedups.into_iter().map(|(name, factor)| (name, factor, 0.0)).collect(),
+            last_ts: None,
+            total: 0,
+        })
+    }
+
+    // the cycles since the previous timed event ran with the current stack
+    fn account(&mut self, ts: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        self.total += elapsed;
+        let removed = self.unwinder.frame_stack.iter()
+            .filter_map(|idx| self.selected.get(idx))
+            .find(|&&i| self.speedups[i].1.is_infinite());
+        let leaf = self.unwinder.frame_stack.last().and_then(|idx| self.selected.get(idx));
+        if let Some(&i) = removed.or(leaf) {
+            let (_, factor, saved) = &mut self.speedups[i];
+            *saved += elapsed as f64 * (1.0 - 1.0 / *factor);
+        }
+    }
+
+    fn report(&self) -> String {
+        let total = self.total as f64;
+        let mut out = format!("[Amdahl] {} cycles traced\n", self.total);
+        for (name, factor, saved) in &self.speedups {
+            let what = if factor.is_infinite() { "removed".to_string() } else { format!("{}x faster", factor) };
+            out += &format!("[Amdahl] {} {}: saves {:.0} cycles ({:.2}%)\n", name, what, saved, saved / total.max(1.0) * 100.0);
+        }
+        let saved: f64 = self.speedups.iter().map(|(_, _, saved)| saved).sum();
+        let projected = total - saved;
+        out += &format!("[Amdahl] projected {:.0} cycles, {:.2}% less, an overall speedup of {:.3}x\n",
+                        projected, saved / total.max(1.0) * 100.0, total / projected.max(1.0));
+        out
+    }
+}
+
+impl AbstractReceiver for AmdahlReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap());
+                let _ = self.unwinder.step_ij(entry);
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.account(entry.timestamp.unwrap());
+                let _ = self.unwinder.step_uj(entry);
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.account(entry.timestamp.unwrap());
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        print!("{}", self.report());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::timing_model::{CycleSpreader, TimingModel};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use serde_json::json;
+use log::debug;
+
+// what the trace saw an instruction do, as flag bits
+const FLAG_TAKEN: u8 = 1;
+const FLAG_NOT_TAKEN: u8 = 1 << 1;
+const FLAG_JUMP: u8 = 1 << 2;
+const FLAG_INDIRECT: u8 = 1 << 3;
+const FLAG_TARGET: u8 = 1 << 4;
+const FLAG_TRAP: u8 = 1 << 5;
+const FLAG_NAMES: [(u8, &str); 6] = [
+    (FLAG_TAKEN, "taken"),
+    (FLAG_NOT_TAKEN, "not-taken"),
+    (FLAG_JUMP, "jump"),
+    (FLAG_INDIRECT, "indirect"),
+    (FLAG_TARGET, "target"),
+    (FLAG_TRAP, "trap"),
+];
+
+#[derive(Default)]
+struct Annotation {
+    count: u64,
+    cycles: u64,
+    flags: u8,
+}
+
+/// Per executed instruction address, its execution count, the cycles spent
+/// on it and flags for what the trace saw it do, for reverse engineering
+/// tools to color the hot paths of a binary we trace but do not build.
+/// Writes `trace.annotations.json` and `trace.annotations.csv` with the
+/// same rows, by address; `scripts/ghidra_annotate.py` and
+/// `scripts/binja_annotate.py` import them. The cycles between two timed
+/// events are spread over the instructions retired in between by the timing
+/// model, evenly unless one is given. The
+/// flags are `taken` and `not-taken` for branches, `jump` and `indirect`
+/// for jumps, `target` for a jump or taken branch target and `trap` for an
+/// instruction a trap was taken at.
+pub struct AnnotationReceiver {
+    receiver: BusReceiver,
+    elf_path: String,
+    annotations: BTreeMap<u64, Annotation>,
+    last_ts: Option<u64>,
+    // the instructions retired since the last timed event
+    pending: CycleSpreader,
+}
+
+impl AnnotationReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, model: Arc<dyn TimingModel>) -> Result<Self> {
+        debug!("AnnotationReceiver::new");
+        Ok(AnnotationReceiver {
+            receiver: BusReceiver { name: "annotation".into(), bus_rx, checksum: 0 },
+            elf_path,
+            annotations: BTreeMap::new(),
+            last_ts: None,
+            pending: CycleSpreader::new(model),
+        })
+    }
+
+    fn account(&mut self, ts: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        for (pc, share) in self.pending.spread(elapsed) {
+            self.annotations.entry(pc).or_default().cycles += share;
+        }
+    }
+
+    fn flag(&mut self, pc: u64, flag: u8) {
+        self.annotations.entry(pc).or_default().flags |= flag;
+    }
+
+    fn flag_names(flags: u8) -> Vec<&'static str> {
+        FLAG_NAMES.iter().filter(|(bit, _)| flags & bit != 0).map(|&(_, name)| name).collect()
+    }
+
+    fn write_annotations(&self) -> std::io::Result<()> {
+        let total_cycles: u64 = self.annotations.values().map(|annotation| annotation.cycles).sum();
+        let mut writer = BufWriter::new(File::create("trace.annotations.json")?);
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"binary\": {},", json!(self.elf_path))?;
+        writeln!(writer, "  \"total_cycles\": {},", total_cycles)?;
+        writeln!(writer, "  \"max_count\": {},", self.annotations.values().map(|annotation| annotation.count).max().unwrap_or(0))?;
+        writeln!(writer, "  \"max_cycles\": {},", self.annotations.values().map(|annotation| annotation.cycles).max().unwrap_or(0))?;
+        writeln!(writer, "  \"addresses\": [")?;
+        for (i, (&pc, annotation)) in self.annotations.iter().enumerate() {
+            let comma = if i + 1 < self.annotations.len() { "," } else { "" };
+            let row = json!({
+                "address": format!("{:#x}", pc),
+                "count": annotation.count,
+                "cycles": annotation.cycles,
+                "flags": Self::flag_names(annotation.flags),
+            });
+            writeln!(writer, "    {}{}", row, comma)?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+
+        let mut writer = BufWriter::new(File::create("trace.annotations.csv")?);
+        writeln!(writer, "address,count,cycles,flags")?;
+        for (&pc, annotation) in &self.annotations {
+            writeln!(writer, "{:#x},{},{},{}", pc, annotation.count, annotation.cycles, Self::flag_names(annotation.flags).join(";"))?;
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for AnnotationReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        let (from, to) = entry.arc;
+        match entry.event {
+            Event::None => {
+                self.annotations.entry(from).or_default().count += 1;
+                self.pending.retire(from, entry.insn.as_ref());
+            }
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::TakenBranch => {
+                self.account(entry.timestamp.unwrap());
+                self.flag(from, FLAG_TAKEN);
+                self.flag(to, FLAG_TARGET);
+            }
+            Event::NonTakenBranch => {
+                self.account(entry.timestamp.unwrap());
+                self.flag(from, FLAG_NOT_TAKEN);
+            }
+            Event::InferrableJump => {
+                self.account(entry.timestamp.unwrap());
+                self.flag(from, FLAG_JUMP);
+                self.flag(to, FLAG_TARGET);
+            }
+            Event::UninferableJump => {
+                self.account(entry.timestamp.unwrap());
+                self.flag(from, FLAG_JUMP | FLAG_INDIRECT);
+                self.flag(to, FLAG_TARGET);
+            }
+            Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap());
+                self.flag(from, FLAG_TRAP);
+            }
+            Event::TrapReturn | Event::End => {
+                self.account(entry.timestamp.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_annotations()?;
+        Ok(())
+    }
+}
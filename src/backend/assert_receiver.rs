@@ -0,0 +1,360 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
+use crate::frontend::context::{parse_u64, Context};
+use bus::BusReader;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{anyhow, Result};
+use log::debug;
+
+// failed assertions of the run, for the exit code
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn violations() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "<" => Some(Comparison::Lt),
+            "<=" | "≤" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" | "≥" => Some(Comparison::Ge),
+            "==" | "=" => Some(Comparison::Eq),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, actual: f64, limit: f64) -> bool {
+        match self {
+            Comparison::Lt => actual < limit,
+            Comparison::Le => actual <= limit,
+            Comparison::Gt => actual > limit,
+            Comparison::Ge => actual >= limit,
+            Comparison::Eq => actual == limit,
+        }
+    }
+}
+
+// which of the invocation durations a latency assertion is about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyStat {
+    Percentile(f64),
+    Mean,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Assertion {
+    // how often a function is called
+    Calls { func: String, comparison: Comparison, limit: u64 },
+    // how long a function runs per invocation, in ticks, or microseconds
+    // when `micros`
+    Latency { func: String, stat: LatencyStat, comparison: Comparison, limit: f64, micros: bool },
+    // no instruction in [start, end) or in a function retires
+    NoExec { start: u64, end: u64 },
+    NoExecFunc { func: String },
+}
+
+// A spec file, one assertion per line, `#` starting a comment:
+//   calls <function> <op> <count>
+//   latency <function> <p<N>|mean|max> <op> <duration>
+//   no-exec <start> <end> | no-exec <function>
+//   ticks-per-us <count>
+// with <op> one of < <= > >= ==, durations in timestamp ticks or with an
+// ns, us or ms suffix once ticks-per-us is set
+pub struct AssertSpec {
+    // the assertion and its line as written
+    pub assertions: Vec<(Assertion, String)>,
+    pub ticks_per_us: Option<f64>,
+}
+
+impl AssertSpec {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let mut spec = AssertSpec { assertions: Vec::new(), ticks_per_us: None };
+        for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bad = || anyhow!("{}:{}: bad assertion `{}`", path, lineno + 1, line);
+            let assertion = match (fields[0], &fields[1..]) {
+                ("calls", [func, op, limit]) => Assertion::Calls {
+                    func: func.to_string(),
+                    comparison: Comparison::parse(op).ok_or_else(bad)?,
+                    limit: limit.parse().map_err(|_| bad())?,
+                },
+                ("latency", [func, stat, op, limit]) => {
+                    let stat = match *stat {
+                        "mean" => LatencyStat::Mean,
+                        "max" => LatencyStat::Max,
+                        _ => LatencyStat::Percentile(stat.strip_prefix('p').and_then(|p| p.parse().ok())
+                            .filter(|p| (0.0..=100.0).contains(p)).ok_or_else(bad)?),
+                    };
+                    let (limit, micros) = parse_duration(limit).ok_or_else(bad)?;
+                    Assertion::Latency { func: func.to_string(), stat, comparison: Comparison::parse(op).ok_or_else(bad)?, limit, micros }
+                }
+                ("no-exec", [start, end]) => Assertion::NoExec {
+                    start: parse_u64(start).map_err(|_| bad())?,
+                    end: parse_u64(end).map_err(|_| bad())?,
+                },
+                ("no-exec", [func]) => Assertion::NoExecFunc { func: func.to_string() },
+                ("ticks-per-us", [ticks]) => {
+                    spec.ticks_per_us = Some(ticks.parse().ok().filter(|&ticks: &f64| ticks > 0.0).ok_or_else(bad)?);
+                    continue;
+                }
+                _ => return Err(bad()),
+            };
+            spec.assertions.push((assertion, line.to_string()));
+        }
+        let micros = spec.assertions.iter().any(|(assertion, _)| matches!(assertion, Assertion::Latency { micros: true, .. }));
+        if micros && spec.ticks_per_us.is_none() {
+            return Err(anyhow!("{}: durations in ns, us or ms need a ticks-per-us line", path));
+        }
+        Ok(spec)
+    }
+}
+
+// (value, whether it is in microseconds) of `<n>` ticks or `<n>ns`, `<n>us`,
+// `<n>µs` or `<n>ms`
+fn parse_duration(text: &str) -> Option<(f64, bool)> {
+    for (suffix, scale) in [("ns", 0.001), ("us", 1.0), ("µs", 1.0), ("ms", 1000.0)] {
+        if let Some(value) = text.strip_suffix(suffix) {
+            return Some((value.parse::<f64>().ok()? * scale, true));
+        }
+    }
+    Some((text.parse().ok()?, false))
+}
+
+// nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// an execution of a no-exec range
+struct Intrusion {
+    start: u64,
+    end: u64,
+    count: u64,
+    // (timestamp, pc) of the first
+    first: Option<(u64, u64)>,
+}
+
+/// Checks the properties of a spec file (see AssertSpec) while decoding:
+/// call counts, per invocation latencies and address ranges that must not
+/// execute. Writes every result to `trace.assert.json`, prints the
+/// violations, and counts them in `violations()` so the run can exit
+/// non-zero and gate a CI job.
+pub struct AssertReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    spec: AssertSpec,
+    // the symbol index of the function of each Calls and Latency assertion
+    funcs: Vec<Option<u32>>,
+    tracked: HashSet<u32>,
+    calls: HashMap<u32, u64>,
+    // invocation durations per function, and the start of the open ones
+    durations: HashMap<u32, Vec<u64>>,
+    starts: HashMap<(Context, u32), Vec<u64>>,
+    // the intrusion of each NoExec and NoExecFunc assertion
+    intrusions: Vec<Option<Intrusion>>,
+    ctx: Context,
+    last_ts: u64,
+}
+
+impl AssertReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, spec: AssertSpec) -> Result<Self> {
+        debug!("AssertReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let find = |func: &str| {
+            unwinder.func_symbol_map().iter().find(|(_, info)| info.name == func)
+                .map(|(&start, info)| (start, info.index))
+                .ok_or_else(|| anyhow!("assertion on `{}`: no such function in the binary", func))
+        };
+        let mut funcs = Vec::new();
+        let mut intrusions = Vec::new();
+        for (assertion, _) in &spec.assertions {
+            let (func, intrusion) = match assertion {
+                Assertion::Calls { func, .. } | Assertion::Latency { func, .. } => (Some(find(func)?.1), None),
+                Assertion::NoExec { start, end } => (None, Some(Intrusion { start: *start, end: *end, count: 0, first: None })),
+                Assertion::NoExecFunc { func } => {
+                    let (start, index) = find(func)?;
+                    let end = unwinder.idx_2_addr_range[&index].1;
+                    // the last function has no successor to bound it
+                    let end = if end > start { end } else { u64::MAX };
+                    (None, Some(Intrusion { start, end, count: 0, first: None }))
+                }
+            };
+            funcs.push(func);
+            intrusions.push(intrusion);
+        }
+        Ok(AssertReceiver {
+            receiver: BusReceiver { name: "assert".into(), bus_rx, checksum: 0 },
+            unwinder,
+            tracked: funcs.iter().flatten().copied().collect(),
+            funcs,
+            spec,
+            calls: HashMap::new(),
+            durations: HashMap::new(),
+            starts: HashMap::new(),
+            intrusions,
+            ctx: Context::default(),
+            last_ts: 0,
+        })
+    }
+
+    fn opened(&mut self, info: Option<SymbolInfo>, ts: u64) {
+        if let Some(info) = info.filter(|info| self.tracked.contains(&info.index)) {
+            *self.calls.entry(info.index).or_default() += 1;
+            self.starts.entry((self.ctx, info.index)).or_default().push(ts);
+        }
+    }
+
+    fn closed(&mut self, closed: Vec<SymbolInfo>, ts: u64) {
+        for info in closed.into_iter().filter(|info| self.tracked.contains(&info.index)) {
+            if let Some(start) = self.starts.get_mut(&(self.ctx, info.index)).and_then(|starts| starts.pop()) {
+                self.durations.entry(info.index).or_default().push(ts.saturating_sub(start));
+            }
+        }
+    }
+
+    // (passed, what was measured) of each assertion
+    fn evaluate(&mut self) -> Vec<(bool, String)> {
+        for durations in self.durations.values_mut() {
+            durations.sort_unstable();
+        }
+        let scale = self.spec.ticks_per_us.unwrap_or(1.0);
+        self.spec.assertions.iter().enumerate().map(|(i, (assertion, _))| match assertion {
+            Assertion::Calls { comparison, limit, .. } => {
+                let calls = self.calls.get(&self.funcs[i].unwrap()).copied().unwrap_or(0);
+                (comparison.holds(calls as f64, *limit as f64), format!("{} calls", calls))
+            }
+            Assertion::Latency { stat, comparison, limit, micros, .. } => {
+                let durations = self.durations.get(&self.funcs[i].unwrap()).map(Vec::as_slice).unwrap_or(&[]);
+                if durations.is_empty() {
+                    // nothing to hold against the limit
+                    return (true, "no completed invocation".to_string());
+                }
+                let ticks = match stat {
+                    LatencyStat::Percentile(p) => percentile(durations, *p) as f64,
+                    LatencyStat::Mean => durations.iter().sum::<u64>() as f64 / durations.len() as f64,
+                    LatencyStat::Max => durations[durations.len() - 1] as f64,
+                };
+                if *micros {
+                    (comparison.holds(ticks / scale, *limit), format!("{:.3}us over {} invocations", ticks / scale, durations.len()))
+                } else {
+                    (comparison.holds(ticks, *limit), format!("{} ticks over {} invocations", ticks, durations.len()))
+                }
+            }
+            Assertion::NoExec { .. } | Assertion::NoExecFunc { .. } => {
+                let intrusion = self.intrusions[i].as_ref().unwrap();
+                match intrusion.first {
+                    None => (true, "not executed".to_string()),
+                    Some((ts, pc)) => (false, format!("{} instructions executed, first {:#x} at {}", intrusion.count, pc, ts)),
+                }
+            }
+        }).collect()
+    }
+
+    fn report(&mut self) -> std::io::Result<()> {
+        let results = self.evaluate();
+        let failed = results.iter().filter(|(passed, _)| !passed).count();
+        VIOLATIONS.fetch_add(failed as u64, Ordering::Relaxed);
+        let json_results: Vec<_> = self.spec.assertions.iter().zip(&results).map(|((_, text), (passed, actual))| json!({
+            "assertion": text,
+            "passed": passed,
+            "actual": actual,
+        })).collect();
+        let violations: Vec<_> = json_results.iter().filter(|result| result["passed"] == false).cloned().collect();
+        let mut writer = BufWriter::new(File::create("trace.assert.json")?);
+        serde_json::to_writer_pretty(&mut writer, &json!({
+            "passed": failed == 0,
+            "results": json_results,
+            "violations": violations,
+        }))?;
+        writer.flush()?;
+        println!("[Assert] {} of {} assertions failed", failed, results.len());
+        for ((_, text), (passed, actual)) in self.spec.assertions.iter().zip(&results) {
+            if !passed {
+                println!("[Assert] FAILED {}: {}", text, actual);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for AssertReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                let pc = entry.arc.0;
+                for intrusion in self.intrusions.iter_mut().flatten() {
+                    if intrusion.start <= pc && pc < intrusion.end {
+                        intrusion.count += 1;
+                        intrusion.first.get_or_insert((self.last_ts, pc));
+                    }
+                }
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let ts = entry.timestamp.unwrap();
+                self.last_ts = ts;
+                if let (true, _, opened) = self.unwinder.step_ij(entry) {
+                    self.opened(opened, ts);
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let ts = entry.timestamp.unwrap();
+                self.last_ts = ts;
+                if let (true, _, closed, opened) = self.unwinder.step_uj(entry) {
+                    self.closed(closed, ts);
+                    self.opened(opened, ts);
+                }
+            }
+            Event::ContextSwitch => {
+                self.ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(self.ctx);
+            }
+            Event::BPHit => {}
+            _ => {
+                if let Some(ts) = entry.timestamp {
+                    self.last_ts = ts;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.report()?;
+        Ok(())
+    }
+}
@@ -1,38 +1,247 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
+use crate::backend::gantt_receiver::csv_field;
+use crate::frontend::context::Context;
 use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
+// an sc followed by its lr again within this many instructions failed and
+// was retried
+const SC_RETRY_WINDOW: u64 = 16;
+// call stacks shown per atomic instruction
+const TOP_STACKS: usize = 3;
+// an operation within this many cycles of one by another hart on the same
+// address is contended
+const CONTENTION_WINDOW: u64 = 1000;
+// target addresses shown in the summary, and the width of their heat bar
+const TOP_TARGETS: usize = 32;
+const HEAT_WIDTH: usize = 20;
+
+// the executions of one atomic instruction
+#[derive(Default)]
+struct AtomicSite {
+    op: String,
+    count: u64,
+    sc_failures: u64,
+    // call stack, as function names, to count
+    stacks: HashMap<Vec<String>, u64>,
+}
+
+// the atomic operations on one address, from the data addresses in the trace
+#[derive(Default)]
+struct AtomicTarget {
+    count: u64,
+    ops: BTreeMap<String, u64>,
+    harts: BTreeMap<u64, u64>,
+    contended: u64,
+    sc_failures: u64,
+    // the hart and timestamp of the last operation
+    last: Option<(u64, u64)>,
+}
+
+// an sc that may still turn out to have failed
+struct PendingSc {
+    sc_pc: u64,
+    lr_pc: u64,
+    // the address its lr reserved, when the trace has it
+    target: Option<u64>,
+    insns: u64,
+}
+
+/// Writes `trace.atomics.txt`, every lr, sc and amo instruction executed
+/// with its timestamp and call stack, then a summary: the counts by
+/// operation, and per atomic instruction its count, the sc failures and the
+/// dominant call stacks. Without data addresses, the instructions stand in
+/// for the locks they work on. An sc failed when the lr before it
+/// runs again within a few instructions, the retry of the usual loop. When
+/// the encoder reports data addresses, the operations are also bucketed by
+/// the address they target, with their mix, the harts they came from and
+/// how often they were contended: within a short window of an operation of
+/// another hart on the address, or an sc failing on it. Those go to the
+/// summary, the hottest first, and to `trace.atomics.csv`, one row per
+/// address, operation and hart.
 pub struct AtomicReceiver {
     writer: BufWriter<File>,
     receiver: BusReceiver,
     unwinder: StackUnwinder,
+    loader: Loader,
     symbol_index: std::collections::BTreeMap<u64, SymbolInfo>,
     call_stack: Vec<SymbolInfo>,
+    ctx: Context,
+    parked_call_stacks: HashMap<Context, Vec<SymbolInfo>>,
     last_ts: u64,  // track most recent timestamp
+    sites: HashMap<u64, AtomicSite>,
+    // per context, the pc of the last lr and the sc to check
+    last_lr: HashMap<Context, u64>,
+    pending_sc: HashMap<Context, PendingSc>,
+    // the atomic instruction whose data access comes next, and its op
+    last_atomic: Option<(u64, String)>,
+    // per context, the address of the last lr
+    lr_target: HashMap<Context, u64>,
+    targets: HashMap<u64, AtomicTarget>,
 }
 
 impl AtomicReceiver {
-    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Self {
-        let unwinder = StackUnwinder::new(elf_path.clone()).unwrap();
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let unwinder = StackUnwinder::new(elf_path.clone(), options)?;
         let mut symbol_index = std::collections::BTreeMap::new();
         for (&addr, info) in unwinder.func_symbol_map().iter() {
             symbol_index.insert(addr, info.clone());
         }
-        AtomicReceiver {
-            writer: BufWriter::new(File::create("trace.atomics.txt").unwrap()),
+        Ok(AtomicReceiver {
+            writer: BufWriter::new(File::create("trace.atomics.txt")?),
             receiver: BusReceiver { name: "atomics".into(), bus_rx, checksum: 0 },
             unwinder,
+            loader: Loader::new(&elf_path).map_err(|e| anyhow!("{}", e))?,
             symbol_index,
             call_stack: Vec::new(),
+            ctx: Context::default(),
+            parked_call_stacks: HashMap::new(),
             last_ts: 0,
+            sites: HashMap::new(),
+            last_lr: HashMap::new(),
+            pending_sc: HashMap::new(),
+            last_atomic: None,
+            lr_target: HashMap::new(),
+            targets: HashMap::new(),
+        })
+    }
+
+    // the operation, `amoswap` of `amoswap.w.aqrl`
+    fn op_name(name: &str) -> &str {
+        name.split('.').next().unwrap_or(name)
+    }
+
+    // settle the pending sc of the context with the instruction at pc
+    fn check_sc(&mut self, pc: u64, op: Option<&str>) {
+        let Some(pending) = self.pending_sc.get_mut(&self.ctx) else { return };
+        pending.insns += 1;
+        let retried = op == Some("lr") && pc == pending.lr_pc;
+        if retried || op.is_some() || pending.insns > SC_RETRY_WINDOW {
+            let pending = self.pending_sc.remove(&self.ctx).unwrap();
+            if retried {
+                self.sites.get_mut(&pending.sc_pc).unwrap().sc_failures += 1;
+                if let Some(target) = pending.target {
+                    self.targets.entry(target).or_default().sc_failures += 1;
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, pc: u64, op: &str) {
+        let stack: Vec<String> = self.call_stack.iter().map(|frame| frame.name.clone()).collect();
+        let site = self.sites.entry(pc).or_default();
+        site.op = op.to_string();
+        site.count += 1;
+        *site.stacks.entry(stack).or_default() += 1;
+        match op {
+            "lr" => {
+                self.last_lr.insert(self.ctx, pc);
+            }
+            "sc" => {
+                if let Some(&lr_pc) = self.last_lr.get(&self.ctx) {
+                    let target = self.lr_target.get(&self.ctx).copied();
+                    self.pending_sc.insert(self.ctx, PendingSc { sc_pc: pc, lr_pc, target, insns: 0 });
+                }
+            }
+            _ => {}
+        }
+        self.last_atomic = Some((pc, op.to_string()));
+    }
+
+    // the data access of the atomic instruction just recorded
+    fn record_target(&mut self, address: u64) {
+        let Some((_, op)) = self.last_atomic.take() else { return };
+        if op == "lr" {
+            self.lr_target.insert(self.ctx, address);
         }
+        let hart = self.ctx.hart;
+        let target = self.targets.entry(address).or_default();
+        target.count += 1;
+        *target.ops.entry(op).or_default() += 1;
+        *target.harts.entry(hart).or_default() += 1;
+        if let Some((last_hart, last_ts)) = target.last {
+            if last_hart != hart && self.last_ts.saturating_sub(last_ts) <= CONTENTION_WINDOW {
+                target.contended += 1;
+            }
+        }
+        target.last = Some((hart, self.last_ts));
+    }
+
+    fn write_targets(&mut self) -> std::io::Result<()> {
+        let total: u64 = self.targets.values().map(|target| target.count).sum();
+        let mut targets: Vec<(&u64, &AtomicTarget)> = self.targets.iter().collect();
+        targets.sort_by_key(|&(&address, target)| (std::cmp::Reverse(target.count), address));
+        writeln!(self.writer, "by target address, of {} operations with one:", total)?;
+        let hottest = targets.first().map_or(1, |(_, target)| target.count);
+        let mut cumulative = 0;
+        for &(&address, target) in targets.iter().take(TOP_TARGETS) {
+            cumulative += target.count;
+            let heat = "#".repeat((target.count * HEAT_WIDTH as u64).div_ceil(hottest) as usize);
+            let ops: Vec<String> = target.ops.iter().map(|(op, count)| format!("{} {}", op, count)).collect();
+            let harts: Vec<String> = target.harts.iter().map(|(hart, count)| format!("hart {} {}", hart, count)).collect();
+            writeln!(self.writer, "  0x{:08x} {:<20} {:>12} {:>6.2}% (cumulative {:>6.2}%)  {}", address, heat, target.count,
+                     target.count as f64 / total as f64 * 100.0, cumulative as f64 / total as f64 * 100.0,
+                     self.loader.find_symbol(address).unwrap_or("?"))?;
+            writeln!(self.writer, "    ops: {}", ops.join(", "))?;
+            writeln!(self.writer, "    harts: {}", harts.join(", "))?;
+            writeln!(self.writer, "    contended: {} ({:.2}%), sc failures: {}", target.contended,
+                     target.contended as f64 / target.count as f64 * 100.0, target.sc_failures)?;
+        }
+
+        let mut csv = BufWriter::new(File::create("trace.atomics.csv")?);
+        writeln!(csv, "address,symbol,op,hart,count")?;
+        for &(&address, target) in targets.iter() {
+            let symbol = csv_field(self.loader.find_symbol(address).unwrap_or(""));
+            for (op, count) in target.ops.iter() {
+                writeln!(csv, "{:#x},{},{},,{}", address, symbol, op, count)?;
+            }
+            for (hart, count) in target.harts.iter() {
+                writeln!(csv, "{:#x},{},,{},{}", address, symbol, hart, count)?;
+            }
+        }
+        csv.flush()
+    }
+
+    fn write_summary(&mut self) -> std::io::Result<()> {
+        let mut ops: BTreeMap<&str, u64> = BTreeMap::new();
+        for site in self.sites.values() {
+            *ops.entry(&site.op).or_default() += site.count;
+        }
+        writeln!(self.writer, "== summary ==")?;
+        writeln!(self.writer, "by operation:")?;
+        for (op, count) in &ops {
+            writeln!(self.writer, "  {:<10} {:>12}", op, count)?;
+        }
+        let mut sites: Vec<(&u64, &AtomicSite)> = self.sites.iter().collect();
+        sites.sort_by_key(|&(&pc, site)| (std::cmp::Reverse(site.count), pc));
+        writeln!(self.writer, "by instruction:")?;
+        for (&pc, site) in sites {
+            let function = self.symbol_index.range(..=pc).next_back().map(|(_, info)| info.name.as_str()).unwrap_or("?");
+            write!(self.writer, "  0x{:08x} {:<10} {:>12}  {}", pc, site.op, site.count, function)?;
+            if site.op == "sc" {
+                write!(self.writer, ", {} failed ({:.2}%)", site.sc_failures, site.sc_failures as f64 / site.count as f64 * 100.0)?;
+            }
+            writeln!(self.writer)?;
+            let mut stacks: Vec<_> = site.stacks.iter().collect();
+            stacks.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (stack, count) in stacks.into_iter().take(TOP_STACKS) {
+                writeln!(self.writer, "    {:>12}  {}", count, stack.join(" > "))?;
+            }
+        }
+        if !self.targets.is_empty() {
+            self.write_targets()?;
+        }
+        Ok(())
     }
 
     /// Is this a load-reserved, store-conditional, or atomic memory operation?
-    fn is_atomic_insn(insn: &rvdasm::insn::Insn) -> bool {
+    pub(crate) fn is_atomic_insn(insn: &rvdasm::insn::Insn) -> bool {
         let name = insn.get_name();
         name.starts_with("lr.")
          || name.starts_with("sc.")
@@ -49,7 +258,11 @@ impl AbstractReceiver for AtomicReceiver {
         self.receiver.checksum += 1;
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
         // If entry carries a timestamp, update last_ts
         if let Some(ts) = entry.timestamp {
             self.last_ts = ts;
@@ -75,31 +288,56 @@ impl AbstractReceiver for AtomicReceiver {
                     }
                 }
             }
+            Event::DataAccess if self.last_atomic.as_ref().is_some_and(|(pc, _)| *pc == entry.arc.0) => {
+                self.record_target(entry.data.unwrap().address);
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                if ctx != self.ctx {
+                    self.unwinder.switch_context(ctx);
+                    let parked = std::mem::take(&mut self.call_stack);
+                    self.parked_call_stacks.insert(self.ctx, parked);
+                    self.call_stack = self.parked_call_stacks.remove(&ctx).unwrap_or_default();
+                    self.ctx = ctx;
+                }
+            }
             _ => {}
         }
 
         // if this entry carries an instruction, check for atomic ops
         if let Some(insn) = entry.insn {
-            if AtomicReceiver::is_atomic_insn(&insn) {
+            // an atomic without a data access has no known target
+            self.last_atomic = None;
+            let atomic = AtomicReceiver::is_atomic_insn(&insn);
+            let name = insn.get_name();
+            let op = if atomic { Some(AtomicReceiver::op_name(&name)) } else { None };
+            self.check_sc(entry.arc.0, op);
+            if let Some(op) = op {
+                self.record(entry.arc.0, op);
+            }
+            if atomic {
                 let ts = self.last_ts;
                 let pc = entry.arc.0;
                 // print the atomic instruction
-                writeln!(self.writer, "[{:>10}] 0x{:08x}: {}", ts, pc, insn.to_string()).unwrap();
+                writeln!(self.writer, "[{:>10}] 0x{:08x}: {}", ts, pc, insn.to_string())?;
                 // print call stack
-                writeln!(self.writer, "  Call stack:").unwrap();
+                writeln!(self.writer, "  Call stack:")?;
                 for frame in &self.call_stack {
                     // find the start address for this frame
                     let addr = self.symbol_index.iter()
                         .find_map(|(&a, info)| if info.index == frame.index { Some(a) } else { None })
                         .unwrap_or(0);
-                    writeln!(self.writer, "    {} @ 0x{:x}", frame.name, addr).unwrap();
+                    writeln!(self.writer, "    {} @ 0x{:x}", frame.name, addr)?;
                 }
-                writeln!(self.writer).unwrap();
+                writeln!(self.writer)?;
             }
         }
+        Ok(())
     }
 
-    fn _flush(&mut self) {
-        self.writer.flush().unwrap();
+    fn _flush(&mut self) -> Result<()> {
+        self.write_summary()?;
+        self.writer.flush()?;
+        Ok(())
     }
 }
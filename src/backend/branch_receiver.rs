@@ -0,0 +1,117 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::gantt_receiver::csv_field;
+use crate::frontend::br_mode::BrMode;
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+#[derive(Default)]
+struct BranchSite {
+    taken: u64,
+    not_taken: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Per static branch, how often it was taken and, in BrPredict mode, how
+/// often the predictor got it right, written to `trace.branches.csv` with
+/// the source line. Rows are ordered by misses, then executions, so the
+/// branches hurting the most come first.
+pub struct BranchReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    loader: Loader,
+    br_mode: BrMode,
+    sites: HashMap<u64, BranchSite>,
+    // the branches still to come from the last BPHit / BPMiss
+    pending_hits: u64,
+    pending_miss: bool,
+}
+
+impl BranchReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, br_mode: BrMode) -> Result<Self> {
+        debug!("BranchReceiver::new");
+        Ok(BranchReceiver {
+            writer: BufWriter::new(File::create("trace.branches.csv")?),
+            receiver: BusReceiver { name: "branch".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            br_mode,
+            sites: HashMap::new(),
+            pending_hits: 0,
+            pending_miss: false,
+        })
+    }
+
+    fn record(&mut self, pc: u64, taken: bool) {
+        let site = self.sites.entry(pc).or_default();
+        if taken {
+            site.taken += 1;
+        } else {
+            site.not_taken += 1;
+        }
+        if self.pending_hits > 0 {
+            self.pending_hits -= 1;
+            site.hits += 1;
+        } else if self.pending_miss {
+            self.pending_miss = false;
+            site.misses += 1;
+        }
+    }
+}
+
+impl AbstractReceiver for BranchReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            // the branches a hit packet resolves follow it, one by one
+            Event::BPHit => self.pending_hits = entry.timestamp.unwrap(),
+            Event::BPMiss => self.pending_miss = true,
+            Event::TakenBranch => self.record(entry.arc.0, true),
+            Event::NonTakenBranch => self.record(entry.arc.0, false),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        let predict = self.br_mode == BrMode::BrPredict;
+        let mut sites: Vec<(&u64, &BranchSite)> = self.sites.iter().collect();
+        sites.sort_by_key(|&(&pc, site)| (std::cmp::Reverse(site.misses), std::cmp::Reverse(site.taken + site.not_taken), pc));
+        write!(self.writer, "pc,function,file,line,taken,not_taken,taken_ratio")?;
+        writeln!(self.writer, "{}", if predict { ",hits,misses,hit_rate" } else { "" })?;
+        for (pc, site) in sites {
+            let function = self.loader.find_symbol(*pc).unwrap_or("");
+            let (file, line) = match self.loader.find_location(*pc) {
+                Ok(Some(loc)) => (loc.file.unwrap_or(""), loc.line.unwrap_or(0)),
+                _ => ("", 0),
+            };
+            let total = site.taken + site.not_taken;
+            write!(self.writer, "{:#x},{},{},{},{},{},{:.4}", pc, csv_field(function), csv_field(file), line,
+                   site.taken, site.not_taken, site.taken as f64 / total as f64)?;
+            if predict {
+                write!(self.writer, ",{},{},{:.4}", site.hits, site.misses,
+                       site.hits as f64 / (site.hits + site.misses).max(1) as f64)?;
+            }
+            writeln!(self.writer)?;
+        }
+        debug!("wrote {} branch sites", self.sites.len());
+        self.writer.flush()?;
+        Ok(())
+    }
+}
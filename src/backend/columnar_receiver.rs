@@ -0,0 +1,178 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::schema::{build_info, EVENT_SCHEMA_VERSION};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+use arrow::array::{ArrayBuilder, ArrayRef, DictionaryArray, StringArray, UInt32Builder, UInt64Builder, UInt8Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, UInt32Type, UInt8Type};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+// rows buffered before a record batch is written out
+const BATCH_ROWS: usize = 64 * 1024;
+// every event that can show up in the `event` column, indexed by Event::to_code
+const EVENTS: &[Event] = &[
+    Event::None, Event::Start, Event::TakenBranch, Event::NonTakenBranch,
+    Event::UninferableJump, Event::InferrableJump, Event::End,
+    Event::TrapException, Event::TrapInterrupt, Event::TrapReturn,
+    Event::BPHit, Event::BPMiss, Event::ContextSwitch, Event::Panic, Event::Resync,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColumnarFormat {
+    ArrowIpc,
+    Parquet,
+}
+
+enum ColumnarWriter {
+    ArrowIpc(FileWriter<File>),
+    Parquet(ArrowWriter<File>),
+}
+
+/// Exports the control-flow events (per-instruction, data access and
+/// register value entries are skipped) as columnar data: event, pc_from,
+/// pc_to, timestamp, function_id and function. `event` and `function` are
+/// dictionary encoded against fixed dictionaries so every batch shares them.
+pub struct ColumnarReceiver {
+    writer: Option<ColumnarWriter>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    schema: SchemaRef,
+    event_names: ArrayRef,
+    function_names: ArrayRef,
+    event: UInt8Builder,
+    pc_from: UInt64Builder,
+    pc_to: UInt64Builder,
+    timestamp: UInt64Builder,
+    function_id: UInt32Builder,
+}
+
+impl ColumnarReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, format: ColumnarFormat) -> Result<Self> {
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let names: Vec<String> = unwinder.symbols().iter().map(|info| info.name.clone()).collect();
+        let function_names: ArrayRef = Arc::new(StringArray::from(names));
+        let event_names: ArrayRef = Arc::new(StringArray::from(EVENTS.iter().map(|e| e.to_string()).collect::<Vec<_>>()));
+
+        let dictionary = |key: DataType| DataType::Dictionary(Box::new(key), Box::new(DataType::Utf8));
+        // parquet keeps the arrow schema metadata in its key-value metadata
+        let metadata = HashMap::from([
+            ("tacit.schema_version".to_string(), EVENT_SCHEMA_VERSION.to_string()),
+            ("tacit.decoder".to_string(), build_info()),
+        ]);
+        let schema = Arc::new(Schema::new_with_metadata(vec![
+            Field::new("event", dictionary(DataType::UInt8), false),
+            Field::new("pc_from", DataType::UInt64, false),
+            Field::new("pc_to", DataType::UInt64, false),
+            Field::new("timestamp", DataType::UInt64, true),
+            Field::new("function_id", DataType::UInt32, true),
+            Field::new("function", dictionary(DataType::UInt32), true),
+        ], metadata));
+
+        let writer = match format {
+            ColumnarFormat::ArrowIpc => {
+                let file = File::create("trace.arrow")?;
+                ColumnarWriter::ArrowIpc(FileWriter::try_new(file, &schema)?)
+            }
+            ColumnarFormat::Parquet => {
+                let file = File::create("trace.parquet")?;
+                let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+                ColumnarWriter::Parquet(ArrowWriter::try_new(file, schema.clone(), Some(props))?)
+            }
+        };
+
+        Ok(ColumnarReceiver {
+            writer: Some(writer),
+            receiver: BusReceiver { name: "columnar".into(), bus_rx, checksum: 0 },
+            unwinder,
+            schema,
+            event_names,
+            function_names,
+            event: UInt8Builder::with_capacity(BATCH_ROWS),
+            pc_from: UInt64Builder::with_capacity(BATCH_ROWS),
+            pc_to: UInt64Builder::with_capacity(BATCH_ROWS),
+            timestamp: UInt64Builder::with_capacity(BATCH_ROWS),
+            function_id: UInt32Builder::with_capacity(BATCH_ROWS),
+        })
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.event.is_empty() {
+            return Ok(());
+        }
+        let event = DictionaryArray::<UInt8Type>::try_new(self.event.finish(), self.event_names.clone())?;
+        let function_id = self.function_id.finish();
+        let function = DictionaryArray::<UInt32Type>::try_new(function_id.clone(), self.function_names.clone())?;
+        let batch = RecordBatch::try_new(self.schema.clone(), vec![
+            Arc::new(event),
+            Arc::new(self.pc_from.finish()),
+            Arc::new(self.pc_to.finish()),
+            Arc::new(self.timestamp.finish()),
+            Arc::new(function_id),
+            Arc::new(function),
+        ])?;
+        match self.writer.as_mut().unwrap() {
+            ColumnarWriter::ArrowIpc(writer) => writer.write(&batch)?,
+            ColumnarWriter::Parquet(writer) => writer.write(&batch)?,
+        }
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for ColumnarReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None | Event::DataAccess | Event::RegisterValue => return Ok(()),
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let _ = self.unwinder.step_ij(entry.clone());
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let _ = self.unwinder.step_uj(entry.clone());
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        self.event.append_value(entry.event.to_code());
+        self.pc_from.append_value(entry.arc.0);
+        self.pc_to.append_value(entry.arc.1);
+        self.timestamp.append_option(entry.timestamp);
+        // the function executing after the event
+        self.function_id.append_option(self.unwinder.frame_stack.last().copied());
+        if self.event.len() >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_batch()?;
+        match self.writer.take().unwrap() {
+            ColumnarWriter::ArrowIpc(mut writer) => writer.finish()?,
+            ColumnarWriter::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::atomic_receiver::AtomicReceiver;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::backend::txt_receiver::TxtReceiver;
+use bus::BusReader;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// what happened in a window, besides its annotated events
+#[derive(Default)]
+struct WindowStats {
+    insns: u64,
+    taken: u64,
+    not_taken: u64,
+    mispredicts: u64,
+    calls: u64,
+    returns: u64,
+    traps: u64,
+    atomics: u64,
+}
+
+/// One time-ordered report of what the trap, atomics, branch and stack
+/// outputs have in separate files, cut into windows of `window` cycles.
+/// Writes `trace.combined.txt`: per window that has entries, a summary line
+/// (instructions, branches taken and mispredicted, calls, traps, atomics),
+/// the call stack at its start, then its calls, returns, traps, context
+/// switches and atomic instructions in trace order, each with its
+/// timestamp, depth and function. Instructions have the timestamp of the
+/// last timed event.
+pub struct CombinedReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    window: u64,
+    // start of the current window, from the first timestamp on
+    window_start: Option<u64>,
+    stack_at_start: String,
+    stats: WindowStats,
+    lines: Vec<String>,
+    last_ts: u64,
+}
+
+impl CombinedReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, window: u64) -> Result<Self> {
+        debug!("CombinedReceiver::new");
+        Ok(CombinedReceiver {
+            writer: BufWriter::new(File::create("trace.combined.txt")?),
+            receiver: BusReceiver { name: "combined".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            window: window.max(1),
+            window_start: None,
+            stack_at_start: "-".to_string(),
+            stats: WindowStats::default(),
+            lines: Vec::new(),
+            last_ts: 0,
+        })
+    }
+
+    fn stack_text(&self) -> String {
+        let symbols = self.unwinder.symbols();
+        let names: Vec<&str> = self.unwinder.frame_stack.iter().map(|&index| symbols[index as usize].name.as_str()).collect();
+        if names.is_empty() { "-".to_string() } else { names.join(" > ") }
+    }
+
+    fn function_at(&self, pc: u64) -> &str {
+        match self.unwinder.function_range_at(pc) {
+            Some((start, _)) => &self.unwinder.func_symbol_map()[&start].name,
+            None => "?",
+        }
+    }
+
+    fn annotate(&mut self, text: String) {
+        let depth = self.unwinder.frame_stack.len();
+        self.lines.push(format!("{:>14} {:>5}  {}", self.last_ts, depth, text));
+    }
+
+    fn write_window(&mut self) -> std::io::Result<()> {
+        let Some(start) = self.window_start else { return Ok(()) };
+        let stats = std::mem::take(&mut self.stats);
+        if stats.insns == 0 && self.lines.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.writer, "=== [{}, {}) {} insns, {} branches ({} taken, {} mispredicted), {} calls, {} returns, {} traps, {} atomics",
+                 start, start + self.window, stats.insns, stats.taken + stats.not_taken, stats.taken, stats.mispredicts,
+                 stats.calls, stats.returns, stats.traps, stats.atomics)?;
+        writeln!(self.writer, "stack: {}", self.stack_at_start)?;
+        for line in self.lines.drain(..) {
+            writeln!(self.writer, "{}", line)?;
+        }
+        writeln!(self.writer)
+    }
+
+    // closes the windows ts is past, the next one starts at the stack of now
+    fn advance(&mut self, ts: u64) -> std::io::Result<()> {
+        let start = *self.window_start.get_or_insert(ts);
+        if ts >= start + self.window {
+            self.write_window()?;
+            self.window_start = Some(ts - (ts - start) % self.window);
+            self.stack_at_start = self.stack_text();
+        }
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for CombinedReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the hit count of BPHit is not a timestamp
+        if entry.event != Event::BPHit {
+            if let Some(ts) = entry.timestamp {
+                self.advance(ts)?;
+                self.last_ts = ts;
+            }
+        }
+        match entry.event {
+            Event::None => {
+                self.stats.insns += 1;
+                if let Some(insn) = entry.insn.as_ref().filter(|insn| AtomicReceiver::is_atomic_insn(insn)) {
+                    self.stats.atomics += 1;
+                    let text = format!("atomic {} at {:#x} in {}", insn.get_name(), entry.arc.0, self.function_at(entry.arc.0));
+                    self.annotate(text);
+                }
+            }
+            Event::TakenBranch => self.stats.taken += 1,
+            Event::NonTakenBranch => self.stats.not_taken += 1,
+            Event::BPMiss => self.stats.mispredicts += 1,
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                if entry.event != Event::InferrableJump {
+                    self.stats.traps += 1;
+                    let text = format!("{} in {}", TxtReceiver::event_text(&entry), self.function_at(entry.arc.0));
+                    self.annotate(text);
+                }
+                if let (true, _, Some(opened)) = self.unwinder.step_ij(entry) {
+                    self.stats.calls += 1;
+                    self.annotate(format!("call {}", opened.name));
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                if entry.event == Event::TrapReturn {
+                    self.annotate(format!("{} in {}", TxtReceiver::event_text(&entry), self.function_at(entry.arc.0)));
+                }
+                let (success, _, closed, opened) = self.unwinder.step_uj(entry);
+                if success {
+                    for frame in closed {
+                        self.stats.returns += 1;
+                        self.annotate(format!("return from {}", frame.name));
+                    }
+                }
+                if let Some(opened) = opened {
+                    self.stats.calls += 1;
+                    self.annotate(format!("call {}", opened.name));
+                }
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+                self.annotate(format!("{}, stack {}", TxtReceiver::event_text(&entry), self.stack_text()));
+            }
+            Event::Start | Event::End | Event::Resync | Event::Panic => {
+                self.annotate(TxtReceiver::event_text(&entry));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_window()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+// Several runs of a program side by side, e.g. builds with different compiler
+// flags or one build on different silicon corners: the traces of every run
+// are decoded against its own binary and the self cycles and calls of each
+// function, matched by name across the runs, are written as one matrix,
+// with the ratio of the cycles of every run to those of the first and the
+// geometric mean of these ratios over the functions that ran in both.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use log::debug;
+
+use crate::backend::event::Entry;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::backend::top_receiver::SelfProfile;
+use crate::frontend::decoder::{decode, DecoderConfig, EntrySink};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub label: String,
+    pub binary: String,
+    // decoded one after the other, their counts summed
+    pub traces: Vec<String>,
+}
+
+impl FromStr for Run {
+    type Err = anyhow::Error;
+
+    // `[<label>=]<binary>:<trace>[,<trace>...]`, the label defaults to the
+    // file name of the first trace
+    fn from_str(s: &str) -> Result<Self> {
+        let (label, rest) = match s.split_once('=') {
+            Some((label, rest)) => (Some(label), rest),
+            None => (None, s),
+        };
+        let malformed = || anyhow!("run `{}` is not `[<label>=]<binary>:<trace>[,<trace>...]`", s);
+        let (binary, traces) = rest.split_once(':').ok_or_else(malformed)?;
+        let traces: Vec<String> = traces.split(',').filter(|trace| !trace.is_empty()).map(String::from).collect();
+        if binary.is_empty() || traces.is_empty() || label == Some("") {
+            return Err(malformed());
+        }
+        let label = match label {
+            Some(label) => label.to_string(),
+            None => Path::new(&traces[0]).file_stem().map_or(traces[0].clone(), |stem| stem.to_string_lossy().into_owned()),
+        };
+        Ok(Run { label, binary: binary.to_string(), traces })
+    }
+}
+
+// self cycles and calls per function name of one run; functions of the same
+// name, e.g. static ones, are counted together
+#[derive(Clone, Debug, Default)]
+pub struct RunProfile {
+    pub label: String,
+    pub functions: HashMap<String, (u64, u64)>,
+    // all the cycles of the run, those outside of any function too
+    pub total: u64,
+}
+
+impl RunProfile {
+    fn cycles(&self, function: &str) -> u64 {
+        self.functions.get(function).map_or(0, |&(cycles, _)| cycles)
+    }
+
+    fn calls(&self, function: &str) -> u64 {
+        self.functions.get(function).map_or(0, |&(_, calls)| calls)
+    }
+}
+
+impl EntrySink for SelfProfile {
+    fn broadcast(&mut self, entry: Entry) {
+        self.observe(entry);
+    }
+}
+
+pub fn profile_run(run: &Run, config: &DecoderConfig, options: &UnwinderOptions) -> Result<RunProfile> {
+    let elf_buffer = std::fs::read(&run.binary)?;
+    let config = DecoderConfig { binary: run.binary.clone(), ..config.clone() };
+    let mut profile = SelfProfile::new(StackUnwinder::new(run.binary.clone(), options)?);
+    for trace in &run.traces {
+        let mut reader = BufReader::new(File::open(trace)?);
+        let packet_count = decode(&elf_buffer, &mut reader, None, &config, &mut profile)?;
+        println!("[Success] Decoded {} packets from {}", packet_count, trace);
+    }
+    let mut functions: HashMap<String, (u64, u64)> = HashMap::new();
+    for (addr, cycles) in profile.self_cycles.iter() {
+        functions.entry(profile.function_name(*addr).to_string()).or_default().0 += cycles;
+    }
+    for (addr, calls) in profile.calls.iter() {
+        functions.entry(profile.function_name(*addr).to_string()).or_default().1 += calls;
+    }
+    let total = profile.self_cycles.values().sum::<u64>() + profile.unattributed;
+    debug!("[compare] {}: {} functions, {} cycles", run.label, functions.len(), total);
+    Ok(RunProfile { label: run.label.clone(), functions, total })
+}
+
+// the cycles of function in run over those in base, when it ran in both
+pub fn ratio(base: &RunProfile, run: &RunProfile, function: &str) -> Option<f64> {
+    let (base, cycles) = (base.cycles(function), run.cycles(function));
+    (base > 0 && cycles > 0).then(|| cycles as f64 / base as f64)
+}
+
+// the geometric mean of the ratios of run to base over the functions that
+// ran in both, every function weighing the same however long it ran
+pub fn geomean_ratio(base: &RunProfile, run: &RunProfile) -> Option<f64> {
+    let logs: Vec<f64> = base.functions.keys().filter_map(|function| ratio(base, run, function)).map(f64::ln).collect();
+    (!logs.is_empty()).then(|| (logs.iter().sum::<f64>() / logs.len() as f64).exp())
+}
+
+// the functions of all runs, hottest in the first run first, then the ones
+// missing from it by their hottest run
+fn rows(profiles: &[RunProfile]) -> Vec<String> {
+    let mut functions: Vec<String> = profiles.iter().flat_map(|profile| profile.functions.keys().cloned()).collect();
+    functions.sort();
+    functions.dedup();
+    let hottest = |function: &str| profiles.iter().map(|profile| profile.cycles(function)).max().unwrap_or(0);
+    functions.sort_by_cached_key(|function| {
+        (std::cmp::Reverse(profiles[0].cycles(function)), std::cmp::Reverse(hottest(function)), function.clone())
+    });
+    functions
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map_or(String::new(), |ratio| format!("{:.4}", ratio))
+}
+
+// one row per function: the cycles and calls of every run, and the ratio of
+// the cycles of every run but the first; then the totals and the geomeans
+pub fn write_csv(profiles: &[RunProfile], path: &str) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let base = &profiles[0];
+    let mut header = vec!["function".to_string()];
+    for (i, profile) in profiles.iter().enumerate() {
+        header.push(format!("{} cycles", profile.label));
+        header.push(format!("{} calls", profile.label));
+        if i > 0 {
+            header.push(format!("{} ratio", profile.label));
+        }
+    }
+    writeln!(writer, "{}", header.join(","))?;
+    for function in rows(profiles) {
+        let mut row = vec![function.clone()];
+        for (i, profile) in profiles.iter().enumerate() {
+            row.push(profile.cycles(&function).to_string());
+            row.push(profile.calls(&function).to_string());
+            if i > 0 {
+                row.push(format_ratio(ratio(base, profile, &function)));
+            }
+        }
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    let mut total = vec!["total".to_string()];
+    let mut geomean = vec!["geomean".to_string()];
+    for (i, profile) in profiles.iter().enumerate() {
+        total.extend([profile.total.to_string(), String::new()]);
+        geomean.extend([String::new(), String::new()]);
+        if i > 0 {
+            total.push(format_ratio((base.total > 0).then(|| profile.total as f64 / base.total as f64)));
+            geomean.push(format_ratio(geomean_ratio(base, profile)));
+        }
+    }
+    writeln!(writer, "{}", total.join(","))?;
+    writeln!(writer, "{}", geomean.join(","))?;
+    writer.flush()
+}
+
+// the n hottest functions of the first run, with the ratios of the others
+fn report(profiles: &[RunProfile], n: usize) -> String {
+    let base = &profiles[0];
+    let rows = rows(profiles);
+    let mut out = format!("{} functions over {} runs, cycles relative to {}:\n", rows.len(), profiles.len(), base.label);
+    out += &format!("{:<32} {:>14}", "function", base.label);
+    for profile in &profiles[1..] {
+        out += &format!(" {:>14} {:>8}", profile.label, "ratio");
+    }
+    out += "\n";
+    for function in rows.iter().take(n) {
+        out += &format!("{:<32} {:>14}", function, base.cycles(function));
+        for profile in &profiles[1..] {
+            out += &format!(" {:>14} {:>8}", profile.cycles(function), format_ratio(ratio(base, profile, function)));
+        }
+        out += "\n";
+    }
+    out += &format!("{:<32} {:>14}", "geomean", "");
+    for profile in &profiles[1..] {
+        out += &format!(" {:>14} {:>8}", "", format_ratio(geomean_ratio(base, profile)));
+    }
+    out += "\n";
+    out
+}
+
+// decode every run on a thread of its own, write `trace.compare.csv` and
+// print the n hottest functions
+pub fn compare(runs: &[Run], config: &DecoderConfig, options: &UnwinderOptions, n: usize) -> Result<()> {
+    if runs.is_empty() {
+        return Err(anyhow!("no runs to compare"));
+    }
+    let profiles = thread::scope(|scope| {
+        let handles: Vec<_> = runs.iter().map(|run| scope.spawn(move || profile_run(run, config, options))).collect();
+        handles.into_iter().zip(runs)
+            .map(|(handle, run)| handle.join().map_err(|_| anyhow!("decoder for run {} panicked", run.label))?)
+            .collect::<Result<Vec<RunProfile>>>()
+    })?;
+    write_csv(&profiles, "trace.compare.csv")?;
+    print!("{}", report(&profiles, n));
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// addresses listed in the hot address section
+const TOP_ADDRESSES: usize = 32;
+// accesses of an instruction before its stride is judged
+const MIN_STRIDE_SAMPLES: u64 = 16;
+// share of the accesses a stride needs to be the pattern of the instruction
+const STRIDE_SHARE: f64 = 0.75;
+// distinct strides kept per instruction, the others count as irregular
+const MAX_STRIDES: usize = 8;
+
+// the addresses one load or store instruction went to
+#[derive(Default)]
+struct Site {
+    accesses: u64,
+    bytes: u64,
+    is_store: bool,
+    last: Option<u64>,
+    // stride to how often it was seen
+    strides: HashMap<i64, u64>,
+    irregular: u64,
+}
+
+impl Site {
+    fn access(&mut self, address: u64, size: u64, is_store: bool) {
+        self.accesses += 1;
+        self.bytes += size;
+        self.is_store |= is_store;
+        if let Some(last) = self.last {
+            let stride = address.wrapping_sub(last) as i64;
+            if let Some(count) = self.strides.get_mut(&stride) {
+                *count += 1;
+            } else if self.strides.len() < MAX_STRIDES {
+                self.strides.insert(stride, 1);
+            } else {
+                self.irregular += 1;
+            }
+        }
+        self.last = Some(address);
+    }
+
+    // `stride 8`, `constant` or `irregular`, with the share of the accesses
+    fn pattern(&self) -> (String, f64) {
+        let steps = self.accesses - 1;
+        let Some((&stride, &count)) = self.strides.iter().max_by_key(|&(&stride, &count)| (count, std::cmp::Reverse(stride))) else {
+            return ("irregular".to_string(), 0.0);
+        };
+        let share = count as f64 / steps.max(1) as f64;
+        if share < STRIDE_SHARE {
+            ("irregular".to_string(), share)
+        } else if stride == 0 {
+            ("constant".to_string(), share)
+        } else {
+            (format!("stride {}", stride), share)
+        }
+    }
+}
+
+// the data traffic of one function
+#[derive(Default)]
+struct Bandwidth {
+    loaded: u64,
+    stored: u64,
+    // self cycles
+    cycles: u64,
+}
+
+/// A memory access profile from the data addresses the encoder reports:
+/// the hottest addresses with the data symbol they fall in, the access
+/// pattern of each load and store instruction (a fixed stride, a constant
+/// address, or irregular), and the bytes loaded and stored per function
+/// against its self cycles. Writes `trace.data.txt`.
+pub struct DataReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    loader: Loader,
+    // address to (loads, stores)
+    addresses: HashMap<u64, (u64, u64)>,
+    sites: HashMap<u64, Site>,
+    // function name to its traffic, and the function of each pc seen
+    functions: HashMap<String, Bandwidth>,
+    function_of: HashMap<u64, String>,
+    last_ts: Option<u64>,
+    accesses: u64,
+}
+
+impl DataReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Result<Self> {
+        debug!("DataReceiver::new");
+        Ok(DataReceiver {
+            writer: BufWriter::new(File::create("trace.data.txt")?),
+            receiver: BusReceiver { name: "data".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            addresses: HashMap::new(),
+            sites: HashMap::new(),
+            functions: HashMap::new(),
+            function_of: HashMap::new(),
+            last_ts: None,
+            accesses: 0,
+        })
+    }
+
+    fn function(&mut self, pc: u64) -> &mut Bandwidth {
+        let loader = &self.loader;
+        let name = self.function_of.entry(pc).or_insert_with(|| loader.find_symbol(pc).unwrap_or("?").to_string());
+        self.functions.entry(name.clone()).or_default()
+    }
+
+    // the cycles since the previous timed event ran in the function of pc
+    fn account(&mut self, ts: u64, pc: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        self.function(pc).cycles += elapsed;
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let w = &mut self.writer;
+        writeln!(w, "{} data accesses to {} addresses from {} instructions", self.accesses, self.addresses.len(), self.sites.len())?;
+
+        let mut hot: Vec<_> = self.addresses.iter().collect();
+        hot.sort_by_key(|&(&address, &(loads, stores))| (std::cmp::Reverse(loads + stores), address));
+        writeln!(w)?;
+        writeln!(w, "hot addresses:")?;
+        writeln!(w, "{:>18} {:>12} {:>12}  symbol", "address", "loads", "stores")?;
+        for (&address, &(loads, stores)) in hot.iter().take(TOP_ADDRESSES) {
+            writeln!(w, "{:>#18x} {:>12} {:>12}  {}", address, loads, stores, self.loader.find_symbol(address).unwrap_or("?"))?;
+        }
+
+        let mut sites: Vec<_> = self.sites.iter().filter(|(_, site)| site.accesses >= MIN_STRIDE_SAMPLES).collect();
+        sites.sort_by_key(|&(&pc, site)| (std::cmp::Reverse(site.accesses), pc));
+        writeln!(w)?;
+        writeln!(w, "access patterns, instructions with at least {} accesses:", MIN_STRIDE_SAMPLES)?;
+        for (&pc, site) in sites {
+            let (pattern, share) = site.pattern();
+            writeln!(w, "  {:#010x} {:<5} {:>12} accesses {:>14} bytes  {} ({:.0}%)  {}", pc,
+                     if site.is_store { "store" } else { "load" }, site.accesses, site.bytes, pattern, share * 100.0,
+                     self.function_of.get(&pc).map(String::as_str).unwrap_or("?"))?;
+        }
+
+        let mut functions: Vec<_> = self.functions.iter().filter(|(_, traffic)| traffic.loaded + traffic.stored > 0).collect();
+        functions.sort_by(|a, b| (b.1.loaded + b.1.stored).cmp(&(a.1.loaded + a.1.stored)).then(a.0.cmp(b.0)));
+        writeln!(w)?;
+        writeln!(w, "bandwidth per function:")?;
+        writeln!(w, "{:>14} {:>14} {:>14} {:>14}  function", "bytes loaded", "bytes stored", "self cycles", "bytes/cycle")?;
+        for (name, traffic) in functions {
+            let per_cycle = (traffic.loaded + traffic.stored) as f64 / traffic.cycles.max(1) as f64;
+            writeln!(w, "{:>14} {:>14} {:>14} {:>14.3}  {}", traffic.loaded, traffic.stored, traffic.cycles, per_cycle, name)?;
+        }
+        w.flush()
+    }
+}
+
+impl AbstractReceiver for DataReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::DataAccess => {
+                let (pc, data) = (entry.arc.0, entry.data.unwrap());
+                self.accesses += 1;
+                let counts = self.addresses.entry(data.address).or_default();
+                if data.is_store {
+                    counts.1 += 1;
+                    self.function(pc).stored += data.size;
+                } else {
+                    counts.0 += 1;
+                    self.function(pc).loaded += data.size;
+                }
+                self.sites.entry(pc).or_default().access(data.address, data.size, data.is_store);
+            }
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::InferrableJump | Event::UninferableJump
+            | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn | Event::End => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::frontend::bp_double_saturating_counter::BpDoubleSaturatingCounter;
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// binary entropy of a taken probability, in bits
+fn entropy(taken: u64, total: u64) -> f64 {
+    if taken == 0 || taken == total {
+        return 0.0;
+    }
+    let p = taken as f64 / total as f64;
+    -(p * p.log2() + (1.0 - p) * (1.0 - p).log2())
+}
+
+// bytes of a packet carrying `value`: a compressed packet holds up to 6
+// bits, otherwise a header byte and a 7-bit-per-byte varint
+fn packet_bytes(value: u64) -> u64 {
+    if value < 64 {
+        1
+    } else {
+        1 + (64 - value.leading_zeros() as u64).div_ceil(7)
+    }
+}
+
+#[derive(Default)]
+struct SiteOutcomes {
+    taken: u64,
+    total: u64,
+    last: Option<bool>,
+    // [after not taken, after taken] as (taken, total)
+    after: [(u64, u64); 2],
+}
+
+/// How much information the branch outcomes carry, and what it costs to
+/// encode them. Writes `trace.entropy.txt`: the whole-trace entropy under a
+/// global, a per-branch, and a per-branch last-outcome model (lower bounds
+/// on the bits any encoder using that model needs), the packet bytes the
+/// one-packet-per-branch modes and BrPredict would spend on the branches,
+/// and the per-branch entropies. The packet costs only count branch
+/// packets, with the time since the previous branch as their timestamp.
+pub struct EntropyReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    loader: Loader,
+    sites: HashMap<u64, SiteOutcomes>,
+    taken: u64,
+    total: u64,
+    predictor: BpDoubleSaturatingCounter,
+    last_ts: u64,
+    per_branch_bytes: u64,
+    predict_bytes: u64,
+    hit_run: u64,
+    misses: u64,
+}
+
+impl EntropyReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, bp_entries: u64) -> Result<Self> {
+        debug!("EntropyReceiver::new");
+        Ok(EntropyReceiver {
+            writer: BufWriter::new(File::create("trace.entropy.txt")?),
+            receiver: BusReceiver { name: "entropy".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            sites: HashMap::new(),
+            taken: 0,
+            total: 0,
+            predictor: BpDoubleSaturatingCounter::new(bp_entries),
+            last_ts: 0,
+            per_branch_bytes: 0,
+            predict_bytes: 0,
+            hit_run: 0,
+            misses: 0,
+        })
+    }
+
+    fn record(&mut self, pc: u64, ts: u64, taken: bool) {
+        let delta = ts.saturating_sub(self.last_ts);
+        self.last_ts = ts;
+        self.total += 1;
+        self.taken += taken as u64;
+
+        let site = self.sites.entry(pc).or_default();
+        site.total += 1;
+        site.taken += taken as u64;
+        if let Some(last) = site.last {
+            let after = &mut site.after[last as usize];
+            after.0 += taken as u64;
+            after.1 += 1;
+        }
+        site.last = Some(taken);
+
+        self.per_branch_bytes += packet_bytes(delta);
+        if self.predictor.train(pc, taken) {
+            self.hit_run += 1;
+        } else {
+            self.end_hit_run();
+            self.misses += 1;
+            self.predict_bytes += packet_bytes(delta);
+        }
+    }
+
+    fn end_hit_run(&mut self) {
+        if self.hit_run > 0 {
+            self.predict_bytes += packet_bytes(self.hit_run);
+            self.hit_run = 0;
+        }
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let global = self.total as f64 * entropy(self.taken, self.total);
+        // summed by pc, floating point sums depend on their order
+        let mut by_pc: Vec<(&u64, &SiteOutcomes)> = self.sites.iter().collect();
+        by_pc.sort_unstable_by_key(|&(&pc, _)| pc);
+        let per_site: f64 = by_pc.iter().map(|(_, site)| site.total as f64 * entropy(site.taken, site.total)).sum();
+        let per_site_last: f64 = by_pc.iter().map(|(_, site)| {
+            site.after.iter().map(|&(taken, total)| total as f64 * entropy(taken, total)).sum::<f64>()
+        }).sum();
+        let per_branch = |bits: f64| bits / self.total.max(1) as f64;
+        let w = &mut self.writer;
+        writeln!(w, "branches: {} ({} static sites), taken: {:.2}%", self.total, self.sites.len(),
+                 self.taken as f64 / self.total.max(1) as f64 * 100.0)?;
+        writeln!(w, "entropy, global model: {:.0} bits ({:.4} bits per branch)", global, per_branch(global))?;
+        writeln!(w, "entropy, per-branch model: {:.0} bits ({:.4} bits per branch)", per_site, per_branch(per_site))?;
+        writeln!(w, "entropy, per-branch last-outcome model: {:.0} bits ({:.4} bits per branch)", per_site_last, per_branch(per_site_last))?;
+        writeln!(w, "packets, one per branch (BrTarget/BrHistory): {} bytes ({:.4} bits per branch)",
+                 self.per_branch_bytes, per_branch(self.per_branch_bytes as f64 * 8.0))?;
+        writeln!(w, "packets, BrPredict: {} bytes ({:.4} bits per branch), {} misses",
+                 self.predict_bytes, per_branch(self.predict_bytes as f64 * 8.0), self.misses)?;
+        writeln!(w)?;
+
+        let mut sites = by_pc;
+        let bits = |site: &SiteOutcomes| site.total as f64 * entropy(site.taken, site.total);
+        sites.sort_by(|a, b| bits(b.1).total_cmp(&bits(a.1)).then(a.0.cmp(b.0)));
+        writeln!(w, "{:>18} {:>10} {:>8} {:>8} {:>12}  location", "pc", "count", "taken%", "entropy", "bits")?;
+        for (pc, site) in sites {
+            let location = match self.loader.find_location(*pc) {
+                Ok(Some(loc)) => format!("{}:{}", loc.file.unwrap_or("?"), loc.line.unwrap_or(0)),
+                _ => "?".to_string(),
+            };
+            writeln!(w, "{:>#18x} {:>10} {:>7.2}% {:>8.4} {:>12.0}  {}", pc, site.total,
+                     site.taken as f64 / site.total as f64 * 100.0, entropy(site.taken, site.total), bits(site), location)?;
+        }
+        w.flush()
+    }
+}
+
+impl AbstractReceiver for EntropyReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::TakenBranch => self.record(entry.arc.0, entry.timestamp.unwrap(), true),
+            Event::NonTakenBranch => self.record(entry.arc.0, entry.timestamp.unwrap(), false),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.end_hit_run();
+        self.write_report()?;
+        Ok(())
+    }
+}
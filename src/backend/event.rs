@@ -1,5 +1,6 @@
 use rvdasm::insn::Insn;
-use crate::frontend::trap_type::TrapType;
+use crate::frontend::trap_type::{cause_name, TrapType};
+use crate::frontend::context::Context;
 use serde::Serialize;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -16,7 +17,15 @@ pub enum Event {
     TrapReturn,
     BPHit,
     BPMiss,
-    Panic
+    ContextSwitch,
+    Panic,
+    // decoding resumed at a sync packet after corrupted packets were dropped
+    Resync,
+    // a load or store the encoder reported the address of, follows the
+    // instruction it belongs to
+    DataAccess,
+    // a register or CSR value the encoder reported
+    RegisterValue,
 }
 
 impl Event {
@@ -43,9 +52,157 @@ impl Event {
             Event::TrapReturn => "TrapReturn".to_string(),
             Event::BPHit => "BPHit".to_string(),
             Event::BPMiss => "BPMiss".to_string(),
+            Event::ContextSwitch => "ContextSwitch".to_string(),
             Event::Panic => "Panic".to_string(),
+            Event::Resync => "Resync".to_string(),
+            Event::DataAccess => "DataAccess".to_string(),
+            Event::RegisterValue => "RegisterValue".to_string(),
         }
     }
+
+    // stable numbering used by the replay format, never reorder
+    pub fn to_code(&self) -> u8 {
+        match self {
+            Event::None => 0,
+            Event::Start => 1,
+            Event::TakenBranch => 2,
+            Event::NonTakenBranch => 3,
+            Event::UninferableJump => 4,
+            Event::InferrableJump => 5,
+            Event::End => 6,
+            Event::TrapException => 7,
+            Event::TrapInterrupt => 8,
+            Event::TrapReturn => 9,
+            Event::BPHit => 10,
+            Event::BPMiss => 11,
+            Event::ContextSwitch => 12,
+            Event::Panic => 13,
+            Event::Resync => 14,
+            Event::DataAccess => 15,
+            Event::RegisterValue => 16,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Event::None),
+            1 => Some(Event::Start),
+            2 => Some(Event::TakenBranch),
+            3 => Some(Event::NonTakenBranch),
+            4 => Some(Event::UninferableJump),
+            5 => Some(Event::InferrableJump),
+            6 => Some(Event::End),
+            7 => Some(Event::TrapException),
+            8 => Some(Event::TrapInterrupt),
+            9 => Some(Event::TrapReturn),
+            10 => Some(Event::BPHit),
+            11 => Some(Event::BPMiss),
+            12 => Some(Event::ContextSwitch),
+            13 => Some(Event::Panic),
+            14 => Some(Event::Resync),
+            15 => Some(Event::DataAccess),
+            16 => Some(Event::RegisterValue),
+            _ => None,
+        }
+    }
+}
+
+// attribute of a TrapReturn entry, the instruction returning: mret, sret or uret
+pub const RETURN_ATTR: &str = "xret";
+
+// attribute of a ContextSwitch entry between correlated traces, the path of
+// the trace switched to
+pub const SOURCE_ATTR: &str = "source";
+
+// what is known about a trap beyond its kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrapInfo {
+    // exception or interrupt code, reported by the encoder or taken from
+    // the vector slot in vectored mode
+    pub cause: Option<u64>,
+    // mtval, reported by the encoder
+    pub tval: Option<u64>,
+    // where the handler starts, past the jump of a vector slot
+    pub handler: Option<u64>,
+}
+
+// a load or store, the size is that of the instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DataAccess {
+    pub address: u64,
+    // bytes
+    pub size: u64,
+    pub is_store: bool,
+}
+
+// a register by its debug spec number, see frontend::register
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegisterValue {
+    pub regno: u16,
+    pub value: u64,
+}
+
+// the value of an entry attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    U64(u64),
+    Str(String),
+}
+
+impl std::fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrValue::U64(value) => write!(f, "{:#x}", value),
+            AttrValue::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// what an entry carries beyond its fixed fields, by name. A new packet
+// payload becomes an attribute set by the frontend and read by the
+// receivers that want it; every other receiver skips the attributes it
+// does not know, so none of them has to change. Empty, as for most
+// entries, it does not allocate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes(Vec<(String, AttrValue)>);
+
+impl Attributes {
+    pub fn get(&self, key: &str) -> Option<&AttrValue> {
+        self.0.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        match self.get(key)? {
+            AttrValue::U64(value) => Some(*value),
+            AttrValue::Str(_) => None,
+        }
+    }
+
+    // replaces the value of a key already set
+    pub fn insert(&mut self, key: &str, value: AttrValue) {
+        match self.0.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) => *old = value,
+            None => self.0.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AttrValue)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// ` key=value` per attribute, in the order they were set
+impl std::fmt::Display for Attributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, value) in self.iter() {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,18 +211,57 @@ pub struct Entry {
     pub arc: (u64, u64), // from, to
     pub insn: Option<Insn>,
     pub timestamp: Option<u64>,
+    pub ctx: Option<Context>, // only set for ContextSwitch events
+    pub trap: Option<TrapInfo>, // only set for trap events, when known
+    pub data: Option<DataAccess>, // only set for DataAccess events
+    pub reg: Option<RegisterValue>, // only set for RegisterValue events
+    pub attrs: Attributes,
 }
 
 impl Entry {
     pub fn new_timed_event(event: Event, timestamp: u64, from: u64, to: u64) -> Self {
-        Self { event, arc: (from, to), insn: None, timestamp: Some(timestamp) }
+        Self { event, arc: (from, to), insn: None, timestamp: Some(timestamp), ctx: None, trap: None, data: None, reg: None, attrs: Attributes::default() }
     }
 
     pub fn new_insn(insn: &Insn, address: u64) -> Self {
-        Self { event: Event::None, arc: (address, address + insn.get_len() as u64), insn: Some(insn.clone()), timestamp: None }
+        Self { event: Event::None, arc: (address, address + insn.len as u64), insn: Some(insn.clone()), timestamp: None, ctx: None, trap: None, data: None, reg: None, attrs: Attributes::default() }
     }
 
     pub fn new_timed_trap(trap_type: TrapType, timestamp: u64, from: u64, to: u64) -> Self {
-        Self { event: Event::from_trap_type(trap_type), arc: (from, to), insn: None, timestamp: Some(timestamp) }
+        Self { event: Event::from_trap_type(trap_type), arc: (from, to), insn: None, timestamp: Some(timestamp), ctx: None, trap: None, data: None, reg: None, attrs: Attributes::default() }
+    }
+
+    // `cause 7 (Machine timer interrupt)`, the code alone when it is not a
+    // standard one; None without a known cause
+    pub fn cause_text(&self) -> Option<String> {
+        let code = self.trap?.cause?;
+        Some(match cause_name(self.event == Event::TrapInterrupt, code) {
+            Some(name) => format!("cause {} ({})", code, name),
+            None => format!("cause {}", code),
+        })
+    }
+
+    pub fn with_trap(mut self, trap: Option<TrapInfo>) -> Self {
+        self.trap = trap;
+        self
+    }
+
+    pub fn with_attr(mut self, key: &str, value: AttrValue) -> Self {
+        self.attrs.insert(key, value);
+        self
+    }
+
+    // untimed like the instruction at pc it follows, arc.1 is the address
+    pub fn new_data_access(pc: u64, access: DataAccess) -> Self {
+        Self { event: Event::DataAccess, arc: (pc, access.address), insn: None, timestamp: None, ctx: None, trap: None, data: Some(access), reg: None, attrs: Attributes::default() }
+    }
+
+    // timed at the last timed packet before it, at its pc
+    pub fn new_register_value(reg: RegisterValue, timestamp: u64, pc: u64) -> Self {
+        Self { event: Event::RegisterValue, arc: (pc, pc), insn: None, timestamp: Some(timestamp), ctx: None, trap: None, data: None, reg: Some(reg), attrs: Attributes::default() }
+    }
+
+    pub fn new_context_switch(ctx: Context, timestamp: u64, pc: u64) -> Self {
+        Self { event: Event::ContextSwitch, arc: (pc, pc), insn: None, timestamp: Some(timestamp), ctx: Some(ctx), trap: None, data: None, reg: None, attrs: Attributes::default() }
     }
 }
@@ -1,13 +1,112 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::StackUnwinder;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::backend::gantt_receiver::csv_field;
 
 use bus::BusReader;
+use anyhow::{anyhow, Result};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::collections::HashMap;
 use log::debug;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FocColumn {
+  // the swept parameter
+  Param,
+  Time,
+  Path,
+  // the index of the execution
+  Index,
+  Function,
+}
+
+impl FocColumn {
+  fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "param" => Some(FocColumn::Param),
+      "time" => Some(FocColumn::Time),
+      "path" => Some(FocColumn::Path),
+      "index" => Some(FocColumn::Index),
+      "function" => Some(FocColumn::Function),
+      _ => None,
+    }
+  }
+}
+
+// Which executions of the control loop are measured and what is written for
+// them. The defaults are those of the FOC benchmark: every other execution
+// warms the caches up, and vq sweeps a full turn over the run.
+#[derive(Clone, Debug)]
+pub struct FocConfig {
+  // per execution of a repetition, whether it is a sample or a warmup
+  pub pattern: Vec<bool>,
+  // name and range of the swept parameter, linear over all executions
+  pub param: String,
+  pub sweep: (f64, f64),
+  pub columns: Vec<FocColumn>,
+  pub csv: bool,
+}
+
+impl Default for FocConfig {
+  fn default() -> Self {
+    Self {
+      pattern: vec![false, true],
+      param: "vq".to_string(),
+      sweep: (0.0, 2.0 * std::f64::consts::PI),
+      columns: vec![FocColumn::Param, FocColumn::Time, FocColumn::Path],
+      csv: false,
+    }
+  }
+}
+
+impl FocConfig {
+  // one `<key> <value>` line per setting, `#` starts a comment:
+  //   pattern ws                   w a warmup, s a sample execution
+  //   sweep vq 0 6.2832            the parameter and the range it sweeps over the run
+  //   columns param,time,path      of param, time, path, index, function
+  //   format csv                   text (the default) or csv
+  pub fn from_file(path: &str) -> Result<Self> {
+    let mut config = Self::default();
+    for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+      let line = line?;
+      let line = line.split('#').next().unwrap_or("").trim();
+      if line.is_empty() {
+        continue;
+      }
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let bad = || anyhow!("{}:{}: bad setting `{}`", path, lineno + 1, line);
+      match (fields[0], &fields[1..]) {
+        ("pattern", [pattern]) => {
+          config.pattern = pattern.chars().map(|c| match c {
+            'w' => Ok(false),
+            's' => Ok(true),
+            _ => Err(bad()),
+          }).collect::<Result<_>>()?;
+          if !config.pattern.contains(&true) {
+            return Err(anyhow!("{}:{}: the pattern has no sample", path, lineno + 1));
+          }
+        }
+        ("sweep", [name, from, to]) => {
+          config.param = name.to_string();
+          config.sweep = (from.parse().map_err(|_| bad())?, to.parse().map_err(|_| bad())?);
+        }
+        ("columns", [columns]) => {
+          config.columns = columns.split(',').map(|name| FocColumn::from_name(name).ok_or_else(bad)).collect::<Result<_>>()?;
+        }
+        ("format", ["text"]) => config.csv = false,
+        ("format", ["csv"]) => config.csv = true,
+        _ => return Err(bad()),
+      }
+    }
+    Ok(config)
+  }
+
+  pub fn from_path(path: &str) -> Result<Self> {
+    if path.is_empty() { Ok(Self::default()) } else { Self::from_file(path) }
+  }
+}
+
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct Path{
   addr: u64,
@@ -23,23 +122,57 @@ pub struct FOCReceiver {
   curr_path: Option<Path>,
   start_timestamp: u64,
   path_time: Vec<(Path, u64)>,
+  config: FocConfig,
 }
 
 impl FOCReceiver {
-  pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Self {
+  pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, config: FocConfig) -> Result<Self> {
     debug!("Creating FOCReceiver");
-    Self {
-      writer: BufWriter::new(File::create("trace.foc.txt").unwrap()),
+    let output = if config.csv { "trace.foc.csv" } else { "trace.foc.txt" };
+    Ok(Self {
+      writer: BufWriter::new(File::create(output)?),
       receiver: BusReceiver {
         name: "foc".to_string(),
         bus_rx,
         checksum: 0,
       },
-      stack_unwinder: StackUnwinder::new(elf_path).unwrap(),
+      stack_unwinder: StackUnwinder::new(elf_path, options)?,
       path_records: HashMap::new(),
       curr_path: None,
       start_timestamp: 0,
       path_time: Vec::new(),
+      config,
+    })
+  }
+
+  fn column(&self, column: FocColumn, i: usize, path: &Path, time: u64) -> String {
+    match column {
+      FocColumn::Param => {
+        // the value at the first execution of the repetition
+        let start = i - i % self.config.pattern.len();
+        let (from, to) = self.config.sweep;
+        format!("{:.3}", from + start as f64 * (to - from) / self.path_time.len() as f64)
+      }
+      FocColumn::Time => time.to_string(),
+      FocColumn::Path => {
+        let bits: String = path.path.iter().map(|&b| if b { '1' } else { '0' }).collect();
+        if self.config.csv { format!("{:#x}-{}", path.addr, bits) } else { format!("PATH:{:#x}-{}", path.addr, bits) }
+      }
+      FocColumn::Index => i.to_string(),
+      FocColumn::Function => {
+        let name = self.stack_unwinder.get_symbol_info(path.addr).name;
+        if self.config.csv { csv_field(&name) } else { name }
+      }
+    }
+  }
+
+  fn column_name(&self, column: FocColumn) -> &str {
+    match column {
+      FocColumn::Param => &self.config.param,
+      FocColumn::Time => "time",
+      FocColumn::Path => "path",
+      FocColumn::Index => "index",
+      FocColumn::Function => "function",
     }
   }
 }
@@ -53,7 +186,11 @@ impl AbstractReceiver for FOCReceiver {
     self.receiver.checksum += 1;
   }
 
-  fn _receive_entry(&mut self, entry: Entry) {
+  fn checksum(&self) -> usize {
+    self.receiver.checksum
+  }
+
+  fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
     match entry.event {
       Event::InferrableJump => {
         let (success, frame_stack_size, _) = self.stack_unwinder.step_ij(entry.clone());
@@ -106,38 +243,30 @@ impl AbstractReceiver for FOCReceiver {
         // ignore other events
       }
     }
+    Ok(())
   }
 
-  fn _flush(&mut self) {
-    // the first one is warmup
-    // let first_entry = self.path_time.remove(0);
-    // self.writer.write_all(format!("warmup   ,time: {},", first_entry.1).as_bytes()).unwrap();
-    // self.writer.write_all(format!("PATH:{:#x}-", first_entry.0.addr).as_bytes()).unwrap();
-    // self.writer.write_all(format!("{}", first_entry.0.path.iter()
-    //     .map(|&b| if b { '1' } else { '0' })
-    //     .collect::<String>())
-    //     .as_bytes()).unwrap();
-    // self.writer.write_all(b"\n").unwrap();
+  fn _flush(&mut self) -> Result<()> {
+    if self.config.csv {
+      let header: Vec<&str> = self.config.columns.iter().map(|&column| self.column_name(column)).collect();
+      writeln!(self.writer, "{}", header.join(","))?;
+    }
     for (i, (path, time)) in self.path_time.iter().enumerate() {
-      // every first one is a cache warmup
-      if i % 2 == 1 {
-        let vq = (i-1) as f32 * 2.0 * std::f32::consts::PI / self.path_time.len() as f32;
-        // format float to 3 decimal places
-        let vq_str = format!("{:.3}", vq);
-        self.writer.write_all(format!("vq: {},", vq_str).as_bytes()).unwrap();
-        // time
-        self.writer.write_all(format!("time: {},", time).as_bytes()).unwrap();
-        // addr
-        self.writer.write_all(format!("PATH:{:#x}-", path.addr).as_bytes()).unwrap();
-        // path, each taken and not taken
-        self.writer.write_all(format!("{}", path.path.iter()
-            .map(|&b| if b { '1' } else { '0' })
-            .collect::<String>())
-            .as_bytes()).unwrap();
-          self.writer.write_all(b"\n").unwrap();
+      // the warmup executions only fill the caches
+      if !self.config.pattern[i % self.config.pattern.len()] {
+        continue;
       }
+      let fields: Vec<String> = self.config.columns.iter().map(|&column| {
+        let value = self.column(column, i, path, *time);
+        match (self.config.csv, column) {
+          (true, _) => value,
+          (false, FocColumn::Path) => value,
+          (false, _) => format!("{}: {}", self.column_name(column), value),
+        }
+      }).collect();
+      writeln!(self.writer, "{}", fields.join(","))?;
     }
-    self.writer.flush().unwrap();
+    self.writer.flush()?;
+    Ok(())
   }
 }
-
@@ -0,0 +1,246 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::Context;
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// Fuchsia trace format, https://fuchsia.dev/fuchsia-src/reference/tracing/trace-format
+const FXT_MAGIC: u64 = 0x0016_5478_4604_0010;
+const RECORD_INIT: u64 = 1;
+const RECORD_STRING: u64 = 2;
+const RECORD_THREAD: u64 = 3;
+const RECORD_EVENT: u64 = 4;
+const RECORD_KERNEL_OBJECT: u64 = 7;
+const EVENT_INSTANT: u64 = 0;
+const EVENT_BEGIN: u64 = 2;
+const EVENT_END: u64 = 3;
+const ARG_UINT64: u64 = 4;
+const ARG_KOID: u64 = 8;
+const OBJ_PROCESS: u64 = 1;
+const OBJ_THREAD: u64 = 2;
+// string and thread references are indices into tables of this size, a
+// full table has its entries redefined round robin
+const MAX_STRINGS: u16 = 0x7fff;
+const MAX_THREADS: u8 = 0xff;
+const MAX_STRING_LEN: usize = 32000;
+// one tick per cycle, shown as a microsecond as in the perfetto json
+const TICKS_PER_SECOND: u64 = 1_000_000;
+
+fn header(record: u64, words: u64) -> u64 {
+    record | words << 4
+}
+
+/// Function durations and traps in the binary Fuchsia Trace Format, which
+/// Perfetto UI loads far faster than the json of `--to-perfetto` and at a
+/// fraction of its size. Writes `trace.fxt`: a duration begin and end per
+/// function frame the unwinder opens and closes, on one thread per hart
+/// within one process per asid, and an instant event per trap with its
+/// cause and pc. Names are interned, each written once.
+pub struct FxtReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    strings: HashMap<String, u16>,
+    // the string at each index, to redefine them once the table is full
+    string_slots: Vec<String>,
+    next_string: u16,
+    threads: HashMap<Context, u8>,
+    thread_slots: Vec<Context>,
+    next_thread: u8,
+    ctx: Context,
+    // the frames, as symbol indices, the trace has open per context
+    last_frames: Vec<u32>,
+    parked_frames: HashMap<Context, Vec<u32>>,
+    last_ts: u64,
+}
+
+impl FxtReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        debug!("FxtReceiver::new");
+        let mut receiver = FxtReceiver {
+            writer: BufWriter::new(File::create("trace.fxt")?),
+            receiver: BusReceiver { name: "fxt".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            strings: HashMap::new(),
+            string_slots: Vec::new(),
+            next_string: 0,
+            threads: HashMap::new(),
+            thread_slots: Vec::new(),
+            next_thread: 0,
+            ctx: Context::default(),
+            last_frames: Vec::new(),
+            parked_frames: HashMap::new(),
+            last_ts: 0,
+        };
+        receiver.write_words(&[FXT_MAGIC, header(RECORD_INIT, 2), TICKS_PER_SECOND])?;
+        Ok(receiver)
+    }
+
+    fn write_words(&mut self, words: &[u64]) -> std::io::Result<()> {
+        for word in words {
+            self.writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // the string table index of s, writing its string record when new
+    fn string_ref(&mut self, s: &str) -> std::io::Result<u64> {
+        if let Some(&index) = self.strings.get(s) {
+            return Ok(index as u64);
+        }
+        let mut end = s.len().min(MAX_STRING_LEN);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let bytes = &s.as_bytes()[..end];
+        // index 0 is the empty string
+        self.next_string = self.next_string % MAX_STRINGS + 1;
+        let index = self.next_string;
+        if let Some(old) = self.string_slots.get_mut(index as usize - 1) {
+            self.strings.remove(old);
+            *old = s.to_string();
+        } else {
+            self.string_slots.push(s.to_string());
+        }
+        self.strings.insert(s.to_string(), index);
+        let words = bytes.len().div_ceil(8) as u64;
+        self.write_words(&[header(RECORD_STRING, 1 + words) | (index as u64) << 16 | (bytes.len() as u64) << 32])?;
+        self.writer.write_all(bytes)?;
+        self.writer.write_all(&[0u8; 8][..(words as usize * 8 - bytes.len())])?;
+        Ok(index as u64)
+    }
+
+    // the thread table index of the current context, writing its thread
+    // record and naming its process and thread when new
+    fn thread_ref(&mut self) -> std::io::Result<u64> {
+        let ctx = self.ctx;
+        if let Some(&index) = self.threads.get(&ctx) {
+            return Ok(index as u64);
+        }
+        self.next_thread = self.next_thread % MAX_THREADS + 1;
+        let index = self.next_thread;
+        if let Some(old) = self.thread_slots.get_mut(index as usize - 1) {
+            self.threads.remove(old);
+            *old = ctx;
+        } else {
+            self.thread_slots.push(ctx);
+        }
+        self.threads.insert(ctx, index);
+        // a koid is unique across processes
+        let (process, thread) = (ctx.asid, ctx.asid << 32 | ctx.hart);
+        self.write_words(&[header(RECORD_THREAD, 3) | (index as u64) << 16, process, thread])?;
+        let name = self.string_ref(&format!("asid {}", ctx.asid))?;
+        self.write_words(&[header(RECORD_KERNEL_OBJECT, 2) | OBJ_PROCESS << 16 | name << 24, process])?;
+        let name = self.string_ref(&format!("hart {}", ctx.hart))?;
+        let arg_name = self.string_ref("process")?;
+        self.write_words(&[header(RECORD_KERNEL_OBJECT, 4) | OBJ_THREAD << 16 | name << 24 | 1 << 40, thread,
+                           header(ARG_KOID, 2) | arg_name << 16, process])?;
+        Ok(index as u64)
+    }
+
+    // an event record with uint64 arguments
+    fn write_event(&mut self, event: u64, category: &str, name: &str, ts: u64, args: &[(&str, u64)]) -> std::io::Result<()> {
+        let thread = self.thread_ref()?;
+        let category = self.string_ref(category)?;
+        let name = self.string_ref(name)?;
+        let mut arg_words = Vec::new();
+        for &(arg, value) in args {
+            let arg = self.string_ref(arg)?;
+            arg_words.extend([header(ARG_UINT64, 2) | arg << 16, value]);
+        }
+        let words = 2 + arg_words.len() as u64;
+        self.write_words(&[header(RECORD_EVENT, words) | event << 16 | (args.len() as u64) << 20 | thread << 24
+                           | category << 32 | name << 48, ts])?;
+        self.write_words(&arg_words)
+    }
+
+    // ends and begins durations to catch up with the unwinder's stack
+    fn diff_stack(&mut self, ts: u64) -> std::io::Result<()> {
+        let new_frames = self.unwinder.frame_stack.clone();
+        let common = self.last_frames.iter().zip(&new_frames).take_while(|(a, b)| a == b).count();
+        let closed: Vec<u32> = self.last_frames[common..].iter().rev().copied().collect();
+        for frame in closed {
+            let name = self.unwinder.symbols()[frame as usize].name.clone();
+            self.write_event(EVENT_END, "function", &name, ts, &[])?;
+        }
+        for &frame in &new_frames[common..] {
+            let name = self.unwinder.symbols()[frame as usize].name.clone();
+            self.write_event(EVENT_BEGIN, "function", &name, ts, &[])?;
+        }
+        self.last_frames = new_frames;
+        Ok(())
+    }
+
+    fn switch_context(&mut self, ctx: Context) {
+        if ctx == self.ctx {
+            return;
+        }
+        self.unwinder.switch_context(ctx);
+        let parked = std::mem::take(&mut self.last_frames);
+        self.parked_frames.insert(self.ctx, parked);
+        self.last_frames = self.parked_frames.remove(&ctx).unwrap_or_default();
+        self.ctx = ctx;
+    }
+}
+
+impl AbstractReceiver for FxtReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the hit count of BPHit is not a timestamp
+        if entry.event != Event::BPHit {
+            if let Some(ts) = entry.timestamp {
+                self.last_ts = ts;
+            }
+        }
+        match entry.event {
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt
+            | Event::UninferableJump | Event::TrapReturn => {
+                let ts = self.last_ts;
+                if let Some(cause) = entry.cause_text() {
+                    self.write_event(EVENT_INSTANT, "trap", &cause, ts, &[("from", entry.arc.0)])?;
+                }
+                if matches!(entry.event, Event::UninferableJump | Event::TrapReturn) {
+                    let _ = self.unwinder.step_uj(entry);
+                } else {
+                    let _ = self.unwinder.step_ij(entry);
+                }
+                self.diff_stack(ts)?;
+            }
+            Event::ContextSwitch => {
+                self.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        // end what is still open, in every context
+        let mut ctxs: Vec<Context> = self.parked_frames.keys().copied().collect();
+        ctxs.sort_by_key(|ctx| (ctx.asid, ctx.hart));
+        ctxs.push(self.ctx);
+        for ctx in ctxs {
+            self.switch_context(ctx);
+            self.unwinder.flush();
+            self.diff_stack(self.last_ts)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
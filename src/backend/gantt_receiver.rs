@@ -0,0 +1,125 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::Context;
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// quoted when the name has a comma or quote in it (C++ templates)
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// One `function,start_ts,end_ts,depth,hart` row per function invocation in
+/// `trace.gantt.csv`, for spreadsheets and plots that want intervals rather
+/// than events. Rows are written as invocations end, depth 0 is the
+/// outermost frame; frames still open at the end of the trace end there.
+pub struct GanttReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    // per context, the open invocations as (symbol index, start), in the
+    // order of the unwinder frame stack
+    open: HashMap<Context, Vec<(u32, u64)>>,
+    ctx: Context,
+    last_ts: u64,
+    rows: u64,
+}
+
+impl GanttReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        debug!("GanttReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let mut writer = BufWriter::new(File::create("trace.gantt.csv")?);
+        writeln!(writer, "function,start_ts,end_ts,depth,hart")?;
+        Ok(GanttReceiver {
+            writer,
+            receiver: BusReceiver { name: "gantt".into(), bus_rx, checksum: 0 },
+            unwinder,
+            open: HashMap::new(),
+            ctx: Context::default(),
+            last_ts: 0,
+            rows: 0,
+        })
+    }
+
+    fn open_frame(&mut self, idx: u32, ts: u64) {
+        self.open.entry(self.ctx).or_default().push((idx, ts));
+    }
+
+    fn close_frame(&mut self, ctx: Context, end: u64) -> std::io::Result<()> {
+        let Some(stack) = self.open.get_mut(&ctx) else { return Ok(()) };
+        let Some((idx, start)) = stack.pop() else { return Ok(()) };
+        let depth = stack.len();
+        let name = &self.unwinder.symbols()[idx as usize].name;
+        writeln!(self.writer, "{},{},{},{},{}", csv_field(name), start, end, depth, ctx.hart)?;
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for GanttReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if let Some(ts) = entry.timestamp {
+            self.last_ts = ts;
+        }
+        match entry.event {
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let ts = entry.timestamp.unwrap();
+                if let (true, _, Some(opened)) = self.unwinder.step_ij(entry) {
+                    self.open_frame(opened.index, ts);
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let ts = entry.timestamp.unwrap();
+                let (_, _, closed, opened) = self.unwinder.step_uj(entry);
+                for _ in closed {
+                    self.close_frame(self.ctx, ts)?;
+                }
+                if let Some(opened) = opened {
+                    self.open_frame(opened.index, ts);
+                }
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(ctx);
+                self.ctx = ctx;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        let mut ctxs: Vec<Context> = self.open.keys().copied().collect();
+        ctxs.sort();
+        for ctx in ctxs {
+            while self.open[&ctx].last().is_some() {
+                self.close_frame(ctx, self.last_ts)?;
+            }
+        }
+        debug!("wrote {} gantt rows", self.rows);
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -5,10 +5,13 @@ use std::fs::File;
 use std::io::BufWriter;
 use gcno_reader::reader::GCNOReader;
 use gcno_reader::cfg::{ControlFlowGraph, ReportedEdge, SourceLocation};
+use gcno_reader::version::GcovVersion;
 use indexmap::IndexMap;
 use addr2line::Loader;
 use object::{Object, ObjectSymbol};
+use std::collections::HashSet;
 use std::fs;
+use anyhow::{anyhow, Result};
 use log::{trace, debug};
 pub struct GcdaReceiver {
   writer: BufWriter<File>,
@@ -17,19 +20,32 @@ pub struct GcdaReceiver {
   loader: Loader,
   func_symbol_map: IndexMap<u64, (String, u64)>,
   cfg: ControlFlowGraph,
+  gcov: GcovVersion,
 }
 
 impl GcdaReceiver {
-  pub fn new(bus_rx: BusReader<Entry>, gcno_path: String, elf_path: String) -> Self {
+  // the gcda is written for `gcov`, by default the version of the gcno;
+  // fails when the gcno does not match the binary or that version
+  pub fn new(bus_rx: BusReader<Entry>, gcno_path: String, elf_path: String, gcov: Option<GcovVersion>) -> Result<Self> {
     // gcno handler
-    let mut gcno_reader = GCNOReader::new(gcno_path.clone()).unwrap();
-    let gcno = gcno_reader.parse().unwrap();
+    let mut gcno_reader = GCNOReader::new(gcno_path.clone())?;
+    let gcno_version = gcno_reader.gcov_version();
+    let gcov = gcov.unwrap_or(gcno_version);
+    if !gcov.same_layout(&gcno_version) {
+      return Err(anyhow!("{} was written by GCC {}, the gcov of GCC {} cannot read it", gcno_path, gcno_version, gcov));
+    }
+    let gcno = gcno_reader.parse()?;
     let cfg = ControlFlowGraph::from(gcno);
     // addr2line handler
-    let loader = Loader::new(elf_path.clone()).unwrap();
+    let loader = Loader::new(elf_path.clone()).map_err(|e| anyhow!("{}: {}", elf_path, e))?;
     // object handler
-    let elf_data = fs::read(elf_path.clone()).unwrap();
-    let obj_file = object::File::parse(&*elf_data).unwrap();
+    let elf_data = fs::read(elf_path.clone())?;
+    let obj_file = object::File::parse(&*elf_data)?;
+    let symbols: HashSet<String> = obj_file.symbols()
+      .filter(|symbol| symbol.kind() == object::SymbolKind::Text)
+      .filter_map(|symbol| symbol.name().ok().map(String::from))
+      .collect();
+    cfg.validate(&symbols).map_err(|e| anyhow!("{}: {}", gcno_path, e))?;
     let edge_map = cfg.report_instrumented_edges();
     let mut func_symbol_map = IndexMap::new();
     for symbol in obj_file.symbols() {
@@ -44,13 +60,14 @@ impl GcdaReceiver {
       }
     }
 
-    Self { writer: BufWriter::new(File::create(gcno_path.clone().replace(".gcno", ".gcda")).unwrap()), 
+    Ok(Self { writer: BufWriter::new(File::create(gcno_path.clone().replace(".gcno", ".gcda"))?), 
           receiver: BusReceiver { name: "gcda".to_string(), bus_rx: bus_rx, checksum: 0 },
           edge_map: edge_map,
           loader: loader,
           func_symbol_map: func_symbol_map,
           cfg: cfg,
-    }
+          gcov,
+    })
   }
 }
 
@@ -64,7 +81,11 @@ impl AbstractReceiver for GcdaReceiver {
     self.receiver.checksum += 1;
   }
 
-  fn _receive_entry(&mut self, entry: Entry) {
+  fn checksum(&self) -> usize {
+    self.receiver.checksum
+  }
+
+  fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
     match entry.event {
       Event::TakenBranch | Event::NonTakenBranch | Event::InferrableJump | Event::UninferableJump => {
         let from_source: SourceLocation = SourceLocation::from_addr2line(self.loader.find_location(entry.arc.0).unwrap());
@@ -89,9 +110,10 @@ impl AbstractReceiver for GcdaReceiver {
       }
       _ => {}
     }
+    Ok(())
   }
 
-  fn _flush(&mut self) {
+  fn _flush(&mut self) -> Result<()> {
     // merge the edge count with the edge map
     for (func_name, edges) in self.edge_map.iter_mut() {
       for edge in edges.iter_mut() {
@@ -105,6 +127,7 @@ impl AbstractReceiver for GcdaReceiver {
         }
       }
     }
-    self.cfg.write_gcda(&self.edge_map, &mut self.writer);
+    self.cfg.write_gcda(&self.edge_map, &mut self.writer, &self.gcov);
+    Ok(())
   }
 }
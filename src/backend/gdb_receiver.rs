@@ -0,0 +1,202 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// hottest blocks, by total cycles, to break at
+const TOP_BLOCKS: usize = 10;
+// an execution this many times the median latency of its block is anomalous,
+// as with --vbb-anomaly
+const ANOMALY_FACTOR: f64 = 10.0;
+// blocks executed fewer times have no typical latency to compare against
+const MIN_SAMPLES: usize = 10;
+const TOP_ANOMALIES: usize = 10;
+const MAX_VIOLATIONS: usize = 32;
+
+// why a location gets a breakpoint
+struct Site {
+    addr: u64,
+    kind: &'static str,
+    why: String,
+}
+
+/// A GDB command file, `trace.gdb`, to pick up interactively where the
+/// offline analysis left off: a breakpoint (or, with `tracepoints`, a
+/// tracepoint collecting the registers) at the hottest basic blocks by
+/// cycles, at the blocks with the most anomalous executions (over 10x the
+/// median latency of the block), and at the targets of the control flow
+/// violations seen, indirect jumps landing at neither a function entry, a
+/// return site nor inside their own function. Each one says why it is
+/// there when hit; load it with `source trace.gdb`.
+pub struct GdbReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    loader: Loader,
+    tracepoints: bool,
+    // (start, end) of a block to the latency of its executions
+    blocks: HashMap<(u64, u64), Vec<u64>>,
+    prev_addr: u64,
+    prev_ts: u64,
+    // (from, to) of a violation to its count
+    violations: HashMap<(u64, u64), u64>,
+}
+
+impl GdbReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, tracepoints: bool) -> Result<Self> {
+        debug!("GdbReceiver::new");
+        Ok(GdbReceiver {
+            receiver: BusReceiver { name: "gdb".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path.clone(), options)?,
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            tracepoints,
+            blocks: HashMap::new(),
+            prev_addr: 0,
+            prev_ts: 0,
+            violations: HashMap::new(),
+        })
+    }
+
+    // `function at file:line` of addr
+    fn location(&self, addr: u64) -> String {
+        let function = self.loader.find_symbol(addr).unwrap_or("?");
+        match self.loader.find_location(addr) {
+            Ok(Some(location)) => format!("{} at {}:{}", function, location.file.unwrap_or("?"), location.line.unwrap_or(0)),
+            _ => function.to_string(),
+        }
+    }
+
+    // an indirect jump landing somewhere no call or return goes
+    fn check_target(&mut self, from: u64, to: u64) {
+        if self.unwinder.func_symbol_map().contains_key(&to) || self.unwinder.follows_call(to) {
+            return;
+        }
+        if let Some((start, end)) = self.unwinder.function_range_at(from) {
+            if to >= start && to < end {
+                return;
+            }
+        }
+        *self.violations.entry((from, to)).or_default() += 1;
+    }
+
+    fn sites(&self) -> Vec<Site> {
+        let mut sites = Vec::new();
+        let total: u64 = self.blocks.values().flatten().sum();
+        let mut hot: Vec<(&(u64, u64), u64, usize)> = self.blocks.iter()
+            .map(|(block, latencies)| (block, latencies.iter().sum(), latencies.len()))
+            .collect();
+        hot.sort_by_key(|&(&block, cycles, _)| (std::cmp::Reverse(cycles), block));
+        for (&(start, end), cycles, count) in hot.into_iter().take(TOP_BLOCKS) {
+            sites.push(Site {
+                addr: start,
+                kind: "hot block",
+                why: format!("{:#x}-{:#x}, {} executions, {} cycles ({:.2}%)", start, end, count, cycles,
+                             cycles as f64 / total.max(1) as f64 * 100.0),
+            });
+        }
+
+        // (block, anomalous executions, worst latency, median)
+        let mut anomalous = Vec::new();
+        for (&block, latencies) in &self.blocks {
+            if latencies.len() < MIN_SAMPLES {
+                continue;
+            }
+            let mut sorted = latencies.clone();
+            sorted.sort_unstable();
+            let median = sorted[sorted.len() / 2];
+            let threshold = median.max(1) as f64 * ANOMALY_FACTOR;
+            let count = sorted.iter().filter(|&&latency| latency as f64 > threshold).count();
+            if count > 0 {
+                anomalous.push((block, count, sorted[sorted.len() - 1], median));
+            }
+        }
+        anomalous.sort_by_key(|&(block, count, worst, _)| (std::cmp::Reverse(count), std::cmp::Reverse(worst), block));
+        for ((start, end), count, worst, median) in anomalous.into_iter().take(TOP_ANOMALIES) {
+            sites.push(Site {
+                addr: start,
+                kind: "anomalous latency",
+                why: format!("{:#x}-{:#x}, {} executions over {}x the median of {} cycles, worst {}", start, end, count,
+                             ANOMALY_FACTOR, median, worst),
+            });
+        }
+
+        let mut violations: Vec<_> = self.violations.iter().collect();
+        violations.sort_by_key(|&(&arc, &count)| (std::cmp::Reverse(count), arc));
+        for (&(from, to), &count) in violations.into_iter().take(MAX_VIOLATIONS) {
+            sites.push(Site {
+                addr: to,
+                kind: "control flow violation",
+                why: format!("indirect jump from {:#x} ({}), {} times", from, self.location(from), count),
+            });
+        }
+        sites
+    }
+
+    fn write_script(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.gdb")?);
+        writeln!(writer, "# breakpoints from the trace, load with `source trace.gdb`")?;
+        writeln!(writer, "set breakpoint pending on")?;
+        for site in self.sites() {
+            let location = self.location(site.addr);
+            writeln!(writer)?;
+            writeln!(writer, "# {}: {}, {}", site.kind, location, site.why)?;
+            if self.tracepoints {
+                writeln!(writer, "trace *{:#x}", site.addr)?;
+                writeln!(writer, "actions")?;
+                writeln!(writer, "  collect $regs")?;
+                writeln!(writer, "end")?;
+            } else {
+                writeln!(writer, "break *{:#x}", site.addr)?;
+                writeln!(writer, "commands")?;
+                writeln!(writer, "  echo {} in {}: {}\\n", site.kind, location.replace('\\', "\\\\"), site.why)?;
+                writeln!(writer, "end")?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for GdbReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.prev_addr = entry.arc.0;
+                self.prev_ts = entry.timestamp.unwrap();
+            }
+            Event::InferrableJump | Event::UninferableJump | Event::TakenBranch | Event::NonTakenBranch => {
+                let (from, to, ts) = (entry.arc.0, entry.arc.1, entry.timestamp.unwrap());
+                self.blocks.entry((self.prev_addr, from)).or_default().push(ts.saturating_sub(self.prev_ts));
+                self.prev_addr = to;
+                self.prev_ts = ts;
+                if entry.event == Event::UninferableJump {
+                    self.check_target(from, to);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("{} blocks, {} control flow violations", self.blocks.len(), self.violations.len());
+        self.write_script()?;
+        Ok(())
+    }
+}
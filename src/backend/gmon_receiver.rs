@@ -0,0 +1,202 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::timing_model::{CycleSpreader, TimingModel};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use object::Object;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use log::debug;
+
+// gmon.out layout, as gprof reads it
+const GMON_MAGIC: &[u8; 4] = b"gmon";
+const GMON_VERSION: u32 = 1;
+const GMON_TAG_TIME_HIST: u8 = 0;
+const GMON_TAG_CG_ARC: u8 = 1;
+// bytes of text per histogram bin, the size of a compressed instruction
+const BIN_BYTES: u64 = 2;
+// a bin holds a u16, gprof adds up the records covering the same range
+const BIN_MAX: u64 = u16::MAX as u64;
+// a stretch of text this long where nothing ran ends a histogram record,
+// so that code far apart, user and kernel or modules, gets a record each
+// rather than bins for the whole address space between them
+const HIST_GAP: u64 = 1 << 16;
+
+/// Writes `gmon.out` for gprof: a histogram of the cycles spent at every
+/// pc, the cycles between two timed events spread over the instructions
+/// retired in between by the timing model, and a call graph arc with its count for
+/// every call site and callee the unwinder sees. gprof shows the samples in
+/// cycles, one per sample.
+pub struct GmonReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    is_64: bool,
+    little_endian: bool,
+    last_ts: Option<u64>,
+    // the instructions retired since the last timed event
+    pending: CycleSpreader,
+    cycles: HashMap<u64, u64>,
+    // (call site, callee entry) to calls
+    arcs: HashMap<(u64, u64), u64>,
+}
+
+impl GmonReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, model: Arc<dyn TimingModel>) -> Result<Self> {
+        debug!("GmonReceiver::new");
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        Ok(GmonReceiver {
+            receiver: BusReceiver { name: "gmon".into(), bus_rx, checksum: 0 },
+            is_64: elf.is_64(),
+            little_endian: elf.is_little_endian(),
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            last_ts: None,
+            pending: CycleSpreader::new(model),
+            cycles: HashMap::new(),
+            arcs: HashMap::new(),
+        })
+    }
+
+    // spread the cycles since the last timed event over the instructions
+    // retired since
+    fn account(&mut self, ts: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        for (pc, share) in self.pending.spread(elapsed) {
+            *self.cycles.entry(pc).or_default() += share;
+        }
+    }
+
+    fn put_u32(&self, buf: &mut Vec<u8>, value: u32) {
+        buf.extend(if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() });
+    }
+
+    fn put_addr(&self, buf: &mut Vec<u8>, addr: u64) {
+        match (self.is_64, self.little_endian) {
+            (true, true) => buf.extend(addr.to_le_bytes()),
+            (true, false) => buf.extend(addr.to_be_bytes()),
+            (false, true) => buf.extend((addr as u32).to_le_bytes()),
+            (false, false) => buf.extend((addr as u32).to_be_bytes()),
+        }
+    }
+
+    // the histogram records of the pcs of one stretch of text, sorted
+    fn write_histogram(&self, buf: &mut Vec<u8>, pcs: &[(u64, u64)]) -> Result<()> {
+        let low = pcs[0].0 / BIN_BYTES * BIN_BYTES;
+        let high = pcs[pcs.len() - 1].0;
+        let bins = u32::try_from((high - low) / BIN_BYTES + 1)
+            .map_err(|_| anyhow!("gmon: {:#x}..{:#x} is too much text for one histogram", low, high))?;
+        let mut counts = vec![0u64; bins as usize];
+        for &(pc, cycles) in pcs {
+            counts[((pc - low) / BIN_BYTES) as usize] += cycles;
+        }
+        // as many records as the fullest bin needs
+        let records = counts.iter().max().unwrap().div_ceil(BIN_MAX).max(1);
+        for _ in 0..records {
+            buf.push(GMON_TAG_TIME_HIST);
+            self.put_addr(buf, low);
+            self.put_addr(buf, low + bins as u64 * BIN_BYTES);
+            self.put_u32(buf, bins);
+            // one sample per cycle
+            self.put_u32(buf, 1);
+            let mut dimen = [0u8; 15];
+            dimen[..6].copy_from_slice(b"cycles");
+            buf.extend(dimen);
+            buf.push(b'c');
+            for count in counts.iter_mut() {
+                let part = (*count).min(BIN_MAX);
+                *count -= part;
+                buf.extend(if self.little_endian { (part as u16).to_le_bytes() } else { (part as u16).to_be_bytes() });
+            }
+        }
+        Ok(())
+    }
+
+    fn write_gmon(&self) -> Result<()> {
+        let mut buf = Vec::new();
+        buf.extend(GMON_MAGIC);
+        self.put_u32(&mut buf, GMON_VERSION);
+        buf.extend([0u8; 12]);
+
+        let mut pcs: Vec<(u64, u64)> = self.cycles.iter().map(|(&pc, &cycles)| (pc, cycles)).collect();
+        pcs.sort();
+        let mut start = 0;
+        for end in 1..=pcs.len() {
+            if end == pcs.len() || pcs[end].0 - pcs[end - 1].0 > HIST_GAP {
+                self.write_histogram(&mut buf, &pcs[start..end])?;
+                start = end;
+            }
+        }
+
+        let mut arcs: Vec<_> = self.arcs.iter().collect();
+        arcs.sort();
+        for (&(from, to), &count) in arcs {
+            buf.push(GMON_TAG_CG_ARC);
+            self.put_addr(&mut buf, from);
+            self.put_addr(&mut buf, to);
+            self.put_u32(&mut buf, count.min(u32::MAX as u64) as u32);
+        }
+        let mut writer = BufWriter::new(File::create("gmon.out")?);
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for GmonReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => self.pending.retire(entry.arc.0, entry.insn.as_ref()),
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap());
+                let (is_jump, from, to) = (entry.event == Event::InferrableJump, entry.arc.0, entry.arc.1);
+                if let (true, _, Some(_)) = self.unwinder.step_ij(entry) {
+                    if is_jump {
+                        *self.arcs.entry((from, to)).or_default() += 1;
+                    }
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.account(entry.timestamp.unwrap());
+                let (is_jump, from, to) = (entry.event == Event::UninferableJump, entry.arc.0, entry.arc.1);
+                if let (true, _, _, Some(_)) = self.unwinder.step_uj(entry) {
+                    if is_jump {
+                        *self.arcs.entry((from, to)).or_default() += 1;
+                    }
+                }
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.account(entry.timestamp.unwrap());
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("{} pcs with cycles, {} call graph arcs", self.cycles.len(), self.arcs.len());
+        self.write_gmon()?;
+        Ok(())
+    }
+}
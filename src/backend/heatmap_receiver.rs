@@ -0,0 +1,150 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::timing_model::{CycleSpreader, TimingModel};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use serde_json::json;
+use log::debug;
+
+#[derive(Default)]
+struct LineHeat {
+    // the executions of its most executed instruction
+    hits: u64,
+    cycles: u64,
+}
+
+/// A source heatmap for editors: per file and line, how often it ran and
+/// the cycles spent on it. Writes `trace.heatmap.json`, the files by cycles
+/// with their lines by number, each with its hits, cycles and share of the
+/// total, and `trace.heatmap.lcov`, the line hits as an lcov tracefile for
+/// coverage gutter extensions. A line's hits are those of its most executed
+/// instruction; the cycles between two timed events are spread over the
+/// instructions retired in between by the timing model, evenly unless one
+/// is given.
+pub struct HeatmapReceiver {
+    receiver: BusReceiver,
+    loader: Loader,
+    // per pc, (executions, cycles)
+    pcs: HashMap<u64, (u64, u64)>,
+    last_ts: Option<u64>,
+    // the instructions retired since the last timed event
+    pending: CycleSpreader,
+}
+
+impl HeatmapReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, model: Arc<dyn TimingModel>) -> Result<Self> {
+        debug!("HeatmapReceiver::new");
+        Ok(HeatmapReceiver {
+            receiver: BusReceiver { name: "heatmap".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            pcs: HashMap::new(),
+            last_ts: None,
+            pending: CycleSpreader::new(model),
+        })
+    }
+
+    fn account(&mut self, ts: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        for (pc, share) in self.pending.spread(elapsed) {
+            self.pcs.entry(pc).or_default().1 += share;
+        }
+    }
+
+    // file to line to its heat
+    fn lines(&self) -> BTreeMap<String, BTreeMap<u32, LineHeat>> {
+        let mut files: BTreeMap<String, BTreeMap<u32, LineHeat>> = BTreeMap::new();
+        for (&pc, &(count, cycles)) in &self.pcs {
+            let Ok(Some(location)) = self.loader.find_location(pc) else { continue };
+            let (Some(file), Some(line)) = (location.file, location.line) else { continue };
+            let heat = files.entry(file.to_string()).or_default().entry(line).or_default();
+            heat.hits = heat.hits.max(count);
+            heat.cycles += cycles;
+        }
+        files
+    }
+
+    fn write_heatmap(&self) -> std::io::Result<()> {
+        let files = self.lines();
+        let total: u64 = files.values().flat_map(|lines| lines.values()).map(|heat| heat.cycles).sum();
+        let mut by_cycles: Vec<(&String, u64)> = files.iter()
+            .map(|(file, lines)| (file, lines.values().map(|heat| heat.cycles).sum()))
+            .collect();
+        by_cycles.sort_by_key(|&(file, cycles)| (std::cmp::Reverse(cycles), file));
+
+        let share = |cycles: u64| cycles as f64 / total.max(1) as f64 * 100.0;
+        let files_json: Vec<_> = by_cycles.iter().map(|&(file, cycles)| {
+            let lines: Vec<_> = files[file].iter().map(|(line, heat)| json!({
+                "line": line,
+                "hits": heat.hits,
+                "cycles": heat.cycles,
+                "share": share(heat.cycles),
+            })).collect();
+            json!({ "path": file, "cycles": cycles, "share": share(cycles), "lines": lines })
+        }).collect();
+        let heatmap = json!({
+            "total_cycles": total,
+            "max_hits": files.values().flat_map(|lines| lines.values()).map(|heat| heat.hits).max().unwrap_or(0),
+            "max_cycles": files.values().flat_map(|lines| lines.values()).map(|heat| heat.cycles).max().unwrap_or(0),
+            "files": files_json,
+        });
+        let mut writer = BufWriter::new(File::create("trace.heatmap.json")?);
+        serde_json::to_writer_pretty(&mut writer, &heatmap)?;
+        writeln!(writer)?;
+        writer.flush()?;
+
+        let mut writer = BufWriter::new(File::create("trace.heatmap.lcov")?);
+        for (file, lines) in &files {
+            writeln!(writer, "SF:{}", file)?;
+            for (line, heat) in lines {
+                writeln!(writer, "DA:{},{}", line, heat.hits)?;
+            }
+            writeln!(writer, "LF:{}", lines.len())?;
+            writeln!(writer, "LH:{}", lines.values().filter(|heat| heat.hits > 0).count())?;
+            writeln!(writer, "end_of_record")?;
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for HeatmapReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                self.pcs.entry(entry.arc.0).or_default().0 += 1;
+                self.pending.retire(entry.arc.0, entry.insn.as_ref());
+            }
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::InferrableJump | Event::UninferableJump
+            | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn | Event::End => {
+                self.account(entry.timestamp.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_heatmap()?;
+        Ok(())
+    }
+}
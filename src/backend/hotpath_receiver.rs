@@ -0,0 +1,141 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+/// The `k` most frequent complete call paths, root to leaf, by the cycles
+/// spent with exactly that stack, and how often the leaf was called from
+/// there: the calling context tree cut down to fit in a review comment.
+/// Writes `trace.hotpaths.txt` and `trace.hotpaths.json`.
+pub struct HotPathReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    k: usize,
+    last_ts: Option<u64>,
+    // the stack, as symbol indices, to (calls, cycles)
+    paths: IndexMap<Vec<u32>, (u64, u64)>,
+    // the path of the current stack
+    current: usize,
+}
+
+impl HotPathReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, k: usize) -> Result<Self> {
+        debug!("HotPathReceiver::new");
+        let mut paths = IndexMap::new();
+        paths.insert(Vec::new(), (0, 0));
+        Ok(HotPathReceiver {
+            receiver: BusReceiver { name: "hotpath".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            k,
+            last_ts: None,
+            paths,
+            current: 0,
+        })
+    }
+
+    // the cycles since the previous timed event ran with the current stack
+    fn account(&mut self, ts: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        self.paths[self.current].1 += elapsed;
+    }
+
+    fn update_path(&mut self, called: bool) {
+        let entry = self.paths.entry(self.unwinder.frame_stack.clone());
+        self.current = entry.index();
+        if called {
+            entry.or_default().0 += 1;
+        } else {
+            entry.or_default();
+        }
+    }
+
+    // the k hottest paths, ties by first execution
+    fn hottest(&self) -> Vec<(&Vec<u32>, &(u64, u64))> {
+        let mut hot: Vec<_> = self.paths.iter().filter(|(path, _)| !path.is_empty()).collect();
+        hot.sort_by_key(|&(_, &(_, cycles))| std::cmp::Reverse(cycles));
+        hot.truncate(self.k);
+        hot
+    }
+
+    fn names(&self, path: &[u32]) -> Vec<String> {
+        path.iter().map(|&index| self.unwinder.symbols()[index as usize].name.clone()).collect()
+    }
+
+    fn write_report(&self) -> std::io::Result<()> {
+        let total: u64 = self.paths.values().map(|&(_, cycles)| cycles).sum();
+        let hot = self.hottest();
+        let mut writer = BufWriter::new(File::create("trace.hotpaths.txt")?);
+        writeln!(writer, "top {} call paths by cycles, of {} cycles and {} paths:", hot.len(), total, self.paths.len() - 1)?;
+        writeln!(writer, "{:>4} {:>14} {:>7} {:>10}  path", "rank", "cycles", "%", "calls")?;
+        for (rank, (path, &(calls, cycles))) in hot.iter().enumerate() {
+            writeln!(writer, "{:>4} {:>14} {:>6.2}% {:>10}  {}", rank + 1, cycles, cycles as f64 / total.max(1) as f64 * 100.0,
+                     calls, self.names(path).join(" > "))?;
+        }
+        writer.flush()?;
+
+        let paths: Vec<_> = hot.iter().map(|(path, &(calls, cycles))| json!({
+            "path": self.names(path),
+            "calls": calls,
+            "cycles": cycles,
+        })).collect();
+        let mut writer = BufWriter::new(File::create("trace.hotpaths.json")?);
+        serde_json::to_writer(&mut writer, &json!({"cycles": total, "paths": paths}))?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for HotPathReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap());
+                if let (true, _, opened) = self.unwinder.step_ij(entry) {
+                    self.update_path(opened.is_some());
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.account(entry.timestamp.unwrap());
+                if let (true, _, _, opened) = self.unwinder.step_uj(entry) {
+                    self.update_path(opened.is_some());
+                }
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.account(entry.timestamp.unwrap());
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+                self.update_path(false);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("{} call paths", self.paths.len() - 1);
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
+use crate::frontend::context::Context;
+use crate::frontend::module::read_module_maps;
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use log::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Function,
+    Module,
+    SelfTime,
+    Inclusive,
+    Count,
+    Source,
+    Address,
+}
+
+impl Field {
+    fn key(&self) -> &'static str {
+        match self {
+            Field::Function => "function",
+            Field::Module => "module",
+            Field::SelfTime => "self",
+            Field::Inclusive => "inclusive",
+            Field::Count => "count",
+            Field::Source => "source",
+            Field::Address => "address",
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [Field::Function, Field::Module, Field::SelfTime, Field::Inclusive, Field::Count, Field::Source, Field::Address]
+            .into_iter()
+            .find(|field| field.key() == s)
+            .ok_or_else(|| anyhow!("unknown hotspots field `{}`, expected function, module, self, inclusive, count, source or address", s))
+    }
+}
+
+/// The columns of `trace.hotspots.csv`, so that it imports as is into the
+/// tool at hand. A schema file has one `<key> = <value>` line per setting,
+/// `#` starting a comment: every field named (function, module, self,
+/// inclusive, count, source, address) becomes a column with the value as
+/// its header, in the order of the lines; `delimiter` is the separator (a
+/// character or `tab`) and `frequency` the clock in Hz, which turns the
+/// times from cycles into seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotspotSchema {
+    pub columns: Vec<(Field, String)>,
+    pub delimiter: char,
+    pub frequency: Option<f64>,
+}
+
+impl Default for HotspotSchema {
+    fn default() -> Self {
+        let fields = [Field::Function, Field::Module, Field::SelfTime, Field::Inclusive, Field::Count, Field::Source];
+        HotspotSchema {
+            columns: fields.iter().map(|field| (*field, field.key().to_string())).collect(),
+            delimiter: ',',
+            frequency: None,
+        }
+    }
+}
+
+impl FromStr for HotspotSchema {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut schema = HotspotSchema { columns: Vec::new(), ..HotspotSchema::default() };
+        for (lineno, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .filter(|(_, value)| !value.is_empty())
+                .ok_or_else(|| anyhow!("line {}: expected `<key> = <value>`, got `{}`", lineno + 1, line))?;
+            match key {
+                "delimiter" => {
+                    schema.delimiter = match value {
+                        "tab" => '\t',
+                        _ if value.chars().count() == 1 => value.chars().next().unwrap(),
+                        _ => return Err(anyhow!("line {}: the delimiter `{}` is not one character or `tab`", lineno + 1, value)),
+                    };
+                }
+                "frequency" => {
+                    let frequency: f64 = value.parse().ok().filter(|hz: &f64| *hz > 0.0)
+                        .ok_or_else(|| anyhow!("line {}: the frequency `{}` is not a clock in Hz", lineno + 1, value))?;
+                    schema.frequency = Some(frequency);
+                }
+                _ => {
+                    let field: Field = key.parse()?;
+                    if schema.columns.iter().any(|(column, _)| *column == field) {
+                        return Err(anyhow!("line {}: field `{}` given twice", lineno + 1, key));
+                    }
+                    schema.columns.push((field, value.to_string()));
+                }
+            }
+        }
+        if schema.columns.is_empty() {
+            schema.columns = HotspotSchema::default().columns;
+        }
+        Ok(schema)
+    }
+}
+
+impl HotspotSchema {
+    pub fn from_file(path: &str) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse().map_err(|e: anyhow::Error| anyhow!("{}: {}", path, e))
+    }
+
+    // quoted when it has the delimiter or a quote in it
+    fn field(&self, s: &str) -> String {
+        if s.contains(self.delimiter) || s.contains('"') {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    }
+
+    fn time(&self, cycles: u64) -> String {
+        match self.frequency {
+            Some(hz) => format!("{}", cycles as f64 / hz),
+            None => cycles.to_string(),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct FuncStats {
+    calls: u64,
+    inclusive: u64,
+    self_time: u64,
+}
+
+// a frame that has been entered but not left yet
+struct OpenFrame {
+    index: u32,
+    start: u64,
+    child_time: u64,
+}
+
+/// Writes `trace.hotspots.csv`, one row per function called with its
+/// module, self and inclusive time, call count and source, hottest first,
+/// in the columns of a `HotspotSchema`. The module is the binary, or the
+/// kernel module the function was loaded with; the inclusive time of a
+/// recursive function counts its outermost frames only.
+pub struct HotspotsReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    schema: HotspotSchema,
+    binary: String,
+    // (start, end, module) of the loaded kernel module sections
+    module_ranges: Vec<(u64, u64, String)>,
+    func_stats: HashMap<u32, FuncStats>,
+    open_frames: Vec<OpenFrame>,
+    ctx: Context,
+    parked_frames: HashMap<Context, Vec<OpenFrame>>,
+    end: u64,
+}
+
+impl HotspotsReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, modules: &str, schema: HotspotSchema) -> Result<Self> {
+        debug!("HotspotsReceiver::new");
+        let mut module_ranges = Vec::new();
+        for module in read_module_maps(modules)? {
+            let (start, end) = module.range()?;
+            module_ranges.push((start, end, module.module));
+        }
+        Ok(HotspotsReceiver {
+            receiver: BusReceiver { name: "hotspots".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path.clone(), options)?,
+            schema,
+            binary: Path::new(&elf_path).file_name().map_or(elf_path.clone(), |name| name.to_string_lossy().into_owned()),
+            module_ranges,
+            func_stats: HashMap::new(),
+            open_frames: Vec::new(),
+            ctx: Context::default(),
+            parked_frames: HashMap::new(),
+            end: 0,
+        })
+    }
+
+    fn enter(&mut self, index: u32, ts: u64) {
+        self.func_stats.entry(index).or_default().calls += 1;
+        self.open_frames.push(OpenFrame { index, start: ts, child_time: 0 });
+    }
+
+    fn leave(&mut self, ts: u64) {
+        if let Some(frame) = self.open_frames.pop() {
+            let duration = ts.saturating_sub(frame.start);
+            let recursive = self.open_frames.iter().any(|open| open.index == frame.index);
+            let stats = self.func_stats.entry(frame.index).or_default();
+            if !recursive {
+                stats.inclusive += duration;
+            }
+            stats.self_time += duration.saturating_sub(frame.child_time);
+            if let Some(parent) = self.open_frames.last_mut() {
+                parent.child_time += duration;
+            }
+        }
+    }
+
+    fn switch_context(&mut self, ctx: Context) {
+        if ctx == self.ctx {
+            return;
+        }
+        self.unwinder.switch_context(ctx);
+        let parked = std::mem::take(&mut self.open_frames);
+        self.parked_frames.insert(self.ctx, parked);
+        self.open_frames = self.parked_frames.remove(&ctx).unwrap_or_default();
+        self.ctx = ctx;
+    }
+
+    fn module(&self, info: &SymbolInfo, addr: u64) -> String {
+        if let Some((_, _, module)) = self.module_ranges.iter().find(|(start, end, _)| *start <= addr && addr < *end) {
+            return module.clone();
+        }
+        if info.is_dynamic() { info.file.clone() } else { self.binary.clone() }
+    }
+
+    fn write_csv(&self) -> std::io::Result<()> {
+        let symbols: HashMap<u32, &SymbolInfo> = self.unwinder.symbols().iter().map(|info| (info.index, info)).collect();
+        let mut functions: Vec<_> = self.func_stats.iter().filter(|(index, _)| symbols.contains_key(index)).collect();
+        functions.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then(a.0.cmp(b.0)));
+        let delimiter = self.schema.delimiter.to_string();
+        let mut writer = BufWriter::new(File::create("trace.hotspots.csv")?);
+        let header: Vec<String> = self.schema.columns.iter().map(|(_, name)| self.schema.field(name)).collect();
+        writeln!(writer, "{}", header.join(&delimiter))?;
+        for (index, stats) in functions {
+            let info = symbols[index];
+            let addr = self.unwinder.idx_2_addr_range[index].0;
+            let row: Vec<String> = self.schema.columns.iter().map(|(field, _)| match field {
+                Field::Function => self.schema.field(&info.name),
+                Field::Module => self.schema.field(&self.module(info, addr)),
+                Field::SelfTime => self.schema.time(stats.self_time),
+                Field::Inclusive => self.schema.time(stats.inclusive),
+                Field::Count => stats.calls.to_string(),
+                Field::Source if info.file.is_empty() || info.is_dynamic() => String::new(),
+                Field::Source => self.schema.field(&format!("{}:{}", info.file, info.line)),
+                Field::Address => format!("{:#x}", addr),
+            }).collect();
+            writeln!(writer, "{}", row.join(&delimiter))?;
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for HotspotsReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start | Event::End => {
+                self.end = self.end.max(entry.timestamp.unwrap());
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let ts = entry.timestamp.unwrap();
+                self.end = self.end.max(ts);
+                if let (true, _, Some(opened)) = self.unwinder.step_ij(entry) {
+                    self.enter(opened.index, ts);
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let ts = entry.timestamp.unwrap();
+                self.end = self.end.max(ts);
+                let (success, _, closed, opened) = self.unwinder.step_uj(entry);
+                if success {
+                    for _ in closed {
+                        self.leave(ts);
+                    }
+                }
+                if let Some(info) = opened {
+                    self.enter(info.index, ts);
+                }
+            }
+            Event::ContextSwitch => {
+                self.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        // close whatever is still open, in every context
+        let mut parked: Vec<Context> = self.parked_frames.keys().cloned().collect();
+        parked.sort_by_key(|ctx| (ctx.asid, ctx.hart));
+        for ctx in parked.into_iter().chain(std::iter::once(self.ctx)) {
+            self.switch_context(ctx);
+            while !self.open_frames.is_empty() {
+                self.leave(self.end);
+            }
+        }
+        self.write_csv()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
+use crate::frontend::context::Context;
+use crate::diagnostics::{self, Category};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use serde_json::json;
+use log::debug;
+
+const HTML_TEMPLATE: &str = include_str!("html_report.html");
+// keep the report openable in a browser for very long traces
+const MAX_FLAME_INTERVALS: usize = 200_000;
+
+#[derive(Default, Clone)]
+struct FuncStats {
+    calls: u64,
+    inclusive: u64,
+    self_time: u64,
+}
+
+// a frame that has been entered but not left yet
+struct OpenFrame {
+    index: u32,
+    start: u64,
+    child_time: u64,
+}
+
+/// Writes `trace.report.html`, a single self-contained page with a function
+/// table, a flame chart and a trap timeline.
+pub struct HtmlReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    symbols: HashMap<u32, SymbolInfo>,
+    func_stats: HashMap<u32, FuncStats>,
+    open_frames: Vec<OpenFrame>,
+    ctx: Context,
+    parked_frames: HashMap<Context, Vec<OpenFrame>>,
+    // (function index, depth, start, end)
+    flame: Vec<(u32, usize, u64, u64)>,
+    dropped_intervals: u64,
+    // (timestamp, event, from, to)
+    traps: Vec<(u64, String, u64, u64)>,
+    start: u64,
+    end: u64,
+}
+
+impl HtmlReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        debug!("HtmlReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let symbols = unwinder.symbols().iter().map(|info| (info.index, info.clone())).collect();
+        Ok(HtmlReceiver {
+            writer: BufWriter::new(File::create("trace.report.html")?),
+            receiver: BusReceiver { name: "html".into(), bus_rx, checksum: 0 },
+            unwinder,
+            symbols,
+            func_stats: HashMap::new(),
+            open_frames: Vec::new(),
+            ctx: Context::default(),
+            parked_frames: HashMap::new(),
+            flame: Vec::new(),
+            dropped_intervals: 0,
+            traps: Vec::new(),
+            start: 0,
+            end: 0,
+        })
+    }
+
+    fn enter(&mut self, index: u32, ts: u64) {
+        self.func_stats.entry(index).or_default().calls += 1;
+        self.open_frames.push(OpenFrame { index, start: ts, child_time: 0 });
+    }
+
+    fn leave(&mut self, ts: u64) {
+        if let Some(frame) = self.open_frames.pop() {
+            let duration = ts.saturating_sub(frame.start);
+            let stats = self.func_stats.entry(frame.index).or_default();
+            stats.inclusive += duration;
+            stats.self_time += duration.saturating_sub(frame.child_time);
+            if let Some(parent) = self.open_frames.last_mut() {
+                parent.child_time += duration;
+            }
+            if self.flame.len() < MAX_FLAME_INTERVALS {
+                self.flame.push((frame.index, self.open_frames.len(), frame.start, ts));
+            } else {
+                self.dropped_intervals += 1;
+            }
+        }
+    }
+
+    fn switch_context(&mut self, ctx: Context) {
+        if ctx == self.ctx {
+            return;
+        }
+        self.unwinder.switch_context(ctx);
+        let parked = std::mem::take(&mut self.open_frames);
+        self.parked_frames.insert(self.ctx, parked);
+        self.open_frames = self.parked_frames.remove(&ctx).unwrap_or_default();
+        self.ctx = ctx;
+    }
+
+    fn report_data(&self) -> serde_json::Value {
+        let mut functions: Vec<_> = self.func_stats.iter().collect();
+        functions.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then(a.0.cmp(b.0)));
+        let functions: Vec<_> = functions.iter().map(|(index, stats)| {
+            let info = &self.symbols[index];
+            json!({
+                "index": index,
+                "name": info.name,
+                "file": info.file,
+                "line": info.line,
+                "calls": stats.calls,
+                "inclusive": stats.inclusive,
+                "self": stats.self_time,
+            })
+        }).collect();
+        let names: HashMap<String, &str> = self.symbols.iter().map(|(index, info)| (index.to_string(), info.name.as_str())).collect();
+        json!({
+            "start": self.start,
+            "end": self.end,
+            "functions": functions,
+            "names": names,
+            "flame": self.flame,
+            "dropped_intervals": self.dropped_intervals,
+            "traps": self.traps,
+        })
+    }
+}
+
+impl AbstractReceiver for HtmlReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.start = entry.timestamp.unwrap();
+                self.end = self.start;
+            }
+            Event::End => {
+                self.end = entry.timestamp.unwrap();
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let ts = entry.timestamp.unwrap();
+                self.end = self.end.max(ts);
+                if entry.event != Event::InferrableJump {
+                    self.traps.push((ts, entry.event.to_string(), entry.arc.0, entry.arc.1));
+                }
+                let (success, _, opened) = self.unwinder.step_ij(entry.clone());
+                if success {
+                    self.enter(opened.unwrap().index, ts);
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let ts = entry.timestamp.unwrap();
+                self.end = self.end.max(ts);
+                if entry.event == Event::TrapReturn {
+                    self.traps.push((ts, entry.event.to_string(), entry.arc.0, entry.arc.1));
+                }
+                let (success, _, closed, opened) = self.unwinder.step_uj(entry.clone());
+                if success {
+                    for _ in closed {
+                        self.leave(ts);
+                    }
+                }
+                if let Some(info) = opened {
+                    self.enter(info.index, ts);
+                }
+            }
+            Event::ContextSwitch => {
+                self.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        // close whatever is still open, in every context
+        let mut parked: Vec<Context> = self.parked_frames.keys().cloned().collect();
+        parked.sort_by_key(|ctx| (ctx.asid, ctx.hart));
+        for ctx in parked.into_iter().chain(std::iter::once(self.ctx)) {
+            self.switch_context(ctx);
+            while !self.open_frames.is_empty() {
+                self.leave(self.end);
+            }
+        }
+        if self.dropped_intervals > 0 {
+            diagnostics::report(Category::IncompleteOutput,
+                                format!("html report: dropped {} flame chart intervals", self.dropped_intervals));
+        }
+
+        // `</` would end the embedding script tag early
+        let data = self.report_data().to_string().replace("</", "<\\/");
+        let html = HTML_TEMPLATE.replace("/*TACIT_DATA*/null", &data);
+        self.writer.write_all(html.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+/// Instructions per cycle over fixed windows of `window` cycles, and the
+/// average cycles per instruction of every function. Only the timed events
+/// carry a timestamp, so the cycles between two of them are spread evenly
+/// over the instructions retired in between. Writes `trace.ipc.csv`, the
+/// time series, and `trace.ipc.json`, with the functions as well.
+pub struct IpcReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    window: u64,
+    last_ts: Option<u64>,
+    // function entry address to the instructions retired since the last
+    // timed event, None outside of any function
+    pending: HashMap<Option<u64>, u64>,
+    // the function range of the last instruction, most are in the same one
+    last_range: Option<(u64, u64)>,
+    // window index to (instructions, cycles)
+    windows: BTreeMap<u64, (f64, u64)>,
+    // function entry address to (instructions, cycles)
+    functions: HashMap<Option<u64>, (u64, f64)>,
+}
+
+impl IpcReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, window: u64) -> Result<Self> {
+        debug!("IpcReceiver::new");
+        Ok(IpcReceiver {
+            receiver: BusReceiver { name: "ipc".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            window: window.max(1),
+            last_ts: None,
+            pending: HashMap::new(),
+            last_range: None,
+            windows: BTreeMap::new(),
+            functions: HashMap::new(),
+        })
+    }
+
+    fn retire(&mut self, pc: u64) {
+        let range = match self.last_range {
+            Some((start, end)) if start <= pc && pc < end => self.last_range,
+            _ => self.unwinder.function_range_at(pc),
+        };
+        self.last_range = range;
+        *self.pending.entry(range.map(|(start, _)| start)).or_default() += 1;
+    }
+
+    // the instructions retired since the previous timed event took the
+    // cycles up to ts
+    fn account(&mut self, ts: u64) {
+        let from = self.last_ts.unwrap_or(ts).min(ts);
+        self.last_ts = Some(ts);
+        let insns: u64 = self.pending.values().sum();
+        let cycles = ts - from;
+        for (function, count) in self.pending.drain() {
+            let (function_insns, function_cycles) = self.functions.entry(function).or_default();
+            *function_insns += count;
+            *function_cycles += cycles as f64 * count as f64 / insns as f64;
+        }
+        if cycles == 0 {
+            // retired in the same cycle, or before the first timestamp
+            if insns > 0 {
+                self.windows.entry(ts / self.window).or_default().0 += insns as f64;
+            }
+            return;
+        }
+        // the instructions go to the windows by their share of the cycles
+        let mut start = from;
+        while start < ts {
+            let index = start / self.window;
+            let end = ts.min((index + 1) * self.window);
+            let window = self.windows.entry(index).or_default();
+            window.0 += insns as f64 * (end - start) as f64 / cycles as f64;
+            window.1 += end - start;
+            start = end;
+        }
+    }
+
+    fn function_name(&self, function: Option<u64>) -> String {
+        match function {
+            Some(start) => self.unwinder.func_symbol_map().get(&start).map(|info| info.name.clone()).unwrap_or_else(|| format!("{:#x}", start)),
+            None => "?".to_string(),
+        }
+    }
+
+    fn write_csv(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.ipc.csv")?);
+        writeln!(writer, "start,end,instructions,cycles,ipc")?;
+        for (&index, &(insns, cycles)) in self.windows.iter() {
+            writeln!(writer, "{},{},{:.0},{},{:.4}", index * self.window, (index + 1) * self.window, insns, cycles,
+                     insns / cycles.max(1) as f64)?;
+        }
+        writer.flush()
+    }
+
+    fn write_json(&self) -> std::io::Result<()> {
+        let windows: Vec<_> = self.windows.iter().map(|(&index, &(insns, cycles))| json!({
+            "start": index * self.window,
+            "instructions": insns.round() as u64,
+            "cycles": cycles,
+            "ipc": insns / cycles.max(1) as f64,
+        })).collect();
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.1.total_cmp(&a.1.1).then(a.0.cmp(b.0)));
+        let functions: Vec<_> = functions.into_iter().map(|(&function, &(insns, cycles))| json!({
+            "function": self.function_name(function),
+            "address": function,
+            "instructions": insns,
+            "cycles": cycles.round() as u64,
+            "cpi": cycles / insns.max(1) as f64,
+        })).collect();
+        let mut writer = BufWriter::new(File::create("trace.ipc.json")?);
+        serde_json::to_writer(&mut writer, &json!({"window": self.window, "windows": windows, "functions": functions}))?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for IpcReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                self.retire(entry.arc.0);
+            }
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::Resync => {
+                // what ran in between is unknown, its cycles are not counted
+                self.pending.clear();
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::UninferableJump | Event::TakenBranch | Event::NonTakenBranch
+            | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn | Event::End => {
+                if let Some(ts) = entry.timestamp {
+                    self.account(ts);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("ipc: {} windows, {} functions", self.windows.len(), self.functions.len());
+        self.write_csv()?;
+        self.write_json()?;
+        Ok(())
+    }
+}
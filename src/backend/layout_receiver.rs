@@ -0,0 +1,244 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// a cluster grows up to a page, so the functions calling each other most
+// share it
+const CLUSTER_BYTES: u64 = 4096;
+// cache line of the conflict model
+const LINE_BYTES: u64 = 64;
+// functions are laid out at this alignment in the suggested order
+const FUNCTION_ALIGN: u64 = 4;
+
+// the cache line indices [first, last] a function covers in a direct
+// mapped cache of `lines` lines, split in two when they wrap around
+fn cache_sets(start: u64, end: u64, lines: u64) -> Vec<(u64, u64)> {
+    let first = start / LINE_BYTES;
+    let last = end.saturating_sub(1).max(start) / LINE_BYTES;
+    if last - first + 1 >= lines {
+        return vec![(0, lines - 1)];
+    }
+    let (first, last) = (first % lines, last % lines);
+    if first <= last { vec![(first, last)] } else { vec![(first, lines - 1), (0, last)] }
+}
+
+fn overlap(a: &[(u64, u64)], b: &[(u64, u64)]) -> bool {
+    a.iter().any(|&(a0, a1)| b.iter().any(|&(b0, b1)| a0 <= b1 && b0 <= a1))
+}
+
+/// A function order for I-cache locality, from the call affinity and self
+/// cycles of the trace and the function sizes in the ELF: functions in
+/// order of cycles per byte join the cluster of their heaviest caller while
+/// it fits in a page, and the clusters are placed densest first (the C3
+/// heuristic). Writes `trace.layout.txt`, one symbol per line for lld's
+/// `--symbol-ordering-file`, `trace.layout.sections.txt` with the
+/// `.text.<name>` sections of `-ffunction-sections` builds, and
+/// `trace.layout.report.txt`, the call affinity between functions that
+/// conflict in a direct mapped cache of `cache_size` bytes and the calls
+/// crossing a page, for the current layout and the suggested one.
+pub struct LayoutReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    cache_size: u64,
+    last_ts: Option<u64>,
+    // per function symbol index
+    cycles: HashMap<u32, u64>,
+    // (caller, callee) to calls
+    calls: HashMap<(u32, u32), u64>,
+}
+
+impl LayoutReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, cache_size: u64) -> Result<Self> {
+        debug!("LayoutReceiver::new");
+        Ok(LayoutReceiver {
+            receiver: BusReceiver { name: "layout".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            cache_size,
+            last_ts: None,
+            cycles: HashMap::new(),
+            calls: HashMap::new(),
+        })
+    }
+
+    // the cycles since the previous timed event ran in the function of pc
+    fn account(&mut self, ts: u64, pc: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        if let Some((start, _)) = self.unwinder.function_range_at(pc) {
+            *self.cycles.entry(self.unwinder.func_symbol_map()[&start].index).or_default() += elapsed;
+        }
+    }
+
+    fn called(&mut self, callee: u32) {
+        let stack = &self.unwinder.frame_stack;
+        if stack.len() >= 2 {
+            *self.calls.entry((stack[stack.len() - 2], callee)).or_default() += 1;
+        }
+    }
+
+    fn size(&self, index: u32) -> u64 {
+        let (start, end) = self.unwinder.idx_2_addr_range[&index];
+        end.saturating_sub(start).max(1)
+    }
+
+    // the functions seen, clustered and ordered
+    fn order(&self) -> Vec<u32> {
+        let mut functions: Vec<u32> = self.cycles.keys().copied()
+            .chain(self.calls.keys().flat_map(|&(caller, callee)| [caller, callee]))
+            .collect();
+        functions.sort_unstable();
+        functions.dedup();
+        let density = |index: &u32| self.cycles.get(index).copied().unwrap_or(0) as f64 / self.size(*index) as f64;
+        // the heaviest caller of each function
+        let mut callers: HashMap<u32, (u32, u64)> = HashMap::new();
+        for (&(caller, callee), &count) in &self.calls {
+            if caller == callee {
+                continue;
+            }
+            let best = callers.entry(callee).or_insert((caller, 0));
+            if count > best.1 || (count == best.1 && caller < best.0) {
+                *best = (caller, count);
+            }
+        }
+
+        let mut cluster_of: HashMap<u32, usize> = functions.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+        let mut clusters: Vec<Vec<u32>> = functions.iter().map(|&f| vec![f]).collect();
+        let mut sizes: Vec<u64> = functions.iter().map(|&f| self.size(f)).collect();
+        let mut by_density = functions.clone();
+        by_density.sort_by(|a, b| density(b).total_cmp(&density(a)).then(a.cmp(b)));
+        for function in by_density {
+            let Some(&(caller, _)) = callers.get(&function) else { continue };
+            let (from, to) = (cluster_of[&function], cluster_of[&caller]);
+            if from == to || sizes[from] + sizes[to] > CLUSTER_BYTES {
+                continue;
+            }
+            // the callee's cluster goes right after its caller's
+            let moved = std::mem::take(&mut clusters[from]);
+            for &f in &moved {
+                cluster_of.insert(f, to);
+            }
+            clusters[to].extend(moved);
+            sizes[to] += sizes[from];
+            sizes[from] = 0;
+        }
+
+        let mut clusters: Vec<(f64, Vec<u32>)> = clusters.into_iter().zip(&sizes).filter(|(cluster, _)| !cluster.is_empty())
+            .map(|(cluster, &size)| (cluster.iter().map(|f| self.cycles.get(f).copied().unwrap_or(0)).sum::<u64>() as f64 / size as f64, cluster))
+            .collect();
+        clusters.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1[0].cmp(&b.1[0])));
+        clusters.into_iter().flat_map(|(_, cluster)| cluster).collect()
+    }
+
+    // (conflicting call affinity, calls crossing a page) with the functions
+    // at the given (start, end)
+    fn cost(&self, ranges: &HashMap<u32, (u64, u64)>) -> (u64, u64) {
+        let lines = (self.cache_size / LINE_BYTES).max(1);
+        let sets: HashMap<u32, Vec<(u64, u64)>> = ranges.iter().map(|(&f, &(start, end))| (f, cache_sets(start, end, lines))).collect();
+        let (mut conflicts, mut crossings) = (0, 0);
+        for (&(caller, callee), &count) in &self.calls {
+            if caller == callee {
+                continue;
+            }
+            if overlap(&sets[&caller], &sets[&callee]) {
+                conflicts += count;
+            }
+            if ranges[&caller].0 / CLUSTER_BYTES != ranges[&callee].0 / CLUSTER_BYTES {
+                crossings += count;
+            }
+        }
+        (conflicts, crossings)
+    }
+
+    fn write_report(&self) -> std::io::Result<()> {
+        let order = self.order();
+        let symbols = self.unwinder.symbols();
+        let mut writer = BufWriter::new(File::create("trace.layout.txt")?);
+        for &f in &order {
+            writeln!(writer, "{}", symbols[f as usize].name)?;
+        }
+        writer.flush()?;
+        let mut writer = BufWriter::new(File::create("trace.layout.sections.txt")?);
+        for &f in &order {
+            writeln!(writer, ".text.{}", symbols[f as usize].name)?;
+        }
+        writer.flush()?;
+
+        let current: HashMap<u32, (u64, u64)> = order.iter().map(|&f| (f, self.unwinder.idx_2_addr_range[&f])).collect();
+        // packed from the lowest address of the functions seen
+        let mut next = current.values().map(|&(start, _)| start).min().unwrap_or(0);
+        let mut suggested = HashMap::new();
+        for &f in &order {
+            suggested.insert(f, (next, next + self.size(f)));
+            next = (next + self.size(f)).next_multiple_of(FUNCTION_ALIGN);
+        }
+        let total: u64 = self.calls.iter().filter(|((caller, callee), _)| caller != callee).map(|(_, count)| count).sum();
+        let (conflicts_before, crossings_before) = self.cost(&current);
+        let (conflicts_after, crossings_after) = self.cost(&suggested);
+        let share = |count: u64| count as f64 / total.max(1) as f64 * 100.0;
+        let reduction = |before: u64, after: u64| if before == 0 { 0.0 } else { (before as f64 - after as f64) / before as f64 * 100.0 };
+        let mut writer = BufWriter::new(File::create("trace.layout.report.txt")?);
+        writeln!(writer, "{} functions ordered, {} calls between functions", order.len(), total)?;
+        writeln!(writer, "calls between functions conflicting in a {} byte direct mapped cache:", self.cache_size)?;
+        writeln!(writer, "  current   {:>14} ({:.2}%)", conflicts_before, share(conflicts_before))?;
+        writeln!(writer, "  suggested {:>14} ({:.2}%), {:.2}% fewer", conflicts_after, share(conflicts_after),
+                 reduction(conflicts_before, conflicts_after))?;
+        writeln!(writer, "calls crossing a {} byte page:", CLUSTER_BYTES)?;
+        writeln!(writer, "  current   {:>14} ({:.2}%)", crossings_before, share(crossings_before))?;
+        writeln!(writer, "  suggested {:>14} ({:.2}%), {:.2}% fewer", crossings_after, share(crossings_after),
+                 reduction(crossings_before, crossings_after))?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for LayoutReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+                if let (true, _, Some(opened)) = self.unwinder.step_ij(entry) {
+                    self.called(opened.index);
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+                if let (true, _, _, Some(opened)) = self.unwinder.step_uj(entry) {
+                    self.called(opened.index);
+                }
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::frontend::context::Context;
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use indexmap::IndexMap;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+/// A first-order Markov model of the basic block sequence: per block, how
+/// often each block followed it. A block runs from the target of one
+/// control transfer to the source of the next, as in the vbb receiver.
+/// Writes `trace.markov.json` (blocks, and transitions with counts and
+/// probabilities) and `trace.markov.dot`. Blocks are numbered in the order
+/// they were first executed; transitions never cross contexts.
+pub struct MarkovReceiver {
+    receiver: BusReceiver,
+    loader: Loader,
+    // (start, end) to execution count, the index is the block id
+    blocks: IndexMap<(u64, u64), u64>,
+    // (from id, to id) to count
+    transitions: IndexMap<(usize, usize), u64>,
+    ctx: Context,
+    // per context, where the running block started and the last block id
+    block_start: HashMap<Context, u64>,
+    prev_block: HashMap<Context, usize>,
+}
+
+impl MarkovReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Result<Self> {
+        debug!("MarkovReceiver::new");
+        Ok(MarkovReceiver {
+            receiver: BusReceiver { name: "markov".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            blocks: IndexMap::new(),
+            transitions: IndexMap::new(),
+            ctx: Context::default(),
+            block_start: HashMap::new(),
+            prev_block: HashMap::new(),
+        })
+    }
+
+    // the running block ends at `end`, the next one starts at `next`
+    fn end_block(&mut self, end: u64, next: u64) {
+        if let Some(start) = self.block_start.insert(self.ctx, next) {
+            let entry = self.blocks.entry((start, end));
+            let id = entry.index();
+            *entry.or_default() += 1;
+            if let Some(prev) = self.prev_block.insert(self.ctx, id) {
+                *self.transitions.entry((prev, id)).or_default() += 1;
+            }
+        }
+    }
+
+    fn label(&self, start: u64) -> String {
+        match self.loader.find_symbol(start) {
+            Some(name) => format!("{:#x} {}", start, name),
+            None => format!("{:#x}", start),
+        }
+    }
+
+    // the count of every transition out of each block, for the probabilities
+    fn out_counts(&self) -> HashMap<usize, u64> {
+        let mut out: HashMap<usize, u64> = HashMap::new();
+        for (&(from, _), count) in self.transitions.iter() {
+            *out.entry(from).or_default() += count;
+        }
+        out
+    }
+
+    fn write_json(&self, out: &HashMap<usize, u64>) -> std::io::Result<()> {
+        let blocks: Vec<_> = self.blocks.iter().enumerate().map(|(id, (&(start, end), count))| json!({
+            "id": id,
+            "start": start,
+            "end": end,
+            "count": count,
+            "function": self.loader.find_symbol(start),
+        })).collect();
+        let transitions: Vec<_> = self.transitions.iter().map(|(&(from, to), &count)| json!({
+            "from": from,
+            "to": to,
+            "count": count,
+            "probability": count as f64 / out[&from] as f64,
+        })).collect();
+        let mut writer = BufWriter::new(File::create("trace.markov.json")?);
+        serde_json::to_writer(&mut writer, &json!({"blocks": blocks, "transitions": transitions}))?;
+        writer.flush()
+    }
+
+    fn write_dot(&self, out: &HashMap<usize, u64>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.markov.dot")?);
+        writeln!(writer, "digraph markov {{")?;
+        writeln!(writer, "  node [shape=box, fontname=monospace];")?;
+        for (id, (&(start, end), count)) in self.blocks.iter().enumerate() {
+            writeln!(writer, "  b{} [label=\"{}\\n..{:#x}\\n{}x\"];", id, self.label(start).replace('"', "\\\""), end, count)?;
+        }
+        for (&(from, to), &count) in self.transitions.iter() {
+            writeln!(writer, "  b{} -> b{} [label=\"{:.3} ({})\"];", from, to, count as f64 / out[&from] as f64, count)?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for MarkovReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.block_start.insert(self.ctx, entry.arc.0);
+            }
+            Event::InferrableJump | Event::UninferableJump | Event::TakenBranch | Event::NonTakenBranch
+            | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn => {
+                self.end_block(entry.arc.0, entry.arc.1);
+            }
+            Event::End => {
+                self.end_block(entry.arc.0, entry.arc.0);
+            }
+            Event::Resync => {
+                // nothing is known about what ran in between
+                self.block_start.insert(self.ctx, entry.arc.1);
+                self.prev_block.remove(&self.ctx);
+            }
+            Event::ContextSwitch => {
+                self.ctx = entry.ctx.unwrap();
+                // the first block of a context starts where it was switched in
+                self.block_start.entry(self.ctx).or_insert(entry.arc.0);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        let out = self.out_counts();
+        debug!("markov model: {} blocks, {} transitions", self.blocks.len(), self.transitions.len());
+        self.write_json(&out)?;
+        self.write_dot(&out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::frontend::insn_map::{code_bytes, read_code};
+use crate::frontend::mem_access::{decode_mem_access, AccessClass, MemAccess};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use object::Object;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+// the loads and stores of one function
+#[derive(Default)]
+struct FunctionTraffic {
+    // per class, (accesses, bytes)
+    classes: BTreeMap<AccessClass, (u64, u64)>,
+    // base register to accesses
+    bases: BTreeMap<u8, u64>,
+    // (pc, access, count) of each instruction
+    sites: Vec<(u64, MemAccess, u64)>,
+}
+
+/// The memory traffic of every function from its load and store
+/// instructions alone, without data addresses: per function the accesses
+/// and bytes by class (load, store, fp load, fp store, atomic), the base
+/// registers, and each instruction with its size, base, static offset and
+/// count. Writes `trace.mem.txt`, the busiest functions first, and with
+/// `log`, every access in order to `trace.mem.log`.
+pub struct MemReceiver {
+    writer: BufWriter<File>,
+    log: Option<BufWriter<File>>,
+    receiver: BusReceiver,
+    loader: Loader,
+    code: Vec<(u64, Vec<u8>)>,
+    rv64: bool,
+    // the access of every pc seen, None for the other instructions
+    decoded: HashMap<u64, Option<MemAccess>>,
+    counts: HashMap<u64, u64>,
+    last_ts: u64,
+}
+
+impl MemReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, log: bool) -> Result<Self> {
+        debug!("MemReceiver::new");
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        Ok(MemReceiver {
+            writer: BufWriter::new(File::create("trace.mem.txt")?),
+            log: if log { Some(BufWriter::new(File::create("trace.mem.log")?)) } else { None },
+            receiver: BusReceiver { name: "mem".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(&elf_path).map_err(|e| anyhow!("{}", e))?,
+            code: read_code(&elf)?,
+            rv64: elf.is_64(),
+            decoded: HashMap::new(),
+            counts: HashMap::new(),
+            last_ts: 0,
+        })
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let mut functions: HashMap<&str, FunctionTraffic> = HashMap::new();
+        for (&pc, &count) in self.counts.iter() {
+            let access = self.decoded[&pc].unwrap();
+            let traffic = functions.entry(self.loader.find_symbol(pc).unwrap_or("?")).or_default();
+            let class = traffic.classes.entry(access.class).or_default();
+            class.0 += count;
+            class.1 += count * access.size;
+            *traffic.bases.entry(access.base).or_default() += count;
+            traffic.sites.push((pc, access, count));
+        }
+        let accesses = |traffic: &FunctionTraffic| traffic.classes.values().map(|(count, _)| count).sum::<u64>();
+        let mut functions: Vec<_> = functions.into_iter().collect();
+        functions.sort_by(|a, b| accesses(&b.1).cmp(&accesses(&a.1)).then(a.0.cmp(b.0)));
+
+        let w = &mut self.writer;
+        let total: u64 = self.counts.values().sum();
+        writeln!(w, "{} memory accesses in {} functions", total, functions.len())?;
+        for (name, mut traffic) in functions {
+            let loaded: u64 = traffic.classes.iter().filter(|(class, _)| class.loads()).map(|(_, (_, bytes))| bytes).sum();
+            let stored: u64 = traffic.classes.iter().filter(|(class, _)| class.stores()).map(|(_, (_, bytes))| bytes).sum();
+            writeln!(w)?;
+            writeln!(w, "{}: {} accesses, {} bytes loaded, {} bytes stored", name, accesses(&traffic), loaded, stored)?;
+            for (class, (count, bytes)) in &traffic.classes {
+                writeln!(w, "  {:<8} {:>12} accesses {:>14} bytes", class.name(), count, bytes)?;
+            }
+            let mut bases: Vec<_> = traffic.bases.iter().collect();
+            bases.sort_by_key(|&(&base, &count)| (std::cmp::Reverse(count), base));
+            let bases: Vec<String> = bases.iter().map(|(&base, count)| format!("{} {}", REG_NAMES[base as usize], count)).collect();
+            writeln!(w, "  bases: {}", bases.join(", "))?;
+            traffic.sites.sort_by_key(|&(pc, _, count)| (std::cmp::Reverse(count), pc));
+            for (pc, access, count) in &traffic.sites {
+                writeln!(w, "  {:#010x} {:<8} {:>2}B {}({}) {:>12}", pc, access.class.name(), access.size,
+                         access.offset, REG_NAMES[access.base as usize], count)?;
+            }
+        }
+        w.flush()
+    }
+}
+
+impl AbstractReceiver for MemReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if entry.event != Event::None {
+            if let Some(ts) = entry.timestamp {
+                self.last_ts = ts;
+            }
+            return Ok(());
+        }
+        let pc = entry.arc.0;
+        let len = (entry.arc.1 - pc) as usize;
+        let (code, rv64) = (&self.code, self.rv64);
+        let access = *self.decoded.entry(pc)
+            .or_insert_with(|| code_bytes(code, pc, len).and_then(|bytes| decode_mem_access(bytes, rv64)));
+        if let Some(access) = access {
+            *self.counts.entry(pc).or_default() += 1;
+            if let Some(log) = &mut self.log {
+                writeln!(log, "{} {:#x} {} {} {}({})", self.last_ts, pc, access.class.name(), access.size,
+                         access.offset, REG_NAMES[access.base as usize])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            log.flush()?;
+        }
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
+use std::ffi::CString;
+use log::debug;
+
+// minimal bindings to the libotf2 2.x writer API
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub type OTF2_Archive = c_void;
+    pub type OTF2_EvtWriter = c_void;
+    pub type OTF2_GlobalDefWriter = c_void;
+    pub type OTF2_ErrorCode = c_int;
+
+    pub const OTF2_SUCCESS: OTF2_ErrorCode = 0;
+    pub const OTF2_FILEMODE_WRITE: u8 = 0;
+    pub const OTF2_SUBSTRATE_POSIX: u8 = 1;
+    pub const OTF2_COMPRESSION_NONE: u8 = 1;
+    pub const OTF2_CHUNK_SIZE_EVENTS_DEFAULT: u64 = 1024 * 1024;
+    pub const OTF2_CHUNK_SIZE_DEFINITIONS_DEFAULT: u64 = 4 * 1024 * 1024;
+    pub const OTF2_FLUSH: u8 = 1;
+    pub const OTF2_UNDEFINED_SYSTEM_TREE_NODE: u32 = u32::MAX;
+    pub const OTF2_REGION_ROLE_FUNCTION: u8 = 1;
+    pub const OTF2_PARADIGM_COMPILER: u8 = 2;
+    pub const OTF2_REGION_FLAG_NONE: u32 = 0;
+    pub const OTF2_LOCATION_GROUP_TYPE_PROCESS: u8 = 1;
+    pub const OTF2_LOCATION_TYPE_CPU_THREAD: u8 = 1;
+
+    #[repr(C)]
+    pub struct OTF2_FlushCallbacks {
+        pub pre_flush: extern "C" fn(*mut c_void, u8, u64, *mut c_void, bool) -> u8,
+        pub post_flush: extern "C" fn(*mut c_void, u8, u64) -> u64,
+    }
+
+    #[link(name = "otf2")]
+    extern "C" {
+        pub fn OTF2_Archive_Open(path: *const c_char, name: *const c_char, mode: u8, chunk_events: u64, chunk_defs: u64, substrate: u8, compression: u8) -> *mut OTF2_Archive;
+        pub fn OTF2_Archive_SetFlushCallbacks(archive: *mut OTF2_Archive, callbacks: *const OTF2_FlushCallbacks, data: *mut c_void) -> OTF2_ErrorCode;
+        pub fn OTF2_Archive_SetSerialCollectiveCallbacks(archive: *mut OTF2_Archive) -> OTF2_ErrorCode;
+        pub fn OTF2_Archive_OpenEvtFiles(archive: *mut OTF2_Archive) -> OTF2_ErrorCode;
+        pub fn OTF2_Archive_CloseEvtFiles(archive: *mut OTF2_Archive) -> OTF2_ErrorCode;
+        pub fn OTF2_Archive_GetEvtWriter(archive: *mut OTF2_Archive, location: u64) -> *mut OTF2_EvtWriter;
+        pub fn OTF2_Archive_CloseEvtWriter(archive: *mut OTF2_Archive, writer: *mut OTF2_EvtWriter) -> OTF2_ErrorCode;
+        pub fn OTF2_Archive_GetGlobalDefWriter(archive: *mut OTF2_Archive) -> *mut OTF2_GlobalDefWriter;
+        pub fn OTF2_Archive_Close(archive: *mut OTF2_Archive) -> OTF2_ErrorCode;
+        pub fn OTF2_EvtWriter_Enter(writer: *mut OTF2_EvtWriter, attributes: *mut c_void, time: u64, region: u32) -> OTF2_ErrorCode;
+        pub fn OTF2_EvtWriter_Leave(writer: *mut OTF2_EvtWriter, attributes: *mut c_void, time: u64, region: u32) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteClockProperties(writer: *mut OTF2_GlobalDefWriter, resolution: u64, offset: u64, length: u64) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteString(writer: *mut OTF2_GlobalDefWriter, id: u32, string: *const c_char) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteRegion(writer: *mut OTF2_GlobalDefWriter, id: u32, name: u32, canonical_name: u32, description: u32, role: u8, paradigm: u8, flags: u32, source_file: u32, begin_line: u32, end_line: u32) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteSystemTreeNode(writer: *mut OTF2_GlobalDefWriter, id: u32, name: u32, class_name: u32, parent: u32) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteLocationGroup(writer: *mut OTF2_GlobalDefWriter, id: u32, name: u32, group_type: u8, parent: u32) -> OTF2_ErrorCode;
+        pub fn OTF2_GlobalDefWriter_WriteLocation(writer: *mut OTF2_GlobalDefWriter, id: u64, name: u32, location_type: u8, num_events: u64, group: u32) -> OTF2_ErrorCode;
+    }
+
+    pub extern "C" fn pre_flush(_data: *mut c_void, _file_type: u8, _location: u64, _caller: *mut c_void, _is_final: bool) -> u8 {
+        OTF2_FLUSH
+    }
+
+    pub extern "C" fn post_flush(_data: *mut c_void, _file_type: u8, _location: u64) -> u64 {
+        0
+    }
+}
+
+fn check(code: ffi::OTF2_ErrorCode, what: &str) -> Result<()> {
+    if code != ffi::OTF2_SUCCESS {
+        return Err(anyhow!("otf2: {} failed with error code {}", what, code));
+    }
+    Ok(())
+}
+
+// (timestamp, region, is_enter)
+type Otf2Event = (u64, u32, bool);
+
+/// Writes an OTF2 archive with one location per hart and enter/leave
+/// events per traced function, for Vampir and the Score-P tooling.
+pub struct Otf2Receiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    // hart -> events, written out at flush
+    locations: IndexMap<u64, Vec<Otf2Event>>,
+    hart: u64,
+    start: u64,
+    end: u64,
+}
+
+impl Otf2Receiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        debug!("Otf2Receiver::new");
+        Ok(Otf2Receiver {
+            receiver: BusReceiver { name: "otf2".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            locations: IndexMap::new(),
+            hart: 0,
+            start: 0,
+            end: 0,
+        })
+    }
+
+    fn record(&mut self, ts: u64, region: u32, is_enter: bool) {
+        self.locations.entry(self.hart).or_default().push((ts, region, is_enter));
+    }
+
+    fn write_archive(&mut self) -> Result<()> {
+        let path = CString::new("trace_otf2").unwrap();
+        let name = CString::new("trace").unwrap();
+        let callbacks = ffi::OTF2_FlushCallbacks { pre_flush: ffi::pre_flush, post_flush: ffi::post_flush };
+        unsafe {
+            let archive = ffi::OTF2_Archive_Open(path.as_ptr(), name.as_ptr(), ffi::OTF2_FILEMODE_WRITE,
+                ffi::OTF2_CHUNK_SIZE_EVENTS_DEFAULT, ffi::OTF2_CHUNK_SIZE_DEFINITIONS_DEFAULT,
+                ffi::OTF2_SUBSTRATE_POSIX, ffi::OTF2_COMPRESSION_NONE);
+            if archive.is_null() {
+                return Err(anyhow!("otf2: could not open archive trace_otf2"));
+            }
+            check(ffi::OTF2_Archive_SetFlushCallbacks(archive, &callbacks, std::ptr::null_mut()), "set flush callbacks")?;
+            check(ffi::OTF2_Archive_SetSerialCollectiveCallbacks(archive), "set collective callbacks")?;
+
+            // events, one writer per hart
+            check(ffi::OTF2_Archive_OpenEvtFiles(archive), "open event files")?;
+            for (hart, events) in self.locations.iter() {
+                let writer = ffi::OTF2_Archive_GetEvtWriter(archive, *hart);
+                for &(ts, region, is_enter) in events.iter() {
+                    if is_enter {
+                        check(ffi::OTF2_EvtWriter_Enter(writer, std::ptr::null_mut(), ts, region), "enter")?;
+                    } else {
+                        check(ffi::OTF2_EvtWriter_Leave(writer, std::ptr::null_mut(), ts, region), "leave")?;
+                    }
+                }
+                check(ffi::OTF2_Archive_CloseEvtWriter(archive, writer), "close event writer")?;
+            }
+            check(ffi::OTF2_Archive_CloseEvtFiles(archive), "close event files")?;
+
+            // global definitions: string ids are handed out in order
+            let defs = ffi::OTF2_Archive_GetGlobalDefWriter(archive);
+            let mut next_string = 0u32;
+            let mut write_string = |s: &str| -> Result<u32> {
+                let c = CString::new(s.replace('\0', "")).unwrap();
+                check(ffi::OTF2_GlobalDefWriter_WriteString(defs, next_string, c.as_ptr()), "string")?;
+                next_string += 1;
+                Ok(next_string - 1)
+            };
+            check(ffi::OTF2_GlobalDefWriter_WriteClockProperties(defs, 1, self.start, self.end.saturating_sub(self.start)), "clock properties")?;
+            let empty = write_string("")?;
+            for info in self.unwinder.symbols() {
+                let func_name = write_string(&info.name)?;
+                let file = write_string(&info.file)?;
+                check(ffi::OTF2_GlobalDefWriter_WriteRegion(defs, info.index, func_name, func_name, empty,
+                    ffi::OTF2_REGION_ROLE_FUNCTION, ffi::OTF2_PARADIGM_COMPILER, ffi::OTF2_REGION_FLAG_NONE,
+                    file, info.line, info.line), "region")?;
+            }
+            let node_name = write_string("tacit")?;
+            let node_class = write_string("node")?;
+            check(ffi::OTF2_GlobalDefWriter_WriteSystemTreeNode(defs, 0, node_name, node_class, ffi::OTF2_UNDEFINED_SYSTEM_TREE_NODE), "system tree node")?;
+            let group_name = write_string("program")?;
+            check(ffi::OTF2_GlobalDefWriter_WriteLocationGroup(defs, 0, group_name, ffi::OTF2_LOCATION_GROUP_TYPE_PROCESS, 0), "location group")?;
+            for (hart, events) in self.locations.iter() {
+                let location_name = write_string(&format!("hart {}", hart))?;
+                check(ffi::OTF2_GlobalDefWriter_WriteLocation(defs, *hart, location_name, ffi::OTF2_LOCATION_TYPE_CPU_THREAD, events.len() as u64, 0), "location")?;
+            }
+            check(ffi::OTF2_Archive_Close(archive), "close archive")
+        }
+    }
+}
+
+impl AbstractReceiver for Otf2Receiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.start = entry.timestamp.unwrap();
+            }
+            Event::End => {
+                self.end = entry.timestamp.unwrap();
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let ts = entry.timestamp.unwrap();
+                let (success, _, opened) = self.unwinder.step_ij(entry.clone());
+                if success {
+                    self.record(ts, opened.unwrap().index, true);
+                }
+                self.end = self.end.max(ts);
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let ts = entry.timestamp.unwrap();
+                let (success, _, closed, opened) = self.unwinder.step_uj(entry.clone());
+                if success {
+                    for frame in closed {
+                        self.record(ts, frame.index, false);
+                    }
+                }
+                if let Some(frame) = opened {
+                    self.record(ts, frame.index, true);
+                }
+                self.end = self.end.max(ts);
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(ctx);
+                self.hart = ctx.hart;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        // close every frame that is still open, on every hart
+        let mut parked: Vec<_> = self.unwinder.parked_stacks.keys().cloned().collect();
+        // in a fixed order, the events of a location must not depend on the hashing
+        parked.sort_by_key(|ctx| (ctx.hart, ctx.asid));
+        for ctx in parked.into_iter().chain(std::iter::once(self.unwinder.ctx)) {
+            self.unwinder.switch_context(ctx);
+            self.hart = ctx.hart;
+            for frame in self.unwinder.flush() {
+                self.record(self.end, frame.index, false);
+            }
+        }
+        debug!("writing otf2 archive with {} locations", self.locations.len());
+        self.write_archive()
+    }
+}
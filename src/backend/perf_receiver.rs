@@ -0,0 +1,233 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use object::{Object, ObjectSection, SectionFlags};
+use object::elf::SHF_EXECINSTR;
+use std::path::Path;
+use log::debug;
+
+// perf.data layout constants, see tools/perf/util/header.h in the kernel tree
+const PERF_MAGIC: &[u8; 8] = b"PERFILE2";
+const PERF_HEADER_SIZE: u64 = 104;
+const PERF_ATTR_SIZE: u64 = 112; // PERF_ATTR_SIZE_VER5
+const PERF_FILE_ATTR_SIZE: u64 = PERF_ATTR_SIZE + 16; // attr + ids section
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+const PERF_SAMPLE_PERIOD: u64 = 1 << 8;
+
+const ATTR_FLAG_MMAP: u64 = 1 << 8;
+const ATTR_FLAG_COMM: u64 = 1 << 9;
+
+const PERF_RECORD_MMAP: u32 = 1;
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_SAMPLE: u32 = 9;
+const PERF_RECORD_MISC_USER: u16 = 2;
+const PERF_CONTEXT_USER: u64 = 0xffff_ffff_ffff_fe00;
+// the default of kernel.perf_event_max_stack, deeper stacks are cut
+const PERF_MAX_STACK_DEPTH: usize = 127;
+
+// the traced program shows up as a single process
+const PERF_PID: u32 = 1;
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) { buf.extend_from_slice(&v.to_le_bytes()); }
+fn push_u32(buf: &mut Vec<u8>, v: u32) { buf.extend_from_slice(&v.to_le_bytes()); }
+fn push_u64(buf: &mut Vec<u8>, v: u64) { buf.extend_from_slice(&v.to_le_bytes()); }
+
+// NUL-terminated and padded to a multiple of 8 bytes
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    let padded = (s.len() + 1).div_ceil(8) * 8;
+    buf.extend_from_slice(s.as_bytes());
+    buf.resize(buf.len() + padded - s.len(), 0);
+}
+
+// the size in the header is a u16, larger records cannot be written
+fn push_record(buf: &mut Vec<u8>, r#type: u32, misc: u16, body: &[u8]) -> Result<()> {
+    let size = u16::try_from(8 + body.len())
+        .map_err(|_| anyhow!("perf: a record of type {} has {} bytes, more than fit in one", r#type, 8 + body.len()))?;
+    push_u32(buf, r#type);
+    push_u16(buf, misc);
+    push_u16(buf, size);
+    buf.extend_from_slice(body);
+    Ok(())
+}
+
+/// Synthesizes a linux-perf `perf.data` with one cycles sample every
+/// `period` timestamp units, so `perf report`/`perf annotate` work on traces.
+pub struct PerfReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    period: u64,
+    records: Vec<u8>,
+    sample_count: u64,
+    next_sample_ts: u64,
+    tid: u32,
+}
+
+impl PerfReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, period: u64) -> Result<Self> {
+        debug!("PerfReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path.clone(), options)?;
+
+        // describe the ELF text as a single file-backed mapping
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let mut text_start = u64::MAX;
+        let mut text_end = 0;
+        let mut text_offset = 0;
+        for section in elf.sections() {
+            if let SectionFlags::Elf { sh_flags } = section.flags() {
+                if sh_flags & (SHF_EXECINSTR as u64) != 0 {
+                    if section.address() < text_start {
+                        text_start = section.address();
+                        text_offset = section.file_range().map(|(offset, _)| offset).unwrap_or(0);
+                    }
+                    text_end = text_end.max(section.address() + section.size());
+                }
+            }
+        }
+        let abs_path = std::fs::canonicalize(&elf_path).map(|p| p.display().to_string()).unwrap_or(elf_path.clone());
+        let comm = Path::new(&elf_path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(elf_path.clone());
+
+        let mut records = Vec::new();
+        let mut body = Vec::new();
+        push_u32(&mut body, PERF_PID);
+        push_u32(&mut body, PERF_PID);
+        push_str(&mut body, &comm);
+        push_record(&mut records, PERF_RECORD_COMM, 0, &body)?;
+
+        body.clear();
+        push_u32(&mut body, PERF_PID);
+        push_u32(&mut body, PERF_PID);
+        push_u64(&mut body, text_start);
+        push_u64(&mut body, text_end.saturating_sub(text_start));
+        push_u64(&mut body, text_offset);
+        push_str(&mut body, &abs_path);
+        push_record(&mut records, PERF_RECORD_MMAP, PERF_RECORD_MISC_USER, &body)?;
+
+        Ok(PerfReceiver {
+            writer: BufWriter::new(File::create("trace.perf.data")?),
+            receiver: BusReceiver { name: "perf".into(), bus_rx, checksum: 0 },
+            unwinder,
+            period: period.max(1),
+            records,
+            sample_count: 0,
+            next_sample_ts: 0,
+            tid: PERF_PID,
+        })
+    }
+
+    /// Emit one sample per period boundary crossed up to `ts`, attributed to `pc`.
+    fn sample_until(&mut self, ts: u64, pc: u64) -> Result<()> {
+        // callers are reported at their entry address, which is enough for symbolization
+        let mut callchain = vec![PERF_CONTEXT_USER, pc];
+        callchain.extend(self.unwinder.current_frame_addrs().iter().rev().skip(1).take(PERF_MAX_STACK_DEPTH - 1));
+        while self.next_sample_ts <= ts {
+            let mut body = Vec::new();
+            push_u64(&mut body, pc);
+            push_u32(&mut body, PERF_PID);
+            push_u32(&mut body, self.tid);
+            push_u64(&mut body, self.next_sample_ts);
+            push_u64(&mut body, self.period);
+            push_u64(&mut body, callchain.len() as u64);
+            for ip in callchain.iter() {
+                push_u64(&mut body, *ip);
+            }
+            push_record(&mut self.records, PERF_RECORD_SAMPLE, PERF_RECORD_MISC_USER, &body)?;
+            self.sample_count += 1;
+            self.next_sample_ts += self.period;
+        }
+        Ok(())
+    }
+
+    fn write_perf_data(&mut self) -> std::io::Result<()> {
+        let attrs_offset = PERF_HEADER_SIZE;
+        let data_offset = attrs_offset + PERF_FILE_ATTR_SIZE;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(PERF_MAGIC);
+        push_u64(&mut header, PERF_HEADER_SIZE);
+        push_u64(&mut header, PERF_FILE_ATTR_SIZE);
+        push_u64(&mut header, attrs_offset);
+        push_u64(&mut header, PERF_FILE_ATTR_SIZE);
+        push_u64(&mut header, data_offset);
+        push_u64(&mut header, self.records.len() as u64);
+        push_u64(&mut header, 0); // event_types, unused
+        push_u64(&mut header, 0);
+        header.resize(PERF_HEADER_SIZE as usize, 0); // no optional feature sections
+
+        let mut attr = Vec::new();
+        push_u32(&mut attr, PERF_TYPE_HARDWARE);
+        push_u32(&mut attr, PERF_ATTR_SIZE as u32);
+        push_u64(&mut attr, PERF_COUNT_HW_CPU_CYCLES);
+        push_u64(&mut attr, self.period);
+        push_u64(&mut attr, PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_TIME | PERF_SAMPLE_CALLCHAIN | PERF_SAMPLE_PERIOD);
+        push_u64(&mut attr, 0); // read_format
+        push_u64(&mut attr, ATTR_FLAG_MMAP | ATTR_FLAG_COMM);
+        attr.resize(PERF_ATTR_SIZE as usize, 0);
+        push_u64(&mut attr, 0); // ids section, empty
+        push_u64(&mut attr, 0);
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&attr)?;
+        self.writer.write_all(&self.records)?;
+        self.writer.flush()
+    }
+}
+
+impl AbstractReceiver for PerfReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::Start => {
+                self.next_sample_ts = entry.timestamp.unwrap() + self.period;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                // the elapsed time belongs to the stack before the jump
+                self.sample_until(entry.timestamp.unwrap(), entry.arc.0)?;
+                let _ = self.unwinder.step_ij(entry.clone());
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.sample_until(entry.timestamp.unwrap(), entry.arc.0)?;
+                let _ = self.unwinder.step_uj(entry.clone());
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.sample_until(entry.timestamp.unwrap(), entry.arc.0)?;
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(ctx);
+                self.tid = PERF_PID + ctx.hart as u32;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("writing {} perf samples", self.sample_count);
+        self.write_perf_data()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
-use crate::backend::event::{Entry, Event};
+use crate::backend::event::{Entry, Event, SOURCE_ATTR};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
+use crate::frontend::context::Context;
 use bus::BusReader;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use serde_json::json;
@@ -17,21 +20,41 @@ pub struct PerfettoReceiver {
     start_ts: u64,
     end_ts: u64,
     last_frames: Vec<u64>, // addresses of frame starts we saw last
+    // each context is its own track: pid = asid, tid = hart
+    ctx: Context,
+    parked_frames: HashMap<Context, Vec<u64>>,
+    // the tracks named after the trace they come from
+    named: HashSet<Context>,
 }
 
 impl PerfettoReceiver {
-    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Self {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
         debug!("PerfettoReceiver::new");
-        let unwinder = StackUnwinder::new(elf_path).unwrap();
-        PerfettoReceiver {
-            writer: BufWriter::new(File::create("trace.perfetto.json").unwrap()),
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        Ok(PerfettoReceiver {
+            writer: BufWriter::new(File::create("trace.perfetto.json")?),
             receiver: BusReceiver { name: "perfetto".into(), bus_rx, checksum: 0 },
             unwinder,
             events: Vec::new(),
             start_ts: 0,
             end_ts: 0,
             last_frames: Vec::new(),
+            ctx: Context::default(),
+            parked_frames: HashMap::new(),
+            named: HashSet::new(),
+        })
+    }
+
+    /// Stash the frames of the outgoing context and pick up the new one's.
+    fn switch_context(&mut self, ctx: Context) {
+        if ctx == self.ctx {
+            return;
         }
+        self.unwinder.switch_context(ctx);
+        let parked = std::mem::take(&mut self.last_frames);
+        self.parked_frames.insert(self.ctx, parked);
+        self.last_frames = self.parked_frames.remove(&ctx).unwrap_or_default();
+        self.ctx = ctx;
     }
 
     /// Diff last_frames vs the unwinder’s current_frame_addrs, and
@@ -54,8 +77,8 @@ impl PerfettoReceiver {
                 "cat": "function",
                 "ph": "E",    // end
                 "ts": ts,
-                "pid": 0,
-                "tid": 0,
+                "pid": self.ctx.asid,
+                "tid": self.ctx.hart,
                 "args": {}
             });
             self.events.push(evt.to_string());
@@ -68,8 +91,8 @@ impl PerfettoReceiver {
                 "cat": "function",
                 "ph": "B",   // begin
                 "ts": ts,
-                "pid": 0,
-                "tid": 0,
+                "pid": self.ctx.asid,
+                "tid": self.ctx.hart,
                 "args": { "addr": format!("0x{:x}", addr) }
             });
             self.events.push(evt.to_string());
@@ -87,7 +110,11 @@ impl AbstractReceiver for PerfettoReceiver {
         self.receiver.checksum += 1;
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
         let ts = entry.timestamp.unwrap_or(0);
         match entry.event {
             Event::Start => {
@@ -112,12 +139,44 @@ impl AbstractReceiver for PerfettoReceiver {
                 }
                 // now diff and emit the proper B/E events
                 self.diff_stack(ts);
+                // mark the trap itself with what caused it
+                if let Some(cause) = entry.cause_text() {
+                    let tval = entry.trap.and_then(|trap| trap.tval).map(|tval| format!("0x{:x}", tval));
+                    let evt = json!({
+                        "name": cause,
+                        "cat": "trap",
+                        "ph": "i",
+                        "s": "t",
+                        "ts": ts,
+                        "pid": self.ctx.asid,
+                        "tid": self.ctx.hart,
+                        "args": { "from": format!("0x{:x}", entry.arc.0), "tval": tval }
+                    });
+                    self.events.push(evt.to_string());
+                }
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                if let Some(source) = entry.attrs.get(SOURCE_ATTR) {
+                    if self.named.insert(ctx) {
+                        let evt = json!({
+                            "name": "thread_name",
+                            "ph": "M",
+                            "pid": ctx.asid,
+                            "tid": ctx.hart,
+                            "args": { "name": source.to_string() }
+                        });
+                        self.events.push(evt.to_string());
+                    }
+                }
+                self.switch_context(ctx);
             }
             _ => {}
         }
+        Ok(())
     }
 
-    fn _flush(&mut self) {
+    fn _flush(&mut self) -> Result<()> {
         if self.end_ts == 0 {
             self.end_ts = self.start_ts;
         }
@@ -127,15 +186,16 @@ impl AbstractReceiver for PerfettoReceiver {
         self.diff_stack(self.end_ts);
 
         // write out the combined traceEvents
-        writeln!(self.writer, "{{").unwrap();
-        writeln!(self.writer, "  \"traceEvents\": [").unwrap();
+        writeln!(self.writer, "{{")?;
+        writeln!(self.writer, "  \"traceEvents\": [")?;
         for (i, ev) in self.events.iter().enumerate() {
             let comma = if i + 1 < self.events.len() { "," } else { "" };
-            writeln!(self.writer, "    {}{}", ev, comma).unwrap();
+            writeln!(self.writer, "    {}{}", ev, comma)?;
         }
-        writeln!(self.writer, "  ]").unwrap();
-        writeln!(self.writer, "}}\n").unwrap();
-        self.writer.flush().unwrap();
+        writeln!(self.writer, "  ]")?;
+        writeln!(self.writer, "}}\n")?;
+        self.writer.flush()?;
+        Ok(())
     }
 }
 
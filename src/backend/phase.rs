@@ -0,0 +1,94 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::stack_unwinder::StackUnwinder;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+
+// where the warmup of a run ends and its steady state begins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhaseBoundary {
+    // this many cycles after the start of the trace
+    Cycles(u64),
+    // the first entry into a function, e.g. a marker
+    Func(String),
+    // the first back edge in a function, the end of the first iteration of
+    // its loop
+    Loop(String),
+}
+
+impl FromStr for PhaseBoundary {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some(("cycles", cycles)) => Ok(PhaseBoundary::Cycles(cycles.parse().map_err(|_| anyhow!("bad cycle count `{}`", cycles))?)),
+            Some(("func", name)) if !name.is_empty() => Ok(PhaseBoundary::Func(name.to_string())),
+            Some(("loop", name)) if !name.is_empty() => Ok(PhaseBoundary::Loop(name.to_string())),
+            _ => Err(anyhow!("unknown phase boundary `{}`, expected cycles=<n>, func=<name> or loop=<name>", s)),
+        }
+    }
+}
+
+enum Boundary {
+    Cycles(u64),
+    Entry(u64),
+    // the [start, end) of the function
+    BackEdge(u64, u64),
+}
+
+// Finds the phase boundary in the entry stream, for the receivers splitting
+// their output in a warmup and a steady state part.
+pub struct PhaseSplitter {
+    boundary: Boundary,
+    start: Option<u64>,
+    split: Option<u64>,
+}
+
+impl PhaseSplitter {
+    pub fn new(boundary: &PhaseBoundary, unwinder: &StackUnwinder) -> Result<Self> {
+        let range = |name: &str| {
+            let (&start, info) = unwinder.func_symbol_map().iter().find(|(_, info)| info.name == name)
+                .ok_or_else(|| anyhow!("phase boundary in `{}`: no such function in the binary", name))?;
+            let end = unwinder.idx_2_addr_range[&info.index].1;
+            // the last function has no successor to bound it
+            Ok::<_, anyhow::Error>((start, if end > start { end } else { u64::MAX }))
+        };
+        let boundary = match boundary {
+            PhaseBoundary::Cycles(cycles) => Boundary::Cycles(*cycles),
+            PhaseBoundary::Func(name) => Boundary::Entry(range(name)?.0),
+            PhaseBoundary::Loop(name) => {
+                let (start, end) = range(name)?;
+                Boundary::BackEdge(start, end)
+            }
+        };
+        Ok(PhaseSplitter { boundary, start: None, split: None })
+    }
+
+    // the timestamp the steady state starts at, once reached
+    pub fn split_at(&self) -> Option<u64> {
+        self.split
+    }
+
+    // true for the one entry that crosses the boundary, which belongs to the
+    // steady state
+    pub fn crosses(&mut self, entry: &Entry) -> bool {
+        if self.split.is_some() || entry.event == Event::BPHit {
+            return false;
+        }
+        let Some(ts) = entry.timestamp else { return false };
+        if entry.event == Event::Start {
+            self.start = Some(ts);
+        }
+        let (from, to) = entry.arc;
+        let crossed = match self.boundary {
+            Boundary::Cycles(cycles) => ts.saturating_sub(self.start.unwrap_or(ts)) >= cycles,
+            Boundary::Entry(addr) => to == addr && matches!(entry.event,
+                Event::InferrableJump | Event::UninferableJump | Event::TrapException | Event::TrapInterrupt),
+            Boundary::BackEdge(start, end) => matches!(entry.event, Event::TakenBranch | Event::InferrableJump)
+                && start <= to && to <= from && from < end,
+        };
+        if crossed {
+            self.split = Some(ts);
+        }
+        crossed
+    }
+}
@@ -0,0 +1,152 @@
+use crate::backend::event::Entry;
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver, TraceMetadata};
+use crate::ffi::{TacitEvent, TacitPlugin, TACIT_PLUGIN_ABI};
+use bus::BusReader;
+use libloading::Library;
+use std::ffi::{c_void, CStr, CString};
+use std::ptr;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use log::debug;
+
+type PluginsFn = unsafe extern "C" fn(count: *mut usize) -> *const TacitPlugin;
+
+/// A plugin library loaded with `--plugin` and the receivers it exports,
+/// see `TacitPlugin` in `include/tacit.h`.
+pub struct PluginLibrary {
+    library: Arc<Library>,
+    plugins: Vec<(String, TacitPlugin)>,
+}
+
+impl PluginLibrary {
+    pub fn open(path: &str) -> Result<Self> {
+        // loading runs the library's initializers, it is trusted as much as
+        // the decoder itself
+        let library = unsafe { Library::new(path) }.map_err(|e| anyhow!("--plugin {}: {}", path, e))?;
+        let plugins = unsafe {
+            let entry = library.get::<PluginsFn>(b"tacit_plugins")
+                .map_err(|e| anyhow!("--plugin {}: no tacit_plugins: {}", path, e))?;
+            let mut count = 0;
+            let first = entry(&mut count);
+            if first.is_null() { Vec::new() } else { std::slice::from_raw_parts(first, count).to_vec() }
+        };
+        let mut named = Vec::new();
+        for plugin in plugins {
+            if plugin.abi_version != TACIT_PLUGIN_ABI {
+                return Err(anyhow!("--plugin {}: built for plugin abi {}, the decoder has {}", path, plugin.abi_version, TACIT_PLUGIN_ABI));
+            }
+            if plugin.name.is_null() || plugin.create.is_none() || plugin.receive.is_none() {
+                return Err(anyhow!("--plugin {}: a receiver lacks its name, create or receive", path));
+            }
+            let name = unsafe { CStr::from_ptr(plugin.name) }.to_string_lossy().into_owned();
+            named.push((name, plugin));
+        }
+        debug!("plugin {}: {} receivers", path, named.len());
+        Ok(PluginLibrary { library: Arc::new(library), plugins: named })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Runs a receiver of a plugin library, handing it every entry as a
+/// `TacitEvent`. Its state is created in `on_start`, so a plugin failing to
+/// open its outputs fails like any other receiver, with the plugin's
+/// `last_error` as the reason; it is reported as `plugin:<name>`.
+pub struct PluginReceiver {
+    receiver: BusReceiver,
+    name: String,
+    plugin: TacitPlugin,
+    // keeps the code of the plugin loaded
+    _library: Arc<Library>,
+    state: *mut c_void,
+}
+
+// the plugin state is only used from the thread running the receiver, as
+// the plugin interface promises
+unsafe impl Send for PluginReceiver {}
+
+impl PluginReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, library: &PluginLibrary, name: &str) -> Result<Self> {
+        debug!("PluginReceiver::new");
+        let plugin = library.plugins.iter().find(|(plugin_name, _)| plugin_name == name)
+            .map(|&(_, plugin)| plugin)
+            .ok_or_else(|| anyhow!("no plugin receiver named `{}`", name))?;
+        Ok(PluginReceiver {
+            receiver: BusReceiver { name: "plugin".into(), bus_rx, checksum: 0 },
+            name: name.to_string(),
+            plugin,
+            _library: library.library.clone(),
+            state: ptr::null_mut(),
+        })
+    }
+
+    fn error(&self, call: &str) -> anyhow::Error {
+        let message = match self.plugin.last_error {
+            Some(last_error) if !self.state.is_null() => unsafe { last_error(self.state) },
+            _ => ptr::null(),
+        };
+        if message.is_null() {
+            anyhow!("{} failed", call)
+        } else {
+            anyhow!("{} failed: {}", call, unsafe { CStr::from_ptr(message) }.to_string_lossy())
+        }
+    }
+}
+
+impl AbstractReceiver for PluginReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn name(&self) -> String {
+        format!("plugin:{}", self.name)
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn on_start(&mut self, metadata: &TraceMetadata) -> Result<()> {
+        let binary = CString::new(metadata.binary.clone())?;
+        let traces = CString::new(metadata.traces.join("\n"))?;
+        // create and receive are checked when loading
+        let create = self.plugin.create.unwrap();
+        self.state = unsafe { create(binary.as_ptr(), traces.as_ptr()) };
+        if self.state.is_null() {
+            return Err(self.error("create"));
+        }
+        Ok(())
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        let event = TacitEvent::from(&entry);
+        let receive = self.plugin.receive.unwrap();
+        if unsafe { receive(self.state, &event) } != 0 {
+            return Err(self.error("receive"));
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        if let Some(flush) = self.plugin.flush {
+            if unsafe { flush(self.state) } != 0 {
+                return Err(self.error("flush"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PluginReceiver {
+    fn drop(&mut self) {
+        if let (Some(destroy), false) = (self.plugin.destroy, self.state.is_null()) {
+            unsafe { destroy(self.state) };
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use crate::backend::event::{Entry, Event, RETURN_ATTR};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::trap_type::TrapVector;
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use log::debug;
+
+// the CSRs reported in FVal register packets that say where traps go and
+// where returns go back to
+const CSR_SSTATUS: u16 = 0x100;
+const CSR_STVEC: u16 = 0x105;
+const CSR_MSTATUS: u16 = 0x300;
+const CSR_MEDELEG: u16 = 0x302;
+const CSR_MIDELEG: u16 = 0x303;
+const CSR_MTVEC: u16 = 0x305;
+const MSTATUS_SPP: u32 = 8;
+const MSTATUS_MPP: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Level {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+const LEVELS: [Level; 3] = [Level::Machine, Level::Supervisor, Level::User];
+
+impl Level {
+    fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0 => Level::User,
+            1 => Level::Supervisor,
+            _ => Level::Machine,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Level::User => "u",
+            Level::Supervisor => "s",
+            Level::Machine => "m",
+        }
+    }
+
+    fn slot(&self) -> usize {
+        LEVELS.iter().position(|level| level == self).unwrap()
+    }
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "m" => Ok(Level::Machine),
+            "s" => Ok(Level::Supervisor),
+            "u" => Ok(Level::User),
+            _ => Err(anyhow!("unknown privilege level `{}`, expected m, s or u", s)),
+        }
+    }
+}
+
+/// Cycles spent per privilege level, over fixed windows of `window` cycles
+/// and per function, for traces of an OS and its applications. The level
+/// follows the traps and their returns: a trap goes to S-mode when it lands
+/// in the stvec handler, or its cause is delegated by the last medeleg or
+/// mideleg the encoder reported, and to M-mode otherwise; a return goes back
+/// to the level its trap was taken from. A return with no trap open enters
+/// a lower level, the MPP or SPP field of the last mstatus or sstatus
+/// reported (S-mode for mret and U-mode for sret by default). Writes
+/// `trace.priv.csv`, the time series, and `trace.priv.json`, with the levels
+/// and functions as well.
+pub struct PrivReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    window: u64,
+    level: Level,
+    // the levels the open traps were taken from, innermost last
+    trap_levels: Vec<Level>,
+    // the last values of the CSRs reported
+    csrs: HashMap<u16, u64>,
+    last_ts: Option<u64>,
+    // (level, function entry address) to the instructions retired since the
+    // last timed event, None outside of any function
+    pending: HashMap<(Level, Option<u64>), u64>,
+    last_range: Option<(u64, u64)>,
+    // window index to the cycles of each level, in LEVELS order
+    windows: BTreeMap<u64, [f64; 3]>,
+    // (level, function entry address) to (instructions, cycles)
+    functions: HashMap<(Level, Option<u64>), (u64, f64)>,
+}
+
+impl PrivReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, window: u64, start: Level) -> Result<Self> {
+        debug!("PrivReceiver::new");
+        Ok(PrivReceiver {
+            receiver: BusReceiver { name: "priv".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            window: window.max(1),
+            level: start,
+            trap_levels: Vec::new(),
+            csrs: HashMap::new(),
+            last_ts: None,
+            pending: HashMap::new(),
+            last_range: None,
+            windows: BTreeMap::new(),
+            functions: HashMap::new(),
+        })
+    }
+
+    fn retire(&mut self, pc: u64) {
+        let range = match self.last_range {
+            Some((start, end)) if start <= pc && pc < end => self.last_range,
+            _ => self.unwinder.function_range_at(pc),
+        };
+        self.last_range = range;
+        *self.pending.entry((self.level, range.map(|(start, _)| start))).or_default() += 1;
+    }
+
+    // the instructions retired since the previous timed event took the
+    // cycles up to ts, by their share; with none retired the cycles go to
+    // the current level
+    fn account(&mut self, ts: u64) {
+        let from = self.last_ts.unwrap_or(ts).min(ts);
+        self.last_ts = Some(ts);
+        let insns: u64 = self.pending.values().sum();
+        let cycles = ts - from;
+        let mut shares = [0.0; 3];
+        if insns == 0 {
+            shares[self.level.slot()] = 1.0;
+        }
+        for ((level, function), count) in self.pending.drain() {
+            let share = count as f64 / insns as f64;
+            shares[level.slot()] += share;
+            let (function_insns, function_cycles) = self.functions.entry((level, function)).or_default();
+            *function_insns += count;
+            *function_cycles += cycles as f64 * share;
+        }
+        let mut start = from;
+        while start < ts {
+            let index = start / self.window;
+            let end = ts.min((index + 1) * self.window);
+            let window = self.windows.entry(index).or_default();
+            for (slot, share) in shares.iter().enumerate() {
+                window[slot] += (end - start) as f64 * share;
+            }
+            start = end;
+        }
+    }
+
+    fn in_handler(&self, tvec: u16, target: u64) -> bool {
+        self.csrs.get(&tvec).is_some_and(|&tvec| {
+            let vector = TrapVector::from_mtvec(tvec);
+            target == vector.base || vector.cause_of(target, true).is_some()
+        })
+    }
+
+    // the level a trap into target goes to, traps in M-mode stay there
+    fn trap_level(&self, entry: &Entry) -> Level {
+        let target = entry.arc.1;
+        if self.level == Level::Machine || self.in_handler(CSR_MTVEC, target) {
+            return Level::Machine;
+        }
+        if self.in_handler(CSR_STVEC, target) {
+            return Level::Supervisor;
+        }
+        let deleg = if entry.event == Event::TrapInterrupt { CSR_MIDELEG } else { CSR_MEDELEG };
+        match (self.csrs.get(&deleg), entry.trap.and_then(|trap| trap.cause)) {
+            (Some(&deleg), Some(cause)) if cause < 64 && deleg >> cause & 1 == 1 => Level::Supervisor,
+            _ => Level::Machine,
+        }
+    }
+
+    // the level an xret with no trap open returns to
+    fn lower_level(&self, entry: &Entry) -> Level {
+        let mstatus = |csr| self.csrs.get(&csr).copied();
+        match entry.attrs.get(RETURN_ATTR).map(|xret| xret.to_string()).as_deref() {
+            Some("sret") => mstatus(CSR_SSTATUS).or(mstatus(CSR_MSTATUS))
+                .map_or(Level::User, |status| Level::from_bits(status >> MSTATUS_SPP & 1)),
+            Some("uret") => Level::User,
+            _ => mstatus(CSR_MSTATUS).map_or(Level::Supervisor, |status| Level::from_bits(status >> MSTATUS_MPP)),
+        }
+    }
+
+    fn function_name(&self, function: Option<u64>) -> String {
+        match function {
+            Some(start) => self.unwinder.func_symbol_map().get(&start).map(|info| info.name.clone()).unwrap_or_else(|| format!("{:#x}", start)),
+            None => "?".to_string(),
+        }
+    }
+
+    fn write_csv(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.priv.csv")?);
+        writeln!(writer, "start,end,m,s,u")?;
+        for (&index, cycles) in self.windows.iter() {
+            writeln!(writer, "{},{},{:.0},{:.0},{:.0}", index * self.window, (index + 1) * self.window,
+                     cycles[0], cycles[1], cycles[2])?;
+        }
+        writer.flush()
+    }
+
+    fn write_json(&self) -> std::io::Result<()> {
+        let mut totals = [0.0; 3];
+        for cycles in self.windows.values() {
+            for (slot, cycles) in cycles.iter().enumerate() {
+                totals[slot] += cycles;
+            }
+        }
+        let total: f64 = totals.iter().sum();
+        let levels: Vec<_> = LEVELS.iter().map(|level| {
+            let instructions: u64 = self.functions.iter().filter(|((l, _), _)| l == level).map(|(_, &(insns, _))| insns).sum();
+            let cycles = totals[level.slot()];
+            json!({
+                "level": level.name(),
+                "cycles": cycles.round() as u64,
+                "instructions": instructions,
+                "share": cycles / total.max(1.0) * 100.0,
+            })
+        }).collect();
+        let windows: Vec<_> = self.windows.iter().map(|(&index, cycles)| json!({
+            "start": index * self.window,
+            "m": cycles[0].round() as u64,
+            "s": cycles[1].round() as u64,
+            "u": cycles[2].round() as u64,
+        })).collect();
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.1.total_cmp(&a.1.1).then(a.0.cmp(b.0)));
+        let functions: Vec<_> = functions.into_iter().map(|(&(level, function), &(insns, cycles))| json!({
+            "function": self.function_name(function),
+            "address": function,
+            "level": level.name(),
+            "instructions": insns,
+            "cycles": cycles.round() as u64,
+        })).collect();
+        let mut writer = BufWriter::new(File::create("trace.priv.json")?);
+        serde_json::to_writer(&mut writer, &json!({
+            "window": self.window,
+            "levels": levels,
+            "windows": windows,
+            "functions": functions,
+        }))?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for PrivReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                self.retire(entry.arc.0);
+            }
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::Resync => {
+                // what ran in between is unknown, its cycles are not counted
+                self.pending.clear();
+                self.last_ts = entry.timestamp;
+            }
+            Event::RegisterValue => {
+                if let Some(reg) = entry.reg {
+                    self.csrs.insert(reg.regno, reg.value);
+                }
+            }
+            Event::TrapException | Event::TrapInterrupt => {
+                if let Some(ts) = entry.timestamp {
+                    self.account(ts);
+                }
+                self.trap_levels.push(self.level);
+                self.level = self.trap_level(&entry);
+            }
+            Event::TrapReturn => {
+                if let Some(ts) = entry.timestamp {
+                    self.account(ts);
+                }
+                self.level = match self.trap_levels.pop() {
+                    Some(level) => level,
+                    None => self.lower_level(&entry),
+                };
+            }
+            Event::InferrableJump | Event::UninferableJump | Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                if let Some(ts) = entry.timestamp {
+                    self.account(ts);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("priv: {} windows, {} functions, {} traps open", self.windows.len(), self.functions.len(),
+               self.trap_levels.len());
+        self.write_csv()?;
+        self.write_json()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::frontend::register::register_name;
+use bus::BusReader;
+use anyhow::Result;
+use indexmap::IndexMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// vcd identifier code of the nth signal, from the printable characters
+fn vcd_id(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+/// The register and CSR values the encoder reports in FVal packets, as a
+/// time series: `trace.regs.csv` has a `timestamp,pc,register,value` row
+/// per sample, `trace.regs.vcd` a 64-bit signal per register that changes
+/// with it, to correlate control flow with the state it ran in. With a
+/// selection, the other registers are left out.
+pub struct RegReceiver {
+    csv: BufWriter<File>,
+    receiver: BusReceiver,
+    // the registers to keep, all without
+    selection: Option<Vec<u16>>,
+    // register to its last value, in order of first sample
+    last: IndexMap<u16, u64>,
+    // (timestamp, register, value) of every change, for the vcd
+    changes: Vec<(u64, u16, u64)>,
+}
+
+impl RegReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, selection: Option<Vec<u16>>) -> Result<Self> {
+        debug!("RegReceiver::new");
+        let mut csv = BufWriter::new(File::create("trace.regs.csv")?);
+        writeln!(csv, "timestamp,pc,register,value")?;
+        let mut last = IndexMap::new();
+        // selected registers get their signals in the order asked for
+        for &regno in selection.iter().flatten() {
+            last.insert(regno, u64::MAX);
+        }
+        Ok(RegReceiver {
+            csv,
+            receiver: BusReceiver { name: "regs".into(), bus_rx, checksum: 0 },
+            selection,
+            last,
+            changes: Vec::new(),
+        })
+    }
+
+    fn write_vcd(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.regs.vcd")?);
+        writeln!(writer, "$version tacit decoder $end")?;
+        writeln!(writer, "$comment timestamps are trace timestamp units $end")?;
+        writeln!(writer, "$timescale 1ns $end")?;
+        writeln!(writer, "$scope module regs $end")?;
+        for (index, &regno) in self.last.keys().enumerate() {
+            writeln!(writer, "$var wire 64 {} {} $end", vcd_id(index), register_name(regno))?;
+        }
+        writeln!(writer, "$upscope $end")?;
+        writeln!(writer, "$enddefinitions $end")?;
+        let mut last_time = None;
+        for &(ts, regno, value) in &self.changes {
+            // vcd time must not go backwards
+            let ts = last_time.map_or(ts, |last: u64| last.max(ts));
+            if last_time != Some(ts) {
+                writeln!(writer, "#{}", ts)?;
+                last_time = Some(ts);
+            }
+            writeln!(writer, "b{:b} {}", value, vcd_id(self.last.get_index_of(&regno).unwrap()))?;
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for RegReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if entry.event != Event::RegisterValue {
+            return Ok(());
+        }
+        let reg = entry.reg.unwrap();
+        if self.selection.as_ref().is_some_and(|selection| !selection.contains(&reg.regno)) {
+            return Ok(());
+        }
+        let ts = entry.timestamp.unwrap();
+        writeln!(self.csv, "{},{:#x},{},{:#x}", ts, entry.arc.0, register_name(reg.regno), reg.value)?;
+        if self.last.insert(reg.regno, reg.value) != Some(reg.value) {
+            self.changes.push((ts, reg.regno, reg.value));
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("{} register value changes", self.changes.len());
+        self.csv.flush()?;
+        self.write_vcd()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::frontend::region::RegionSpec;
+use crate::diagnostics::{self, Category};
+use bus::BusReader;
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use anyhow::Result;
+use log::debug;
+
+// one execution of a region
+struct Instance {
+    begin: u64,
+    end: u64,
+    insns: u64,
+    calls: u64,
+    // ended by the end of the trace rather than its end marker
+    unterminated: bool,
+}
+
+struct Region {
+    label: String,
+    begin: u64,
+    end: u64,
+    open: bool,
+    instances: Vec<Instance>,
+}
+
+/// Per-region statistics for benchmark-harness style region-of-interest
+/// measurement: a region runs from an execution of its begin marker to the
+/// next one of its end marker. Writes `trace.regions.txt`: per region the
+/// totals and the cycles, instructions and calls of every execution.
+/// Instructions have the timestamp of the last timed event, so the cycles
+/// of an execution are accurate to the block around its markers.
+pub struct RegionReceiver {
+    receiver: BusReceiver,
+    regions: Vec<Region>,
+    // function entries, a jump to one is a call
+    functions: HashSet<u64>,
+    last_ts: u64,
+}
+
+impl RegionReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, specs: &[RegionSpec]) -> Result<Self> {
+        debug!("RegionReceiver::new");
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let mut regions = Vec::new();
+        for spec in specs {
+            let (begin, end) = spec.resolve(&elf)?;
+            regions.push(Region { label: spec.to_string(), begin, end, open: false, instances: Vec::new() });
+        }
+        let functions = elf.symbols().filter(|sym| sym.kind() == SymbolKind::Text).map(|sym| sym.address()).collect();
+        Ok(RegionReceiver {
+            receiver: BusReceiver { name: "region".into(), bus_rx, checksum: 0 },
+            regions,
+            functions,
+            last_ts: 0,
+        })
+    }
+
+    fn write_report(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.regions.txt")?);
+        for (i, region) in self.regions.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            let instances = &region.instances;
+            writeln!(writer, "{}: {} executions", region.label, instances.len())?;
+            if instances.is_empty() {
+                continue;
+            }
+            let cycles: Vec<u64> = instances.iter().map(|instance| instance.end - instance.begin).collect();
+            let total_cycles: u64 = cycles.iter().sum();
+            let total_insns: u64 = instances.iter().map(|instance| instance.insns).sum();
+            writeln!(writer, "cycles: total {} min {} mean {:.2} max {}", total_cycles, cycles.iter().min().unwrap(),
+                     total_cycles as f64 / cycles.len() as f64, cycles.iter().max().unwrap())?;
+            writeln!(writer, "instructions: total {}, ipc {:.3}", total_insns, total_insns as f64 / total_cycles.max(1) as f64)?;
+            writeln!(writer, "calls: total {}", instances.iter().map(|instance| instance.calls).sum::<u64>())?;
+            writeln!(writer, "{:>6} {:>14} {:>14} {:>12} {:>12} {:>8}", "#", "begin", "end", "cycles", "insns", "calls")?;
+            for (n, instance) in instances.iter().enumerate() {
+                writeln!(writer, "{:>6} {:>14} {:>14} {:>12} {:>12} {:>8}{}", n, instance.begin, instance.end,
+                         instance.end - instance.begin, instance.insns, instance.calls,
+                         if instance.unterminated { "  (no end marker)" } else { "" })?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for RegionReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the hit count of BPHit is not a timestamp
+        if entry.event != Event::BPHit {
+            if let Some(ts) = entry.timestamp {
+                self.last_ts = ts;
+            }
+        }
+        match entry.event {
+            Event::None => {
+                let (pc, ts) = (entry.arc.0, self.last_ts);
+                for region in self.regions.iter_mut() {
+                    if region.begin == pc && !region.open {
+                        region.open = true;
+                        region.instances.push(Instance { begin: ts, end: ts, insns: 0, calls: 0, unterminated: false });
+                    }
+                    if region.open {
+                        region.instances.last_mut().unwrap().insns += 1;
+                    }
+                    if region.end == pc && region.open {
+                        region.open = false;
+                        region.instances.last_mut().unwrap().end = ts;
+                    }
+                }
+            }
+            Event::InferrableJump | Event::UninferableJump if self.functions.contains(&entry.arc.1) => {
+                // the call of the end marker is not part of the region
+                for region in self.regions.iter_mut().filter(|region| region.open && region.end != entry.arc.1) {
+                    region.instances.last_mut().unwrap().calls += 1;
+                }
+            }
+            Event::End => {
+                for region in self.regions.iter_mut().filter(|region| region.open) {
+                    diagnostics::report(Category::IncompleteOutput, format!("region {} still open at the end of the trace", region.label));
+                    region.open = false;
+                    let instance = region.instances.last_mut().unwrap();
+                    instance.end = self.last_ts;
+                    instance.unterminated = true;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
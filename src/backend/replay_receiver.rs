@@ -0,0 +1,239 @@
+use crate::backend::event::{AttrValue, Entry};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::schema::{build_info, EVENT_SCHEMA_VERSION};
+use bus::BusReader;
+use crate::backend::stack_unwinder::UnwinderOptions;
+use crate::frontend::insn_map::{code_bytes, read_code};
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayCode, OverlayMap};
+use tacit_wire::write_varint;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use object::Object;
+
+// Layout of trace.replay.bin:
+//   header: REPLAY_MAGIC, version (u16 le), xlen (u8, 32 or 64), then
+//   since version 2 the event schema version (u16 le) and the build info of
+//   the decoder that wrote it (varint length, utf-8)
+//   records: tag (u8), payload length (varint), payload
+// RECORD_INSN payload: pc (varint), raw instruction bytes
+//   emitted the first time a pc is executed, so the reader only has to
+//   disassemble the instructions that were actually retired, and again
+//   when an overlay brings other code to the pc, replacing the first one
+// RECORD_ENTRY payload: event code (u8), flags (u8), from, to (varint),
+//   then timestamp if FLAG_TIMESTAMP, hart and asid if FLAG_CTX (varint);
+//   FLAG_INSN means the entry carries the instruction defined at `from`;
+//   FLAG_TRAP adds a byte of TRAP_* bits and the varints they announce;
+//   FLAG_DATA adds the access size (varint) and a byte, 1 for stores, the
+//   address is `to`; FLAG_REG adds the register number and value (varint);
+//   FLAG_ATTRS adds the attribute count (varint) and per attribute its key,
+//   a value type (u8, ATTR_*) and its value, each length-prefixed so that
+//   values of unknown types are skipped; a u64 value is a varint
+// varints use the same encoding as the trace packets: 7 bits per byte,
+// least significant group first, the last byte has the top bit set.
+// Readers skip records with unknown tags.
+pub const REPLAY_MAGIC: &[u8; 8] = b"TACITRPL";
+pub const REPLAY_VERSION: u16 = 2;
+pub const RECORD_INSN: u8 = 1;
+pub const RECORD_ENTRY: u8 = 2;
+pub const FLAG_TIMESTAMP: u8 = 0b001;
+pub const FLAG_INSN: u8 = 0b010;
+pub const FLAG_CTX: u8 = 0b100;
+pub const FLAG_TRAP: u8 = 0b1000;
+pub const FLAG_DATA: u8 = 0b1_0000;
+pub const FLAG_REG: u8 = 0b10_0000;
+pub const FLAG_ATTRS: u8 = 0b100_0000;
+pub const TRAP_CAUSE: u8 = 0b01;
+pub const TRAP_HANDLER: u8 = 0b10;
+pub const TRAP_TVAL: u8 = 0b100;
+pub const ATTR_U64: u8 = 0;
+pub const ATTR_STR: u8 = 1;
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+// RECORD_ENTRY payload of an entry, the instruction itself is not included
+pub fn write_entry(buf: &mut Vec<u8>, entry: &Entry) {
+    let mut flags = 0;
+    if entry.timestamp.is_some() {
+        flags |= FLAG_TIMESTAMP;
+    }
+    if entry.insn.is_some() {
+        flags |= FLAG_INSN;
+    }
+    if entry.ctx.is_some() {
+        flags |= FLAG_CTX;
+    }
+    if entry.trap.is_some() {
+        flags |= FLAG_TRAP;
+    }
+    if entry.data.is_some() {
+        flags |= FLAG_DATA;
+    }
+    if entry.reg.is_some() {
+        flags |= FLAG_REG;
+    }
+    if !entry.attrs.is_empty() {
+        flags |= FLAG_ATTRS;
+    }
+    buf.push(entry.event.to_code());
+    buf.push(flags);
+    write_varint(buf, entry.arc.0);
+    write_varint(buf, entry.arc.1);
+    if let Some(timestamp) = entry.timestamp {
+        write_varint(buf, timestamp);
+    }
+    if let Some(ctx) = entry.ctx {
+        write_varint(buf, ctx.hart);
+        write_varint(buf, ctx.asid);
+    }
+    if let Some(trap) = entry.trap {
+        let mut bits = 0;
+        if trap.cause.is_some() {
+            bits |= TRAP_CAUSE;
+        }
+        if trap.handler.is_some() {
+            bits |= TRAP_HANDLER;
+        }
+        if trap.tval.is_some() {
+            bits |= TRAP_TVAL;
+        }
+        buf.push(bits);
+        for value in [trap.cause, trap.handler, trap.tval].into_iter().flatten() {
+            write_varint(buf, value);
+        }
+    }
+    if let Some(data) = entry.data {
+        write_varint(buf, data.size);
+        buf.push(data.is_store as u8);
+    }
+    if let Some(reg) = entry.reg {
+        write_varint(buf, reg.regno as u64);
+        write_varint(buf, reg.value);
+    }
+    if !entry.attrs.is_empty() {
+        write_varint(buf, entry.attrs.iter().count() as u64);
+        for (key, value) in entry.attrs.iter() {
+            write_bytes(buf, key.as_bytes());
+            match value {
+                AttrValue::U64(value) => {
+                    buf.push(ATTR_U64);
+                    let mut bytes = Vec::new();
+                    write_varint(&mut bytes, *value);
+                    write_bytes(buf, &bytes);
+                }
+                AttrValue::Str(value) => {
+                    buf.push(ATTR_STR);
+                    write_bytes(buf, value.as_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Writes the fully decoded entry stream to `trace.replay.bin`, to be fed
+/// back through the receivers later without decoding the packets again.
+pub struct ReplayReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    // the code of the binary and of the kernel modules
+    code: Vec<(u64, Vec<u8>)>,
+    // the code of the overlays, read instead of `code` while loaded
+    overlays: Option<OverlayCode>,
+    // the encoding written for each pc so far
+    defined_insns: HashMap<u64, Vec<u8>>,
+    payload: Vec<u8>,
+}
+
+impl ReplayReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let xlen: u8 = if elf.architecture() == object::Architecture::Riscv32 { 32 } else { 64 };
+        let mut code = read_code(&elf)?;
+        for module in read_module_maps(&options.modules)? {
+            code.push(module.load_code()?);
+        }
+        let overlays = match OverlayMap::from_paths(&options.overlays, &options.process_map, &options.sideband, &elf)? {
+            Some(map) => Some(OverlayCode::load(&map)?),
+            None => None,
+        };
+        let mut writer = BufWriter::new(File::create("trace.replay.bin")?);
+        writer.write_all(REPLAY_MAGIC)?;
+        writer.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        writer.write_all(&[xlen])?;
+        let mut info = EVENT_SCHEMA_VERSION.to_le_bytes().to_vec();
+        write_bytes(&mut info, build_info().as_bytes());
+        writer.write_all(&info)?;
+        Ok(ReplayReceiver {
+            writer,
+            receiver: BusReceiver { name: "replay".into(), bus_rx, checksum: 0 },
+            code,
+            overlays,
+            defined_insns: HashMap::new(),
+            payload: Vec::new(),
+        })
+    }
+
+    // the instruction at pc as the trace executed it
+    fn raw_bytes(&self, pc: u64, len: usize) -> Result<&[u8]> {
+        let bytes = match &self.overlays {
+            Some(overlays) => overlays.bytes(&self.code, pc, len),
+            None => code_bytes(&self.code, pc, len),
+        };
+        bytes.ok_or_else(|| anyhow!("replay: no instruction bytes at pc {:#x}", pc))
+    }
+
+    fn write_record(&mut self, tag: u8) -> std::io::Result<()> {
+        let mut header = vec![tag];
+        write_varint(&mut header, self.payload.len() as u64);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&self.payload)?;
+        self.payload.clear();
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for ReplayReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if let Some(insn) = &entry.insn {
+            let pc = entry.arc.0;
+            let bytes = self.raw_bytes(pc, insn.len)?;
+            if self.defined_insns.get(&pc).is_none_or(|defined| defined[..] != *bytes) {
+                let bytes = bytes.to_vec();
+                write_varint(&mut self.payload, pc);
+                self.payload.extend_from_slice(&bytes);
+                self.defined_insns.insert(pc, bytes);
+                self.write_record(RECORD_INSN)?;
+            }
+        }
+        // the instruction at `from` ran before execution reached `to`
+        if let Some(overlays) = self.overlays.as_mut() {
+            overlays.tracker.observe(entry.timestamp, entry.arc.1);
+        }
+        write_entry(&mut self.payload, &entry);
+        self.write_record(RECORD_ENTRY)?;
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::Context;
+use bus::BusReader;
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// profile.proto field numbers, see github.com/google/pprof/proto/profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_PERIOD_TYPE: u32 = 11;
+const PROFILE_PERIOD: u32 = 12;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const SAMPLE_LABEL: u32 = 3;
+const LABEL_KEY: u32 = 1;
+const LABEL_NUM: u32 = 3;
+const LOCATION_ID: u32 = 1;
+const LOCATION_ADDRESS: u32 = 3;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const LINE_LINE: u32 = 2;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_FILENAME: u32 = 4;
+const FUNCTION_START_LINE: u32 = 5;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_BYTES: u32 = 2;
+
+fn pb_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn pb_uint(buf: &mut Vec<u8>, field: u32, v: u64) {
+    pb_varint(buf, (field << 3 | WIRE_VARINT) as u64);
+    pb_varint(buf, v);
+}
+
+fn pb_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    pb_varint(buf, (field << 3 | WIRE_BYTES) as u64);
+    pb_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn pb_packed(buf: &mut Vec<u8>, field: u32, values: impl Iterator<Item = u64>) {
+    let mut packed = Vec::new();
+    values.for_each(|v| pb_varint(&mut packed, v));
+    pb_bytes(buf, field, &packed);
+}
+
+fn pb_value_type(buf: &mut Vec<u8>, field: u32, r#type: u64, unit: u64) {
+    let mut value_type = Vec::new();
+    pb_uint(&mut value_type, VALUE_TYPE_TYPE, r#type);
+    pb_uint(&mut value_type, VALUE_TYPE_UNIT, unit);
+    pb_bytes(buf, field, &value_type);
+}
+
+// how often a sample is taken
+#[derive(Clone, Copy, Debug)]
+pub enum SampleRate {
+    // every N retired instructions
+    Insns(u64),
+    // every N timestamp units
+    Period(u64),
+}
+
+/// Downsamples the trace into call stack samples: the unwinder follows every
+/// jump, so the stacks stay exact, but only one stack per `rate` is kept.
+/// Writes a sampled speedscope profile (consecutive identical stacks merged)
+/// and an uncompressed pprof profile (identical stacks aggregated), both much
+/// smaller than the evented outputs of long traces.
+pub struct SampleReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    rate: SampleRate,
+    ctx: Context,
+    insns: u64,
+    next_sample_ts: Option<u64>,
+    // per context, the runs of (stack, weight) in time order
+    runs: IndexMap<Context, Vec<(Vec<u32>, u64)>>,
+    // per (context, stack), the total weight
+    totals: IndexMap<(Context, Vec<u32>), u64>,
+}
+
+impl SampleReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, rate: SampleRate) -> Result<Self> {
+        debug!("SampleReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        Ok(SampleReceiver {
+            receiver: BusReceiver { name: "sample".into(), bus_rx, checksum: 0 },
+            unwinder,
+            rate,
+            ctx: Context::default(),
+            insns: 0,
+            next_sample_ts: None,
+            runs: IndexMap::new(),
+            totals: IndexMap::new(),
+        })
+    }
+
+    fn sample(&mut self, weight: u64) {
+        let stack = self.unwinder.frame_stack.clone();
+        let runs = self.runs.entry(self.ctx).or_default();
+        match runs.last_mut() {
+            Some((last, last_weight)) if *last == stack => *last_weight += weight,
+            _ => runs.push((stack.clone(), weight)),
+        }
+        *self.totals.entry((self.ctx, stack)).or_default() += weight;
+    }
+
+    // take the samples due by `ts`, before the stack changes
+    fn sample_until(&mut self, ts: u64) {
+        if let SampleRate::Period(period) = self.rate {
+            let next = self.next_sample_ts.get_or_insert(ts + period);
+            let mut due = 0;
+            while *next <= ts {
+                due += 1;
+                *next += period;
+            }
+            for _ in 0..due {
+                self.sample(period);
+            }
+        }
+    }
+
+    fn write_speedscope(&self) -> std::io::Result<()> {
+        let frames: Vec<_> = self.unwinder.symbols().iter()
+            .map(|info| json!({"name": info.name, "file": info.file, "line": info.line}))
+            .collect();
+        let profiles: Vec<_> = self.runs.iter().map(|(ctx, runs)| {
+            let total: u64 = runs.iter().map(|(_, weight)| weight).sum();
+            json!({
+                "type": "sampled",
+                "name": format!("hart {} asid {}", ctx.hart, ctx.asid),
+                "unit": "none",
+                "startValue": 0,
+                "endValue": total,
+                "samples": runs.iter().map(|(stack, _)| stack).collect::<Vec<_>>(),
+                "weights": runs.iter().map(|(_, weight)| weight).collect::<Vec<_>>(),
+            })
+        }).collect();
+        let profile = json!({
+            "$schema": "https://www.speedscope.app/file-format-schema.json",
+            "shared": {"frames": frames},
+            "profiles": profiles,
+        });
+        let mut writer = BufWriter::new(File::create("trace.samples.speedscope.json")?);
+        serde_json::to_writer(&mut writer, &profile)?;
+        writer.flush()
+    }
+
+    fn write_pprof(&self) -> std::io::Result<()> {
+        let mut strings: IndexMap<String, ()> = IndexMap::new();
+        let mut intern = |s: &str| -> u64 { strings.insert_full(s.to_string(), ()).0 as u64 };
+        intern("");
+        let (sample_type, unit) = match self.rate {
+            SampleRate::Insns(_) => ("instructions", "count"),
+            SampleRate::Period(_) => ("time", "ticks"),
+        };
+        let period = match self.rate {
+            SampleRate::Insns(n) | SampleRate::Period(n) => n,
+        };
+
+        let mut profile = Vec::new();
+        pb_value_type(&mut profile, PROFILE_SAMPLE_TYPE, intern("samples"), intern("count"));
+        pb_value_type(&mut profile, PROFILE_SAMPLE_TYPE, intern(sample_type), intern(unit));
+        let (hart, asid) = (intern("hart"), intern("asid"));
+        for ((ctx, stack), weight) in self.totals.iter() {
+            let mut sample = Vec::new();
+            // ids are symbol indices + 1, leaf first
+            pb_packed(&mut sample, SAMPLE_LOCATION_ID, stack.iter().rev().map(|&idx| idx as u64 + 1));
+            pb_packed(&mut sample, SAMPLE_VALUE, [weight / period, *weight].into_iter());
+            for (key, num) in [(hart, ctx.hart), (asid, ctx.asid)] {
+                let mut label = Vec::new();
+                pb_uint(&mut label, LABEL_KEY, key);
+                pb_uint(&mut label, LABEL_NUM, num);
+                pb_bytes(&mut sample, SAMPLE_LABEL, &label);
+            }
+            pb_bytes(&mut profile, PROFILE_SAMPLE, &sample);
+        }
+        for info in self.unwinder.symbols() {
+            let id = info.index as u64 + 1;
+            let mut line = Vec::new();
+            pb_uint(&mut line, LINE_FUNCTION_ID, id);
+            pb_uint(&mut line, LINE_LINE, info.line as u64);
+            let mut location = Vec::new();
+            pb_uint(&mut location, LOCATION_ID, id);
+            pb_uint(&mut location, LOCATION_ADDRESS, self.unwinder.idx_2_addr_range[&info.index].0);
+            pb_bytes(&mut location, LOCATION_LINE, &line);
+            pb_bytes(&mut profile, PROFILE_LOCATION, &location);
+
+            let mut function = Vec::new();
+            pb_uint(&mut function, FUNCTION_ID, id);
+            pb_uint(&mut function, FUNCTION_NAME, intern(&info.name));
+            pb_uint(&mut function, FUNCTION_FILENAME, intern(&info.file));
+            pb_uint(&mut function, FUNCTION_START_LINE, info.line as u64);
+            pb_bytes(&mut profile, PROFILE_FUNCTION, &function);
+        }
+        pb_value_type(&mut profile, PROFILE_PERIOD_TYPE, intern(sample_type), intern(unit));
+        pb_uint(&mut profile, PROFILE_PERIOD, period);
+        for s in strings.keys() {
+            pb_bytes(&mut profile, PROFILE_STRING_TABLE, s.as_bytes());
+        }
+
+        let mut writer = BufWriter::new(File::create("trace.samples.pb")?);
+        writer.write_all(&profile)?;
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for SampleReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                if let SampleRate::Insns(n) = self.rate {
+                    self.insns += 1;
+                    if self.insns == n {
+                        self.insns = 0;
+                        self.sample(n);
+                    }
+                }
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.sample_until(entry.timestamp.unwrap());
+                let _ = self.unwinder.step_ij(entry);
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.sample_until(entry.timestamp.unwrap());
+                let _ = self.unwinder.step_uj(entry);
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.sample_until(entry.timestamp.unwrap());
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(ctx);
+                self.ctx = ctx;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("writing {} distinct sampled stacks", self.totals.len());
+        self.write_speedscope()?;
+        self.write_pprof()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+// Versioning of the serialized event stream. The replay file, the arrow and
+// parquet exports and the websocket stream carry the event schema version
+// and the decoder that wrote them, so an archived trace stays readable as
+// the decoder evolves, and a reader refuses a schema newer than its own
+// rather than misreading it.
+use anyhow::{anyhow, Result};
+
+// bump when the meaning of the serialized entries changes: event codes,
+// fields or how a field is read. New entry attributes need no bump, readers
+// skip the ones they do not know.
+pub const EVENT_SCHEMA_VERSION: u16 = 1;
+
+// the decoder writing an output, e.g.
+// `ltrace-decoder 0.1.0 (x86_64-linux, release, features: serve)`
+pub fn build_info() -> String {
+    let features: Vec<&str> = [
+        ("otf2", cfg!(feature = "otf2")),
+        ("columnar", cfg!(feature = "columnar")),
+        ("async", cfg!(feature = "async")),
+        ("serve", cfg!(feature = "serve")),
+        ("script", cfg!(feature = "script")),
+    ].into_iter().filter(|&(_, enabled)| enabled).map(|(name, _)| name).collect();
+    format!("{} {} ({}-{}, {}, features: {})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"),
+            std::env::consts::ARCH, std::env::consts::OS,
+            if cfg!(debug_assertions) { "debug" } else { "release" },
+            if features.is_empty() { "none".to_string() } else { features.join(",") })
+}
+
+// `source` names the input in the error
+pub fn check_schema(version: u16, source: &str) -> Result<()> {
+    if version > EVENT_SCHEMA_VERSION {
+        return Err(anyhow!("{} has event schema version {}, this decoder reads up to {}", source, version, EVENT_SCHEMA_VERSION));
+    }
+    Ok(())
+}
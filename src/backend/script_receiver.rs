@@ -0,0 +1,138 @@
+use crate::backend::event::{AttrValue, Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver, TraceMetadata};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use log::debug;
+
+fn defines(ast: &AST, name: &str, params: usize) -> bool {
+    ast.iter_functions().any(|f| f.name == name && f.params.len() == params)
+}
+
+/// Runs a Rhai script over the trace, for one-off analyses that do not
+/// justify a receiver of their own. The script may define `on_start()`,
+/// `on_entry(e)`, called per event, `on_insn(e)`, called per retired
+/// instruction, and `on_end()`, all optional; they share their results
+/// through `this`, a map that lives for the whole run. `e` is a map of
+/// `event`, `from`, `to`, `timestamp` (`()` when untimed), `symbol` (the
+/// function of `from`), `hart`, `asid` and `attrs`, the entry attributes by
+/// name, plus `cause` for traps and `insn` for instructions. `print` goes to stdout; a script error fails the
+/// receiver with its position.
+pub struct ScriptReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    path: String,
+    state: Dynamic,
+    has_entry: bool,
+    has_insn: bool,
+}
+
+impl ScriptReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, path: &str) -> Result<Self> {
+        debug!("ScriptReceiver::new");
+        let mut engine = Engine::new();
+        // the default nesting limits, meant for untrusted scripts, reject
+        // ordinary ones in debug builds
+        engine.set_max_expr_depths(0, 0);
+        let ast = engine.compile_file(path.into()).map_err(|e| anyhow!("--script {}: {}", path, e))?;
+        let (has_entry, has_insn) = (defines(&ast, "on_entry", 1), defines(&ast, "on_insn", 1));
+        let mut receiver = ScriptReceiver {
+            receiver: BusReceiver { name: "script".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            engine,
+            ast,
+            scope: Scope::new(),
+            path: path.to_string(),
+            state: Dynamic::from_map(Map::new()),
+            has_entry,
+            has_insn,
+        };
+        // the top level runs once, before the first entry
+        receiver.engine.run_ast_with_scope(&mut receiver.scope, &receiver.ast)
+            .map_err(|e| anyhow!("--script {}: {}", path, e))?;
+        Ok(receiver)
+    }
+
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) -> Result<()> {
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args)
+            .map(drop)
+            .map_err(|e| anyhow!("{}: {}: {}", self.path, name, e))
+    }
+
+    fn entry_map(&self, entry: &Entry) -> Map {
+        let (from, to) = entry.arc;
+        let ctx = entry.ctx.unwrap_or_default();
+        let symbol = match self.unwinder.function_range_at(from) {
+            Some((start, _)) => self.unwinder.func_symbol_map()[&start].name.clone(),
+            None => String::new(),
+        };
+        let mut map = Map::new();
+        map.insert("event".into(), entry.event.to_string().into());
+        // addresses are integers of the script, i64
+        map.insert("from".into(), (from as i64).into());
+        map.insert("to".into(), (to as i64).into());
+        map.insert("timestamp".into(), entry.timestamp.map_or(Dynamic::UNIT, |ts| (ts as i64).into()));
+        map.insert("symbol".into(), symbol.into());
+        map.insert("hart".into(), (ctx.hart as i64).into());
+        map.insert("asid".into(), (ctx.asid as i64).into());
+        if let Some(cause) = entry.cause_text() {
+            map.insert("cause".into(), cause.into());
+        }
+        if let Some(insn) = &entry.insn {
+            map.insert("insn".into(), insn.to_string().into());
+        }
+        let mut attrs = Map::new();
+        for (key, value) in entry.attrs.iter() {
+            attrs.insert(key.into(), match value {
+                AttrValue::U64(value) => (*value as i64).into(),
+                AttrValue::Str(value) => value.clone().into(),
+            });
+        }
+        map.insert("attrs".into(), attrs.into());
+        map
+    }
+}
+
+impl AbstractReceiver for ScriptReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn on_start(&mut self, _metadata: &TraceMetadata) -> Result<()> {
+        if defines(&self.ast, "on_start", 0) {
+            self.call("on_start", ())?;
+        }
+        Ok(())
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        let name = match entry.event {
+            Event::None if self.has_insn => "on_insn",
+            Event::None => return Ok(()),
+            _ if self.has_entry => "on_entry",
+            _ => return Ok(()),
+        };
+        let map = self.entry_map(&entry);
+        self.call(name, (map,))
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        if defines(&self.ast, "on_end", 0) {
+            self.call("on_end", ())?;
+        }
+        Ok(())
+    }
+}
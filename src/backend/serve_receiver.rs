@@ -0,0 +1,166 @@
+use crate::backend::event::{AttrValue, Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::schema::{build_info, EVENT_SCHEMA_VERSION};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::json;
+use tungstenite::Message;
+use log::{info, warn};
+
+// messages queued per client before a slow client starts losing events
+const CLIENT_QUEUE: usize = 4096;
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Streams the control-flow events as json over WebSocket while decoding,
+/// with a `stats` message every second. Clients see the events decoded after
+/// they connect; a client that cannot keep up drops events instead of
+/// stalling the decoder.
+pub struct ServeReceiver {
+    receiver: BusReceiver,
+    clients: Arc<Mutex<Vec<SyncSender<String>>>>,
+    start_time: Instant,
+    last_stats: Instant,
+    insn_count: u64,
+    event_counts: HashMap<String, u64>,
+    last_timestamp: u64,
+    dropped: u64,
+}
+
+impl ServeReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, addr: &str) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("serving decoded events on ws://{}", listener.local_addr()?);
+        let clients: Arc<Mutex<Vec<SyncSender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        // the first message of every client, before any event
+        let hello = json!({ "type": "hello", "schema_version": EVENT_SCHEMA_VERSION, "decoder": build_info() }).to_string();
+        // the acceptor lives as long as the process
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut socket = match tungstenite::accept(stream) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!("websocket handshake failed: {}", e);
+                        continue;
+                    }
+                };
+                let (tx, rx) = sync_channel::<String>(CLIENT_QUEUE);
+                let _ = tx.try_send(hello.clone());
+                accepted.lock().unwrap().push(tx);
+                thread::spawn(move || {
+                    for text in rx {
+                        if socket.send(Message::text(text)).is_err() {
+                            return;
+                        }
+                    }
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                });
+            }
+        });
+        let now = Instant::now();
+        Ok(ServeReceiver {
+            receiver: BusReceiver { name: "serve".into(), bus_rx, checksum: 0 },
+            clients,
+            start_time: now,
+            last_stats: now,
+            insn_count: 0,
+            event_counts: HashMap::new(),
+            last_timestamp: 0,
+            dropped: 0,
+        })
+    }
+
+    fn send(&mut self, text: String) {
+        let mut clients = self.clients.lock().unwrap();
+        let mut dropped = 0;
+        clients.retain(|client| match client.try_send(text.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                dropped += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        self.dropped += dropped;
+    }
+
+    fn send_stats(&mut self) {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        let stats = json!({
+            "type": "stats",
+            "elapsed_s": elapsed,
+            "insn_count": self.insn_count,
+            "insns_per_s": self.insn_count as f64 / elapsed.max(1e-9),
+            "event_counts": self.event_counts,
+            "last_timestamp": self.last_timestamp,
+            "dropped_messages": self.dropped,
+        });
+        self.send(stats.to_string());
+        self.last_stats = Instant::now();
+    }
+}
+
+impl AbstractReceiver for ServeReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if entry.event == Event::None {
+            self.insn_count += 1;
+            // keep the clock check off the per-instruction path
+            if self.insn_count.is_multiple_of(4096) && self.last_stats.elapsed() >= STATS_INTERVAL {
+                self.send_stats();
+            }
+            return Ok(());
+        }
+        *self.event_counts.entry(entry.event.to_string()).or_default() += 1;
+        if let Some(timestamp) = entry.timestamp {
+            self.last_timestamp = timestamp;
+        }
+        let mut event = json!({
+            "type": "event",
+            "event": entry.event.to_string(),
+            "from": entry.arc.0,
+            "to": entry.arc.1,
+            "timestamp": entry.timestamp,
+        });
+        if let Some(ctx) = entry.ctx {
+            event["hart"] = json!(ctx.hart);
+            event["asid"] = json!(ctx.asid);
+        }
+        for (key, value) in entry.attrs.iter() {
+            event[key] = match value {
+                AttrValue::U64(value) => json!(value),
+                AttrValue::Str(value) => json!(value),
+            };
+        }
+        self.send(event.to_string());
+        if self.last_stats.elapsed() >= STATS_INTERVAL {
+            self.send_stats();
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.send_stats();
+        // dropping the senders lets the client threads drain and close
+        self.clients.lock().unwrap().clear();
+        Ok(())
+    }
+}
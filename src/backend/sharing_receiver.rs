@@ -0,0 +1,217 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// cache lines listed in the report, and writers listed per line
+const TOP_LINES: usize = 32;
+const TOP_SITES: usize = 8;
+// the bytes of a line are tracked in a u128
+const MAX_LINE_SIZE: u64 = 128;
+
+// a store: the hart, the instruction and the call stack it was made from
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Site {
+    hart: u64,
+    pc: u64,
+    stack: Vec<u64>,
+}
+
+// the last store of a hart to a line
+struct LastStore {
+    timestamp: u64,
+    bytes: u128,
+    site: Site,
+}
+
+#[derive(Default)]
+struct Line {
+    candidates: u64,
+    // stores in a candidate pair to the bytes they wrote
+    sites: HashMap<Site, (u64, u128)>,
+}
+
+/// False-sharing candidates across harts, from the data addresses in the
+/// trace: two harts storing to distinct bytes of the same cache line less
+/// than `window` cycles apart, each store with its call stack. Stores to the
+/// same bytes are true sharing and only counted. The harts come from the
+/// context switches of a sideband file or of `--correlate`; a store is timed
+/// by the last timed event before it. Writes `trace.sharing.txt`.
+pub struct SharingReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    loader: Loader,
+    line_size: u64,
+    window: u64,
+    hart: u64,
+    last_ts: u64,
+    // line address to the last store of every hart to it
+    last_stores: HashMap<u64, HashMap<u64, LastStore>>,
+    lines: HashMap<u64, Line>,
+    stores: u64,
+    true_sharing: u64,
+}
+
+impl SharingReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, line_size: u64, window: u64) -> Result<Self> {
+        debug!("SharingReceiver::new");
+        if !line_size.is_power_of_two() || line_size > MAX_LINE_SIZE {
+            return Err(anyhow!("a cache line of {} bytes is not a power of two up to {}", line_size, MAX_LINE_SIZE));
+        }
+        Ok(SharingReceiver {
+            writer: BufWriter::new(File::create("trace.sharing.txt")?),
+            receiver: BusReceiver { name: "sharing".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path.clone(), options)?,
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            line_size,
+            window,
+            hart: 0,
+            last_ts: 0,
+            last_stores: HashMap::new(),
+            lines: HashMap::new(),
+            stores: 0,
+            true_sharing: 0,
+        })
+    }
+
+    fn store(&mut self, pc: u64, address: u64, size: u64) {
+        self.stores += 1;
+        let site = Site { hart: self.hart, pc, stack: self.unwinder.current_frame_addrs() };
+        let end = address + size.max(1);
+        let mut line = address & !(self.line_size - 1);
+        // an access across lines stores to each of them
+        while line < end {
+            let first = address.max(line) - line;
+            let last = end.min(line + self.line_size) - line;
+            let bytes = (u128::MAX >> (128 - (last - first))) << first;
+            self.store_line(line, bytes, site.clone());
+            line += self.line_size;
+        }
+    }
+
+    fn store_line(&mut self, line: u64, bytes: u128, site: Site) {
+        let last_stores = self.last_stores.entry(line).or_default();
+        for (&hart, other) in last_stores.iter() {
+            if hart == site.hart || self.last_ts.saturating_sub(other.timestamp) > self.window {
+                continue;
+            }
+            if other.bytes & bytes != 0 {
+                self.true_sharing += 1;
+                continue;
+            }
+            let candidate = self.lines.entry(line).or_default();
+            candidate.candidates += 1;
+            for (site, bytes) in [(&other.site, other.bytes), (&site, bytes)] {
+                let (count, written) = candidate.sites.entry(site.clone()).or_default();
+                *count += 1;
+                *written |= bytes;
+            }
+        }
+        last_stores.insert(site.hart, LastStore { timestamp: self.last_ts, bytes, site });
+    }
+
+    fn stack_text(&self, site: &Site) -> String {
+        let names: Vec<&str> = site.stack.iter()
+            .map(|addr| self.unwinder.func_symbol_map().get(addr).map_or("?", |info| info.name.as_str()))
+            .collect();
+        if names.is_empty() { "?".to_string() } else { names.join(" > ") }
+    }
+
+    // `0-7`, or `0-3,8-11` for bytes apart
+    fn byte_ranges(bytes: u128) -> String {
+        let mut ranges = Vec::new();
+        let mut first = None;
+        for i in 0..=128 {
+            let set = i < 128 && bytes >> i & 1 == 1;
+            match (set, first) {
+                (true, None) => first = Some(i),
+                (false, Some(start)) => {
+                    ranges.push(if start == i - 1 { start.to_string() } else { format!("{}-{}", start, i - 1) });
+                    first = None;
+                }
+                _ => {}
+            }
+        }
+        ranges.join(",")
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let candidates: u64 = self.lines.values().map(|line| line.candidates).sum();
+        let mut report = format!("{} false-sharing candidates on {} cache lines of {} bytes, from {} stores: stores of distinct harts to distinct bytes of a line within {} cycles\n",
+                                 candidates, self.lines.len(), self.line_size, self.stores, self.window);
+        report += &format!("{} stores of distinct harts to the same bytes within the window are true sharing, left out\n", self.true_sharing);
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by_key(|&(&address, line)| (std::cmp::Reverse(line.candidates), address));
+        for (&address, line) in lines.iter().take(TOP_LINES) {
+            let mut harts: Vec<u64> = line.sites.keys().map(|site| site.hart).collect();
+            harts.sort();
+            harts.dedup();
+            let harts: Vec<String> = harts.iter().map(u64::to_string).collect();
+            report += &format!("\nline {:#x} ({}): {} candidates, harts {}\n", address,
+                               self.loader.find_symbol(address).unwrap_or("?"), line.candidates, harts.join(", "));
+            let mut sites: Vec<_> = line.sites.iter().collect();
+            sites.sort_by_key(|&(site, &(count, _))| (std::cmp::Reverse(count), site.hart, site.pc));
+            for (site, &(count, bytes)) in sites.iter().take(TOP_SITES) {
+                report += &format!("  hart {:<3} bytes {:<10} {:>10} stores at {:#010x}  {}\n", site.hart,
+                                   Self::byte_ranges(bytes), count, site.pc, self.stack_text(site));
+            }
+        }
+        self.writer.write_all(report.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl AbstractReceiver for SharingReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::DataAccess => {
+                let data = entry.data.unwrap();
+                if data.is_store {
+                    self.store(entry.arc.0, data.address, data.size);
+                }
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.unwinder.switch_context(ctx);
+                self.hart = ctx.hart;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.last_ts = entry.timestamp.unwrap();
+                self.unwinder.step_ij(entry);
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.last_ts = entry.timestamp.unwrap();
+                self.unwinder.step_uj(entry);
+            }
+            Event::Start | Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.last_ts = entry.timestamp.unwrap();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -1,14 +1,19 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::StackUnwinder;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::backend::phase::{PhaseBoundary, PhaseSplitter};
+use crate::frontend::context::Context;
+use crate::diagnostics::{self, Category};
 
 use bus::BusReader;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use indexmap::IndexMap;
 
 use serde_json::{json, Value};
 use serde::Serialize;
 
+use anyhow::Result;
 use log::{debug, warn};
 
 
@@ -20,32 +25,38 @@ pub struct ProfileEntry {
 }
 
 pub struct SpeedscopeReceiver {
-    writer: BufWriter<File>,
     receiver: BusReceiver,
     frames: Vec<Value>, 
     start: u64,
     end: u64,
-    profile_entries: Vec<ProfileEntry>,
+    // one profile per context, each written to its own file
+    profiles: IndexMap<Context, Vec<ProfileEntry>>,
+    ctx: Context,
     stack_unwinder: StackUnwinder,
+    // with a phase boundary, the profiles up to it once it is crossed
+    phase: Option<PhaseSplitter>,
+    warmup: Option<IndexMap<Context, Vec<ProfileEntry>>>,
 }
 
 impl SpeedscopeReceiver {
     
-    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Self {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
         debug!("SpeedscopeReceiver::new");
         
         // create the stack unwinder
-        let stack_unwinder = StackUnwinder::new(elf_path.clone()).unwrap();
+        let stack_unwinder = StackUnwinder::new(elf_path.clone(), options)?;
+        Ok(Self::with_unwinder(bus_rx, stack_unwinder))
+    }
 
+    pub fn with_unwinder(bus_rx: BusReader<Entry>, stack_unwinder: StackUnwinder) -> Self {
 
         // for each function symbol, add a frame to the frames vector
         let mut frames = Vec::new();
-        for (_, func_info) in stack_unwinder.func_symbol_map().iter() {
+        for func_info in stack_unwinder.symbols() {
             frames.push(json!({"name": func_info.name, "line": func_info.line, "file": func_info.file}));
         }
 
         Self { 
-            writer: BufWriter::new(File::create("trace.speedscope.json").unwrap()),
             receiver: BusReceiver { 
                 name: "speedscope".to_string(), 
                 bus_rx, 
@@ -55,8 +66,141 @@ impl SpeedscopeReceiver {
             start: 0,
             end: 0,
             stack_unwinder,
-            profile_entries: Vec::new(),
+            profiles: IndexMap::new(),
+            ctx: Context::default(),
+            phase: None,
+            warmup: None,
+        }
+    }
+
+    // split the profiles at the boundary into a warmup and a steady state
+    // part, each written on its own
+    pub fn with_phase(mut self, boundary: &PhaseBoundary) -> Result<Self> {
+        self.phase = Some(PhaseSplitter::new(boundary, &self.stack_unwinder)?);
+        Ok(self)
+    }
+
+    // closes the open frames of every context at ts into the warmup
+    // profiles and reopens them in the steady state ones
+    fn split_phase(&mut self, ts: u64) {
+        let current = self.ctx;
+        let mut ctxs: Vec<Context> = self.profiles.keys().cloned().collect();
+        if !ctxs.contains(&current) {
+            ctxs.push(current);
+        }
+        let mut warmup = IndexMap::new();
+        for ctx in ctxs {
+            self.stack_unwinder.switch_context(ctx);
+            let stack = self.stack_unwinder.frame_stack.clone();
+            let mut entries = self.profiles.shift_remove(&ctx).unwrap_or_default();
+            entries.extend(stack.iter().rev().map(|&frame| ProfileEntry { r#type: "C".to_string(), frame, at: ts }));
+            warmup.insert(ctx, entries);
+            self.profiles.insert(ctx, stack.iter().map(|&frame| ProfileEntry { r#type: "O".to_string(), frame, at: ts }).collect());
+        }
+        self.stack_unwinder.switch_context(current);
+        self.warmup = Some(warmup);
+    }
+
+    fn push_entry(&mut self, r#type: &str, frame: u32, at: u64) {
+        self.profiles.entry(self.ctx).or_default().push(ProfileEntry {
+            r#type: r#type.to_string(),
+            frame,
+            at,
+        });
+    }
+
+    fn write_profile(&self, path: &str, span: (u64, u64), profile_entries: &[ProfileEntry]) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_profile_to(&mut writer, span, profile_entries)
+    }
+
+    // the profiles of every context, one file per context unless single,
+    // spanning [start, end]
+    fn write_profiles(&self, prefix: &str, span: (u64, u64), profiles: &IndexMap<Context, Vec<ProfileEntry>>, single: bool) -> std::io::Result<()> {
+        if single {
+            let entries = profiles.get(&Context::default()).map(|v| v.as_slice()).unwrap_or(&[]);
+            self.write_profile(&format!("{}.json", prefix), span, entries)
+        } else {
+            for (ctx, entries) in profiles.iter() {
+                self.write_profile(&format!("{}.{}.json", prefix, ctx.file_suffix()), span, entries)?;
+            }
+            Ok(())
+        }
+    }
+
+    // folded stacks, `caller;callee cycles` per distinct stack, the cycles
+    // between two events going to the stack open in between
+    fn write_folded(&self, path: &str, span: (u64, u64), profiles: &IndexMap<Context, Vec<ProfileEntry>>) -> std::io::Result<()> {
+        let mut folded: IndexMap<String, u64> = IndexMap::new();
+        for entries in profiles.values() {
+            let mut stack: Vec<u32> = Vec::new();
+            let mut last = span.0;
+            for entry in entries {
+                if !stack.is_empty() && entry.at > last {
+                    let names: Vec<&str> = stack.iter().map(|&frame| self.frames[frame as usize]["name"].as_str().unwrap()).collect();
+                    *folded.entry(names.join(";")).or_default() += entry.at - last;
+                }
+                last = entry.at;
+                if entry.r#type == "O" {
+                    stack.push(entry.frame);
+                } else if let Some(depth) = stack.iter().rposition(|&frame| frame == entry.frame) {
+                    stack.truncate(depth);
+                }
+            }
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (stack, cycles) in folded {
+            writeln!(writer, "{} {}", stack, cycles)?;
         }
+        writer.flush()
+    }
+
+    fn write_profile_to<W: Write>(&self, writer: &mut W, span: (u64, u64), profile_entries: &[ProfileEntry]) -> std::io::Result<()> {
+
+        // Write the JSON structure manually in a deterministic order
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"version\": \"0.0.1\",")?;
+        writeln!(writer, "  \"$schema\": \"https://www.speedscope.app/file-format-schema.json\",")?;
+        writeln!(writer, "  \"shared\": {{")?;
+        writeln!(writer, "    \"frames\": [")?;
+        
+        // Write frames in order
+        for (i, frame) in self.frames.iter().enumerate() {
+            let comma = if i < self.frames.len() - 1 { "," } else { "" };
+            writeln!(writer, "      {{")?;
+            writeln!(writer, "        \"name\": \"{}\",", frame["name"].as_str().unwrap())?;
+            writeln!(writer, "        \"file\": \"{}\",", frame["file"].as_str().unwrap())?;
+            writeln!(writer, "        \"line\": {}", frame["line"].as_u64().unwrap())?;
+            writeln!(writer, "      }}{}", comma)?;
+        }
+        
+        writeln!(writer, "    ]")?;
+        writeln!(writer, "  }},")?;
+        writeln!(writer, "  \"profiles\": [")?;
+        writeln!(writer, "    {{")?;
+        writeln!(writer, "      \"name\": \"tacit\",")?;
+        writeln!(writer, "      \"type\": \"evented\",")?;
+        writeln!(writer, "      \"unit\": \"none\",")?;
+        writeln!(writer, "      \"startValue\": {},", span.0)?;
+        writeln!(writer, "      \"endValue\": {},", span.1)?;
+        writeln!(writer, "      \"events\": [")?;
+        
+        // Write profile entries in order
+        for (i, entry) in profile_entries.iter().enumerate() {
+            let comma = if i < profile_entries.len() - 1 { "," } else { "" };
+            writeln!(writer, "        {{")?;
+            writeln!(writer, "          \"type\": \"{}\",", entry.r#type)?;
+            writeln!(writer, "          \"frame\": {},", entry.frame)?;
+            writeln!(writer, "          \"at\": {}", entry.at)?;
+            writeln!(writer, "        }}{}", comma)?;
+        }
+        
+        writeln!(writer, "      ]")?;
+        writeln!(writer, "    }}")?;
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")?;
+        
+        writer.flush()
     }
 }
 
@@ -70,38 +214,42 @@ impl AbstractReceiver for SpeedscopeReceiver {
         self.receiver.checksum += 1;
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the entry crossing the boundary belongs to the steady state
+        if self.phase.as_mut().is_some_and(|phase| phase.crosses(&entry)) {
+            self.split_phase(entry.timestamp.unwrap());
+        }
         match entry.event {
             Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
                 let (success, _frame_stack_size, opened_frame) = self.stack_unwinder.step_ij(entry.clone());
                 if success {
-                    self.profile_entries.push(ProfileEntry {
-                        r#type: "O".to_string(), // opening a frame
-                        frame: opened_frame.unwrap().index,
-                        at: entry.timestamp.unwrap(),
-                    });
+                    // opening a frame
+                    self.push_entry("O", opened_frame.unwrap().index, entry.timestamp.unwrap());
                 }
             }
             Event::UninferableJump | Event::TrapReturn => {
                 let (success, _frame_stack_size, closed_frames, opened_frame) = self.stack_unwinder.step_uj(entry.clone());
                 if success {
                     for frame in closed_frames {
-                        self.profile_entries.push(ProfileEntry {
-                            r#type: "C".to_string(), // closing a frame
-                            frame: frame.index,
-                            at: entry.timestamp.unwrap(),
-                        });
+                        // closing a frame
+                        self.push_entry("C", frame.index, entry.timestamp.unwrap());
                     }
                 }
                 if let Some(opened_frame) = opened_frame {
                     warn!("tail call detected");
-                    self.profile_entries.push(ProfileEntry {
-                        r#type: "O".to_string(), // opening a frame
-                        frame: opened_frame.index,
-                        at: entry.timestamp.unwrap(),
-                    });
+                    // opening a frame
+                    self.push_entry("O", opened_frame.index, entry.timestamp.unwrap());
                 }
             }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.stack_unwinder.switch_context(ctx);
+                self.ctx = ctx;
+            }
             Event::Start => {
                 // debug!("start: {}", entry.timestamp.unwrap());
                 self.start = entry.timestamp.unwrap();
@@ -114,69 +262,61 @@ impl AbstractReceiver for SpeedscopeReceiver {
                 // do nothing
             }
         }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        let ctxs = self.close_all_frames();
+
+        // a trace without context switches keeps the single-file output
+        let single = ctxs.iter().all(|ctx| *ctx == Context::default());
+        let Some(phase) = self.phase.as_ref() else {
+            self.write_profiles("trace.speedscope", (self.start, self.end), &self.profiles, single)?;
+            return Ok(());
+        };
+        let split = phase.split_at().unwrap_or_else(|| {
+            diagnostics::report(Category::IncompleteOutput, "the phase boundary was never crossed, the whole trace is warmup".to_string());
+            self.end
+        });
+        let (warmup, steady) = match self.warmup.take() {
+            Some(warmup) => (warmup, std::mem::take(&mut self.profiles)),
+            None => (std::mem::take(&mut self.profiles), IndexMap::new()),
+        };
+        for (name, span, profiles) in [("warmup", (self.start, split), &warmup), ("steady", (split, self.end), &steady)] {
+            self.write_profiles(&format!("trace.speedscope.{}", name), span, profiles, single)?;
+            self.write_folded(&format!("trace.folded.{}.txt", name), span, profiles)?;
+        }
+        Ok(())
     }
+}
 
-    fn _flush(&mut self) {
+impl SpeedscopeReceiver {
+    // ends the trace and returns the contexts that have a profile
+    pub fn close_all_frames(&mut self) -> Vec<Context> {
         // if there's no end time, set it to the last timestamp
         if self.end == 0 {
-            self.end = self.profile_entries.last().unwrap().at;
+            self.end = self.profiles.values().filter_map(|entries| entries.last()).map(|e| e.at).max().unwrap_or(self.start);
         }
         
-        // forcefully close all open frames
-        let closed_frames = self.stack_unwinder.flush();
-        for frame in closed_frames {
-            self.profile_entries.push(ProfileEntry {
-                r#type: "C".to_string(), // closing a frame
-                frame: frame.index,
-                at: self.end,
-            });
+        // forcefully close all open frames, in every context
+        let ctxs: Vec<Context> = self.profiles.keys().cloned().collect();
+        for ctx in ctxs.iter() {
+            self.stack_unwinder.switch_context(*ctx);
+            self.ctx = *ctx;
+            let closed_frames = self.stack_unwinder.flush();
+            for frame in closed_frames {
+                // closing a frame
+                self.push_entry("C", frame.index, self.end);
+            }
         }
+        ctxs
+    }
 
-
-        
-        // Write the JSON structure manually in a deterministic order
-        writeln!(self.writer, "{{").unwrap();
-        writeln!(self.writer, "  \"version\": \"0.0.1\",").unwrap();
-        writeln!(self.writer, "  \"$schema\": \"https://www.speedscope.app/file-format-schema.json\",").unwrap();
-        writeln!(self.writer, "  \"shared\": {{").unwrap();
-        writeln!(self.writer, "    \"frames\": [").unwrap();
-        
-        // Write frames in order
-        for (i, frame) in self.frames.iter().enumerate() {
-            let comma = if i < self.frames.len() - 1 { "," } else { "" };
-            writeln!(self.writer, "      {{").unwrap();
-            writeln!(self.writer, "        \"name\": \"{}\",", frame["name"].as_str().unwrap()).unwrap();
-            writeln!(self.writer, "        \"file\": \"{}\",", frame["file"].as_str().unwrap()).unwrap();
-            writeln!(self.writer, "        \"line\": {}", frame["line"].as_u64().unwrap()).unwrap();
-            writeln!(self.writer, "      }}{}", comma).unwrap();
-        }
-        
-        writeln!(self.writer, "    ]").unwrap();
-        writeln!(self.writer, "  }},").unwrap();
-        writeln!(self.writer, "  \"profiles\": [").unwrap();
-        writeln!(self.writer, "    {{").unwrap();
-        writeln!(self.writer, "      \"name\": \"tacit\",").unwrap();
-        writeln!(self.writer, "      \"type\": \"evented\",").unwrap();
-        writeln!(self.writer, "      \"unit\": \"none\",").unwrap();
-        writeln!(self.writer, "      \"startValue\": {},", self.start).unwrap();
-        writeln!(self.writer, "      \"endValue\": {},", self.end).unwrap();
-        writeln!(self.writer, "      \"events\": [").unwrap();
-        
-        // Write profile entries in order
-        for (i, entry) in self.profile_entries.iter().enumerate() {
-            let comma = if i < self.profile_entries.len() - 1 { "," } else { "" };
-            writeln!(self.writer, "        {{").unwrap();
-            writeln!(self.writer, "          \"type\": \"{}\",", entry.r#type).unwrap();
-            writeln!(self.writer, "          \"frame\": {},", entry.frame).unwrap();
-            writeln!(self.writer, "          \"at\": {}", entry.at).unwrap();
-            writeln!(self.writer, "        }}{}", comma).unwrap();
-        }
-        
-        writeln!(self.writer, "      ]").unwrap();
-        writeln!(self.writer, "    }}").unwrap();
-        writeln!(self.writer, "  ]").unwrap();
-        writeln!(self.writer, "}}").unwrap();
-        
-        self.writer.flush().unwrap();
+    // the speedscope json of one context, after close_all_frames
+    pub fn profile_json(&self, ctx: Context) -> String {
+        let entries = self.profiles.get(&ctx).map(|v| v.as_slice()).unwrap_or(&[]);
+        let mut buffer = Vec::new();
+        self.write_profile_to(&mut buffer, (self.start, self.end), entries).unwrap();
+        String::from_utf8(buffer).unwrap()
     }
 }
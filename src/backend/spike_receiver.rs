@@ -0,0 +1,94 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::UnwinderOptions;
+use crate::frontend::insn_map::{code_bytes, read_code};
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayCode, OverlayMap};
+use bus::BusReader;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Writes the retired instructions in the format of spike's `-l` log
+/// (`core   0: 0x... (0x...) insn`) so spike-based diff scripts can consume it.
+pub struct SpikeReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    // the code of the binary and of the kernel modules, for the raw encodings
+    code: Vec<(u64, Vec<u8>)>,
+    // the code of the overlays, read instead of `code` while loaded
+    overlays: Option<OverlayCode>,
+    hart: u64,
+}
+
+impl SpikeReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let mut code = read_code(&elf)?;
+        for module in read_module_maps(&options.modules)? {
+            code.push(module.load_code()?);
+        }
+        let overlays = match OverlayMap::from_paths(&options.overlays, &options.process_map, &options.sideband, &elf)? {
+            Some(map) => Some(OverlayCode::load(&map)?),
+            None => None,
+        };
+        Ok(SpikeReceiver {
+            writer: BufWriter::new(File::create("trace.spike.log")?),
+            receiver: BusReceiver { name: "spike".into(), bus_rx, checksum: 0 },
+            code,
+            overlays,
+            hart: 0,
+        })
+    }
+
+    // the encoding of the instruction at pc as the trace executed it, 0 when
+    // no code covers it
+    fn raw_bits(&self, pc: u64, len: usize) -> u64 {
+        let bytes = match &self.overlays {
+            Some(overlays) => overlays.bytes(&self.code, pc, len),
+            None => code_bytes(&self.code, pc, len),
+        };
+        bytes.map_or(0, |bytes| bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+    }
+}
+
+impl AbstractReceiver for SpikeReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                if let Some(insn) = entry.insn {
+                    let pc = entry.arc.0;
+                    let bits = self.raw_bits(pc, insn.len);
+                    writeln!(self.writer, "core {:3}: 0x{:016x} (0x{:08x}) {}", self.hart, pc, bits, insn)?;
+                }
+            }
+            Event::ContextSwitch => {
+                self.hart = entry.ctx.unwrap().hart;
+            }
+            _ => {}
+        }
+        // the instruction at `from` ran before execution reached `to`
+        if let Some(overlays) = self.overlays.as_mut() {
+            overlays.tracker.observe(entry.timestamp, entry.arc.1);
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// a back edge jumping at most this many bytes back closes a tight loop
+const MAX_BACK_EDGE: u64 = 64;
+// a spinning loop runs at most this many distinct instructions
+const MAX_DISTINCT_PCS: usize = 16;
+// and goes around at least this many times in a row
+const MIN_ITERATIONS: u64 = 32;
+
+// what the loop waits with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SpinKind {
+    LrSc,
+    Amo,
+    Polling,
+}
+
+impl SpinKind {
+    fn name(&self) -> &'static str {
+        match self {
+            SpinKind::LrSc => "lr/sc spinlock",
+            SpinKind::Amo => "amo spinlock",
+            SpinKind::Polling => "polling loop",
+        }
+    }
+}
+
+// the loop running now, closed by the back edge tail -> head
+struct Run {
+    head: u64,
+    tail: u64,
+    iterations: u64,
+    cycles: u64,
+    last_ts: u64,
+    pcs: HashSet<u64>,
+    names: HashSet<String>,
+}
+
+// the spinning runs of one loop
+#[derive(Default)]
+struct Spin {
+    runs: u64,
+    iterations: u64,
+    cycles: u64,
+    longest: u64,
+    distinct_pcs: usize,
+    names: HashSet<String>,
+}
+
+impl Spin {
+    fn kind(&self) -> SpinKind {
+        let has = |prefix: &str| self.names.iter().any(|name| name.starts_with(prefix));
+        if has("lr.") && has("sc.") {
+            SpinKind::LrSc
+        } else if has("amo") {
+            SpinKind::Amo
+        } else {
+            SpinKind::Polling
+        }
+    }
+}
+
+/// Busy-waits: tight loops, closed by a short back edge, that go around many
+/// times in a row over few distinct instructions. Writes `trace.spin.txt`,
+/// the loops by the cycles they spun for, telling lr/sc and amo spinlocks
+/// from loops polling memory or a device.
+pub struct SpinReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    loader: Loader,
+    run: Option<Run>,
+    // (head, tail) to the spinning runs of the loop
+    spins: HashMap<(u64, u64), Spin>,
+}
+
+impl SpinReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Result<Self> {
+        debug!("SpinReceiver::new");
+        Ok(SpinReceiver {
+            writer: BufWriter::new(File::create("trace.spin.txt")?),
+            receiver: BusReceiver { name: "spin".into(), bus_rx, checksum: 0 },
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            run: None,
+            spins: HashMap::new(),
+        })
+    }
+
+    fn end_run(&mut self) {
+        let Some(run) = self.run.take() else { return };
+        if run.iterations < MIN_ITERATIONS || run.pcs.len() > MAX_DISTINCT_PCS {
+            return;
+        }
+        let spin = self.spins.entry((run.head, run.tail)).or_default();
+        spin.runs += 1;
+        spin.iterations += run.iterations;
+        spin.cycles += run.cycles;
+        spin.longest = spin.longest.max(run.iterations);
+        spin.distinct_pcs = spin.distinct_pcs.max(run.pcs.len());
+        spin.names.extend(run.names);
+    }
+
+    // a control transfer from `from` to `to` at ts
+    fn transfer(&mut self, from: u64, to: u64, ts: u64) {
+        if let Some(run) = &mut self.run {
+            if run.head == to && run.tail == from {
+                run.iterations += 1;
+                run.cycles += ts.saturating_sub(run.last_ts);
+                run.last_ts = ts;
+                return;
+            }
+            if run.head <= from && from <= run.tail && run.head <= to && to <= run.tail {
+                // a branch inside the loop body
+                return;
+            }
+        }
+        self.end_run();
+        if to <= from && from - to <= MAX_BACK_EDGE {
+            self.run = Some(Run {
+                head: to,
+                tail: from,
+                iterations: 1,
+                cycles: 0,
+                last_ts: ts,
+                pcs: HashSet::new(),
+                names: HashSet::new(),
+            });
+        }
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let mut spins: Vec<_> = self.spins.iter().collect();
+        spins.sort_by_key(|&(&loop_arc, spin)| (std::cmp::Reverse(spin.cycles), loop_arc));
+        let total: u64 = spins.iter().map(|(_, spin)| spin.cycles).sum();
+        writeln!(self.writer, "{} spinning loops, {} cycles of waiting", spins.len(), total)?;
+        for (&(head, tail), spin) in spins {
+            let location = self.loader.find_location(head).ok().flatten()
+                .map(|l| format!("{}:{}", l.file.unwrap_or("?"), l.line.unwrap_or(0)))
+                .unwrap_or_else(|| "?".to_string());
+            writeln!(self.writer, "{:#x}..{:#x} {} ({}): {}", head, tail, self.loader.find_symbol(head).unwrap_or("?"),
+                     location, spin.kind().name())?;
+            writeln!(self.writer, "  {} cycles, {} iterations in {} runs, longest {}, {} distinct instructions",
+                     spin.cycles, spin.iterations, spin.runs, spin.longest, spin.distinct_pcs)?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl AbstractReceiver for SpinReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::None => {
+                if let (Some(run), Some(insn)) = (&mut self.run, &entry.insn) {
+                    if run.pcs.insert(entry.arc.0) {
+                        run.names.insert(insn.get_name());
+                    }
+                }
+            }
+            Event::TakenBranch | Event::InferrableJump | Event::UninferableJump => {
+                self.transfer(entry.arc.0, entry.arc.1, entry.timestamp.unwrap());
+            }
+            // the loop exits through its back edge not taken
+            Event::NonTakenBranch if self.run.as_ref().is_some_and(|run| run.tail == entry.arc.0) => {
+                self.end_run();
+            }
+            Event::TrapException | Event::TrapInterrupt | Event::TrapReturn | Event::End
+            | Event::ContextSwitch | Event::Resync => {
+                self.end_run();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.end_run();
+        self.write_report()?;
+        Ok(())
+    }
+}
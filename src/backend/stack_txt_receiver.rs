@@ -1,21 +1,41 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo};
+use crate::backend::stack_unwinder::{StackUnwinder, SymbolInfo, UnwinderOptions};
 use bus::BusReader;
+use anyhow::Result;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::collections::BTreeMap;
 
+// ` (aka a, b)` when the unwinder kept the other names at the address, and
+// ` [dynamic]` for runtime-generated code
+fn alias_suffix(info: &SymbolInfo) -> String {
+    let mut suffix = String::new();
+    if !info.aliases.is_empty() {
+        suffix += &format!(" (aka {})", info.aliases.join(", "));
+    }
+    if info.is_dynamic() {
+        suffix += " [dynamic]";
+    }
+    suffix
+}
+
+/// Writes `trace.stack.txt`, the call stack after every jump and trap. In
+/// delta mode only the frames entered and left are written, uftrace style,
+/// indented by their depth, which keeps the output to a line per call and
+/// return. Frames deeper than `max_depth` are left out, 0 for no limit.
 pub struct StackTxtReceiver {
     writer: BufWriter<File>,
     receiver: BusReceiver,
     stack_unwinder: StackUnwinder,
     symbol_index: BTreeMap<u64, SymbolInfo>,
+    delta: bool,
+    max_depth: usize,
 }
 
 impl StackTxtReceiver {
-    pub fn new(bus_rx: BusReader<Entry>, elf_path: String) -> Self {
-        let stack_unwinder = StackUnwinder::new(elf_path.clone()).unwrap();
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, delta: bool, max_depth: usize) -> Result<Self> {
+        let stack_unwinder = StackUnwinder::new(elf_path.clone(), options)?;
 
         // Build a map from function start address -> SymbolInfo
         let mut symbol_index = BTreeMap::new();
@@ -23,12 +43,34 @@ impl StackTxtReceiver {
             symbol_index.insert(addr, info.clone());
         }
 
-        StackTxtReceiver {
-            writer: BufWriter::new(File::create("trace.stack.txt").unwrap()),
+        Ok(StackTxtReceiver {
+            writer: BufWriter::new(File::create("trace.stack.txt")?),
             receiver: BusReceiver { name: "stacktxt".into(), bus_rx, checksum: 0 },
             stack_unwinder,
             symbol_index,
+            delta,
+            max_depth,
+        })
+    }
+
+    fn too_deep(&self, depth: usize) -> bool {
+        self.max_depth > 0 && depth > self.max_depth
+    }
+
+    // `name() {` at the depth of the frame, the cause of a trap after it
+    fn write_enter(&mut self, ts: u64, depth: usize, info: &SymbolInfo, cause: Option<String>) -> std::io::Result<()> {
+        if self.too_deep(depth) {
+            return Ok(());
+        }
+        let cause = cause.map(|cause| format!(" /* {} */", cause)).unwrap_or_default();
+        writeln!(self.writer, "[timestamp: {}] {:indent$}{}() {{{}", ts, "", info.name, cause, indent = 2 * depth.saturating_sub(1))
+    }
+
+    fn write_exit(&mut self, ts: u64, depth: usize, info: &SymbolInfo) -> std::io::Result<()> {
+        if self.too_deep(depth) {
+            return Ok(());
         }
+        writeln!(self.writer, "[timestamp: {}] {:indent$}}} /* {} */", ts, "", info.name, indent = 2 * depth.saturating_sub(1))
     }
 
     /// Look up the symbol whose start address is the greatest <= PC
@@ -41,15 +83,50 @@ impl StackTxtReceiver {
         writeln!(self.writer, "  Call stack:")?;
         // This requires you add to StackUnwinder:
         //    pub fn current_frame_addrs(&self) -> &[u64];
-        for frame_addr in self.stack_unwinder.current_frame_addrs() {
-            let info = &self.symbol_index[&frame_addr];
-            writeln!(self.writer, "    {} @ 0x{:x}", info.name, frame_addr)?;
+        let frames = self.stack_unwinder.current_frame_addrs();
+        let depth = if self.max_depth > 0 { self.max_depth.min(frames.len()) } else { frames.len() };
+        for &frame_addr in &frames[..depth] {
+            let info = &self.stack_unwinder.get_symbol_info(frame_addr);
+            writeln!(self.writer, "    {}{} @ 0x{:x}", info.name, alias_suffix(info), frame_addr)?;
         }
         writeln!(self.writer)?;
         Ok(())
     }
 }
 
+impl StackTxtReceiver {
+    fn receive_delta(&mut self, entry: Entry) -> std::io::Result<()> {
+        let ts = entry.timestamp.unwrap_or(0);
+        match entry.event {
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let cause = entry.cause_text();
+                if let (true, depth, Some(opened)) = self.stack_unwinder.step_ij(entry) {
+                    self.write_enter(ts, depth, &opened, cause)?;
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let (_, depth, closed, opened) = self.stack_unwinder.step_uj(entry);
+                // the depth the closed frames were popped down to
+                let base = depth - opened.is_some() as usize;
+                for (i, info) in closed.iter().enumerate() {
+                    self.write_exit(ts, base + closed.len() - i, info)?;
+                }
+                if let Some(opened) = opened {
+                    self.write_enter(ts, depth, &opened, None)?;
+                }
+            }
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                self.stack_unwinder.switch_context(ctx);
+                writeln!(self.writer, "[timestamp: {}] {:?} -> hart {} asid {}, depth {}", ts, entry.event, ctx.hart, ctx.asid,
+                         self.stack_unwinder.frame_stack.len())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
 impl AbstractReceiver for StackTxtReceiver {
     fn bus_rx(&mut self) -> &mut BusReader<Entry> {
         &mut self.receiver.bus_rx
@@ -59,24 +136,36 @@ impl AbstractReceiver for StackTxtReceiver {
         self.receiver.checksum += 1;
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if self.delta {
+            self.receive_delta(entry)?;
+            return Ok(());
+        }
         match entry.event {
             Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
                 let ts = entry.timestamp.unwrap_or(0);
-                let pc = entry.arc.1;
+                let pc = entry.trap.and_then(|trap| trap.handler).unwrap_or(entry.arc.1);
 
                 // update the unwinder’s internal stack
                 let _ = self.stack_unwinder.step_ij(entry.clone());
 
                 // describe the new PC
                 let sym_desc = if let Some((start, info)) = self.lookup_symbol(pc) {
-                    format!("{} @ 0x{:x}", info.name, start)
+                    format!("{}{} @ 0x{:x}", info.name, alias_suffix(info), start)
                 } else {
                     format!("0x{:x}", pc)
                 };
 
-                writeln!(self.writer, "[timestamp: {}] {:?} -> {}", ts, entry.event, sym_desc).unwrap();
-                self.dump_current_stack().unwrap();
+                let cause = match entry.cause_text() {
+                    Some(cause) => format!(" ({})", cause),
+                    None => String::new(),
+                };
+                writeln!(self.writer, "[timestamp: {}] {:?}{} -> {}", ts, entry.event, cause, sym_desc)?;
+                self.dump_current_stack()?;
             }
 
             Event::UninferableJump | Event::TrapReturn => {
@@ -87,19 +176,32 @@ impl AbstractReceiver for StackTxtReceiver {
                 let _ = self.stack_unwinder.step_uj(entry.clone());
 
                 let sym_desc = if let Some((start, info)) = self.lookup_symbol(pc) {
-                    format!("{} @ 0x{:x}", info.name, start)
+                    format!("{}{} @ 0x{:x}", info.name, alias_suffix(info), start)
                 } else {
                     format!("0x{:x}", pc)
                 };
 
-                writeln!(self.writer, "[timestamp: {}] {:?} -> {}", ts, entry.event, sym_desc).unwrap();
-                self.dump_current_stack().unwrap();
+                writeln!(self.writer, "[timestamp: {}] {:?} -> {}", ts, entry.event, sym_desc)?;
+                self.dump_current_stack()?;
+            }
+
+            Event::ContextSwitch => {
+                let ts = entry.timestamp.unwrap_or(0);
+                let ctx = entry.ctx.unwrap();
+
+                // each context unwinds its own stack
+                self.stack_unwinder.switch_context(ctx);
+
+                writeln!(self.writer, "[timestamp: {}] {:?} -> hart {} asid {}", ts, entry.event, ctx.hart, ctx.asid)?;
+                self.dump_current_stack()?;
             }
             _ => {}
         }
+        Ok(())
     }
 
-    fn _flush(&mut self) {
-        self.writer.flush().unwrap();
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
     }
 }
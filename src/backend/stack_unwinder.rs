@@ -1,23 +1,28 @@
 use indexmap::IndexMap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 // objdump dependency
-use rvdasm::disassembler::*;
 use rvdasm::insn::*;
-use object::{Object, ObjectSection, ObjectSymbol, SectionFlags};
+use object::{Object, ObjectSection, ObjectSymbol, SectionFlags, SymbolKind};
 use object::elf::SHF_EXECINSTR;
 
 use std::fs::File;
 use std::io::Read;
 use gcno_reader::cfg::SourceLocation;
 
-use std::fs;
-use addr2line::Loader;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use addr2line::gimli;
 
-use log::{trace, debug, warn};
+use log::{trace, debug};
 use anyhow::Result;
 
 use crate::backend::event::{Entry, Event};
+use crate::frontend::context::Context;
+use crate::frontend::insn_map::build_insn_map;
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayInsns, OverlayMap};
+use crate::diagnostics::{self, Category};
 
 // everything you need to know about a symbol
 #[derive(Clone)]
@@ -26,6 +31,43 @@ pub struct SymbolInfo {
     pub index: u32, 
     pub line: u32,
     pub file: String,
+    // the other names at the same address, only kept when asked for
+    pub aliases: Vec<String>,
+}
+
+impl SymbolInfo {
+    // named by a map file rather than the ELF
+    pub fn is_dynamic(&self) -> bool {
+        self.file == DYNAMIC_FILE
+    }
+}
+
+// source file of the symbols that come from map files
+pub const DYNAMIC_FILE: &str = "[dynamic]";
+
+// how good a name is among the symbols sharing an address: named over
+// unnamed, then GLOBAL over WEAK over LOCAL, then FUNC over other types,
+// then the larger size; equal ranks go to the alphabetically first name
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct AliasRank {
+    named: bool,
+    binding: u8,
+    func: bool,
+    size: u64,
+    name: Reverse<String>,
+}
+
+impl AliasRank {
+    fn of<'data>(symbol: &impl ObjectSymbol<'data>, name: &str) -> Self {
+        let binding = if symbol.is_weak() { 1 } else if symbol.is_global() { 2 } else { 0 };
+        AliasRank {
+            named: !name.trim().is_empty(),
+            binding,
+            func: symbol.kind() == SymbolKind::Text,
+            size: symbol.size(),
+            name: Reverse(name.to_string()),
+        }
+    }
 }
 
 pub struct StackUnwinder {
@@ -37,107 +79,326 @@ pub struct StackUnwinder {
     pub insn_map: HashMap<u64, Insn>,
     // stack model
     pub frame_stack: Vec<u32>, // Queue of index
+    // context owning frame_stack
+    pub ctx: Context,
+    // frame stacks of the contexts that are switched out
+    pub parked_stacks: HashMap<Context, Vec<u32>>,
+    // function start addresses, sorted
+    pub func_addrs_sorted: Vec<u64>,
+    // indices of the setjmp-like and longjmp-like functions
+    pub setjmp_funcs: HashSet<u32>,
+    pub longjmp_funcs: HashSet<u32>,
+    // stubs (register save/restore, plt, veneers) that get no frame, their
+    // time goes to the function around them
+    pub stub_funcs: HashSet<u32>,
+    // (context, return address of a setjmp call) -> frame stack depth at the call
+    pub checkpoints: HashMap<(Context, u64), usize>,
+    // contexts inside a longjmp, waiting for its jump back to a setjmp site
+    pub longjmp_pending: HashSet<Context>,
+    // index -> symbol info of every function, including the unloaded overlays
+    symbols: Vec<SymbolInfo>,
+    overlays: Option<OverlaySymbols>,
 }
 
-impl StackUnwinder {
-    pub fn new(elf_path: String) -> Result<Self> {
-        // create insn_map
-        let mut elf_file = File::open(elf_path.clone())?;
-        let mut elf_buffer = Vec::new();
-        elf_file.read_to_end(&mut elf_buffer)?;
-        let elf = object::File::parse(&*elf_buffer)?;
-        let elf_arch = elf.architecture();
+// the symbol side of the overlays, on top of their instruction maps
+struct OverlaySymbols {
+    insns: OverlayInsns,
+    // per region the base ELF symbols in it, and per overlay its own
+    base: Vec<IndexMap<u64, SymbolInfo>>,
+    overlays: Vec<IndexMap<u64, SymbolInfo>>,
+}
 
-        let xlen = if elf_arch == object::Architecture::Riscv64 {
-            Xlen::XLEN64
-        } else if elf_arch == object::Architecture::Riscv32 {
-            Xlen::XLEN32
-        } else {
-            panic!("Unsupported architecture: {:?}", elf_arch);
+impl OverlaySymbols {
+    fn load(map: &OverlayMap, insn_map: &HashMap<u64, Insn>, func_symbol_map: &IndexMap<u64, SymbolInfo>,
+            keep_aliases: bool, idx_2_addr_range: &mut IndexMap<u32, (u64, u64)>) -> Result<Self> {
+        let insns = OverlayInsns::load(map, insn_map)?;
+        let base = insns.tracker.regions.iter()
+            .map(|&(start, end)| func_symbol_map.iter()
+                .filter(|(&addr, _)| addr >= start && addr < end)
+                .map(|(&addr, info)| (addr, info.clone()))
+                .collect())
+            .collect();
+        let mut next_index = func_symbol_map.len() as u32;
+        let mut overlays = Vec::new();
+        for overlay in map.overlays.iter() {
+            let data = std::fs::read(&overlay.elf)?;
+            let elf = object::File::parse(&*data)?;
+            let (sec_start, sec_end) = overlay.section_range(&elf)?;
+            let mut symbols: IndexMap<u64, SymbolInfo> = read_elf_symbols(&elf, keep_aliases)?.into_iter()
+                .filter(|&(addr, _)| addr >= sec_start && addr < sec_end)
+                .map(|(addr, info)| (overlay.relocate(addr), info))
+                .filter(|&(addr, _)| overlay.contains(addr))
+                .collect();
+            symbols.sort_keys();
+            let starts: Vec<u64> = symbols.keys().copied().collect();
+            for (i, info) in symbols.values_mut().enumerate() {
+                info.index = next_index;
+                next_index += 1;
+                // the last function of an overlay ends with its region
+                let end = starts.get(i + 1).copied().unwrap_or(overlay.end);
+                idx_2_addr_range.insert(info.index, (starts[i], end));
+            }
+            debug!("overlay {} {:?} at {:#x}: {} functions", overlay.elf, overlay.section, overlay.start, symbols.len());
+            overlays.push(symbols);
+        }
+        Ok(Self { insns, base, overlays })
+    }
+
+    // returns whether func_symbol_map changed
+    fn sync(&mut self, timestamp: Option<u64>, pc: u64, insn_map: &mut HashMap<u64, Insn>,
+            func_symbol_map: &mut IndexMap<u64, SymbolInfo>) -> bool {
+        let changes = self.insns.sync(timestamp, pc, insn_map);
+        for &(region, overlay) in changes.iter() {
+            let (start, end) = self.insns.tracker.regions[region];
+            func_symbol_map.retain(|&addr, _| addr < start || addr >= end);
+            let contents = match overlay {
+                Some(overlay) => &self.overlays[overlay],
+                None => &self.base[region],
+            };
+            func_symbol_map.extend(contents.iter().map(|(&addr, info)| (addr, info.clone())));
+        }
+        !changes.is_empty()
+    }
+}
+
+const SETJMP_SYMBOLS: &[&str] = &["setjmp", "_setjmp", "__setjmp", "sigsetjmp", "__sigsetjmp"];
+const LONGJMP_SYMBOLS: &[&str] = &["longjmp", "_longjmp", "__longjmp", "siglongjmp", "__longjmp_chk",
+                                   "__libc_longjmp", "__libc_siglongjmp"];
+
+// linker/compiler generated stubs the stack model steps through without a
+// frame of their own, `*` matches any run of characters
+const STUB_PATTERNS: &[&str] = &["__riscv_save_*", "__riscv_restore_*", "*@plt", "*_veneer", "__long_branch_*"];
+
+// unwinder settings from the command line, on top of the builtin names
+#[derive(Clone, Debug, Default)]
+pub struct UnwinderOptions {
+    pub setjmp: Vec<String>,
+    pub longjmp: Vec<String>,
+    pub stubs: Vec<String>,
+    // fill in `SymbolInfo::aliases`
+    pub keep_aliases: bool,
+    // (start, size, name) of runtime-generated code, see `read_symbol_map`
+    pub dynamic_symbols: Vec<(u64, u64, String)>,
+    // path to an overlay description file, empty for none
+    pub overlays: String,
+    // path to a process map, empty for none, and the sideband file whose
+    // context switches it follows
+    pub process_map: String,
+    pub sideband: String,
+    // path to a kernel module map, empty for none
+    pub modules: String,
+}
+
+// read a perf-style map file (`/tmp/perf-<pid>.map`): one `START SIZE name`
+// line per symbol, hex numbers with or without `0x`
+pub fn read_symbol_map(path: &str) -> Result<Vec<(u64, u64, String)>> {
+    let parse_hex = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16);
+    let mut symbols = Vec::new();
+    for (line_no, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, char::is_whitespace);
+        let parsed = match (fields.next(), fields.next(), fields.next()) {
+            (Some(start), Some(size), Some(name)) => parse_hex(start).ok().zip(parse_hex(size).ok())
+                .map(|(start, size)| (start, size, name.trim().to_string())),
+            _ => None,
         };
+        match parsed {
+            Some(symbol) => symbols.push(symbol),
+            None => return Err(anyhow::anyhow!("{}:{}: expected `START SIZE name`, got `{}`", path, line_no + 1, line)),
+        }
+    }
+    Ok(symbols)
+}
 
-        let das = Disassembler::new(xlen);
+// instructions that write the return address, the one after them is where
+// the callee returns to
+const CALL_OPCODES: &[&str] = &["jal", "jalr", "call", "c.jal", "c.jalr"];
 
-        let mut insn_map = HashMap::new();
-        for section in elf.sections() {
-            if let object::SectionFlags::Elf { sh_flags } = section.flags() {
-                if sh_flags & (SHF_EXECINSTR as u64) != 0 {
-                    let addr = section.address();
-                    let data = section.data()?;
-                    let sec_map = das.disassemble_all(&data, addr);
-                    debug!(
-                        "section `{}` @ {:#x}: {} insns",
-                        section.name().unwrap_or("<unnamed>"),
-                        addr,
-                        sec_map.len()
-                    );
-                    insn_map.extend(sec_map);
-                }
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else { return false };
+            if rest.is_empty() {
+                return true;
             }
+            // try every position for the rest of the pattern
+            (0..=name.len()).filter(|&i| name.is_char_boundary(i)).any(|i| glob_match(rest, &name[i..]))
         }
-        if insn_map.is_empty() {
-            return Err(anyhow::anyhow!("No executable instructions found in ELF file"));
+    }
+}
+
+// (offset, name) of the functions of a section of a relocatable object,
+// sorted; their addresses are offsets into the section, which leaves the
+// DWARF unrelocated and the source locations unknown
+fn read_section_symbols(ko: &object::File, section: object::SectionIndex) -> Vec<(u64, String)> {
+    let mut best: HashMap<u64, (AliasRank, String)> = HashMap::new();
+    for symbol in ko.symbols() {
+        if symbol.section_index() != Some(section) || symbol.kind() != SymbolKind::Text {
+            continue;
         }
-        trace!("[StackUnwinder::new] found {} instructions", insn_map.len());
-        
-        // Re‑open ELF for symbol processing
-        let elf_data = fs::read(&elf_path)?;
-        let obj_file = object::File::parse(&*elf_data)?;
-        let loader = Loader::new(&elf_path)
-            .map_err(|e| anyhow::Error::msg(e.to_string()))?;
-
-        // Gather indices of all executable sections
-        let exec_secs: std::collections::HashSet<_> = obj_file
-            .sections()
-            .filter_map(|sec| {
-                if let SectionFlags::Elf { sh_flags } = sec.flags() {
-                    if sh_flags & (SHF_EXECINSTR as u64) != 0 {
-                        return Some(sec.index());
-                    }
+        let Ok(name) = symbol.name() else { continue };
+        if name.trim().is_empty() || name.starts_with("$x") {
+            continue;
+        }
+        let rank = AliasRank::of(&symbol, name);
+        if best.get(&symbol.address()).is_none_or(|(kept, _)| rank > *kept) {
+            best.insert(symbol.address(), (rank, name.to_string()));
+        }
+    }
+    let mut symbols: Vec<(u64, String)> = best.into_iter().map(|(offset, (_, name))| (offset, name)).collect();
+    symbols.sort();
+    symbols
+}
+
+// the function symbols of an ELF with their source locations, indexed from 0
+fn read_elf_symbols(elf: &object::File, keep_aliases: bool) -> Result<IndexMap<u64, SymbolInfo>> {
+    // source locations come straight from the DWARF sections
+    let endian = if elf.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+    let dwarf_sections = gimli::DwarfSections::load(|id| -> Result<Cow<[u8]>, gimli::Error> {
+        Ok(elf.section_by_name(id.name()).and_then(|sec| sec.uncompressed_data().ok()).unwrap_or(Cow::Borrowed(&[])))
+    })?;
+    let addr_ctx = addr2line::Context::from_dwarf(dwarf_sections.borrow(|section| gimli::EndianSlice::new(section, endian)))?;
+
+    // Gather indices of all executable sections
+    let exec_secs: std::collections::HashSet<_> = elf
+        .sections()
+        .filter_map(|sec| {
+            if let SectionFlags::Elf { sh_flags } = sec.flags() {
+                if sh_flags & (SHF_EXECINSTR as u64) != 0 {
+                    return Some(sec.index());
                 }
-                None
-            })
-            .collect();
+            }
+            None
+        })
+        .collect();
 
-        // Build func_symbol_map from _all_ symbols in executable sections
-        let mut func_symbol_map: IndexMap<u64, SymbolInfo> = IndexMap::new();
-        let mut next_index = 0;
-        for symbol in obj_file.symbols() {
-            // only symbols tied to an exec section
-            if let Some(sec_idx) = symbol.section_index() {
-                if exec_secs.contains(&sec_idx) {
-                    if let Ok(name) = symbol.name() {
-                        if !name.starts_with("$x") {
-                            let addr = symbol.address();
-                            // lookup source location (may return None)
-                            if let Ok(Some(loc)) = loader.find_location(addr) {
-                                let src: SourceLocation = SourceLocation::from_addr2line(Some(loc));
+    // Build func_symbol_map from _all_ symbols in executable sections
+    let mut func_symbol_map: IndexMap<u64, SymbolInfo> = IndexMap::new();
+    // rank of the name kept for each address, and every name seen there
+    let mut kept_rank: HashMap<u64, AliasRank> = HashMap::new();
+    let mut all_names: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut next_index = 0;
+    for symbol in elf.symbols() {
+        // only symbols tied to an exec section
+        if let Some(sec_idx) = symbol.section_index() {
+            if exec_secs.contains(&sec_idx) {
+                if let Ok(name) = symbol.name() {
+                    if !name.starts_with("$x") {
+                        let addr = symbol.address();
+                        // lookup source location (may return None)
+                        if let Ok(Some(loc)) = addr_ctx.find_location(addr) {
+                            let src: SourceLocation = SourceLocation::from_addr2line(Some(loc));
+                            let rank = AliasRank::of(&symbol, name);
+                            if keep_aliases && !name.trim().is_empty() {
+                                all_names.entry(addr).or_default().push(name.to_string());
+                            }
+                            // aliases: keep the best ranked name, whatever the symbol table order
+                            if let Some(existing) = func_symbol_map.get_mut(&addr) {
+                                if rank > kept_rank[&addr] {
+                                    debug!("func_addr 0x{:x}: preferring `{}` over alias `{}`", addr, name, existing.name);
+                                    existing.name = name.to_string();
+                                    existing.line = src.lines;
+                                    existing.file = src.file.to_string();
+                                    kept_rank.insert(addr, rank);
+                                } else {
+                                    debug!("func_addr 0x{:x}: preferring `{}` over alias `{}`", addr, existing.name, name);
+                                }
+                            } else {
                                 let info = SymbolInfo {
                                     name: name.to_string(),
                                     index: next_index,
                                     line: src.lines,
                                     file: src.file.to_string(),
+                                    aliases: Vec::new(),
                                 };
-                                // dedupe aliases: prefer non‑empty over empty
-                                if let Some(existing) = func_symbol_map.get_mut(&addr) {
-                                    if existing.name.trim().is_empty() && !info.name.trim().is_empty() {
-                                        *existing = info;
-                                    } else {
-                                        warn!(
-                                            "func_addr 0x{:x} already in map as `{}`, ignoring alias `{}`",
-                                            addr, existing.name, info.name
-                                        );
-                                    }
-                                } else {
-                                    func_symbol_map.insert(addr, info);
-                                    next_index += 1;
-                                }
+                                func_symbol_map.insert(addr, info);
+                                kept_rank.insert(addr, rank);
+                                next_index += 1;
                             }
                         }
                     }
                 }
             }
         }
+    }
+    for (addr, mut names) in all_names {
+        let info = &mut func_symbol_map[&addr];
+        names.sort();
+        names.dedup();
+        names.retain(|name| *name != info.name);
+        info.aliases = names;
+    }
+    Ok(func_symbol_map)
+}
+
+impl StackUnwinder {
+    pub fn new(elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let mut elf_file = File::open(elf_path.clone())?;
+        let mut elf_buffer = Vec::new();
+        elf_file.read_to_end(&mut elf_buffer)?;
+        Self::from_elf_bytes(&elf_buffer, options)
+    }
+
+    // build from ELF contents already in memory, no file access needed
+    // unless overlays or kernel modules are configured
+    pub fn from_elf_bytes(elf_buffer: &[u8], options: &UnwinderOptions) -> Result<Self> {
+        // create insn_map
+        let elf = object::File::parse(elf_buffer)?;
+        let mut insn_map = build_insn_map(&elf)?.insns;
+        trace!("[StackUnwinder::new] found {} instructions", insn_map.len());
+
+        // Build func_symbol_map from _all_ symbols in executable sections
+        let mut func_symbol_map = read_elf_symbols(&elf, options.keep_aliases)?;
+        let mut next_index = func_symbol_map.len() as u32;
+
+        // code that only exists at run time, named by the map files
+        for (addr, _, name) in options.dynamic_symbols.iter() {
+            if let Some(existing) = func_symbol_map.get(addr) {
+                debug!("func_addr 0x{:x} already in map as `{}`, ignoring dynamic `{}`", addr, existing.name, name);
+                continue;
+            }
+            func_symbol_map.insert(*addr, SymbolInfo {
+                name: name.clone(),
+                index: next_index,
+                line: 0,
+                file: DYNAMIC_FILE.to_string(),
+                aliases: Vec::new(),
+            });
+            next_index += 1;
+        }
+
+        // the functions of the kernel modules where they were loaded, each
+        // ends with the next one of its section or with the section
+        let mut module_ranges = Vec::new();
+        for module in read_module_maps(&options.modules)? {
+            let data = std::fs::read(&module.ko)?;
+            let ko = object::File::parse(&*data)?;
+            let section = module.find(&ko)?;
+            insn_map.extend(module.load_insns()?);
+            let symbols = read_section_symbols(&ko, section.index());
+            debug!("module {} `{}` at {:#x}: {} functions", module.module, module.section, module.address, symbols.len());
+            for (i, (offset, name)) in symbols.iter().enumerate() {
+                let addr = module.address + offset;
+                if let Some(existing) = func_symbol_map.get(&addr) {
+                    debug!("func_addr 0x{:x} already in map as `{}`, ignoring `{}` of module {}", addr, existing.name, name, module.module);
+                    continue;
+                }
+                let end = symbols.get(i + 1).map_or(section.size(), |(next, _)| *next);
+                module_ranges.push((addr, module.address + end));
+                func_symbol_map.insert(addr, SymbolInfo {
+                    name: name.clone(),
+                    index: next_index,
+                    line: 0,
+                    file: String::new(),
+                    aliases: Vec::new(),
+                });
+                next_index += 1;
+            }
+        }
 
         // print the size of the func_symbol_map
         debug!("func_symbol_map size: {}", func_symbol_map.len());
@@ -154,27 +415,138 @@ impl StackUnwinder {
             let next_addr = func_symbol_addr_sorted[next_position];
             idx_2_addr_range.insert(func_info.index, (addr.clone(), next_addr.clone()));
         }
+        // the map files know where dynamic code ends
+        for (addr, size, _) in options.dynamic_symbols.iter() {
+            let info = &func_symbol_map[addr];
+            if info.is_dynamic() && *size > 0 {
+                idx_2_addr_range.insert(info.index, (*addr, addr + size));
+            }
+        }
+        for (addr, end) in module_ranges {
+            idx_2_addr_range.insert(func_symbol_map[&addr].index, (addr, end));
+        }
+
+        // every overlay's symbols get their own indices and ranges, and are
+        // swapped into func_symbol_map while the overlay is loaded
+        let overlays = match OverlayMap::from_paths(&options.overlays, &options.process_map, &options.sideband, &elf)? {
+            Some(map) => Some(OverlaySymbols::load(&map, &insn_map, &func_symbol_map, options.keep_aliases, &mut idx_2_addr_range)?),
+            None => None,
+        };
+        let mut symbols: Vec<SymbolInfo> = func_symbol_map.values().cloned().collect();
+        if let Some(overlays) = &overlays {
+            symbols.extend(overlays.overlays.iter().flat_map(|syms| syms.values().cloned()));
+        }
+
+        let funcs_named = |builtin: &[&str], extra: &[String]| -> HashSet<u32> {
+            symbols.iter()
+                .filter(|info| builtin.iter().copied().chain(extra.iter().map(String::as_str))
+                    .any(|pattern| glob_match(pattern, &info.name)))
+                .map(|info| info.index)
+                .collect()
+        };
+        let setjmp_funcs = funcs_named(SETJMP_SYMBOLS, &options.setjmp);
+        let longjmp_funcs = funcs_named(LONGJMP_SYMBOLS, &options.longjmp);
+        let stub_funcs = funcs_named(STUB_PATTERNS, &options.stubs);
+        debug!("{} setjmp-like, {} longjmp-like and {} stub functions", setjmp_funcs.len(), longjmp_funcs.len(), stub_funcs.len());
 
         Ok(Self {
             func_symbol_map: func_symbol_map,
+            symbols,
             idx_2_addr_range: idx_2_addr_range,
             insn_map: insn_map,
             frame_stack: Vec::new(),
+            ctx: Context::default(),
+            parked_stacks: HashMap::new(),
+            func_addrs_sorted: func_symbol_addr_sorted,
+            setjmp_funcs,
+            longjmp_funcs,
+            stub_funcs,
+            checkpoints: HashMap::new(),
+            longjmp_pending: HashSet::new(),
+            overlays,
         })
     }
 
     pub fn func_symbol_map(&self) -> &IndexMap<u64, SymbolInfo> {
         &self.func_symbol_map
     }
+
+    // every function, overlays included, the position is the index
+    pub fn symbols(&self) -> &[SymbolInfo] {
+        &self.symbols
+    }
+
+    // swap in the instructions and symbols of the overlays loaded by the
+    // time of the entry, and as execution reaches its target
+    pub fn sync_overlays(&mut self, entry: &Entry) {
+        if let Some(overlays) = self.overlays.as_mut() {
+            if overlays.sync(entry.timestamp, entry.arc.1, &mut self.insn_map, &mut self.func_symbol_map) {
+                self.func_addrs_sorted = self.func_symbol_map.keys().copied().collect();
+                self.func_addrs_sorted.sort();
+            }
+        }
+    }
+
+    // (start, end) of the function containing addr
+    pub fn function_range_at(&self, addr: u64) -> Option<(u64, u64)> {
+        let i = self.func_addrs_sorted.partition_point(|&start| start <= addr).checked_sub(1)?;
+        let start = self.func_addrs_sorted[i];
+        let (_, end) = self.idx_2_addr_range[&self.func_symbol_map[&start].index];
+        // the last function has no successor to bound it
+        let end = if end > start { end } else { u64::MAX };
+        if addr < end { Some((start, end)) } else { None }
+    }
+
+    // checkpoint the stack depth when setjmp is called, so that the longjmp
+    // landing back after that call site can restore it
+    fn note_call(&mut self, call_pc: u64, index: u32) {
+        if self.setjmp_funcs.contains(&index) {
+            if let Some(insn) = self.insn_map.get(&call_pc) {
+                // the caller's depth, setjmp's own frame is already pushed
+                self.checkpoints.insert((self.ctx, call_pc + insn.len as u64), self.frame_stack.len() - 1);
+            }
+        }
+        if self.longjmp_funcs.contains(&index) {
+            self.longjmp_pending.insert(self.ctx);
+        }
+    }
+
+    // is addr a return address, i.e. right after a call instruction
+    pub fn follows_call(&self, addr: u64) -> bool {
+        [2, 4].iter().any(|&len| {
+            self.insn_map.get(&addr.wrapping_sub(len))
+                .is_some_and(|insn| insn.len as u64 == len && CALL_OPCODES.contains(&insn.get_name().as_str()))
+        })
+    }
+
+    // park the current frame stack and resume the one of the new context
+    pub fn switch_context(&mut self, ctx: Context) {
+        if ctx == self.ctx {
+            return;
+        }
+        let parked = std::mem::take(&mut self.frame_stack);
+        self.parked_stacks.insert(self.ctx, parked);
+        self.frame_stack = self.parked_stacks.remove(&ctx).unwrap_or_default();
+        self.ctx = ctx;
+    }
     
     // return (success, frame_stack_size, symbol_info)
     pub fn step_ij(&mut self, entry: Entry) -> (bool, usize, Option<SymbolInfo>) {
         assert!(entry.event == Event::InferrableJump || entry.event == Event::TrapException || entry.event == Event::TrapInterrupt);
+        self.sync_overlays(&entry);
+        // a trap through a vector slot opens the frame of its handler
+        let target = entry.trap.and_then(|trap| trap.handler).unwrap_or(entry.arc.1);
 
-        if self.func_symbol_map.contains_key(&entry.arc.1) {
-            let frame_idx = self.func_symbol_map[&entry.arc.1].index;
+        if self.func_symbol_map.contains_key(&target) {
+            let frame_idx = self.func_symbol_map[&target].index;
+            if self.stub_funcs.contains(&frame_idx) {
+                return (false, self.frame_stack.len(), None);
+            }
             self.frame_stack.push(frame_idx);
-            return (true, self.frame_stack.len(), Some(self.func_symbol_map[&entry.arc.1].clone()));
+            if entry.event == Event::InferrableJump {
+                self.note_call(entry.arc.0, frame_idx);
+            }
+            return (true, self.frame_stack.len(), Some(self.func_symbol_map[&target].clone()));
         } else {
             // warn!("step_ij: func_symbol_map does not contain the jump address: {:#x}", entry.arc.1);
             return (false, self.frame_stack.len(), None);
@@ -189,34 +561,65 @@ impl StackUnwinder {
             || entry.event == Event::TrapReturn);
 
         // Address of the branch instruction (the "previous insn")
-        let prev_insn = self.insn_map.get(&entry.arc.0)
-            .expect("missing insn in map");
+        let is_indirect = self.insn_map.get(&entry.arc.0)
+            .expect("missing insn in map")
+            .is_indirect_jump();
+        self.sync_overlays(&entry);
         let target = entry.arc.1;
         let mut closed = Vec::new();
 
         // 1) mret: always pop exactly one frame
         if entry.event == Event::TrapReturn {
             if let Some(idx) = self.frame_stack.pop() {
-                let start = self.idx_2_addr_range[&idx].0;
-                let sym = self.func_symbol_map[&start].clone();
+                let sym = self.symbols[idx as usize].clone();
                 return (true, self.frame_stack.len(), vec![sym], None);
             } else {
                 // nothing to pop
+                diagnostics::report(Category::StackDesync,
+                                    format!("trap return at {:#x} with no frame open", entry.arc.0));
                 return (false, 0, Vec::new(), None);
             }
         }
 
+        // longjmp lands after the call of a setjmp: drop every frame opened
+        // since then at once
+        if is_indirect && self.longjmp_pending.contains(&self.ctx) {
+            if let Some(&depth) = self.checkpoints.get(&(self.ctx, target)) {
+                self.longjmp_pending.remove(&self.ctx);
+                while self.frame_stack.len() > depth {
+                    let popped = self.frame_stack.pop().unwrap();
+                    closed.push(self.symbols[popped as usize].clone());
+                }
+                return (true, self.frame_stack.len(), closed, None);
+            }
+        }
+
+        // A jump inside the function it comes from (switch jump tables) is
+        // neither a call nor a return, unless it lands after a call site,
+        // which is a return in a recursive function
+        if is_indirect {
+            if let Some((start, end)) = self.function_range_at(entry.arc.0) {
+                if target > start && target < end && !self.follows_call(target) {
+                    return (false, self.frame_stack.len(), Vec::new(), None);
+                }
+            }
+        }
+
         // If we see a CALL (indirect), push the new function
-        let is_call = prev_insn.is_indirect_jump() && self.func_symbol_map.get(&target).is_some();
+        let is_call = is_indirect && self.func_symbol_map.get(&target).is_some();
         if is_call {
             let info = self.func_symbol_map.get(&target).unwrap().clone();
+            if self.stub_funcs.contains(&info.index) {
+                return (false, self.frame_stack.len(), Vec::new(), None);
+            }
             self.frame_stack.push(info.index);
+            self.note_call(entry.arc.0, info.index);
             return (true, self.frame_stack.len(), Vec::new(), Some(info));
         }
 
         // Otherwise, if it's an indirect jump *and* we still have frames,
         //    treat it like a return within the unwinding loop.
-        if prev_insn.is_indirect_jump() && !self.frame_stack.is_empty() {
+        if is_indirect && !self.frame_stack.is_empty() {
             loop {
                 let &idx = self.frame_stack.last().unwrap();
                 let (start, end) = self.idx_2_addr_range[&idx];
@@ -226,8 +629,7 @@ impl StackUnwinder {
                 }
                 // else pop one more
                 let popped = self.frame_stack.pop().unwrap();
-                let func_start = self.idx_2_addr_range[&popped].0;
-                closed.push(self.func_symbol_map[&func_start].clone());
+                closed.push(self.symbols[popped as usize].clone());
 
                 if self.frame_stack.is_empty() {
                     // maybe it was a tail‐call
@@ -235,6 +637,8 @@ impl StackUnwinder {
                         self.frame_stack.push(info.index);
                         return (true, self.frame_stack.len(), closed, Some(info.clone()));
                     } else {
+                        diagnostics::report(Category::StackDesync,
+                                            format!("return from {:#x} to {:#x} closed every frame", entry.arc.0, target));
                         return (true, 0, closed, None);
                     }
                 }
@@ -250,13 +654,17 @@ impl StackUnwinder {
         let mut closed_frames = Vec::new();
         while let Some(frame_idx) = self.frame_stack.pop() {
             trace!("closing frame while flushing: {}", frame_idx);
-            closed_frames.push(self.func_symbol_map[&self.idx_2_addr_range[&frame_idx].0].clone());
+            closed_frames.push(self.symbols[frame_idx as usize].clone());
         }
         closed_frames
     }
 
     pub fn get_symbol_info(&self, addr: u64) -> SymbolInfo {
-        self.func_symbol_map[&addr].clone()
+        match self.func_symbol_map.get(&addr) {
+            Some(info) => info.clone(),
+            // a frame of an overlay unloaded since
+            None => self.symbols.iter().find(|info| self.idx_2_addr_range[&info.index].0 == addr).unwrap().clone(),
+        }
     }
 
     pub fn current_frame_addrs(&self) -> Vec<u64> {
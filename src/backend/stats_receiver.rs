@@ -1,9 +1,109 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
 use crate::frontend::br_mode;
+use crate::frontend::c_header::{CHeader, C_HEADER_MASK};
+use crate::frontend::insn_map::build_insn_map;
+use crate::frontend::packet::{varint_fields, VarintField, TRACE_MAGIC};
 use bus::BusReader;
+use tacit_wire::{VAR_LAST, VAR_MASK};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+// Where the bytes of tacit traces go, from their packet framing: the
+// compressed packets (header and timestamp in one byte), the headers of the
+// full packets, and their varint payloads.
+#[derive(Default)]
+pub struct PacketBreakdown {
+    c_packets: u64,
+    f_packets: u64,
+    f_header_bytes: u64,
+    address_bytes: u64,
+    timestamp_bytes: u64,
+    data_bytes: u64,
+    other_bytes: u64,
+    trace_header_bytes: u64,
+    // varint length in bytes to count
+    varint_lengths: BTreeMap<u64, u64>,
+}
+
+impl PacketBreakdown {
+    pub fn from_traces(encoded_traces: &[String]) -> Result<Self> {
+        let mut breakdown = PacketBreakdown::default();
+        for encoded_trace in encoded_traces {
+            breakdown.scan(BufReader::new(File::open(encoded_trace)?).bytes())?;
+        }
+        Ok(breakdown)
+    }
+
+    // a truncated last packet is left out
+    fn scan(&mut self, mut bytes: impl Iterator<Item = std::io::Result<u8>>) -> Result<()> {
+        let mut first = true;
+        while let Some(byte) = bytes.next() {
+            let byte = byte?;
+            if first && byte == TRACE_MAGIC[0] {
+                // magic, version, parameter length and parameters
+                let mut header = bytes.by_ref().take(TRACE_MAGIC.len() + 1).collect::<std::io::Result<Vec<u8>>>()?;
+                let params = header.last().copied().unwrap_or(0) as usize;
+                header.extend(bytes.by_ref().take(params).collect::<std::io::Result<Vec<u8>>>()?);
+                self.trace_header_bytes += 1 + header.len() as u64;
+                first = false;
+                continue;
+            }
+            first = false;
+            if CHeader::from(byte & C_HEADER_MASK) != CHeader::CNa {
+                self.c_packets += 1;
+                continue;
+            }
+            self.f_packets += 1;
+            self.f_header_bytes += 1;
+            for field in varint_fields(byte) {
+                let mut len = 0;
+                for byte in bytes.by_ref() {
+                    len += 1;
+                    if byte? & VAR_MASK == VAR_LAST {
+                        break;
+                    }
+                }
+                *self.varint_lengths.entry(len).or_default() += 1;
+                match field {
+                    VarintField::Address => self.address_bytes += len,
+                    VarintField::Timestamp => self.timestamp_bytes += len,
+                    VarintField::DataAddress => self.data_bytes += len,
+                    VarintField::Other => self.other_bytes += len,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, writer: &mut impl Write, insn_count: u64) -> std::io::Result<()> {
+        let total = self.c_packets + self.f_header_bytes + self.address_bytes + self.timestamp_bytes
+            + self.data_bytes + self.other_bytes + self.trace_header_bytes;
+        if total == 0 {
+            return Ok(());
+        }
+        writeln!(writer, "payload breakdown: bytes, share, bits per instruction")?;
+        for (category, bytes) in [
+            (format!("compressed packets ({})", self.c_packets), self.c_packets),
+            (format!("full packet headers ({})", self.f_packets), self.f_header_bytes),
+            ("address payloads".to_string(), self.address_bytes),
+            ("timestamp payloads".to_string(), self.timestamp_bytes),
+            ("data address payloads".to_string(), self.data_bytes),
+            ("value and check payloads".to_string(), self.other_bytes),
+            ("trace header".to_string(), self.trace_header_bytes),
+        ] {
+            writeln!(writer, "  {:<32} {:>12} {:>7.2}% {:>9.4}", category, bytes,
+                     bytes as f64 / total as f64 * 100.0, bytes as f64 * 8.0 / insn_count as f64)?;
+        }
+        writeln!(writer, "varint lengths: bytes, count")?;
+        for (len, count) in self.varint_lengths.iter() {
+            writeln!(writer, "  {:>2} {:>12}", len, count)?;
+        }
+        Ok(())
+    }
+}
 
 pub struct StatsReceiver {
     writer: BufWriter<File>,
@@ -14,18 +114,27 @@ pub struct StatsReceiver {
     insn_count: u64,
     hit_count: u64,
     miss_count: u64,
+    // undecodable words of the binary, see frontend::insn_map
+    opaque: HashMap<u64, u64>,
+    opaque_count: u64,
+    breakdown: Option<PacketBreakdown>,
 }
 
 impl StatsReceiver {
-    pub fn new(bus_rx: BusReader<Entry>, br_mode: br_mode::BrMode, file_size: u64) -> Self {
-        Self { writer: BufWriter::new(File::create("trace.stats.txt").unwrap()), 
+    pub fn new(bus_rx: BusReader<Entry>, br_mode: br_mode::BrMode, file_size: u64, elf_path: String, breakdown: Option<PacketBreakdown>) -> Result<Self> {
+        let elf_data = std::fs::read(&elf_path)?;
+        let opaque = build_insn_map(&object::File::parse(&*elf_data)?)?.opaque;
+        Ok(Self { writer: BufWriter::new(File::create("trace.stats.txt")?),
                 receiver: BusReceiver { name: "stats".to_string(), bus_rx: bus_rx, checksum: 0 },
                 packet_count: 0,
                 insn_count: 0,
                 hit_count: 0,
                 miss_count: 0,
+                opaque,
+                opaque_count: 0,
+                breakdown,
                 br_mode: br_mode,
-                file_size: file_size }
+                file_size: file_size })
     }
 }
 
@@ -39,10 +148,17 @@ impl AbstractReceiver for StatsReceiver {
         self.receiver.checksum += 1;
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
         match entry.event {
             Event::None => {
                 self.insn_count += 1;
+                if self.opaque.contains_key(&entry.arc.0) {
+                    self.opaque_count += 1;
+                }
             }
             Event::BPHit => {
                 if self.br_mode == br_mode::BrMode::BrPredict {
@@ -65,19 +181,27 @@ impl AbstractReceiver for StatsReceiver {
                 self.packet_count += 1;
             }
         }
+        Ok(())
     }
 
-    fn _flush(&mut self) {
-        self.writer.write_all(format!("instruction count: {}\n", self.insn_count).as_bytes()).unwrap();
-        self.writer.write_all(format!("packet count: {}\n", self.packet_count).as_bytes()).unwrap();
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.write_all(format!("instruction count: {}\n", self.insn_count).as_bytes())?;
+        self.writer.write_all(format!("packet count: {}\n", self.packet_count).as_bytes())?;
+        if !self.opaque.is_empty() {
+            self.writer.write_all(format!("opaque instruction count: {} ({} undecodable words in the binary)\n", self.opaque_count, self.opaque.len()).as_bytes())?;
+        }
         if self.br_mode == br_mode::BrMode::BrPredict {
-            self.writer.write_all(format!("hit rate: {:.2}%\n", self.hit_count as f64 / (self.hit_count + self.miss_count) as f64 * 100.0).as_bytes()).unwrap();
+            self.writer.write_all(format!("hit rate: {:.2}%\n", self.hit_count as f64 / (self.hit_count + self.miss_count) as f64 * 100.0).as_bytes())?;
         }
         let bpi = self.file_size as f64 * 8.0 / self.insn_count as f64; //convert bytes to bits
-        self.writer.write_all(format!("bits per instruction: {:.4}\n", bpi).as_bytes()).unwrap(); 
-        self.writer.write_all(format!("trace payload size: {:.2}KiB\n", self.file_size as f64 / 1024.0).as_bytes()).unwrap();
+        self.writer.write_all(format!("bits per instruction: {:.4}\n", bpi).as_bytes())?; 
+        self.writer.write_all(format!("trace payload size: {:.2}KiB\n", self.file_size as f64 / 1024.0).as_bytes())?;
         let bpp = self.file_size as f64 * 8.0 / self.packet_count as f64;
-        self.writer.write_all(format!("bits per packet: {:.4}\n", bpp).as_bytes()).unwrap();
-        self.writer.flush().unwrap();
+        self.writer.write_all(format!("bits per packet: {:.4}\n", bpp).as_bytes())?;
+        if let Some(breakdown) = &self.breakdown {
+            breakdown.write(&mut self.writer, self.insn_count)?;
+        }
+        self.writer.flush()?;
+        Ok(())
     }
 }
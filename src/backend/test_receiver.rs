@@ -0,0 +1,199 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::parse_u64;
+use crate::frontend::region::Marker;
+use crate::frontend::register::GPR_BASE;
+use crate::diagnostics::{self, Category};
+use bus::BusReader;
+use addr2line::Loader;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use anyhow::{anyhow, Result};
+use log::debug;
+
+// the first argument of the start marker names the test
+const A0: u16 = GPR_BASE + 10;
+
+// one test of the suite, from its start marker to its end marker or the
+// next start
+struct Test {
+    name: String,
+    start: u64,
+    end: u64,
+    // per pc, its executions
+    pcs: HashMap<u64, u64>,
+}
+
+/// Per-test coverage and cycles of a test suite run in one trace. A test
+/// starts at an execution of the start marker (e.g. `test_start(name_id)`)
+/// and runs until the end marker, if any, or the next start. It is named by
+/// the last value of `a0` the trace carried before its start, looked up in
+/// the names file, or numbered in order without register values. Writes
+/// `trace.tests.txt`, per test its cycles, instructions and the functions,
+/// instructions and source lines it covered, and `trace.tests.csv`, one row
+/// per function and test running it, for "which test covers this function".
+pub struct TestReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    loader: Loader,
+    start_pc: u64,
+    end_pc: Option<u64>,
+    names: HashMap<u64, String>,
+    tests: Vec<Test>,
+    open: bool,
+    last_a0: Option<u64>,
+    last_ts: u64,
+}
+
+impl TestReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, start: &Marker, end: Option<&Marker>, names_path: &str) -> Result<Self> {
+        debug!("TestReceiver::new");
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let start_pc = start.resolve(&elf)?;
+        let end_pc = end.map(|end| end.resolve(&elf)).transpose()?;
+        let names = if names_path.is_empty() { HashMap::new() } else { Self::read_names(names_path)? };
+        Ok(TestReceiver {
+            receiver: BusReceiver { name: "test".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path.clone(), options)?,
+            loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
+            start_pc,
+            end_pc,
+            names,
+            tests: Vec::new(),
+            open: false,
+            last_a0: None,
+            last_ts: 0,
+        })
+    }
+
+    // one `<id> <name>` line per test, `#` starts a comment
+    fn read_names(path: &str) -> Result<HashMap<u64, String>> {
+        let mut names = HashMap::new();
+        for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (id, name) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("{}:{}: expected `<id> <name>`", path, lineno + 1))?;
+            names.insert(parse_u64(id).map_err(|_| anyhow!("{}:{}: bad test id `{}`", path, lineno + 1, id))?, name.trim().to_string());
+        }
+        Ok(names)
+    }
+
+    fn close(&mut self) {
+        if self.open {
+            self.open = false;
+            self.tests.last_mut().unwrap().end = self.last_ts;
+        }
+    }
+
+    fn begin(&mut self) {
+        self.close();
+        let name = match self.last_a0 {
+            Some(id) => self.names.get(&id).cloned().unwrap_or_else(|| format!("test {}", id)),
+            None => format!("#{}", self.tests.len()),
+        };
+        self.tests.push(Test { name, start: self.last_ts, end: self.last_ts, pcs: HashMap::new() });
+        self.open = true;
+    }
+
+    fn function_of(&self, pc: u64) -> Option<&str> {
+        let (start, _) = self.unwinder.function_range_at(pc)?;
+        Some(&self.unwinder.func_symbol_map()[&start].name)
+    }
+
+    fn write_report(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.tests.txt")?);
+        let mut csv = BufWriter::new(File::create("trace.tests.csv")?);
+        writeln!(csv, "function,test,instructions,distinct_instructions")?;
+        writeln!(writer, "{} tests", self.tests.len())?;
+        for test in &self.tests {
+            // per function, (instructions, distinct pcs)
+            let mut functions: HashMap<&str, (u64, u64)> = HashMap::new();
+            let mut lines = BTreeSet::new();
+            for (&pc, &count) in &test.pcs {
+                let function = functions.entry(self.function_of(pc).unwrap_or("?")).or_default();
+                function.0 += count;
+                function.1 += 1;
+                if let Ok(Some(location)) = self.loader.find_location(pc) {
+                    if let (Some(file), Some(line)) = (location.file, location.line) {
+                        lines.insert((file.to_string(), line));
+                    }
+                }
+            }
+            let mut functions: Vec<_> = functions.into_iter().collect();
+            functions.sort_by_key(|&(name, (count, _))| (std::cmp::Reverse(count), name));
+            writeln!(writer)?;
+            writeln!(writer, "{}: {} cycles, {} instructions, {} functions, {} distinct instructions, {} source lines",
+                     test.name, test.end - test.start, test.pcs.values().sum::<u64>(), functions.len(), test.pcs.len(), lines.len())?;
+            for &(name, (count, distinct)) in &functions {
+                writeln!(writer, "  {:>12} {:>8}  {}", count, distinct, name)?;
+                writeln!(csv, "{},{},{},{}", name, test.name.replace(',', ";"), count, distinct)?;
+            }
+        }
+        writer.flush()?;
+        csv.flush()
+    }
+}
+
+impl AbstractReceiver for TestReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the hit count of BPHit is not a timestamp
+        if entry.event != Event::BPHit {
+            if let Some(ts) = entry.timestamp {
+                self.last_ts = ts;
+            }
+        }
+        match entry.event {
+            Event::None => {
+                let pc = entry.arc.0;
+                if pc == self.start_pc {
+                    self.begin();
+                }
+                if self.open {
+                    *self.tests.last_mut().unwrap().pcs.entry(pc).or_default() += 1;
+                }
+                if Some(pc) == self.end_pc {
+                    self.close();
+                }
+            }
+            Event::RegisterValue => {
+                let reg = entry.reg.unwrap();
+                if reg.regno == A0 {
+                    self.last_a0 = Some(reg.value);
+                }
+            }
+            Event::End => {
+                if self.open {
+                    diagnostics::report(Category::IncompleteOutput,
+                                        format!("test {} still running at the end of the trace", self.tests.last().unwrap().name));
+                }
+                self.close();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+// How the cycles between two timed events are shared among the instructions
+// retired in between. The trace only times control flow, so by default every
+// instruction gets the same share; a timing model gives each instruction a
+// cost instead, e.g. 3 for loads and 4 for mul, and the cycles are shared in
+// proportion to the costs. The receivers that attribute cycles to single
+// instructions or source lines are handed the model and spread the cycles
+// through a `CycleSpreader`.
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rvdasm::insn::Insn;
+
+use crate::backend::stack_unwinder::glob_match;
+
+pub trait TimingModel: Send + Sync {
+    // the cycles insn takes relative to the others, 0 for none
+    fn cost(&self, insn: &Insn) -> u64;
+}
+
+// every instruction the same, what the trace alone tells
+pub struct Uniform;
+
+impl TimingModel for Uniform {
+    fn cost(&self, _insn: &Insn) -> u64 {
+        1
+    }
+}
+
+const LOADS: &[&str] = &["lb", "lh", "lw", "ld", "lbu", "lhu", "lwu", "flh", "flw", "fld", "flq",
+                         "c.lw", "c.ld", "c.lq", "c.lwsp", "c.ldsp", "c.lqsp", "c.flw", "c.fld", "c.flwsp", "c.fldsp"];
+const STORES: &[&str] = &["sb", "sh", "sw", "sd", "fsh", "fsw", "fsd", "fsq",
+                          "c.sw", "c.sd", "c.sq", "c.swsp", "c.sdsp", "c.sqsp", "c.fsw", "c.fsd", "c.fswsp", "c.fsdsp"];
+
+// what a line of a cost table matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Matcher {
+    Load,
+    Store,
+    Branch,
+    Jump,
+    // a mnemonic, `*` matching any run of characters
+    Mnemonic(String),
+}
+
+impl Matcher {
+    fn matches(&self, insn: &Insn, name: &str) -> bool {
+        match self {
+            Matcher::Load => LOADS.contains(&name),
+            Matcher::Store => STORES.contains(&name),
+            Matcher::Branch => insn.is_branch(),
+            Matcher::Jump => insn.is_direct_jump() || insn.is_indirect_jump(),
+            Matcher::Mnemonic(pattern) => glob_match(pattern, name),
+        }
+    }
+}
+
+/// A timing model from a table of `<what> <cycles>` lines, the first line
+/// that matches an instruction giving its cost: `what` is `load`, `store`,
+/// `branch`, `jump`, or a mnemonic where `*` matches any run of characters
+/// (`mul*`, `fdiv.*`); `default <cycles>` costs the other instructions, 1
+/// unless given. `#` starts a comment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostTable {
+    pub rules: Vec<(Matcher, u64)>,
+    pub default: u64,
+}
+
+impl FromStr for CostTable {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut table = CostTable { rules: Vec::new(), default: 1 };
+        for (lineno, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let cycles = match fields[..] {
+                [_, cycles] => cycles.parse::<u64>().ok(),
+                _ => None,
+            }.ok_or_else(|| anyhow!("line {}: expected `<what> <cycles>`, got `{}`", lineno + 1, line))?;
+            let matcher = match fields[0] {
+                "default" => {
+                    table.default = cycles;
+                    continue;
+                }
+                "load" => Matcher::Load,
+                "store" => Matcher::Store,
+                "branch" => Matcher::Branch,
+                "jump" => Matcher::Jump,
+                mnemonic => Matcher::Mnemonic(mnemonic.to_string()),
+            };
+            table.rules.push((matcher, cycles));
+        }
+        Ok(table)
+    }
+}
+
+impl CostTable {
+    pub fn from_file(path: &str) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse().map_err(|e: anyhow::Error| anyhow!("{}: {}", path, e))
+    }
+}
+
+impl TimingModel for CostTable {
+    fn cost(&self, insn: &Insn) -> u64 {
+        let name = insn.get_name();
+        self.rules.iter().find(|(matcher, _)| matcher.matches(insn, &name)).map_or(self.default, |&(_, cycles)| cycles)
+    }
+}
+
+// the cycles a span of `elapsed` gives each of the costs, in proportion to
+// them and to every one the same when they are all 0; what does not divide
+// goes to the first ones that cost something
+pub fn spread(elapsed: u64, costs: &[u64]) -> Vec<u64> {
+    let total: u64 = costs.iter().sum();
+    if total == 0 && !costs.is_empty() {
+        return spread(elapsed, &vec![1; costs.len()]);
+    }
+    let mut shares: Vec<u64> = costs.iter().map(|&cost| (elapsed as u128 * cost as u128 / total.max(1) as u128) as u64).collect();
+    let mut left = elapsed - shares.iter().sum::<u64>();
+    for (share, &cost) in shares.iter_mut().zip(costs) {
+        if left == 0 {
+            break;
+        }
+        if cost > 0 {
+            *share += 1;
+            left -= 1;
+        }
+    }
+    shares
+}
+
+/// The instructions retired since the last timed event, whose cycles it
+/// shares among them by a timing model once the next timed event comes.
+pub struct CycleSpreader {
+    model: Arc<dyn TimingModel>,
+    // (pc, cost)
+    pending: Vec<(u64, u64)>,
+}
+
+impl CycleSpreader {
+    pub fn new(model: Arc<dyn TimingModel>) -> Self {
+        CycleSpreader { model, pending: Vec::new() }
+    }
+
+    pub fn retire(&mut self, pc: u64, insn: Option<&Insn>) {
+        self.pending.push((pc, insn.map_or(1, |insn| self.model.cost(insn))));
+    }
+
+    // (pc, cycles) of the instructions retired, in order, and none pending
+    // anymore
+    pub fn spread(&mut self, elapsed: u64) -> Vec<(u64, u64)> {
+        let costs: Vec<u64> = self.pending.iter().map(|&(_, cost)| cost).collect();
+        let shares = spread(elapsed, &costs);
+        self.pending.drain(..).map(|(pc, _)| pc).zip(shares).collect()
+    }
+}
@@ -0,0 +1,129 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use log::debug;
+
+// self cycles and calls per function, from the entries of a decode. The
+// cycles up to a timed event belong to the function the event was taken
+// in, so no stack is needed for them; the unwinder only counts the calls.
+pub struct SelfProfile {
+    pub unwinder: StackUnwinder,
+    last_ts: Option<u64>,
+    // per function entry address
+    pub self_cycles: HashMap<u64, u64>,
+    pub calls: HashMap<u64, u64>,
+    pub unattributed: u64,
+}
+
+impl SelfProfile {
+    pub fn new(unwinder: StackUnwinder) -> Self {
+        SelfProfile { unwinder, last_ts: None, self_cycles: HashMap::new(), calls: HashMap::new(), unattributed: 0 }
+    }
+
+    // the cycles since the previous timed event ran in the function of pc
+    fn account(&mut self, ts: u64, pc: u64) {
+        let elapsed = ts.saturating_sub(self.last_ts.unwrap_or(ts));
+        self.last_ts = Some(ts);
+        match self.unwinder.function_range_at(pc) {
+            Some((start, _)) => *self.self_cycles.entry(start).or_default() += elapsed,
+            None => self.unattributed += elapsed,
+        }
+    }
+
+    pub fn observe(&mut self, entry: Entry) {
+        match entry.event {
+            Event::Start => {
+                self.last_ts = entry.timestamp;
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+                if let (true, _, Some(opened)) = self.unwinder.step_ij(entry) {
+                    *self.calls.entry(self.unwinder.idx_2_addr_range[&opened.index].0).or_default() += 1;
+                }
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+                if let (_, _, _, Some(opened)) = self.unwinder.step_uj(entry) {
+                    *self.calls.entry(self.unwinder.idx_2_addr_range[&opened.index].0).or_default() += 1;
+                }
+            }
+            Event::TakenBranch | Event::NonTakenBranch | Event::End => {
+                self.account(entry.timestamp.unwrap(), entry.arc.0);
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn function_name(&self, addr: u64) -> &str {
+        self.unwinder.func_symbol_map().get(&addr).map(|info| info.name.as_str()).unwrap_or("?")
+    }
+}
+
+/// Prints the `n` hottest functions by self cycles to stdout at the end of
+/// the decode.
+pub struct TopReceiver {
+    receiver: BusReceiver,
+    profile: SelfProfile,
+    n: usize,
+}
+
+impl TopReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, n: usize) -> Result<Self> {
+        debug!("TopReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        Ok(TopReceiver {
+            receiver: BusReceiver { name: "top".into(), bus_rx, checksum: 0 },
+            profile: SelfProfile::new(unwinder),
+            n,
+        })
+    }
+
+    fn report(&self) -> String {
+        let profile = &self.profile;
+        let total: u64 = profile.self_cycles.values().sum::<u64>() + profile.unattributed;
+        let mut hot: Vec<(&u64, &u64)> = profile.self_cycles.iter().collect();
+        // ties by address, so the report is the same run to run
+        hot.sort_by_key(|&(&addr, &cycles)| (std::cmp::Reverse(cycles), addr));
+        let mut out = format!("top {} functions by self cycles, of {} cycles:\n", self.n.min(hot.len()), total);
+        out += &format!("{:>4} {:>14} {:>7} {:>10}  {}\n", "rank", "self cycles", "%", "calls", "function");
+        for (rank, (addr, cycles)) in hot.iter().take(self.n).enumerate() {
+            out += &format!("{:>4} {:>14} {:>6.2}% {:>10}  {}\n", rank + 1, cycles,
+                            **cycles as f64 / total.max(1) as f64 * 100.0, profile.calls.get(*addr).unwrap_or(&0),
+                            profile.function_name(**addr));
+        }
+        if profile.unattributed > 0 {
+            out += &format!("{} cycles outside of any function\n", profile.unattributed);
+        }
+        out
+    }
+}
+
+impl AbstractReceiver for TopReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        self.profile.observe(entry);
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        print!("{}", self.report());
+        Ok(())
+    }
+}
@@ -1,57 +1,286 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::UnwinderOptions;
+use crate::frontend::insn_map::{build_insn_map, code_bytes, read_code};
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayCode, OverlayMap};
+use crate::frontend::register::register_name;
 use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
+// bumped whenever the columns of trace.txt change
+pub const TXT_FORMAT_VERSION: u32 = 2;
+
+// what the lines show besides the pc and the instruction
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TxtOptions {
+    // the timestamp of the last timed event, the columnar format always has it
+    pub timestamp: bool,
+    // the enclosing function
+    pub symbols: bool,
+    // the source file:line
+    pub source: bool,
+    // the free-form format of format version 1
+    pub legacy: bool,
+}
+
+/// Writes `trace.txt`, every instruction and event decoded. The format has a
+/// `# tacit trace.txt v<version>` line and a line naming the columns, then
+/// one tab separated line per instruction or event:
+/// `timestamp hart pc bytes mnemonic operands event`, with `function` and
+/// `source` appended when asked for. Instruction lines have `-` as event,
+/// event lines have `-` in the instruction columns. The timestamp of an
+/// instruction is that of the last timed event.
 pub struct TxtReceiver {
     writer: BufWriter<File>,
     receiver: BusReceiver,
+    // `.insn` text of the words rvdasm cannot decode
+    opaque: HashMap<u64, String>,
+    options: TxtOptions,
+    last_timestamp: u64,
+    hart: u64,
+    // the code of the binary and of the kernel modules, for the raw bytes
+    code: Vec<(u64, Vec<u8>)>,
+    // the code of the overlays, read instead of `code` while loaded
+    overlays: Option<OverlayCode>,
+    loader: Option<Loader>,
+    // the function and source annotation of each pc seen
+    annotations: HashMap<u64, (String, String)>,
 }
 
 impl TxtReceiver {
-    pub fn new(bus_rx: BusReader<Entry>) -> Self {
-        Self { writer: BufWriter::new(File::create("trace.txt").unwrap()), 
-                receiver: BusReceiver { name: "txt".to_string(), bus_rx: bus_rx, checksum: 0 } }
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, unwinder_options: &UnwinderOptions, options: TxtOptions) -> Result<Self> {
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let insn_map = build_insn_map(&elf)?;
+        let opaque = insn_map.opaque.keys().map(|pc| (*pc, insn_map.opaque_text(*pc).unwrap())).collect();
+        let mut code = read_code(&elf)?;
+        for module in read_module_maps(&unwinder_options.modules)? {
+            code.push(module.load_code()?);
+        }
+        let overlays = match OverlayMap::from_paths(&unwinder_options.overlays, &unwinder_options.process_map,
+                                                    &unwinder_options.sideband, &elf)? {
+            Some(map) => Some(OverlayCode::load(&map)?),
+            None => None,
+        };
+        let loader = if options.symbols || options.source { Some(Loader::new(&elf_path).map_err(|e| anyhow!("{}", e))?) } else { None };
+        let mut writer = BufWriter::new(File::create("trace.txt")?);
+        if !options.legacy {
+            writeln!(writer, "# tacit trace.txt v{}", TXT_FORMAT_VERSION)?;
+            write!(writer, "# timestamp\thart\tpc\tbytes\tmnemonic\toperands\tevent")?;
+            if options.symbols {
+                write!(writer, "\tfunction")?;
+            }
+            if options.source {
+                write!(writer, "\tsource")?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(Self { writer,
+                receiver: BusReceiver { name: "txt".to_string(), bus_rx: bus_rx, checksum: 0 },
+                opaque,
+                options,
+                last_timestamp: 0,
+                hart: 0,
+                code,
+                overlays,
+                loader,
+                annotations: HashMap::new() })
     }
-}
 
-impl AbstractReceiver for TxtReceiver {
+    // the function and `<file>:<line>` of pc, as asked for
+    fn annotation(&mut self, pc: u64) -> &(String, String) {
+        let (options, loader) = (self.options, &self.loader);
+        self.annotations.entry(pc).or_insert_with(|| {
+            let Some(loader) = loader else { return (String::new(), String::new()) };
+            let function = if options.symbols { loader.find_symbol(pc).unwrap_or("?").to_string() } else { String::new() };
+            let source = match loader.find_location(pc) {
+                Ok(Some(location)) if options.source => format!("{}:{}", location.file.unwrap_or("?"), location.line.unwrap_or(0)),
+                _ if options.source => "?".to_string(),
+                _ => String::new(),
+            };
+            (function, source)
+        })
+    }
 
-    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
-        &mut self.receiver.bus_rx
+    // the instruction bytes at pc, as they are in memory
+    fn raw_bytes(&self, pc: u64, len: usize) -> String {
+        let bytes = match &self.overlays {
+            Some(overlays) => overlays.bytes(&self.code, pc, len),
+            None => code_bytes(&self.code, pc, len),
+        };
+        match bytes {
+            Some(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            None => "-".to_string(),
+        }
     }
 
-    fn _bump_checksum(&mut self) {
-        self.receiver.checksum += 1;
+    // `<event>` followed by ` key=value` details
+    pub(crate) fn event_text(entry: &Entry) -> String {
+        let mut text = entry.event.to_string();
+        match entry.event {
+            Event::BPHit => text += &format!(" count={}", entry.timestamp.unwrap()),
+            Event::ContextSwitch => {
+                let ctx = entry.ctx.unwrap();
+                text += &format!(" hart={} asid={}", ctx.hart, ctx.asid);
+            }
+            Event::DataAccess => {
+                let data = entry.data.unwrap();
+                text += &format!(" {}={:#x} size={}", if data.is_store { "store" } else { "load" }, data.address, data.size);
+            }
+            Event::RegisterValue => {
+                let reg = entry.reg.unwrap();
+                text += &format!(" {}={:#x}", register_name(reg.regno), reg.value);
+            }
+            Event::Start | Event::End | Event::BPMiss | Event::Panic => {}
+            _ => text += &format!(" to={:#x}", entry.arc.1),
+        }
+        if let Some(trap) = entry.trap {
+            if let Some(cause) = trap.cause {
+                text += &format!(" cause={}", cause);
+            }
+            if let Some(tval) = trap.tval {
+                text += &format!(" tval={:#x}", tval);
+            }
+            if let Some(handler) = trap.handler {
+                text += &format!(" handler={:#x}", handler);
+            }
+        }
+        text += &entry.attrs.to_string();
+        text
     }
 
-    fn _receive_entry(&mut self, entry: Entry) {
+    fn write_columns(&mut self, entry: Entry) -> std::io::Result<()> {
+        let mut line = match entry.event {
+            Event::None => {
+                let pc = entry.arc.0;
+                let (mnemonic, operands) = if let Some(text) = self.opaque.get(&pc) {
+                    let (mnemonic, operands) = text.split_once(' ').unwrap_or((text, ""));
+                    (mnemonic.to_string(), operands.to_string())
+                } else if let Some(insn) = &entry.insn {
+                    let text = insn.to_string();
+                    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+                    (mnemonic.to_string(), operands.trim().to_string())
+                } else {
+                    ("-".to_string(), String::new())
+                };
+                let operands = if operands.is_empty() { "-".to_string() } else { operands };
+                let attrs = if entry.attrs.is_empty() { "-".to_string() } else { entry.attrs.to_string().trim_start().to_string() };
+                format!("{}\t{}\t{:#x}\t{}\t{}\t{}\t{}", self.last_timestamp, self.hart, pc,
+                        self.raw_bytes(pc, (entry.arc.1 - pc) as usize), mnemonic, operands, attrs)
+            }
+            Event::BPHit => {
+                format!("{}\t{}\t{:#x}\t-\t-\t-\t{}", self.last_timestamp, self.hart, entry.arc.0, Self::event_text(&entry))
+            }
+            _ => {
+                if let Some(timestamp) = entry.timestamp {
+                    self.last_timestamp = timestamp;
+                }
+                if let Some(ctx) = entry.ctx {
+                    self.hart = ctx.hart;
+                }
+                format!("{}\t{}\t{:#x}\t-\t-\t-\t{}", self.last_timestamp, self.hart, entry.arc.0, Self::event_text(&entry))
+            }
+        };
+        if self.loader.is_some() {
+            let (function, source) = self.annotation(entry.arc.0).clone();
+            if self.options.symbols {
+                line += &format!("\t{}", function);
+            }
+            if self.options.source {
+                line += &format!("\t{}", source);
+            }
+        }
+        writeln!(self.writer, "{}", line)
+    }
+
+    fn write_legacy(&mut self, entry: Entry) -> std::io::Result<()> {
         match entry.event {
             Event::None => {
                 // only arc.0 is used for none type events
-                self.writer.write_all(format!("{:#x}:", entry.arc.0).as_bytes()).unwrap();
-                if let Some(insn) = entry.insn {
-                    self.writer.write_all(format!(" {}", insn.to_string()).as_bytes()).unwrap();
+                if self.options.timestamp {
+                    self.writer.write_all(format!("[{}] ", self.last_timestamp).as_bytes())?;
+                }
+                self.writer.write_all(format!("{:#x}:", entry.arc.0).as_bytes())?;
+                if let Some(text) = self.opaque.get(&entry.arc.0) {
+                    self.writer.write_all(format!(" {}", text).as_bytes())?;
+                } else if let Some(insn) = entry.insn {
+                    self.writer.write_all(format!(" {}", insn.to_string()).as_bytes())?;
+                }
+                if self.loader.is_some() {
+                    let (function, source) = self.annotation(entry.arc.0).clone();
+                    if self.options.symbols {
+                        self.writer.write_all(format!(" <{}>", function).as_bytes())?;
+                    }
+                    if self.options.source {
+                        self.writer.write_all(format!(" {}", source).as_bytes())?;
+                    }
                 }
-                self.writer.write_all(b"\n").unwrap();
+                self.writer.write_all(b"\n")?;
             }
             Event::BPHit => {
-                self.writer.write_all(format!("[hit count: {}]", entry.timestamp.unwrap()).as_bytes()).unwrap();
-                self.writer.write_all(b" BPHit\n").unwrap();
+                self.writer.write_all(format!("[hit count: {}]", entry.timestamp.unwrap()).as_bytes())?;
+                self.writer.write_all(b" BPHit\n")?;
             }
             _ => {
                 if let Some(timestamp) = entry.timestamp {
-                    self.writer.write_all(format!("[timestamp: {}]", timestamp).as_bytes()).unwrap();
+                    self.last_timestamp = timestamp;
+                    self.writer.write_all(format!("[timestamp: {}]", timestamp).as_bytes())?;
                     // write the event
-                    self.writer.write_all(format!(" {}", entry.event.to_string()).as_bytes()).unwrap();
-                    self.writer.write_all(b"\n").unwrap();
+                    self.writer.write_all(format!(" {}", entry.event.to_string()).as_bytes())?;
+                    if let Some(cause) = entry.cause_text() {
+                        self.writer.write_all(format!(" {}", cause).as_bytes())?;
+                    }
+                    if let Some(trap) = entry.trap {
+                        if let Some(tval) = trap.tval {
+                            self.writer.write_all(format!(" tval {:#x}", tval).as_bytes())?;
+                        }
+                        if let Some(handler) = trap.handler {
+                            self.writer.write_all(format!(" handler {:#x}", handler).as_bytes())?;
+                        }
+                    }
+                    self.writer.write_all(b"\n")?;
                 }
             }
         }
+        Ok(())
+    }
+}
+
+impl AbstractReceiver for TxtReceiver {
+
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the instruction at `from` ran before execution reached `to`
+        let (timestamp, to) = (entry.timestamp, entry.arc.1);
+        if self.options.legacy {
+            self.write_legacy(entry)?;
+        } else {
+            self.write_columns(entry)?;
+        }
+        if let Some(overlays) = self.overlays.as_mut() {
+            overlays.tracker.observe(timestamp, to);
+        }
+        Ok(())
     }
 
-    fn _flush(&mut self) {
-        self.writer.flush().unwrap();
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
     }
 }
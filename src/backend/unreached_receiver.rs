@@ -0,0 +1,141 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::debug;
+
+// a function of the binary and what of it never ran
+struct Unreached {
+    name: String,
+    location: String,
+    start: u64,
+    size: u64,
+    executed: u64,
+    // (start, size) of the never executed blocks
+    blocks: Vec<(u64, u64)>,
+}
+
+/// The code of the binary that never ran during the trace, for dead code
+/// elimination and coverage gaps. Writes `trace.unreached.txt`: the
+/// functions that were never entered, and for the others the static basic
+/// blocks never executed, with their sizes, largest first. A function
+/// covers the instructions from its symbol to the next one, as far as they
+/// are contiguous.
+pub struct UnreachedReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    executed: HashSet<u64>,
+}
+
+impl UnreachedReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        debug!("UnreachedReceiver::new");
+        Ok(UnreachedReceiver {
+            writer: BufWriter::new(File::create("trace.unreached.txt")?),
+            receiver: BusReceiver { name: "unreached".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            executed: HashSet::new(),
+        })
+    }
+
+    fn unreached(&self) -> Vec<Unreached> {
+        let insn_map = &self.unwinder.insn_map;
+        let mut pcs: Vec<u64> = insn_map.keys().copied().collect();
+        pcs.sort();
+        let mut functions = Vec::new();
+        for (&start, info) in self.unwinder.func_symbol_map().iter() {
+            let (_, end) = self.unwinder.idx_2_addr_range[&info.index];
+            let end = if end > start { end } else { u64::MAX };
+            let mut function = Unreached {
+                name: info.name.clone(),
+                location: format!("{}:{}", info.file, info.line),
+                start,
+                size: 0,
+                executed: 0,
+                blocks: Vec::new(),
+            };
+            // the never executed block being collected
+            let mut block: Option<(u64, u64)> = None;
+            let mut expected = start;
+            for &pc in &pcs[pcs.partition_point(|&pc| pc < start)..] {
+                if pc >= end || pc != expected {
+                    break;
+                }
+                let insn = &insn_map[&pc];
+                expected = pc + insn.len as u64;
+                function.size += insn.len as u64;
+                if self.executed.contains(&pc) {
+                    function.executed += insn.len as u64;
+                    function.blocks.extend(block.take());
+                    continue;
+                }
+                let (_, size) = block.get_or_insert((pc, 0));
+                *size += insn.len as u64;
+                if insn.is_branch() || insn.is_direct_jump() || insn.is_indirect_jump() {
+                    function.blocks.extend(block.take());
+                }
+            }
+            function.blocks.extend(block);
+            if function.size > function.executed {
+                functions.push(function);
+            }
+        }
+        functions
+    }
+
+    fn write_report(&mut self) -> std::io::Result<()> {
+        let functions = self.unreached();
+        let (mut never, mut partial): (Vec<_>, Vec<_>) = functions.into_iter().partition(|f| f.executed == 0);
+        never.sort_by_key(|f| (std::cmp::Reverse(f.size), f.start));
+        partial.sort_by_key(|f| (std::cmp::Reverse(f.size - f.executed), f.start));
+
+        let w = &mut self.writer;
+        writeln!(w, "never executed functions: {} ({} bytes)", never.len(), never.iter().map(|f| f.size).sum::<u64>())?;
+        for f in &never {
+            writeln!(w, "  {:>8} bytes  {:#x}  {}  {}", f.size, f.start, f.name, f.location)?;
+        }
+        writeln!(w)?;
+        writeln!(w, "partially executed functions: {} ({} bytes never executed)", partial.len(),
+                 partial.iter().map(|f| f.size - f.executed).sum::<u64>())?;
+        for f in &mut partial {
+            writeln!(w, "  {} ({:#x}, {}): {} of {} bytes executed", f.name, f.start, f.location, f.executed, f.size)?;
+            f.blocks.sort_by_key(|&(start, size)| (std::cmp::Reverse(size), start));
+            for (start, size) in &f.blocks {
+                writeln!(w, "    {:>8} bytes  {:#x}..{:#x}", size, start, start + size)?;
+            }
+        }
+        w.flush()
+    }
+}
+
+impl AbstractReceiver for UnreachedReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if entry.event == Event::None {
+            self.executed.insert(entry.arc.0);
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        debug!("{} distinct instructions executed", self.executed.len());
+        self.write_report()?;
+        Ok(())
+    }
+}
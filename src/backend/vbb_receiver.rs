@@ -2,37 +2,93 @@ use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
 
 use bus::BusReader;
+use anyhow::{anyhow, Result};
+use addr2line::Loader;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::collections::HashMap;
 
+// blocks executed fewer times have no typical latency to compare against
+const MIN_SAMPLES: usize = 10;
+
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct BB {
   start_addr: u64,
   end_addr: u64,
 }
 
+/// Writes every basic block to `trace.vbb.txt`, with its function and source
+/// line, execution count, mean, min and max latency, total cycles and
+/// standard deviation, by address or by total cycles. With an anomaly
+/// factor, the executions that took longer than that many times the median
+/// latency of their block go to `trace.vbb.anomalies.txt`, with their
+/// timestamp and function: the sporadic cache, TLB or contention hiccups a
+/// mean hides.
 pub struct VBBReceiver {
   writer: BufWriter<File>,
   receiver: BusReceiver,
-  bb_records: HashMap<BB, Vec<u64>>,
+  // the latency and end timestamp of every execution
+  bb_records: HashMap<BB, Vec<(u64, u64)>>,
+  anomaly_factor: f64,
+  sort_by_total: bool,
+  loader: Loader,
   prev_addr: u64,
   prev_timestamp: u64,
 }
 
 impl VBBReceiver {
-  pub fn new(bus_rx: BusReader<Entry>) -> Self {
-    Self {
-      writer: BufWriter::new(File::create("trace.vbb.txt").unwrap()),
+  pub fn new(bus_rx: BusReader<Entry>, elf_path: String, anomaly_factor: f64, sort_by_total: bool) -> Result<Self> {
+    Ok(Self {
+      writer: BufWriter::new(File::create("trace.vbb.txt")?),
       receiver: BusReceiver {
         name: "vbb".to_string(),
         bus_rx,
         checksum: 0,
       },
       bb_records: HashMap::new(),
+      anomaly_factor,
+      sort_by_total,
+      loader: Loader::new(elf_path).map_err(|e| anyhow!("{}", e))?,
       prev_addr: 0,
       prev_timestamp: 0,
+    })
+  }
+}
+
+impl VBBReceiver {
+  // `file:line` of the block start
+  fn location(&self, addr: u64) -> String {
+    match self.loader.find_location(addr) {
+      Ok(Some(location)) => format!("{}:{}", location.file.unwrap_or("?"), location.line.unwrap_or(0)),
+      _ => "?".to_string(),
+    }
+  }
+
+  fn write_anomalies(&self) -> std::io::Result<()> {
+    // (timestamp, block, latency, median)
+    let mut anomalies = Vec::new();
+    for (bb, intervals) in self.bb_records.iter() {
+      if intervals.len() < MIN_SAMPLES {
+        continue;
+      }
+      let mut latencies: Vec<u64> = intervals.iter().map(|&(x, _)| x).collect();
+      latencies.sort_unstable();
+      let median = latencies[latencies.len() / 2];
+      let threshold = median.max(1) as f64 * self.anomaly_factor;
+      for &(latency, timestamp) in intervals {
+        if latency as f64 > threshold {
+          anomalies.push((timestamp, bb, latency, median));
+        }
+      }
+    }
+    anomalies.sort_by_key(|&(timestamp, bb, _, _)| (timestamp, bb.start_addr, bb.end_addr));
+    let mut writer = BufWriter::new(File::create("trace.vbb.anomalies.txt")?);
+    writeln!(writer, "{} executions over {}x the median latency of their block", anomalies.len(), self.anomaly_factor)?;
+    for (timestamp, bb, latency, median) in anomalies {
+      writeln!(writer, "TIMESTAMP: {}, BB: {:#x}-{:#x}, FUNCTION: {}, LATENCY: {}, MEDIAN: {}",
+        timestamp, bb.start_addr, bb.end_addr, self.loader.find_symbol(bb.start_addr).unwrap_or("?"), latency, median)?;
     }
+    writer.flush()
   }
 }
 
@@ -45,7 +101,11 @@ impl AbstractReceiver for VBBReceiver {
     self.receiver.checksum += 1;
   }
 
-  fn _receive_entry(&mut self, entry: Entry) {
+  fn checksum(&self) -> usize {
+    self.receiver.checksum
+  }
+
+  fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
     match entry.event {
       Event::Start => {
         self.prev_addr = entry.arc.0;
@@ -55,40 +115,47 @@ impl AbstractReceiver for VBBReceiver {
         let curr_addr = entry.arc.0;
         let curr_timestamp = entry.timestamp.unwrap();
         let bb = BB { start_addr: self.prev_addr, end_addr: curr_addr };
-        if self.bb_records.contains_key(&bb) {
-          self.bb_records.get_mut(&bb).unwrap().push(curr_timestamp - self.prev_timestamp);
-        } else {
-          self.bb_records.insert(bb, vec![curr_timestamp - self.prev_timestamp]);
-        }
+        self.bb_records.entry(bb).or_default().push((curr_timestamp - self.prev_timestamp, curr_timestamp));
         self.prev_addr = entry.arc.1;
         self.prev_timestamp = curr_timestamp;
       }
       _ => {}
     }
+    Ok(())
   }
 
-  fn _flush(&mut self) {
-    for (bb, intervals) in self.bb_records.iter() {
-      if intervals.is_empty() {
-        continue;
-      }
-      
-      // Calculate mean manually
-      let sum: u64 = intervals.iter().sum();
+  fn _flush(&mut self) -> Result<()> {
+    let mut blocks: Vec<(&BB, u64)> = self.bb_records.iter()
+      .map(|(bb, intervals)| (bb, intervals.iter().map(|&(x, _)| x).sum()))
+      .collect();
+    if self.sort_by_total {
+      blocks.sort_by_key(|&(bb, total)| (std::cmp::Reverse(total), bb.start_addr, bb.end_addr));
+    } else {
+      blocks.sort_by_key(|&(bb, _)| (bb.start_addr, bb.end_addr));
+    }
+    for (bb, sum) in blocks {
+      let intervals = &self.bb_records[bb];
       let mean = sum as f64 / intervals.len() as f64;
-      
+      let min = intervals.iter().map(|&(x, _)| x).min().unwrap();
+      let max = intervals.iter().map(|&(x, _)| x).max().unwrap();
+
       // Calculate standard deviation manually with a more stable algorithm
       let variance = intervals.iter()
-        .map(|&x| {
+        .map(|&(x, _)| {
           let diff = x as f64 - mean;
           diff * diff
         })
         .sum::<f64>() / intervals.len() as f64;
       let stddev = variance.sqrt();
-      
-      self.writer.write_all(format!("BB: {:#x}-{:#x}, MEAN: {}, COUNT: {}, STDDEV: {}\n", 
-        bb.start_addr, bb.end_addr, mean, intervals.len(), stddev).as_bytes()).unwrap();
+
+      writeln!(self.writer, "BB: {:#x}-{:#x}, FUNCTION: {}, LINE: {}, COUNT: {}, MEAN: {:.2}, MIN: {}, MAX: {}, TOTAL: {}, STDDEV: {:.2}",
+        bb.start_addr, bb.end_addr, self.loader.find_symbol(bb.start_addr).unwrap_or("?"), self.location(bb.start_addr),
+        intervals.len(), mean, min, max, sum, stddev)?;
+    }
+    self.writer.flush()?;
+    if self.anomaly_factor > 0.0 {
+      self.write_anomalies()?;
     }
-    self.writer.flush().unwrap();
+    Ok(())
   }
 }
@@ -0,0 +1,131 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use bus::BusReader;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// vcd identifier codes of each signal
+const PC_ID: &str = "!";
+const FUNC_ID: &str = "\"";
+const DEPTH_ID: &str = "#";
+const TRAP_LEVEL_ID: &str = "$";
+const EVENT_ID: &str = "%";
+
+// values of the `event` signal, listed in the vcd header
+const EVENT_CODES: &[(Event, u64)] = &[
+    (Event::Start, 1),
+    (Event::TakenBranch, 2),
+    (Event::NonTakenBranch, 3),
+    (Event::InferrableJump, 4),
+    (Event::UninferableJump, 5),
+    (Event::TrapException, 6),
+    (Event::TrapInterrupt, 7),
+    (Event::TrapReturn, 8),
+    (Event::End, 9),
+];
+
+/// Value-change-dump of the current PC, function, call depth and trap level,
+/// to be opened next to RTL waveforms in GTKWave.
+pub struct VcdReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    last_time: Option<u64>,
+    trap_level: u64,
+}
+
+impl VcdReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create("trace.vcd")?);
+        writeln!(writer, "$version tacit decoder $end")?;
+        writeln!(writer, "$comment timestamps are trace timestamp units $end")?;
+        let codes: Vec<String> = EVENT_CODES.iter().map(|(event, code)| format!("{}={}", code, event.to_string())).collect();
+        writeln!(writer, "$comment event codes: {} $end", codes.join(" "))?;
+        writeln!(writer, "$timescale 1ns $end")?;
+        writeln!(writer, "$scope module tacit $end")?;
+        writeln!(writer, "$var wire 64 {} pc $end", PC_ID)?;
+        writeln!(writer, "$var wire 32 {} func $end", FUNC_ID)?;
+        writeln!(writer, "$var wire 16 {} depth $end", DEPTH_ID)?;
+        writeln!(writer, "$var wire 8 {} trap_level $end", TRAP_LEVEL_ID)?;
+        writeln!(writer, "$var wire 8 {} event $end", EVENT_ID)?;
+        writeln!(writer, "$upscope $end")?;
+        writeln!(writer, "$enddefinitions $end")?;
+        Ok(VcdReceiver {
+            writer,
+            receiver: BusReceiver { name: "vcd".into(), bus_rx, checksum: 0 },
+            unwinder: StackUnwinder::new(elf_path, options)?,
+            last_time: None,
+            trap_level: 0,
+        })
+    }
+
+    fn dump(&mut self, ts: u64, event: &Event, pc: u64) -> std::io::Result<()> {
+        // vcd time must not go backwards
+        let ts = self.last_time.map_or(ts, |last| last.max(ts));
+        if self.last_time != Some(ts) {
+            writeln!(self.writer, "#{}", ts)?;
+            self.last_time = Some(ts);
+        }
+        let code = EVENT_CODES.iter().find(|(e, _)| e == event).map_or(0, |(_, code)| *code);
+        writeln!(self.writer, "b{:b} {}", pc, PC_ID)?;
+        match self.unwinder.frame_stack.last() {
+            Some(index) => writeln!(self.writer, "b{:b} {}", index, FUNC_ID)?,
+            None => writeln!(self.writer, "bx {}", FUNC_ID)?,
+        }
+        writeln!(self.writer, "b{:b} {}", self.unwinder.frame_stack.len(), DEPTH_ID)?;
+        writeln!(self.writer, "b{:b} {}", self.trap_level, TRAP_LEVEL_ID)?;
+        writeln!(self.writer, "b{:b} {}", code, EVENT_ID)
+    }
+}
+
+impl AbstractReceiver for VcdReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        match entry.event {
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                if entry.event != Event::InferrableJump {
+                    self.trap_level += 1;
+                }
+                let _ = self.unwinder.step_ij(entry.clone());
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                if entry.event == Event::TrapReturn {
+                    self.trap_level = self.trap_level.saturating_sub(1);
+                }
+                let _ = self.unwinder.step_uj(entry.clone());
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        match entry.event {
+            Event::Start => self.dump(entry.timestamp.unwrap(), &entry.event, entry.arc.0)?,
+            Event::End => self.dump(entry.timestamp.unwrap(), &entry.event, entry.arc.0)?,
+            Event::TakenBranch | Event::NonTakenBranch | Event::InferrableJump | Event::UninferableJump
+            | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn => {
+                self.dump(entry.timestamp.unwrap(), &entry.event, entry.arc.1)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::insn_map::{code_bytes, read_code};
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayCode, OverlayMap};
+use bus::BusReader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const OPCODE_LOAD_FP: u32 = 0b000_0111;
+const OPCODE_STORE_FP: u32 = 0b010_0111;
+const OPCODE_OP_V: u32 = 0b101_0111;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VectorClass {
+    Config,
+    UnitStrideLoad,
+    StridedLoad,
+    IndexedLoad,
+    UnitStrideStore,
+    StridedStore,
+    IndexedStore,
+    IntArith,
+    FpArith,
+    MulMaskArith,
+}
+
+impl VectorClass {
+    fn name(&self) -> &'static str {
+        match self {
+            VectorClass::Config => "config (vset*)",
+            VectorClass::UnitStrideLoad => "unit-stride load",
+            VectorClass::StridedLoad => "strided load",
+            VectorClass::IndexedLoad => "indexed load",
+            VectorClass::UnitStrideStore => "unit-stride store",
+            VectorClass::StridedStore => "strided store",
+            VectorClass::IndexedStore => "indexed store",
+            VectorClass::IntArith => "integer arithmetic",
+            VectorClass::FpArith => "floating-point arithmetic",
+            VectorClass::MulMaskArith => "multiply/mask/reduction",
+        }
+    }
+}
+
+// classify from the encoding rather than the mnemonic, so that vector code
+// is recognized even where the disassembler does not know the V extension
+pub fn vector_class(word: u32) -> Option<VectorClass> {
+    let funct3 = (word >> 12) & 0b111;
+    match word & 0b111_1111 {
+        OPCODE_OP_V => Some(match funct3 {
+            0b111 => VectorClass::Config,
+            0b000 | 0b011 | 0b100 => VectorClass::IntArith,
+            0b001 | 0b101 => VectorClass::FpArith,
+            _ => VectorClass::MulMaskArith,
+        }),
+        // the scalar fp loads/stores use widths 1 to 4 in the same opcodes
+        opcode @ (OPCODE_LOAD_FP | OPCODE_STORE_FP) if matches!(funct3, 0b000 | 0b101 | 0b110 | 0b111) => {
+            let mop = (word >> 26) & 0b11;
+            let load = opcode == OPCODE_LOAD_FP;
+            Some(match (mop, load) {
+                (0b00, true) => VectorClass::UnitStrideLoad,
+                (0b10, true) => VectorClass::StridedLoad,
+                (_, true) => VectorClass::IndexedLoad,
+                (0b00, false) => VectorClass::UnitStrideStore,
+                (0b10, false) => VectorClass::StridedStore,
+                (_, false) => VectorClass::IndexedStore,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Dynamic vector vs scalar instruction counts, the vector instruction mix,
+/// and which functions execute vector code, to `trace.vector.txt`.
+pub struct VectorReceiver {
+    writer: BufWriter<File>,
+    receiver: BusReceiver,
+    // the functions, those of the loaded overlays swapped in as the trace goes
+    unwinder: StackUnwinder,
+    // the code of the binary and of the kernel modules, for the raw encodings
+    code: Vec<(u64, Vec<u8>)>,
+    // the code of the overlays, read instead of `code` while loaded
+    overlays: Option<OverlayCode>,
+    // per pc: vector class and containing function, filled on first retirement
+    // and forgotten when an overlay changes the code
+    pc_info: HashMap<u64, (Option<VectorClass>, Option<u32>)>,
+    insn_count: u64,
+    class_counts: HashMap<VectorClass, u64>,
+    // function index -> (instructions, vector instructions)
+    func_counts: HashMap<u32, (u64, u64)>,
+}
+
+impl VectorReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions) -> Result<Self> {
+        let elf_data = std::fs::read(&elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let mut code = read_code(&elf)?;
+        for module in read_module_maps(&options.modules)? {
+            code.push(module.load_code()?);
+        }
+        let overlays = match OverlayMap::from_paths(&options.overlays, &options.process_map, &options.sideband, &elf)? {
+            Some(map) => Some(OverlayCode::load(&map)?),
+            None => None,
+        };
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        Ok(VectorReceiver {
+            writer: BufWriter::new(File::create("trace.vector.txt")?),
+            receiver: BusReceiver { name: "vector".into(), bus_rx, checksum: 0 },
+            unwinder,
+            code,
+            overlays,
+            pc_info: HashMap::new(),
+            insn_count: 0,
+            class_counts: HashMap::new(),
+            func_counts: HashMap::new(),
+        })
+    }
+
+    // the word at pc as the trace executed it
+    fn raw_word(&self, pc: u64) -> Option<u32> {
+        let bytes = match &self.overlays {
+            Some(overlays) => overlays.bytes(&self.code, pc, 4),
+            None => code_bytes(&self.code, pc, 4),
+        };
+        bytes.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn function_at(&self, pc: u64) -> Option<u32> {
+        let (start, _) = self.unwinder.function_range_at(pc)?;
+        Some(self.unwinder.func_symbol_map()[&start].index)
+    }
+
+    fn count(&mut self, entry: &Entry) {
+        let pc = entry.arc.0;
+        let (class, func) = match self.pc_info.get(&pc) {
+            Some(info) => *info,
+            None => {
+                // compressed instructions are never vector ones
+                let is_32bit = entry.insn.as_ref().is_none_or(|insn| insn.len == 4);
+                let class = if is_32bit { self.raw_word(pc).and_then(vector_class) } else { None };
+                let info = (class, self.function_at(pc));
+                self.pc_info.insert(pc, info);
+                info
+            }
+        };
+        self.insn_count += 1;
+        if let Some(class) = class {
+            *self.class_counts.entry(class).or_default() += 1;
+        }
+        if let Some(func) = func {
+            let counts = self.func_counts.entry(func).or_default();
+            counts.0 += 1;
+            if class.is_some() {
+                counts.1 += 1;
+            }
+        }
+    }
+}
+
+impl AbstractReceiver for VectorReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        if entry.event == Event::None {
+            self.count(&entry);
+        }
+        // the instruction at `from` ran before execution reached `to`
+        if let Some(overlays) = self.overlays.as_mut() {
+            if !overlays.tracker.observe(entry.timestamp, entry.arc.1).is_empty() {
+                self.pc_info.clear();
+            }
+            self.unwinder.sync_overlays(&entry);
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        let vector_count: u64 = self.class_counts.values().sum();
+        let percent = |part: u64, whole: u64| part as f64 / whole.max(1) as f64 * 100.0;
+        writeln!(self.writer, "instructions: {}", self.insn_count)?;
+        writeln!(self.writer, "vector instructions: {} ({:.2}%)", vector_count, percent(vector_count, self.insn_count))?;
+        writeln!(self.writer, "scalar instructions: {} ({:.2}%)", self.insn_count - vector_count,
+                 percent(self.insn_count - vector_count, self.insn_count))?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "vector instruction mix:")?;
+        let mut classes: Vec<_> = self.class_counts.iter().collect();
+        classes.sort();
+        for (class, count) in classes {
+            writeln!(self.writer, "  {:<28} {:>12} ({:.2}%)", class.name(), count, percent(*count, vector_count))?;
+        }
+        writeln!(self.writer)?;
+        writeln!(self.writer, "functions executing vector code:")?;
+        writeln!(self.writer, "  {:<40} {:>12} {:>12} {:>8}", "function", "vector", "total", "vector%")?;
+        let mut funcs: Vec<_> = self.func_counts.iter().filter(|(_, &(_, vector))| vector > 0).collect();
+        funcs.sort_by(|a, b| b.1.1.cmp(&a.1.1).then(a.0.cmp(b.0)));
+        for (index, &(total, vector)) in funcs {
+            let name = &self.unwinder.symbols()[*index as usize].name;
+            writeln!(self.writer, "  {:<40} {:>12} {:>12} {:>7.2}%", name, vector, total, percent(vector, total))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
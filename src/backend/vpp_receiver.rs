@@ -1,18 +1,58 @@
 use crate::backend::event::{Entry, Event};
 use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
-use crate::backend::stack_unwinder::StackUnwinder;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::backend::gantt_receiver::csv_field;
 
 use bus::BusReader;
+use anyhow::Result;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::collections::HashMap;
 
+// nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+  let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+  sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// count, mean, min, max and percentiles of the times of a path or block
+struct Summary {
+  count: usize,
+  mean: f64,
+  min: u64,
+  max: u64,
+  p50: u64,
+  p90: u64,
+  p99: u64,
+}
+
+impl Summary {
+  fn new(values: &[u64]) -> Self {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    Self {
+      count: sorted.len(),
+      mean: sorted.iter().sum::<u64>() as f64 / sorted.len() as f64,
+      min: sorted[0],
+      max: sorted[sorted.len() - 1],
+      p50: percentile(&sorted, 50.0),
+      p90: percentile(&sorted, 90.0),
+      p99: percentile(&sorted, 99.0),
+    }
+  }
+}
+
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct Path {
   addr: u64,
   path: Vec<bool>,
 }
 
+/// The time of every path through a function, a path being the function
+/// and the outcomes of its branches in order. Writes `trace.vpp.txt`, per
+/// path its intervals and their statistics, and with `bb_summary` the time of
+/// each of its basic blocks; with `csv`, the statistics of the paths also go
+/// to `trace.vpp.csv`.
 pub struct VPPReceiver {
   writer: BufWriter<File>,
   receiver: BusReceiver,
@@ -24,25 +64,54 @@ pub struct VPPReceiver {
   start_timestamps: Vec<u64>, // stack for start timestamps of currently in-progress paths
   bb_timestamps: Vec<Vec<u64>>, // timestamps of each basic block
   use_bb_analysis: bool,
+  bb_summary: bool,
+  csv: bool,
 }
 
 impl VPPReceiver {
-  pub fn new(bus_rx: BusReader<Entry>, elf_path: String, use_bb_analysis: bool) -> Self {
-    Self {
-      writer: BufWriter::new(File::create("trace.vpp.txt").unwrap()),
+  pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, use_bb_analysis: bool, bb_summary: bool, csv: bool) -> Result<Self> {
+    Ok(Self {
+      writer: BufWriter::new(File::create("trace.vpp.txt")?),
       receiver: BusReceiver {
         name: "vpp".to_string(),
         bus_rx,
         checksum: 0,
       },
-      stack_unwinder: StackUnwinder::new(elf_path).unwrap(),
+      stack_unwinder: StackUnwinder::new(elf_path, options)?,
       path_records: HashMap::new(),
       path_bb_records: HashMap::new(),
       curr_paths: Vec::new(),
       start_timestamps: Vec::new(),
       bb_timestamps: Vec::new(),
       use_bb_analysis: use_bb_analysis,
+      bb_summary: bb_summary && use_bb_analysis,
+      csv,
+    })
+  }
+
+  // the time of each basic block of the path: from its start, relative to
+  // the path start, to the start of the next one or the end of the path
+  fn bb_summaries(intervals: &[u64], bb_records: &[Vec<u64>]) -> Vec<Summary> {
+    let blocks = bb_records.iter().map(|starts| starts.len()).min().unwrap_or(0);
+    (0..blocks).map(|i| {
+      let times: Vec<u64> = bb_records.iter().zip(intervals).map(|(starts, &end)| {
+        starts.get(i + 1).copied().unwrap_or(end) - starts[i]
+      }).collect();
+      Summary::new(&times)
+    }).collect()
+  }
+
+  fn write_csv(&self, paths: &[(&Path, Summary)]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create("trace.vpp.csv")?);
+    writeln!(writer, "address,path,function,file,line,count,mean,min,max,p50,p90,p99")?;
+    for (path, summary) in paths {
+      let symbol_info = self.stack_unwinder.get_symbol_info(path.addr);
+      writeln!(writer, "{:#x},{},{},{},{},{},{:.2},{},{},{},{},{}", path.addr,
+        path.path.iter().map(|&b| if b { '1' } else { '0' }).collect::<String>(),
+        csv_field(&symbol_info.name), csv_field(&symbol_info.file), symbol_info.line,
+        summary.count, summary.mean, summary.min, summary.max, summary.p50, summary.p90, summary.p99)?;
     }
+    writer.flush()
   }
 }
 
@@ -55,7 +124,11 @@ impl AbstractReceiver for VPPReceiver {
     self.receiver.checksum += 1;
   }
 
-  fn _receive_entry(&mut self, entry: Entry) {
+  fn checksum(&self) -> usize {
+    self.receiver.checksum
+  }
+
+  fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
     match entry.event {
       Event::InferrableJump => {
         let (success, _ , _) = self.stack_unwinder.step_ij(entry.clone());
@@ -119,27 +192,45 @@ impl AbstractReceiver for VPPReceiver {
         // ignore other events
       }
     }
+    Ok(())
   }
 
-  fn _flush(&mut self) {
-    for (path, intervals) in self.path_records.iter() {
+  fn _flush(&mut self) -> Result<()> {
+    let mut summaries = Vec::new();
+    // by function and then branch outcomes, the same run to run
+    let mut paths: Vec<(&Path, &Vec<u64>)> = self.path_records.iter().collect();
+    paths.sort_by(|a, b| (a.0.addr, &a.0.path).cmp(&(b.0.addr, &b.0.path)));
+    for (path, intervals) in paths {
       // addr
-      self.writer.write_all(format!("PATH:{:#x}-", path.addr).as_bytes()).unwrap();
+      self.writer.write_all(format!("PATH:{:#x}-", path.addr).as_bytes())?;
       // path, each taken and not taken
       self.writer.write_all(format!("{}\n", path.path.iter()
           .map(|&b| if b { '1' } else { '0' })
           .collect::<String>())
-          .as_bytes()).unwrap();
+          .as_bytes())?;
       // information about the path, can obtain from the stack unwinder
       let symbol_info = self.stack_unwinder.get_symbol_info(path.addr);
-      self.writer.write_all(format!("INFO: {}: {}, line: {}\n", symbol_info.name, symbol_info.file, symbol_info.line).as_bytes()).unwrap();
+      self.writer.write_all(format!("INFO: {}: {}, line: {}\n", symbol_info.name, symbol_info.file, symbol_info.line).as_bytes())?;
       // intervals
-      self.writer.write_all(format!("INTERVALS: {:?}\n", intervals).as_bytes()).unwrap();
+      self.writer.write_all(format!("INTERVALS: {:?}\n", intervals).as_bytes())?;
+      let summary = Summary::new(intervals);
+      self.writer.write_all(format!("STATS: count: {}, mean: {:.2}, min: {}, max: {}, p50: {}, p90: {}, p99: {}\n",
+        summary.count, summary.mean, summary.min, summary.max, summary.p50, summary.p90, summary.p99).as_bytes())?;
       if self.use_bb_analysis {
-        self.writer.write_all(format!("BB INTERVALS: {:?}\n", self.path_bb_records.get(path).unwrap()).as_bytes()).unwrap();
+        self.writer.write_all(format!("BB INTERVALS: {:?}\n", self.path_bb_records.get(path).unwrap()).as_bytes())?;
       }
-      self.writer.write_all(b"\n").unwrap();
+      if self.bb_summary {
+        for (i, bb) in Self::bb_summaries(intervals, self.path_bb_records.get(path).unwrap()).iter().enumerate() {
+          self.writer.write_all(format!("BB {}: mean: {:.2}, min: {}, max: {}, p90: {}\n", i, bb.mean, bb.min, bb.max, bb.p90).as_bytes())?;
+        }
+      }
+      self.writer.write_all(b"\n")?;
+      summaries.push((path, summary));
+    }
+    self.writer.flush()?;
+    if self.csv {
+      self.write_csv(&summaries)?;
     }
-    self.writer.flush().unwrap();
+    Ok(())
   }
 }
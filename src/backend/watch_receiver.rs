@@ -0,0 +1,173 @@
+use crate::backend::event::{Entry, Event};
+use crate::backend::abstract_receiver::{AbstractReceiver, BusReceiver};
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::parse_u64;
+use bus::BusReader;
+use indexmap::IndexSet;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use log::debug;
+
+// a location to report the executions of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watch {
+    Pc(u64),
+    // executions of its entry
+    Func(String),
+}
+
+impl FromStr for Watch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some(("pc", pc)) => Ok(Watch::Pc(parse_u64(pc)?)),
+            Some(("func", name)) if !name.is_empty() => Ok(Watch::Func(name.to_string())),
+            _ => Err(anyhow!("unknown watch `{}`, expected pc=<address> or func=<name>", s)),
+        }
+    }
+}
+
+// the executions of one watched location
+struct Watched {
+    label: String,
+    // (timestamp, index of the stack) of every execution
+    hits: Vec<(u64, usize)>,
+}
+
+/// Every execution of the watched pcs and function entries, for the ad-hoc
+/// "when does this run and who calls it" question. Writes
+/// `trace.watch.txt`: per location the executions with the inter-arrival
+/// time statistics, then each execution with its timestamp, the time since
+/// the previous one and the call stack. An instruction has the timestamp of
+/// the last timed event.
+pub struct WatchReceiver {
+    receiver: BusReceiver,
+    unwinder: StackUnwinder,
+    watched: Vec<Watched>,
+    // pc to the locations watching it
+    by_pc: HashMap<u64, Vec<usize>>,
+    // the distinct stacks seen at a hit
+    stacks: IndexSet<Vec<u32>>,
+    last_ts: u64,
+}
+
+impl WatchReceiver {
+    pub fn new(bus_rx: BusReader<Entry>, elf_path: String, options: &UnwinderOptions, watches: &[Watch]) -> Result<Self> {
+        debug!("WatchReceiver::new");
+        let unwinder = StackUnwinder::new(elf_path, options)?;
+        let mut watched = Vec::new();
+        let mut by_pc: HashMap<u64, Vec<usize>> = HashMap::new();
+        for watch in watches {
+            let (label, pc) = match watch {
+                Watch::Pc(pc) => (format!("pc {:#x}", pc), *pc),
+                Watch::Func(name) => {
+                    let pc = unwinder.func_symbol_map().iter().find(|(_, info)| &info.name == name)
+                        .map(|(&pc, _)| pc)
+                        .ok_or_else(|| anyhow!("--watch func={}: no such function in the binary", name))?;
+                    (format!("func {} at {:#x}", name, pc), pc)
+                }
+            };
+            by_pc.entry(pc).or_default().push(watched.len());
+            watched.push(Watched { label, hits: Vec::new() });
+        }
+        Ok(WatchReceiver {
+            receiver: BusReceiver { name: "watch".into(), bus_rx, checksum: 0 },
+            unwinder,
+            watched,
+            by_pc,
+            stacks: IndexSet::new(),
+            last_ts: 0,
+        })
+    }
+
+    fn stack_text(&self, index: usize) -> String {
+        let stack = &self.stacks[index];
+        if stack.is_empty() {
+            return "-".to_string();
+        }
+        let names: Vec<&str> = stack.iter().map(|&symbol| self.unwinder.symbols()[symbol as usize].name.as_str()).collect();
+        names.join(" > ")
+    }
+
+    fn write_report(&self) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create("trace.watch.txt")?);
+        for (i, watched) in self.watched.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            writeln!(writer, "{}: {} executions", watched.label, watched.hits.len())?;
+            let mut deltas: Vec<u64> = watched.hits.windows(2).map(|pair| pair[1].0.saturating_sub(pair[0].0)).collect();
+            if !deltas.is_empty() {
+                deltas.sort_unstable();
+                let n = deltas.len() as f64;
+                let mean = deltas.iter().sum::<u64>() as f64 / n;
+                let stddev = (deltas.iter().map(|&delta| (delta as f64 - mean).powi(2)).sum::<f64>() / n).sqrt();
+                // nearest rank
+                let median = deltas[(deltas.len() - 1) / 2];
+                writeln!(writer, "inter-arrival: min {} median {} mean {:.2} max {} stddev {:.2}",
+                         deltas[0], median, mean, deltas[deltas.len() - 1], stddev)?;
+            }
+            writeln!(writer, "{:>14} {:>12}  stack", "timestamp", "delta")?;
+            let mut previous = None;
+            for &(ts, stack) in &watched.hits {
+                let delta = previous.map_or("-".to_string(), |previous: u64| ts.saturating_sub(previous).to_string());
+                writeln!(writer, "{:>14} {:>12}  {}", ts, delta, self.stack_text(stack))?;
+                previous = Some(ts);
+            }
+        }
+        writer.flush()
+    }
+}
+
+impl AbstractReceiver for WatchReceiver {
+    fn bus_rx(&mut self) -> &mut BusReader<Entry> {
+        &mut self.receiver.bus_rx
+    }
+
+    fn _bump_checksum(&mut self) {
+        self.receiver.checksum += 1;
+    }
+
+    fn checksum(&self) -> usize {
+        self.receiver.checksum
+    }
+
+    fn _receive_entry(&mut self, entry: Entry) -> Result<()> {
+        // the hit count of BPHit is not a timestamp
+        if entry.event != Event::BPHit {
+            if let Some(ts) = entry.timestamp {
+                self.last_ts = ts;
+            }
+        }
+        match entry.event {
+            Event::None => {
+                if let Some(watchers) = self.by_pc.get(&entry.arc.0) {
+                    let (stack, _) = self.stacks.insert_full(self.unwinder.frame_stack.clone());
+                    for &watcher in watchers {
+                        self.watched[watcher].hits.push((self.last_ts, stack));
+                    }
+                }
+            }
+            Event::InferrableJump | Event::TrapException | Event::TrapInterrupt => {
+                let _ = self.unwinder.step_ij(entry);
+            }
+            Event::UninferableJump | Event::TrapReturn => {
+                let _ = self.unwinder.step_uj(entry);
+            }
+            Event::ContextSwitch => {
+                self.unwinder.switch_context(entry.ctx.unwrap());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn _flush(&mut self) -> Result<()> {
+        self.write_report()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+// Problems noticed while decoding that do not stop it: a missing symbol, an
+// instruction that cannot be decoded, a call stack out of step with the
+// trace, a truncated or corrupted packet stream, an analysis the trace
+// ended too early for. Each one is reported to a
+// process-wide collector that counts them by category and keeps the first
+// few messages, logged as they happen and summarized at the end of the run.
+// `--diag-json` writes the summary to `decode.diag.json`, `--diag-fatal`
+// fails the run when given categories occurred.
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde_json::json;
+
+use crate::backend::schema;
+
+// messages kept per category, the rest are only counted
+const MAX_SAMPLES: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // a function named on the command line or jumped to is not in the binary
+    MissingSymbol,
+    // words that do not disassemble, or an instruction not of the class
+    // (branch, jump) its packet is about
+    UnknownOpcode,
+    // a return with no frame left to return from
+    StackDesync,
+    // a trap return to somewhere else than where the trap was taken
+    ReturnMismatch,
+    // the packet stream ended without a sync packet, or unchecked
+    TruncatedPacket,
+    // a check packet failed, the packets it covers are dropped
+    CorruptedPacket,
+    // data addresses left without a load or store to go with
+    UnmatchedData,
+    // a trace header newer than this decoder
+    FormatVersion,
+    // an endpoint output missing parts: a region or test still open at the
+    // end of the trace, a phase boundary never crossed, intervals past a limit
+    IncompleteOutput,
+}
+
+const CATEGORIES: [Category; 9] = [Category::MissingSymbol, Category::UnknownOpcode, Category::StackDesync,
+                                   Category::ReturnMismatch, Category::TruncatedPacket, Category::CorruptedPacket,
+                                   Category::UnmatchedData, Category::FormatVersion, Category::IncompleteOutput];
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::MissingSymbol => "missing-symbol",
+            Category::UnknownOpcode => "unknown-opcode",
+            Category::StackDesync => "stack-desync",
+            Category::ReturnMismatch => "return-mismatch",
+            Category::TruncatedPacket => "truncated-packet",
+            Category::CorruptedPacket => "corrupted-packet",
+            Category::UnmatchedData => "unmatched-data",
+            Category::FormatVersion => "format-version",
+            Category::IncompleteOutput => "incomplete-output",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Category {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        CATEGORIES.iter().find(|category| category.name() == s).copied().ok_or_else(|| {
+            let names: Vec<&str> = CATEGORIES.iter().map(|category| category.name()).collect();
+            anyhow!("unknown diagnostic category `{}`, expected one of {}", s, names.join(", "))
+        })
+    }
+}
+
+// the categories of a `--diag-fatal` list, `all` for every one
+pub fn parse_categories(list: &str) -> Result<Vec<Category>> {
+    if list == "all" {
+        return Ok(CATEGORIES.to_vec());
+    }
+    list.split(',').filter(|s| !s.is_empty()).map(str::parse).collect()
+}
+
+struct Diagnostics {
+    counts: [AtomicU64; 9],
+    samples: Mutex<Vec<(Category, String)>>,
+}
+
+static DIAGNOSTICS: Diagnostics = Diagnostics {
+    counts: [const { AtomicU64::new(0) }; 9],
+    samples: Mutex::new(Vec::new()),
+};
+
+pub fn report(category: Category, message: String) {
+    let seen = DIAGNOSTICS.counts[category as usize].fetch_add(1, Ordering::Relaxed);
+    if seen < MAX_SAMPLES {
+        warn!("[{}] {}", category, message);
+        DIAGNOSTICS.samples.lock().unwrap().push((category, message));
+    }
+}
+
+pub fn count(category: Category) -> u64 {
+    DIAGNOSTICS.counts[category as usize].load(Ordering::Relaxed)
+}
+
+// for a run repeated in the same process
+pub fn reset() {
+    for count in &DIAGNOSTICS.counts {
+        count.store(0, Ordering::Relaxed);
+    }
+    DIAGNOSTICS.samples.lock().unwrap().clear();
+}
+
+// the counts of the categories that occurred, empty when none did
+pub fn summary() -> String {
+    let counts: Vec<String> = CATEGORIES.iter()
+        .filter(|&&category| count(category) > 0)
+        .map(|&category| format!("{} {}", count(category), category))
+        .collect();
+    counts.join(", ")
+}
+
+pub fn write_json(path: &str, fatal: &[Category]) -> Result<()> {
+    let samples = DIAGNOSTICS.samples.lock().unwrap();
+    let categories: serde_json::Map<String, serde_json::Value> = CATEGORIES.iter().map(|&category| {
+        let messages: Vec<&str> = samples.iter().filter(|(c, _)| *c == category).map(|(_, m)| m.as_str()).collect();
+        (category.name().to_string(), json!({
+            "count": count(category),
+            "fatal": fatal.contains(&category),
+            "samples": messages,
+        }))
+    }).collect();
+    let diag = json!({
+        "decoder": schema::build_info(),
+        "categories": categories,
+    });
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &diag)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// the fatal categories that occurred, with their counts
+pub fn fatal_occurred(fatal: &[Category]) -> Vec<(Category, u64)> {
+    fatal.iter().map(|&category| (category, count(category))).filter(|&(_, n)| n > 0).collect()
+}
@@ -0,0 +1,221 @@
+// C ABI for linking the decoder into C/C++ tools, see include/tacit.h.
+// The packet decoder runs on its own thread, exactly like the command line
+// tool, and tacit_next_event pulls the entries off its bus one at a time.
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::thread::{self, JoinHandle};
+
+use bus::{Bus, BusReader};
+use anyhow::{anyhow, Result};
+
+use crate::backend::event::Entry;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::decoder::{trace_decoder, DecoderConfig, BUS_SIZE};
+
+/// One decoded entry. `kind` is the event code of the replay format, 0 being
+/// a retired instruction at `from`.
+#[repr(C)]
+pub struct TacitEvent {
+    pub kind: u8,
+    pub has_timestamp: u8,
+    pub from: u64,
+    pub to: u64,
+    pub timestamp: u64,
+    // only meaningful for context switches (kind 12)
+    pub hart: u64,
+    pub asid: u64,
+    // only meaningful for data accesses (kind 15)
+    pub address: u64,
+    pub size: u64,
+    pub is_store: u8,
+    // only meaningful for register values (kind 16)
+    pub regno: u16,
+    pub value: u64,
+}
+
+impl From<&Entry> for TacitEvent {
+    fn from(entry: &Entry) -> Self {
+        let ctx = entry.ctx.unwrap_or_default();
+        let data = entry.data.unwrap_or_default();
+        let reg = entry.reg.unwrap_or_default();
+        TacitEvent {
+            kind: entry.event.to_code(),
+            has_timestamp: entry.timestamp.is_some() as u8,
+            from: entry.arc.0,
+            to: entry.arc.1,
+            timestamp: entry.timestamp.unwrap_or(0),
+            hart: ctx.hart,
+            asid: ctx.asid,
+            address: data.address,
+            size: data.size,
+            is_store: data.is_store as u8,
+            regno: reg.regno,
+            value: reg.value,
+        }
+    }
+}
+
+/// A function symbol of the binary. The strings live as long as the decoder.
+#[repr(C)]
+pub struct TacitSymbol {
+    pub index: u32,
+    pub line: u32,
+    pub start: u64,
+    pub end: u64,
+    pub name: *const c_char,
+    pub file: *const c_char,
+}
+
+/// Version of TacitPlugin, a plugin built against another one is rejected.
+pub const TACIT_PLUGIN_ABI: u32 = 3;
+
+/// A receiver in a plugin library, loaded with `--plugin`. The library
+/// exports `const TacitPlugin *tacit_plugins(size_t *count)`, its receivers
+/// by name. `create` gets the binary and the encoded traces, newline
+/// separated, and returns the state handed to the other calls, NULL on
+/// failure. `receive` and `flush` return 0 on success; on failure
+/// `last_error`, if set, says why. `flush`, `last_error` and `destroy` may
+/// be NULL. Every call of one receiver comes from the same thread.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TacitPlugin {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub create: Option<unsafe extern "C" fn(binary: *const c_char, traces: *const c_char) -> *mut c_void>,
+    pub receive: Option<unsafe extern "C" fn(state: *mut c_void, event: *const TacitEvent) -> i32>,
+    pub flush: Option<unsafe extern "C" fn(state: *mut c_void) -> i32>,
+    pub last_error: Option<unsafe extern "C" fn(state: *mut c_void) -> *const c_char>,
+    pub destroy: Option<unsafe extern "C" fn(state: *mut c_void)>,
+}
+
+pub struct TacitDecoder {
+    bus_rx: BusReader<Entry>,
+    handle: Option<JoinHandle<Result<()>>>,
+    symbols: Vec<TacitSymbol>,
+    // backing storage of the symbol strings
+    _strings: Vec<CString>,
+    error: Option<CString>,
+}
+
+fn c_str(s: *const c_char) -> Result<String> {
+    if s.is_null() {
+        return Err(anyhow!("null path"));
+    }
+    Ok(unsafe { CStr::from_ptr(s) }.to_str()?.to_string())
+}
+
+fn open(config: DecoderConfig) -> Result<TacitDecoder> {
+    let options = UnwinderOptions {
+        overlays: config.overlays.clone(),
+        process_map: config.process_map.clone(),
+        sideband: config.sideband.clone(),
+        modules: config.modules.clone(),
+        ..Default::default()
+    };
+    let unwinder = StackUnwinder::new(config.binary.clone(), &options)?;
+    let mut strings = Vec::new();
+    let mut symbols = Vec::new();
+    for info in unwinder.symbols() {
+        let name = CString::new(info.name.clone())?;
+        let file = CString::new(info.file.clone())?;
+        let (start, end) = unwinder.idx_2_addr_range[&info.index];
+        // the CString heap buffers do not move when the CStrings are moved
+        symbols.push(TacitSymbol { index: info.index, line: info.line, start, end, name: name.as_ptr(), file: file.as_ptr() });
+        strings.push(name);
+        strings.push(file);
+    }
+
+    let mut bus: Bus<Entry> = Bus::new(BUS_SIZE);
+    let bus_rx = bus.add_rx();
+    let handle = thread::spawn(move || trace_decoder(&config, bus));
+    Ok(TacitDecoder { bus_rx, handle: Some(handle), symbols, _strings: strings, error: None })
+}
+
+/// Starts decoding `encoded_trace` against `binary`. Returns NULL on failure.
+///
+/// # Safety
+/// Both paths must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn tacit_open(binary: *const c_char, encoded_trace: *const c_char, br_mode: u64, bp_entries: u64) -> *mut TacitDecoder {
+    let result = catch_unwind(|| -> Result<TacitDecoder> {
+        open(DecoderConfig {
+            encoded_trace: c_str(encoded_trace)?,
+            binary: c_str(binary)?,
+            br_mode,
+            bp_entries,
+            sideband: String::new(),
+            ..Default::default()
+        })
+    });
+    match result {
+        Ok(Ok(decoder)) => Box::into_raw(Box::new(decoder)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Blocks until the next entry is decoded. Returns 1 when `event` was filled,
+/// 0 at the end of the trace and -1 on a decoding error (see tacit_last_error).
+///
+/// # Safety
+/// `decoder` must come from tacit_open and `event` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tacit_next_event(decoder: *mut TacitDecoder, event: *mut TacitEvent) -> i32 {
+    let decoder = &mut *decoder;
+    match decoder.bus_rx.recv() {
+        Ok(entry) => {
+            *event = TacitEvent::from(&entry);
+            1
+        }
+        // the decoder thread dropped the bus, find out how it ended
+        Err(_) => {
+            let result = match decoder.handle.take() {
+                Some(handle) => match handle.join() {
+                    Ok(result) => result,
+                    Err(e) => Err(anyhow!("decoder thread panicked: {:?}", e)),
+                },
+                None => Ok(()),
+            };
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    decoder.error = CString::new(e.to_string()).ok();
+                    -1
+                }
+            }
+        }
+    }
+}
+
+/// Points `symbols` at the function symbol table and returns its length.
+///
+/// # Safety
+/// `decoder` must come from tacit_open and `symbols` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tacit_symbols(decoder: *const TacitDecoder, symbols: *mut *const TacitSymbol) -> usize {
+    let decoder = &*decoder;
+    *symbols = decoder.symbols.as_ptr();
+    decoder.symbols.len()
+}
+
+/// Message of the last decoding error, NULL if there was none.
+///
+/// # Safety
+/// `decoder` must come from tacit_open.
+#[no_mangle]
+pub unsafe extern "C" fn tacit_last_error(decoder: *const TacitDecoder) -> *const c_char {
+    let decoder = &*decoder;
+    decoder.error.as_ref().map_or(ptr::null(), |e| e.as_ptr())
+}
+
+/// Releases the decoder. A decoder thread that is still running finishes
+/// the trace in the background and its entries are dropped.
+///
+/// # Safety
+/// `decoder` must come from tacit_open and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn tacit_close(decoder: *mut TacitDecoder) {
+    if !decoder.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(decoder))));
+    }
+}
@@ -0,0 +1,72 @@
+// How the encoder compresses the addresses it puts in packets. Full
+// addresses (sync packets, trap sources) are shifted right by `shift`, jump
+// and trap targets are additionally made relative to the pc of the jump.
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use tacit_wire::sign_extend;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    // target XOR pc, both shifted
+    #[default]
+    Xor,
+    // the shifted target itself
+    Absolute,
+    // shifted target - pc, sign-extended from the width of the varint
+    Delta,
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xor" => Ok(Compression::Xor),
+            "absolute" => Ok(Compression::Absolute),
+            "delta" => Ok(Compression::Delta),
+            _ => Err(anyhow!("unknown address compression `{}`, expected xor, absolute or delta", s)),
+        }
+    }
+}
+
+impl Compression {
+    // numbering used by the trace header, never reorder
+    pub fn from_code(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(Compression::Xor),
+            1 => Ok(Compression::Absolute),
+            2 => Ok(Compression::Delta),
+            _ => Err(anyhow!("unknown address compression code {}", code)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressScheme {
+    pub shift: u32,
+    pub compression: Compression,
+}
+
+// what the tacit encoder does: instructions are at least 2-byte aligned
+impl Default for AddressScheme {
+    fn default() -> Self {
+        Self { shift: 1, compression: Compression::Xor }
+    }
+}
+
+impl AddressScheme {
+    pub fn absolute(&self, value: u64) -> u64 {
+        value << self.shift
+    }
+
+    // target of a jump or trap taken at pc, `bits` is the payload width of
+    // the varint the value came in
+    pub fn target(&self, value: u64, bits: u32, pc: u64) -> u64 {
+        match self.compression {
+            Compression::Xor => (value ^ (pc >> self.shift)) << self.shift,
+            Compression::Absolute => value << self.shift,
+            Compression::Delta => pc.wrapping_add((sign_extend(value, bits) << self.shift) as u64),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// Async packet input for embedding the decoder in a tokio service that
+// receives the trace over the network. Packets are framed asynchronously and
+// then parsed and decoded by the same code as the blocking path, so no
+// thread is held per connection while waiting for bytes.
+use anyhow::Result;
+use tacit_wire::{VAR_LAST, VAR_MASK};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::frontend::context::ContextMap;
+use crate::frontend::decoder::{DecoderConfig, EntrySink, PacketDecoder};
+use crate::frontend::packet::{self, Packet};
+use crate::frontend::source::TraceFormat;
+use crate::diagnostics::{self, Category};
+use crate::metrics;
+use crate::watchdog;
+
+// fetch the bytes of one packet, then parse them with the blocking reader
+pub async fn read_packet_async<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Packet> {
+    let first_byte = stream.read_u8().await?;
+    read_packet_after_async(first_byte, stream).await
+}
+
+async fn read_packet_after_async<R: AsyncRead + Unpin>(first_byte: u8, stream: &mut R) -> Result<Packet> {
+    let mut bytes = vec![first_byte];
+    for _ in 0..packet::varint_count(first_byte) {
+        loop {
+            let byte = stream.read_u8().await?;
+            bytes.push(byte);
+            if byte & VAR_MASK == VAR_LAST {
+                break;
+            }
+        }
+    }
+    packet::read_packet(&mut bytes.as_slice())
+}
+
+// async counterpart of decoder::decode, returns the number of packets decoded
+pub async fn decode_async<R: AsyncRead + Unpin>(elf_buffer: &[u8], encoded_trace_reader: &mut R, ctx_map: Option<ContextMap>,
+                                                args: &DecoderConfig, bus: &mut impl EntrySink) -> Result<u64> {
+    if args.format != TraceFormat::Tacit {
+        return Err(anyhow::anyhow!("async decoding only reads tacit packets, not {:?}", args.format));
+    }
+    if !args.byte_order.is_native() {
+        return Err(anyhow::anyhow!("async decoding only reads traces in stream order, not {:?}", args.byte_order));
+    }
+    let mut decoder = PacketDecoder::new(elf_buffer, ctx_map, args)?;
+    let mut first_byte = encoded_trace_reader.read_u8().await?;
+    if first_byte == packet::TRACE_MAGIC[0] {
+        // magic, version and parameter length, then the parameters
+        let mut bytes = vec![0u8; packet::TRACE_MAGIC.len() + 2];
+        encoded_trace_reader.read_exact(&mut bytes[1..]).await?;
+        let mut params = vec![0u8; bytes[bytes.len() - 1] as usize];
+        encoded_trace_reader.read_exact(&mut params).await?;
+        bytes.extend(params);
+        decoder.apply_header(&packet::read_header(&mut &bytes[1..])?);
+        first_byte = encoded_trace_reader.read_u8().await?;
+    }
+    decoder.start(packet::expect_sync(read_packet_after_async(first_byte, encoded_trace_reader).await?)?, bus);
+    let mut ended = false;
+    while let Ok(packet) = read_packet_async(encoded_trace_reader).await {
+        if watchdog::aborted() {
+            return Err(anyhow::anyhow!("decoding aborted by the watchdog after {} packets", decoder.packet_count));
+        }
+        if decoder.step(packet, bus) {
+            ended = true;
+            break;
+        }
+    }
+    if !ended {
+        metrics::record_truncated();
+        diagnostics::report(Category::TruncatedPacket,
+                            format!("the packet stream ended after {} packets without a sync packet", decoder.packet_count));
+    }
+    Ok(decoder.packet_count)
+}
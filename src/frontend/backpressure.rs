@@ -0,0 +1,241 @@
+// What the frontend does when a slow receiver lets the bus fill up.
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bus::Bus;
+use rvdasm::insn::Insn;
+use tacit_wire::{read_varint, write_varint};
+
+use crate::backend::event::Entry;
+use crate::backend::replay_receiver::write_entry;
+use crate::frontend::decoder::EntrySink;
+use crate::frontend::replay_reader::parse_entry;
+use crate::metrics;
+use crate::watchdog;
+
+const SPILL_PATH: &str = "trace.spill.bin";
+// spilled entries written between two attempts to move them back to the bus
+const SPILL_BATCH: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    // wait for the slowest receiver, nothing is lost
+    #[default]
+    Block,
+    // drop the entries that do not fit, every receiver misses them
+    Drop,
+    // queue the entries that do not fit in a file, in order
+    Spill,
+}
+
+impl FromStr for Backpressure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "block" => Ok(Backpressure::Block),
+            "drop" => Ok(Backpressure::Drop),
+            "spill" => Ok(Backpressure::Spill),
+            _ => Err(anyhow!("unknown backpressure policy `{}`, expected block, drop or spill", s)),
+        }
+    }
+}
+
+// entries on disk waiting for room on the bus, oldest first
+struct Spill {
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    // the instructions are kept aside, there are only as many as the code
+    // has: `written` as the entries were spilled, `insns` as they are read
+    // back; a zero length in the file takes the next of `redefined` in, for
+    // a pc an overlay put other code at
+    written: HashMap<u64, Insn>,
+    redefined: VecDeque<(u64, Insn)>,
+    insns: HashMap<u64, Insn>,
+    payload: Vec<u8>,
+    pending: u64,
+    since_drain: u64,
+}
+
+impl Spill {
+    fn create() -> Result<Self> {
+        let writer = BufWriter::new(File::create(SPILL_PATH)?);
+        let reader = BufReader::new(OpenOptions::new().read(true).open(SPILL_PATH)?);
+        Ok(Spill {
+            writer,
+            reader,
+            written: HashMap::new(),
+            redefined: VecDeque::new(),
+            insns: HashMap::new(),
+            payload: Vec::new(),
+            pending: 0,
+            since_drain: 0,
+        })
+    }
+
+    fn push(&mut self, entry: &Entry) -> Result<()> {
+        let mut header = Vec::new();
+        if let Some(insn) = &entry.insn {
+            let pc = entry.arc.0;
+            if self.written.get(&pc).is_none_or(|written| !same_insn(written, insn)) {
+                self.written.insert(pc, insn.clone());
+                self.redefined.push_back((pc, insn.clone()));
+                write_varint(&mut header, 0);
+            }
+        }
+        self.payload.clear();
+        write_entry(&mut self.payload, entry);
+        write_varint(&mut header, self.payload.len() as u64);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&self.payload)?;
+        self.pending += 1;
+        self.since_drain += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Entry> {
+        let mut len = read_varint(&mut self.reader)? as usize;
+        while len == 0 {
+            let (pc, insn) = self.redefined.pop_front().ok_or_else(|| anyhow!("spill file redefines more instructions than spilled"))?;
+            self.insns.insert(pc, insn);
+            len = read_varint(&mut self.reader)? as usize;
+        }
+        let mut data = vec![0u8; len];
+        self.reader.read_exact(&mut data)?;
+        self.pending -= 1;
+        parse_entry(&data, &self.insns)
+    }
+}
+
+// whether two instructions decode the same, rvdasm keeps no encoding
+fn same_insn(a: &Insn, b: &Insn) -> bool {
+    a.len == b.len && a.to_string() == b.to_string()
+}
+
+/// The bus as seen by the frontend, applying the backpressure policy.
+pub struct BusSink {
+    bus: Bus<Entry>,
+    policy: Backpressure,
+    spill: Option<Spill>,
+    // read back from the spill but not yet accepted by the bus
+    retry: Option<Entry>,
+    pub dropped: u64,
+    pub spilled: u64,
+    // why the spill file was given up, for finish to return
+    error: Option<anyhow::Error>,
+}
+
+impl BusSink {
+    pub fn new(bus: Bus<Entry>, policy: Backpressure) -> Result<Self> {
+        let spill = if policy == Backpressure::Spill { Some(Spill::create()?) } else { None };
+        Ok(BusSink { bus, policy, spill, retry: None, dropped: 0, spilled: 0, error: None })
+    }
+
+    // hands the entry back when the bus is full, like Bus::try_broadcast
+    #[allow(clippy::result_large_err)]
+    fn try_send(&mut self, entry: Entry) -> Result<(), Entry> {
+        let event = entry.event.clone();
+        self.bus.try_broadcast(entry)?;
+        metrics::record_entry(&event);
+        Ok(())
+    }
+
+    // move spilled entries back to the bus while it has room
+    fn drain(&mut self) -> Result<()> {
+        let spill = self.spill.as_mut().unwrap();
+        spill.writer.flush()?;
+        spill.since_drain = 0;
+        loop {
+            let entry = match self.retry.take() {
+                Some(entry) => entry,
+                None if self.spill.as_ref().unwrap().pending > 0 => self.spill.as_mut().unwrap().pop()?,
+                None => return Ok(()),
+            };
+            if let Err(entry) = self.try_send(entry) {
+                self.retry = Some(entry);
+                return Ok(());
+            }
+        }
+    }
+
+    fn backlog(&self) -> bool {
+        self.retry.is_some() || self.spill.as_ref().is_some_and(|spill| spill.pending > 0)
+    }
+
+    fn spill(&mut self, entry: Entry) {
+        let spill = self.spill.as_mut().unwrap();
+        if let Err(e) = spill.push(&entry) {
+            self.abandon_spill(e);
+            EntrySink::broadcast(&mut self.bus, entry);
+            return;
+        }
+        self.spilled += 1;
+        metrics::record_spilled();
+        if spill.since_drain >= SPILL_BATCH {
+            if let Err(e) = self.drain() {
+                self.abandon_spill(e);
+            }
+        }
+    }
+
+    // the spill file failed, the entries still in it are lost and the
+    // following ones wait for the slowest receiver instead
+    fn abandon_spill(&mut self, error: anyhow::Error) {
+        let lost = self.spill.take().map_or(0, |spill| spill.pending);
+        let _ = fs::remove_file(SPILL_PATH);
+        self.policy = Backpressure::Block;
+        self.error = Some(error.context(format!("spill file {} failed, {} entries lost", SPILL_PATH, lost)));
+        if let Some(entry) = self.retry.take() {
+            EntrySink::broadcast(&mut self.bus, entry);
+        }
+    }
+
+    // hand everything still spilled to the receivers, waiting as needed, and
+    // report what the policy cost; an error if the spill file failed
+    pub fn finish(mut self) -> Result<()> {
+        if self.policy == Backpressure::Spill {
+            self.spill.as_mut().unwrap().writer.flush()?;
+            if let Some(entry) = self.retry.take() {
+                EntrySink::broadcast(&mut self.bus, entry);
+            }
+            while self.spill.as_ref().unwrap().pending > 0 && !watchdog::aborted() {
+                let entry = self.spill.as_mut().unwrap().pop()?;
+                EntrySink::broadcast(&mut self.bus, entry);
+            }
+            drop(self.spill.take());
+            fs::remove_file(SPILL_PATH)?;
+            println!("[Backpressure] spilled {} entries to disk", self.spilled);
+        } else if self.policy == Backpressure::Drop {
+            println!("[Backpressure] dropped {} entries", self.dropped);
+        }
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl EntrySink for BusSink {
+    fn broadcast(&mut self, entry: Entry) {
+        match self.policy {
+            Backpressure::Block => EntrySink::broadcast(&mut self.bus, entry),
+            Backpressure::Drop => {
+                if self.try_send(entry).is_err() {
+                    self.dropped += 1;
+                    metrics::record_dropped();
+                }
+            }
+            Backpressure::Spill => {
+                // once spilling, everything goes through the spill to keep the order
+                if self.backlog() {
+                    self.spill(entry);
+                } else if let Err(entry) = self.try_send(entry) {
+                    self.spill(entry);
+                }
+            }
+        }
+    }
+}
@@ -76,4 +76,13 @@ impl BpDoubleSaturatingCounter {
         }
         prediction // return the prediction before update
     }
+
+    // the encoder side of predict: train on the outcome, true if the
+    // counter had predicted it
+    pub fn train(&mut self, pc: u64, taken: bool) -> bool {
+        let index = (pc >> 1) % self.num_entries;
+        let hit = self.counters[index as usize].judge() == taken;
+        self.predict(pc, hit);
+        hit
+    }
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+// Byte order of the link the trace was captured on. Some capture paths
+// deliver the trace bytes swapped within 16- or 32-bit words, or with the
+// bits of every byte reversed; the reader puts them back in stream order
+// before any packet (or varint) is parsed.
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::diagnostics::{self, Category};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteOrder {
+    // bytes per swapped word, 1 for none
+    pub word: usize,
+    pub bit_reversed: bool,
+}
+
+impl Default for ByteOrder {
+    fn default() -> Self {
+        Self { word: 1, bit_reversed: false }
+    }
+}
+
+// `little`, `big16` or `big32`, each optionally followed by `-bitrev`
+impl FromStr for ByteOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (order, bit_reversed) = match s.strip_suffix("-bitrev") {
+            Some(order) => (order, true),
+            None => (s, false),
+        };
+        let word = match order {
+            "little" => 1,
+            "big16" => 2,
+            "big32" => 4,
+            _ => return Err(anyhow!("unknown byte order `{}`, expected little, big16 or big32, optionally with -bitrev", s)),
+        };
+        Ok(Self { word, bit_reversed })
+    }
+}
+
+impl ByteOrder {
+    pub fn is_native(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// the trace in stream order, read from a link of the given byte order; a
+// partial word at the end cannot be put back in order and is dropped, as a
+// truncated packet
+pub struct LinkReader<R> {
+    inner: R,
+    order: ByteOrder,
+    word: [u8; 4],
+    pos: usize,
+    len: usize,
+}
+
+impl<R: Read> LinkReader<R> {
+    pub fn new(inner: R, order: ByteOrder) -> Self {
+        Self { inner, order, word: [0; 4], pos: 0, len: 0 }
+    }
+
+    // the next word in stream order, false at the end of the link
+    fn refill(&mut self) -> std::io::Result<bool> {
+        let word = &mut self.word[..self.order.word];
+        let mut filled = 0;
+        while filled < word.len() {
+            match self.inner.read(&mut word[filled..])? {
+                0 => {
+                    if filled > 0 {
+                        diagnostics::report(Category::TruncatedPacket,
+                                            format!("{} bytes at the end of the trace do not fill a {}-byte word, dropping them",
+                                                    filled, word.len()));
+                    }
+                    return Ok(false);
+                }
+                n => filled += n,
+            }
+        }
+        word.reverse();
+        if self.order.bit_reversed {
+            word.iter_mut().for_each(|byte| *byte = byte.reverse_bits());
+        }
+        self.pos = 0;
+        self.len = word.len();
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LinkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.len && !self.refill()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.word[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
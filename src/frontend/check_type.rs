@@ -0,0 +1,21 @@
+pub const CHECK_TYPE_MASK: u8 = 0b1110_0000;
+pub const CHECK_TYPE_OFFSET: u8 = 5;
+
+// what an FCheck packet carries, flags of the varints following the first
+// byte in this order
+pub const CHECK_SEQUENCE: u8 = 0b001; // count of check packets so far
+pub const CHECK_CRC: u8 = 0b010;      // CRC of the bytes since the previous one
+
+// CRC-16/CCITT-FALSE over the trace bytes since the previous check packet, or
+// since the start of the trace (header included); the check packet itself
+// is not covered
+pub const CRC_INIT: u16 = 0xffff;
+const CRC_POLY: u16 = 0x1021;
+
+pub fn crc16(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ ((byte as u16) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 { (crc << 1) ^ CRC_POLY } else { crc << 1 };
+    }
+    crc
+}
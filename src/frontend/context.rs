@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use anyhow::Result;
+
+// a hart + address space pair, each of which gets its own stack model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Context {
+    pub hart: u64,
+    pub asid: u64,
+}
+
+impl Context {
+    pub fn new(hart: u64, asid: u64) -> Self {
+        Self { hart, asid }
+    }
+
+    // used to tell per-context output files apart
+    pub fn file_suffix(&self) -> String {
+        format!("hart{}.asid{}", self.hart, self.asid)
+    }
+}
+
+pub fn parse_u64(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(s.parse::<u64>()?),
+    }
+}
+
+// timestamp-ordered list of context switches read from a sideband file
+#[derive(Clone)]
+pub struct ContextMap {
+    switches: Vec<(u64, Context)>,
+    // timestamp-ordered mtvec writes
+    mtvec: Vec<(u64, u64)>,
+}
+
+impl ContextMap {
+    pub fn from_file(path: &str) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?), path)
+    }
+
+    // one `<timestamp> <hart> <asid>` triple per line, or `<timestamp> mtvec
+    // <value>` for a trap vector change, `#` starts a comment
+    pub fn from_reader<R: BufRead>(reader: R, path: &str) -> Result<Self> {
+        let mut switches = Vec::new();
+        let mut mtvec = Vec::new();
+        for (lineno, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(anyhow::anyhow!("{}:{}: expected `<timestamp> <hart> <asid>`, got `{}`", path, lineno + 1, line));
+            }
+            let timestamp = parse_u64(fields[0])?;
+            if fields[1] == "mtvec" {
+                mtvec.push((timestamp, parse_u64(fields[2])?));
+                continue;
+            }
+            switches.push((timestamp, Context::new(parse_u64(fields[1])?, parse_u64(fields[2])?)));
+        }
+        switches.sort_by_key(|(timestamp, _)| *timestamp);
+        mtvec.sort_by_key(|(timestamp, _)| *timestamp);
+        Ok(Self { switches, mtvec })
+    }
+
+    // the context active at the given timestamp
+    pub fn lookup(&self, timestamp: u64) -> Context {
+        match self.switches.partition_point(|(ts, _)| *ts <= timestamp) {
+            0 => Context::default(),
+            i => self.switches[i - 1].1,
+        }
+    }
+
+    // the mtvec value at the given timestamp, if the sideband set one by then
+    pub fn mtvec_at(&self, timestamp: u64) -> Option<u64> {
+        match self.mtvec.partition_point(|(ts, _)| *ts <= timestamp) {
+            0 => None,
+            i => Some(self.mtvec[i - 1].1),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Traces captured side by side into separate files, one per hart or per
+// controller, decoded together: each trace is decoded on its own thread and
+// their entries are merged onto the bus in timestamp order, a trace being
+// the context of hart N, its position on the command line. The receivers
+// that keep a call stack or a track per context then show every source on
+// its own, on one timeline. The timestamps are aligned by the cycles to add
+// to each trace, or by lining up the first sync packets of the traces.
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use bus::Bus;
+use log::debug;
+
+use crate::backend::event::{AttrValue, Entry, Event, SOURCE_ATTR};
+use crate::frontend::backpressure::BusSink;
+use crate::frontend::context::Context;
+use crate::frontend::decoder::{decode, DecoderConfig, EntrySink};
+
+// chunks decoded ahead of the merge, per trace
+const CHUNKS_IN_FLIGHT: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    // the traces started together, their first sync packets are the same time
+    Sync,
+    // cycles added to the timestamps of each trace
+    Offsets(Vec<i64>),
+}
+
+impl FromStr for Alignment {
+    type Err = anyhow::Error;
+
+    // `sync`, or one comma-separated offset per trace
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "sync" {
+            return Ok(Alignment::Sync);
+        }
+        s.split(',').map(|offset| offset.trim().parse::<i64>()
+            .map_err(|_| anyhow!("unknown alignment `{}`, expected `sync` or offsets like `0,-1200`", s)))
+            .collect::<Result<Vec<i64>>>()
+            .map(Alignment::Offsets)
+    }
+}
+
+// the entries of a trace up to a timed one, which orders them; what is left
+// when the trace ends goes untimed
+struct Chunk {
+    timestamp: Option<u64>,
+    entries: Vec<Entry>,
+}
+
+struct ChunkSink {
+    tx: SyncSender<Chunk>,
+    entries: Vec<Entry>,
+}
+
+impl EntrySink for ChunkSink {
+    fn broadcast(&mut self, entry: Entry) {
+        let timestamp = entry.timestamp;
+        self.entries.push(entry);
+        if timestamp.is_some() {
+            // a closed channel is a merge that stopped, the error is its own
+            let _ = self.tx.send(Chunk { timestamp, entries: std::mem::take(&mut self.entries) });
+        }
+    }
+}
+
+impl ChunkSink {
+    fn finish(self) {
+        if !self.entries.is_empty() {
+            let _ = self.tx.send(Chunk { timestamp: None, entries: self.entries });
+        }
+    }
+}
+
+struct Source<'a> {
+    path: &'a str,
+    rx: Receiver<Chunk>,
+    next: Option<Chunk>,
+    offset: i64,
+    // the aligned timestamp of the last chunk merged
+    last: u64,
+}
+
+impl Source<'_> {
+    // when the next chunk happened, on the merged timeline
+    fn next_timestamp(&self) -> Option<u64> {
+        let chunk = self.next.as_ref()?;
+        Some(chunk.timestamp.map_or(self.last, |ts| ts.saturating_add_signed(self.offset)))
+    }
+}
+
+// the offsets lining up the first timestamp of every trace with that of the
+// first one
+fn sync_offsets(sources: &[Source]) -> Vec<i64> {
+    let first = |source: &Source| source.next.as_ref().and_then(|chunk| chunk.timestamp);
+    let base = sources.first().and_then(first).unwrap_or(0);
+    sources.iter().map(|source| first(source).map_or(0, |ts| base as i64 - ts as i64)).collect()
+}
+
+// the context switch to source i, before its entry
+fn switch_to(i: usize, source: &Source, entry: &Entry) -> Entry {
+    Entry::new_context_switch(Context::new(i as u64, 0), entry.timestamp.unwrap_or(source.last), entry.arc.0)
+        .with_attr(SOURCE_ATTR, AttrValue::Str(source.path.to_string()))
+}
+
+// the entries of the sources onto sink by time, a context switch marking
+// every change of source; the first Start and the last End stand for all
+fn merge(sources: &mut [Source], sink: &mut impl EntrySink) {
+    let mut current = None;
+    let mut started = false;
+    let mut end: Option<(usize, Entry)> = None;
+    loop {
+        let next = sources.iter().enumerate()
+            .filter_map(|(i, source)| source.next_timestamp().map(|ts| (ts, i)))
+            .min();
+        let Some((ts, i)) = next else {
+            break;
+        };
+        let chunk = sources[i].next.take().unwrap();
+        sources[i].next = sources[i].rx.recv().ok();
+        sources[i].last = ts;
+        for mut entry in chunk.entries {
+            entry.timestamp = entry.timestamp.map(|ts| ts.saturating_add_signed(sources[i].offset));
+            match entry.event {
+                Event::Start if started => continue,
+                Event::Start => {
+                    started = true;
+                    sink.broadcast(entry);
+                    continue;
+                }
+                Event::End => {
+                    if end.as_ref().is_none_or(|(_, last)| entry.timestamp >= last.timestamp) {
+                        end = Some((i, entry));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if current != Some(i) {
+                sink.broadcast(switch_to(i, &sources[i], &entry));
+                current = Some(i);
+            }
+            sink.broadcast(entry);
+        }
+    }
+    if let Some((i, entry)) = end {
+        if current != Some(i) {
+            sink.broadcast(switch_to(i, &sources[i], &entry));
+        }
+        sink.broadcast(entry);
+    }
+}
+
+// decode every trace on a thread of its own and merge them onto sink,
+// returns the packets decoded per trace
+pub fn correlate(elf_buffer: &[u8], args: &DecoderConfig, encoded_traces: &[String], alignment: &Alignment,
+                 sink: &mut impl EntrySink) -> Result<Vec<u64>> {
+    if let Alignment::Offsets(offsets) = alignment {
+        if offsets.len() != encoded_traces.len() {
+            return Err(anyhow!("{} offsets given for {} traces", offsets.len(), encoded_traces.len()));
+        }
+    }
+    thread::scope(|scope| {
+        let mut sources = Vec::new();
+        let mut handles = Vec::new();
+        for path in encoded_traces {
+            let (tx, rx) = sync_channel(CHUNKS_IN_FLIGHT);
+            handles.push(scope.spawn(move || -> Result<u64> {
+                let mut reader = BufReader::new(File::open(path)?);
+                let mut chunks = ChunkSink { tx, entries: Vec::new() };
+                let packet_count = decode(elf_buffer, &mut reader, None, args, &mut chunks)?;
+                chunks.finish();
+                Ok(packet_count)
+            }));
+            sources.push(Source { path, rx, next: None, offset: 0, last: 0 });
+        }
+        for source in sources.iter_mut() {
+            source.next = source.rx.recv().ok();
+        }
+        let offsets = match alignment {
+            Alignment::Sync => sync_offsets(&sources),
+            Alignment::Offsets(offsets) => offsets.clone(),
+        };
+        for (source, offset) in sources.iter_mut().zip(offsets) {
+            debug!("[correlate] {} shifted by {} cycles", source.path, offset);
+            source.offset = offset;
+        }
+        merge(&mut sources, sink);
+        handles.into_iter().zip(encoded_traces)
+            .map(|(handle, path)| handle.join().map_err(|_| anyhow!("decoding {} panicked", path))?)
+            .collect()
+    })
+}
+
+// frontend decoding traces captured side by side onto one timeline
+pub fn correlated_decoder(args: &DecoderConfig, encoded_traces: &[String], alignment: &Alignment, bus: Bus<Entry>) -> Result<()> {
+    let elf_buffer = std::fs::read(&args.binary)?;
+    let mut sink = BusSink::new(bus, args.backpressure)?;
+    let packet_counts = match args.regions.clone() {
+        Some(mut filter) => correlate(&elf_buffer, args, encoded_traces, alignment, &mut filter.sink(&mut sink))?,
+        None => correlate(&elf_buffer, args, encoded_traces, alignment, &mut sink)?,
+    };
+    for (packet_count, encoded_trace) in packet_counts.iter().zip(encoded_traces) {
+        println!("[Success] Decoded {} packets from {}", packet_count, encoded_trace);
+    }
+    sink.finish()?;
+
+    Ok(())
+}
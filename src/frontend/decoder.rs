@@ -0,0 +1,659 @@
+use crate::frontend::f_header::FHeader;
+use crate::frontend::bp_double_saturating_counter::BpDoubleSaturatingCounter;
+use crate::frontend::br_mode::BrMode;
+use crate::frontend::context::{Context, ContextMap};
+use crate::frontend::replay_reader::ReplayReader;
+use crate::frontend::packet::{Packet, TraceHeader, TRACE_VERSION};
+use crate::frontend::address::{AddressScheme, Compression};
+use crate::frontend::source::TraceFormat;
+use crate::frontend::byte_order::{ByteOrder, LinkReader};
+use crate::frontend::backpressure::{Backpressure, BusSink};
+use crate::frontend::insn_map::{build_insn_map, code_bytes, read_code};
+use crate::frontend::mem_access::{decode_mem_access, MemAccess};
+use crate::frontend::module::read_module_maps;
+use crate::frontend::overlay::{OverlayInsns, OverlayMap};
+use crate::frontend::region::RegionFilter;
+use crate::frontend::trap_type::{cause_code, TrapType, TrapVector};
+use crate::frontend::val_type::ValType;
+use crate::backend::event::{AttrValue, DataAccess, Entry, Event, RegisterValue, TrapInfo, RETURN_ATTR};
+use crate::diagnostics::{self, Category};
+use crate::metrics;
+use crate::watchdog;
+
+// file IO
+use std::fs::File;
+use std::io::{Read, BufReader};
+// collections
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::time::Instant;
+// objdump dependency
+use rvdasm::insn::*;
+// elf dependency
+use object::Object;
+// bus dependency
+use bus::Bus;
+// error handling
+use anyhow::Result;
+// logging
+use log::{debug, info, trace};
+
+const BRANCH_OPCODES: &[&str] = &["beq", "bge", "bgeu", "blt", "bltu", "bne", "beqz", "bnez",
+                                "bgez", "blez", "bltz", "bgtz", "bgt", "ble", "bgtu", "bleu",
+                                "c.beqz", "c.bnez", "c.bltz", "c.bgez"];
+const IJ_OPCODES: &[&str] = &["jal", "j", "call", "tail", "c.j", "c.jal"];
+const UJ_OPCODES: &[&str] = &["jalr", "jr", "c.jr", "c.jalr", "ret"];
+const XRET_OPCODES: &[&str] = &["mret", "sret", "uret"];
+// traps nest once per privilege level a handler traps into, deeper stacks
+// are returns the decoder missed
+const MAX_NESTED_TRAPS: usize = 8;
+pub const BUS_SIZE: usize = 1024;
+
+// where the decoded entries go: the bus feeding the receiver threads, or a
+// receiver driven in place where there are no threads (wasm)
+pub trait EntrySink {
+    fn broadcast(&mut self, entry: Entry);
+}
+
+impl EntrySink for Bus<Entry> {
+    fn broadcast(&mut self, entry: Entry) {
+        metrics::record_entry(&entry.event);
+        // only a full bus is timed, that is a receiver holding up the frontend
+        if let Err(mut entry) = self.try_broadcast(entry) {
+            let start = Instant::now();
+            // retry rather than block in the bus, so a watchdog abort gets through
+            while let Err(rejected) = self.try_broadcast(entry) {
+                if watchdog::aborted() {
+                    break;
+                }
+                entry = rejected;
+                thread::yield_now();
+            }
+            metrics::record_stall(start.elapsed());
+        }
+    }
+}
+
+// what the packet decoder needs to know, independent of the command line
+#[derive(Clone, Debug, Default)]
+pub struct DecoderConfig {
+    // path to the encoded trace file
+    pub encoded_trace: String,
+    // path to the binary file
+    pub binary: String,
+    // branch mode
+    pub br_mode: u64,
+    // branch prediction number of entries
+    pub bp_entries: u64,
+    // path to a sideband file of context switches, empty for none
+    pub sideband: String,
+    // what to do when a receiver falls behind and the bus is full
+    pub backpressure: Backpressure,
+    // path to an overlay description file, empty for none
+    pub overlays: String,
+    // path to a process map of the mappings of every address space, empty
+    // for none; they follow the context switches of the sideband
+    pub process_map: String,
+    // path to a map of the kernel modules loaded, empty for none
+    pub modules: String,
+    // how the encoder compresses addresses
+    pub address: AddressScheme,
+    // mtvec value, for the handlers of vectored interrupts; a sideband
+    // file can also set it over time
+    pub mtvec: Option<u64>,
+    // packet format of the encoded trace
+    pub format: TraceFormat,
+    // byte order of the link the trace was captured on
+    pub byte_order: ByteOrder,
+    // only the entries inside these regions reach the receivers
+    pub regions: Option<RegionFilter>,
+}
+
+// step until encountering a br/jump
+pub fn step_bb(pc: u64, insn_map: &HashMap<u64, Insn>, bus: &mut impl EntrySink, br_mode: &BrMode) -> u64 {
+    let mut pc = pc;
+    let stop_on_ij = *br_mode == BrMode::BrTarget;
+    loop {
+        trace!("stepping bb pc: {:x}", pc);
+        let insn = insn_map.get(&pc).unwrap();
+        bus.broadcast(Entry::new_insn(insn, pc));
+        if stop_on_ij {
+            if insn.is_branch() || insn.is_direct_jump() || insn.is_indirect_jump() {
+                break;
+            } else {
+                pc += insn.len as u64;
+            }
+        } else {
+            if insn.is_branch() || insn.is_indirect_jump() {
+                break;
+            } else if insn.is_direct_jump() {
+                let new_pc = (pc as i64 + insn.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
+                pc = new_pc;
+            } else {
+                pc += insn.len as u64;
+            }
+        }
+    }
+    pc
+}
+
+pub fn step_bb_until(pc: u64, insn_map: &HashMap<u64, Insn>, target_pc: u64, bus: &mut impl EntrySink) -> u64 {
+    // println!("stepping bb from pc: {:x} until pc: {:x}", pc, target_pc);
+    let mut pc = pc;
+
+    loop {
+        let insn = insn_map.get(&pc).unwrap();
+        bus.broadcast(Entry::new_insn(insn, pc));
+        if insn.is_branch() || insn.is_direct_jump() {
+            break;
+        }
+        if pc == target_pc {
+            break;
+        }
+        pc += insn.len as u64;
+    }
+    pc
+}
+
+// step up to the instruction an asynchronous interrupt was taken at, which
+// did not retire and runs again once the handler returns. It can be
+// anywhere in a block, so only the pc itself ends the walk; direct jumps
+// are followed as step_bb does. A branch or indirect jump on the way means
+// the interrupt was not in this block, the walk stops there.
+pub fn step_until_interrupted(pc: u64, insn_map: &HashMap<u64, Insn>, interrupted_pc: u64, bus: &mut impl EntrySink,
+                              br_mode: &BrMode) -> u64 {
+    let mut pc = pc;
+    while pc != interrupted_pc {
+        let Some(insn) = insn_map.get(&pc) else {
+            debug!("interrupt at {:#x} not reached, no instruction at {:#x}", interrupted_pc, pc);
+            break;
+        };
+        bus.broadcast(Entry::new_insn(insn, pc));
+        if insn.is_branch() || insn.is_indirect_jump() || (insn.is_direct_jump() && *br_mode == BrMode::BrTarget) {
+            debug!("interrupt at {:#x} not reached, stopped at {:#x}", interrupted_pc, pc);
+            break;
+        }
+        pc = if insn.is_direct_jump() {
+            (pc as i64 + insn.get_imm().unwrap().get_val_signed_imm() as i64) as u64
+        } else {
+            pc + insn.len as u64
+        };
+    }
+    pc
+}
+
+// the instruction the packet resolves is not of the class it is about, the
+// trace and the binary disagree and decoding cannot go on
+fn opcode_mismatch(pc: u64, timestamp: u64, insn: &Insn, expected: &str, bus: &mut impl EntrySink) -> ! {
+    diagnostics::report(Category::UnknownOpcode,
+                        format!("{} at {:#x} resolves a {} packet, timestamp {}", insn.get_name(), pc, expected, timestamp));
+    bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
+    panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn);
+}
+
+// data addresses read from FVal packets, waiting for the loads and stores
+// they belong to
+#[derive(Default)]
+struct DataQueue {
+    // the scalar loads and stores of the binary
+    accesses: HashMap<u64, MemAccess>,
+    // (address, is_store), oldest first
+    pending: VecDeque<(u64, bool)>,
+}
+
+// hands the pending data addresses, in order, to the loads and stores
+// stepped past
+struct DataSink<'a, S: EntrySink> {
+    bus: &'a mut S,
+    data: &'a mut DataQueue,
+}
+
+impl<S: EntrySink> EntrySink for DataSink<'_, S> {
+    fn broadcast(&mut self, entry: Entry) {
+        let pc = entry.arc.0;
+        let is_insn = entry.event == Event::None;
+        self.bus.broadcast(entry);
+        if !is_insn || self.data.pending.is_empty() {
+            return;
+        }
+        if let Some(access) = self.data.accesses.get(&pc) {
+            let (address, is_store) = self.data.pending.pop_front().unwrap();
+            self.bus.broadcast(Entry::new_data_access(pc, DataAccess { address, size: access.size, is_store }));
+        }
+    }
+}
+
+// emit a ContextSwitch entry if the sideband says the context changed by now
+pub fn sync_context(ctx_map: &Option<ContextMap>, ctx: &mut Context, timestamp: u64, pc: u64, bus: &mut impl EntrySink) {
+    if let Some(ctx_map) = ctx_map {
+        let new_ctx = ctx_map.lookup(timestamp);
+        if new_ctx != *ctx {
+            trace!("context switch at {}: {:?} -> {:?}", timestamp, ctx, new_ctx);
+            *ctx = new_ctx;
+            bus.broadcast(Entry::new_context_switch(new_ctx, timestamp, pc));
+        }
+    }
+}
+
+// frontend decoding packets and pushing entries to the bus
+pub fn trace_decoder(args: &DecoderConfig, bus: Bus<Entry>) -> Result<()> {
+    traces_decoder(args, std::slice::from_ref(&args.encoded_trace), bus)
+}
+
+// several traces of the same binary decoded one after the other onto the
+// same bus, each framed by its own Start and End, so that the receivers
+// add up their counts
+pub fn traces_decoder(args: &DecoderConfig, encoded_traces: &[String], bus: Bus<Entry>) -> Result<()> {
+    let elf_buffer = std::fs::read(&args.binary)?;
+    let mut sink = BusSink::new(bus, args.backpressure)?;
+    for encoded_trace in encoded_traces {
+        let mut encoded_trace_reader = BufReader::new(File::open(encoded_trace)?);
+        let ctx_map = if args.sideband.is_empty() { None } else { Some(ContextMap::from_file(&args.sideband)?) };
+        let packet_count = match args.regions.clone() {
+            Some(mut filter) => decode(&elf_buffer, &mut encoded_trace_reader, ctx_map, args, &mut filter.sink(&mut sink))?,
+            None => decode(&elf_buffer, &mut encoded_trace_reader, ctx_map, args, &mut sink)?,
+        };
+        if encoded_traces.len() > 1 {
+            println!("[Success] Decoded {} packets from {}", packet_count, encoded_trace);
+        } else {
+            println!("[Success] Decoded {} packets", packet_count);
+        }
+    }
+    sink.finish()?;
+
+    Ok(())
+}
+
+// packet-by-packet decoding state, so that any packet source (blocking
+// reads, async streams) can drive the same frontend
+pub struct PacketDecoder {
+    insn_map: HashMap<u64, Insn>,
+    overlays: Option<OverlayInsns>,
+    mtvec: Option<u64>,
+    address: AddressScheme,
+    // FVal values waiting for the trap they describe
+    trap_vals: TrapInfo,
+    data: DataQueue,
+    bp_counter: BpDoubleSaturatingCounter,
+    br_mode: BrMode,
+    mode_is_predict: bool,
+    // translated packets name the last instruction retired before an
+    // interrupt, tacit ones the instruction interrupted
+    translated: bool,
+    ctx_map: Option<ContextMap>,
+    ctx: Context,
+    // per context, the pcs the open traps were taken at, innermost last
+    trap_pcs: HashMap<Context, Vec<u64>>,
+    pc: u64,
+    timestamp: u64,
+    pub packet_count: u64,
+}
+
+impl PacketDecoder {
+    pub fn new(elf_buffer: &[u8], ctx_map: Option<ContextMap>, args: &DecoderConfig) -> Result<Self> {
+        let elf = object::File::parse(elf_buffer)?;
+        let insn_map = build_insn_map(&elf)?;
+        // the unwinders build the same map, only the frontend reports on it
+        if !insn_map.opaque.is_empty() {
+            diagnostics::report(Category::UnknownOpcode,
+                                format!("{} words could not be disassembled, treating them as opaque .insn", insn_map.opaque.len()));
+        }
+        for (pc, low) in &insn_map.skipped {
+            diagnostics::report(Category::UnknownOpcode,
+                                format!("unsupported instruction length at {:#x} ({:#06x}), skipping a parcel", pc, low));
+        }
+        let mut insn_map = insn_map.insns;
+        for module in read_module_maps(&args.modules)? {
+            insn_map.extend(module.load_insns()?);
+        }
+        debug!("[main] found {} instructions", insn_map.len());
+        let code = read_code(&elf)?;
+        let accesses = insn_map.iter().filter_map(|(&pc, insn)| {
+            let access = decode_mem_access(code_bytes(&code, pc, insn.len)?, elf.is_64())?;
+            Some((pc, access))
+        }).collect();
+        let overlays = match OverlayMap::from_paths(&args.overlays, &args.process_map, &args.sideband, &elf)? {
+            Some(map) => Some(OverlayInsns::load(&map, &insn_map)?),
+            None => None,
+        };
+
+        let (address, br_mode, mode_is_predict) = if args.format == TraceFormat::Tacit {
+            let br_mode = BrMode::from(args.br_mode);
+            let mode_is_predict = br_mode == BrMode::BrPredict || br_mode == BrMode::BrHistory;
+            (args.address, br_mode, mode_is_predict)
+        } else {
+            // translated packets carry full addresses and one packet per
+            // branch, inferable jumps are followed without one
+            (AddressScheme { shift: 0, compression: Compression::Absolute }, BrMode::BrHistory, false)
+        };
+        Ok(Self {
+            insn_map,
+            overlays,
+            mtvec: args.mtvec,
+            address,
+            trap_vals: TrapInfo::default(),
+            data: DataQueue { accesses, pending: VecDeque::new() },
+            bp_counter: BpDoubleSaturatingCounter::new(args.bp_entries),
+            br_mode,
+            mode_is_predict,
+            translated: args.format != TraceFormat::Tacit,
+            ctx_map,
+            ctx: Context::default(),
+            trap_pcs: HashMap::new(),
+            pc: 0,
+            timestamp: 0,
+            packet_count: 0,
+        })
+    }
+
+    // the encoder settings a trace header names take over the configured ones
+    pub fn apply_header(&mut self, header: &TraceHeader) {
+        println!("[Header] trace format version {}: br_mode {}, {} bp entries, address shift {}, {:?} targets",
+                 header.version, header.br_mode, header.bp_entries, header.address.shift, header.address.compression);
+        if header.version > TRACE_VERSION {
+            diagnostics::report(Category::FormatVersion,
+                                format!("trace format version {} is newer than {}, ignoring the parameters this decoder does not know",
+                                        header.version, TRACE_VERSION));
+        }
+        self.address = header.address;
+        self.br_mode = BrMode::from(header.br_mode);
+        self.mode_is_predict = self.br_mode == BrMode::BrPredict || self.br_mode == BrMode::BrHistory;
+        self.bp_counter = BpDoubleSaturatingCounter::new(header.bp_entries);
+    }
+
+    // the first packet is the sync packet carrying the start address
+    pub fn start(&mut self, packet: Packet, bus: &mut impl EntrySink) {
+        trace!("packet: {:?}", packet);
+        metrics::record_packet(&packet);
+        self.pc = self.address.absolute(packet.target_address);
+        self.timestamp = packet.timestamp;
+        bus.broadcast(Entry::new_timed_event(Event::Start, packet.timestamp, self.pc, 0));
+        sync_context(&self.ctx_map, &mut self.ctx, self.timestamp, self.pc, bus);
+        self.sync_overlays(self.timestamp, self.pc);
+    }
+
+    // what is known of a trap into `target`: the values the encoder reported
+    // before it, and the handler when mtvec is known
+    fn trap_info(&mut self, trap_type: &TrapType, timestamp: u64, target: u64) -> Option<TrapInfo> {
+        let mut info = std::mem::take(&mut self.trap_vals);
+        if matches!(trap_type, TrapType::TReturn) {
+            return None;
+        }
+        let mtvec = self.ctx_map.as_ref().and_then(|ctx_map| ctx_map.mtvec_at(timestamp)).or(self.mtvec);
+        if let Some(mtvec) = mtvec {
+            let vector = TrapVector::from_mtvec(mtvec);
+            info.cause = info.cause.or(vector.cause_of(target, matches!(trap_type, TrapType::TInterrupt)));
+            info.handler = Some(vector.handler(target, &self.insn_map));
+        }
+        if info == TrapInfo::default() { None } else { Some(info) }
+    }
+
+    // a trap taken at `from`, its return is checked against it
+    fn enter_trap(&mut self, from: u64) {
+        let saved = self.trap_pcs.entry(self.ctx).or_default();
+        if saved.len() == MAX_NESTED_TRAPS {
+            saved.remove(0);
+        }
+        saved.push(from);
+    }
+
+    // the return instruction at `from` going back to `target`: the epc of
+    // the innermost open trap holds the pc it was taken at, or the next
+    // instruction once the handler skipped over it (ecall, emulation). A
+    // return with no trap open is the entry into a lower privilege level.
+    fn leave_trap(&mut self, from: u64, target: u64) -> Option<&'static str> {
+        let name = self.insn_map.get(&from).map(|insn| insn.get_name()).unwrap_or_default();
+        let xret = XRET_OPCODES.iter().find(|&&op| op == name).copied();
+        if xret.is_none() {
+            diagnostics::report(Category::UnknownOpcode,
+                                format!("trap return at {:#x} is `{}`, not mret, sret or uret", from, name));
+        }
+        let Some(saved) = self.trap_pcs.get_mut(&self.ctx).and_then(Vec::pop) else {
+            trace!("{} at {:#x} with no trap open, to {:#x}", xret.unwrap_or("trap return"), from, target);
+            return xret;
+        };
+        let next = self.insn_map.get(&saved).map_or(saved, |insn| saved + insn.len as u64);
+        if target != saved && target != next {
+            diagnostics::report(Category::ReturnMismatch,
+                                format!("{} at {:#x} returns to {:#x}, the trap was taken at {:#x}",
+                                        xret.unwrap_or("trap return"), from, target, saved));
+        }
+        xret
+    }
+
+    // load the overlays due by now before stepping on from pc
+    fn sync_overlays(&mut self, timestamp: u64, pc: u64) {
+        if let Some(overlays) = self.overlays.as_mut() {
+            for (region, overlay) in overlays.sync(Some(timestamp), pc, &mut self.insn_map) {
+                trace!("overlay region {} now holds {:?} at {}", region, overlay, timestamp);
+            }
+        }
+    }
+
+    // decode one packet, returns true once the trace has ended
+    pub fn step(&mut self, packet: Packet, bus: &mut impl EntrySink) -> bool {
+        if self.data.pending.is_empty() {
+            return self.step_packet(packet, bus);
+        }
+        let mut data = std::mem::take(&mut self.data);
+        let ended = self.step_packet(packet, &mut DataSink { bus, data: &mut data });
+        // an address read by this packet queues up behind the older ones
+        data.pending.append(&mut self.data.pending);
+        self.data = data;
+        if ended && !self.data.pending.is_empty() {
+            diagnostics::report(Category::UnmatchedData,
+                                format!("{} data addresses left without a load or store at the end of the trace", self.data.pending.len()));
+        }
+        ended
+    }
+
+    fn step_packet(&mut self, packet: Packet, bus: &mut impl EntrySink) -> bool {
+        let mut pc = self.pc;
+        let mut timestamp = self.timestamp;
+        self.packet_count += 1;
+        // special handling for the last packet, should be unlikely hinted
+        trace!("[{}]: packet: {:?}", self.packet_count, packet);
+        metrics::record_packet(&packet);
+        if packet.f_header == FHeader::FSync && packet.resync {
+            // the source dropped corrupted packets, carry on from the sync
+            pc = self.address.absolute(packet.target_address);
+            timestamp = packet.timestamp;
+            info!("resuming at {:#x} after corrupted packets", pc);
+            bus.broadcast(Entry::new_timed_event(Event::Resync, timestamp, self.pc, pc));
+        } else if packet.f_header == FHeader::FSync {
+            pc = step_bb_until(pc, &self.insn_map, self.address.absolute(packet.target_address), bus);
+            println!("detected FSync packet, trace ending!");
+            bus.broadcast(Entry::new_timed_event(Event::End, packet.timestamp, pc, 0));
+            self.pc = pc;
+            return true;
+        } else if packet.f_header == FHeader::FVal {
+            // no control flow, the value is for the next trap or load or store, or
+            // a register sample
+            match packet.val_type {
+                ValType::VCause => self.trap_vals.cause = Some(cause_code(packet.value)),
+                ValType::VTval => self.trap_vals.tval = Some(packet.value),
+                ValType::VLoad => self.data.pending.push_back((packet.value, false)),
+                ValType::VStore => self.data.pending.push_back((packet.value, true)),
+                ValType::VReg => {
+                    let reg = RegisterValue { regno: packet.regno, value: packet.value };
+                    bus.broadcast(Entry::new_register_value(reg, self.timestamp, self.pc));
+                }
+                ValType::VNone => trace!("ignoring FVal packet of unknown type"),
+            }
+            return false;
+        } else if packet.f_header == FHeader::FCheck {
+            // verified by the packet source, if at all
+            return false;
+        } else if packet.f_header == FHeader::FTrap {
+            let from = self.address.absolute(packet.from_address);
+            pc = if matches!(packet.trap_type, TrapType::TInterrupt) && !self.translated {
+                step_until_interrupted(pc, &self.insn_map, from, bus, &self.br_mode)
+            } else {
+                step_bb_until(pc, &self.insn_map, from, bus)
+            };
+            pc = self.address.target(packet.target_address, packet.target_bits, pc);
+            timestamp += packet.timestamp;
+            let trap = self.trap_info(&packet.trap_type, timestamp, pc);
+            let xret = if matches!(packet.trap_type, TrapType::TReturn) {
+                self.leave_trap(from, pc)
+            } else {
+                self.enter_trap(from);
+                None
+            };
+            let mut entry = Entry::new_timed_trap(packet.trap_type, timestamp, from, pc).with_trap(trap);
+            if let Some(xret) = xret {
+                entry = entry.with_attr(RETURN_ATTR, AttrValue::Str(xret.to_string()));
+            }
+            bus.broadcast(entry);
+        } else if self.mode_is_predict && packet.f_header == FHeader::FTb { // predicted hit
+            bus.broadcast(Entry::new_timed_event(Event::BPHit, packet.timestamp, pc, pc));
+            // predict for timestamp times
+            for _ in 0..packet.timestamp {
+                pc = step_bb(pc, &self.insn_map, bus, &self.br_mode);
+                let insn_to_resolve = self.insn_map.get(&pc).unwrap();
+                if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                     opcode_mismatch(pc, timestamp, insn_to_resolve, "branch", bus);
+                 }
+                let taken = self.bp_counter.predict(pc, true);
+                if taken {
+                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
+                    bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
+                    pc = new_pc;
+                } else {
+                    let new_pc = pc + insn_to_resolve.len as u64;
+                    bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
+                    pc = new_pc;
+                }
+            }
+        } else if self.mode_is_predict && packet.f_header == FHeader::FNt { // predicted miss
+            timestamp += packet.timestamp;
+            bus.broadcast(Entry::new_timed_event(Event::BPMiss, timestamp, pc, pc));
+            pc = step_bb(pc, &self.insn_map, bus, &self.br_mode);
+            let insn_to_resolve = self.insn_map.get(&pc).unwrap();
+            if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                 opcode_mismatch(pc, timestamp, insn_to_resolve, "branch", bus);
+             }
+            let taken = self.bp_counter.predict(pc, false);
+            if !taken { // reverse as we mispredicted
+                let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
+                bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
+                pc = new_pc;
+            } else {
+                let new_pc = pc + insn_to_resolve.len as u64;
+                bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
+                pc = new_pc;
+            }
+        } else  {
+            // trace!("pc before step_bb: {:x}", pc);
+            pc = step_bb(pc, &self.insn_map, bus, &self.br_mode);
+            let insn_to_resolve = self.insn_map.get(&pc).unwrap();
+            // trace!("pc after step_bb: {:x}", pc);
+            timestamp += packet.timestamp;
+            match packet.f_header {
+                FHeader::FTb => {
+                    if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                        opcode_mismatch(pc, timestamp, insn_to_resolve, "branch", bus);
+                    }
+                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
+                    bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
+                    // trace!("pc before br: {:x}, after taken branch: {:x}", pc, new_pc);
+                    pc = new_pc;
+                }
+                FHeader::FNt => {
+                    if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                        opcode_mismatch(pc, timestamp, insn_to_resolve, "branch", bus);
+                    }
+                    let new_pc = pc + insn_to_resolve.len as u64;
+                    bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
+                    // trace!("pc before nt: {:x}, after nt: {:x}", pc, new_pc);
+                    pc = new_pc;
+                }
+                FHeader::FIj => {
+                    if !IJ_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                        opcode_mismatch(pc, timestamp, insn_to_resolve, "inferable jump", bus);
+                    }
+                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
+                    bus.broadcast(Entry::new_timed_event(Event::InferrableJump, timestamp, pc, new_pc));
+                    // trace!("pc before ij: {:x}, after ij: {:x}", pc, new_pc);
+                    pc = new_pc;
+                }
+                FHeader::FUj => {
+                    if !UJ_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
+                        opcode_mismatch(pc, timestamp, insn_to_resolve, "uninferable jump", bus);
+                    }
+                    let new_pc = self.address.target(packet.target_address, packet.target_bits, pc);
+                    bus.broadcast(Entry::new_timed_event(Event::UninferableJump, timestamp, pc, new_pc));
+                    // trace!("pc before uj: {:x}, after uj: {:x}", pc, new_pc);
+                    pc = new_pc;
+                }
+                _ => {
+                    bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
+                    panic!("unknown FHeader: {:?}", packet.f_header);
+                }
+            }
+            // log the timestamp
+        }
+        sync_context(&self.ctx_map, &mut self.ctx, timestamp, pc, bus);
+        self.sync_overlays(timestamp, pc);
+        self.pc = pc;
+        self.timestamp = timestamp;
+        false
+    }
+}
+
+// decode a whole trace from in-memory ELF contents and any packet stream,
+// returns the number of packets decoded
+pub fn decode<R: Read>(elf_buffer: &[u8], encoded_trace_reader: &mut R, ctx_map: Option<ContextMap>,
+                       args: &DecoderConfig, bus: &mut impl EntrySink) -> Result<u64> {
+    let mut decoder = PacketDecoder::new(elf_buffer, ctx_map, args)?;
+    let mut source = if args.byte_order.is_native() {
+        args.format.source(encoded_trace_reader)
+    } else {
+        args.format.source(LinkReader::new(encoded_trace_reader, args.byte_order))
+    };
+    let (header, first_packet) = source.read_first_packet()?;
+    if let Some(header) = header {
+        decoder.apply_header(&header);
+    }
+    decoder.start(first_packet, bus);
+    let mut ended = false;
+    while let Ok(packet) = source.read_packet() {
+        if watchdog::aborted() {
+            return Err(anyhow::anyhow!("decoding aborted by the watchdog after {} packets", decoder.packet_count));
+        }
+        if decoder.step(packet, bus) {
+            ended = true;
+            break;
+        }
+    }
+    if !ended {
+        metrics::record_truncated();
+        diagnostics::report(Category::TruncatedPacket,
+                            format!("the packet stream ended after {} packets without a sync packet", decoder.packet_count));
+    }
+    Ok(decoder.packet_count)
+}
+
+// frontend replaying previously decoded entries to the bus
+pub fn replay_decoder(replay_trace: &str, backpressure: Backpressure, mut regions: Option<RegionFilter>, bus: Bus<Entry>) -> Result<()> {
+    let mut reader = ReplayReader::open(replay_trace)?;
+    debug!("[replay] format version {}, event schema version {}, written by {}", reader.version, reader.schema_version,
+           if reader.writer.is_empty() { "an unknown decoder" } else { &reader.writer });
+    let mut sink = BusSink::new(bus, backpressure)?;
+    let mut entry_count = 0;
+    while let Some(entry) = reader.read_entry()? {
+        if watchdog::aborted() {
+            return Err(anyhow::anyhow!("replay aborted by the watchdog after {} entries", entry_count));
+        }
+        match regions.as_mut() {
+            Some(filter) => filter.sink(&mut sink).broadcast(entry),
+            None => sink.broadcast(entry),
+        }
+        entry_count += 1;
+    }
+
+    sink.finish()?;
+    println!("[Success] Replayed {} entries", entry_count);
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+// RISC-V Efficient Trace (E-Trace) input. te_inst packets come in the
+// encapsulation of the trace connectors spec: a header byte with the payload
+// length in the low 5 bits (0 for idle) and no source id or timestamp. The
+// payload is a little-endian bit stream with these encoder parameters:
+// 64-bit addresses with iaddress_lsb 1, differential addresses, 2 privilege
+// bits, 6 ecause bits, no time, context or return stack fields. Payloads may
+// drop their upper bits, those are copies of the last bit sent.
+//
+// Translated are start syncs, branch maps, addresses (as uninferable jump
+// targets), traps and the end of tracing. An address right before a trap is
+// the last instruction retired before it, so addresses are held back until
+// the next packet says which they are.
+use std::collections::VecDeque;
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use log::trace;
+use tacit_wire::{sign_extend, BitReader};
+
+use crate::frontend::f_header::FHeader;
+use crate::frontend::packet::{Packet, TraceHeader};
+use crate::frontend::source::PacketSource;
+use crate::frontend::trap_type::TrapType;
+use crate::frontend::val_type::ValType;
+
+const LENGTH_MASK: u8 = 0b0001_1111;
+const EXTEND_BIT: u8 = 0b1000_0000;
+
+const ADDRESS_LSB: u32 = 1;
+const ADDRESS_BITS: u32 = 64 - ADDRESS_LSB;
+const PRIVILEGE_BITS: u32 = 2;
+const ECAUSE_BITS: u32 = 6;
+const TVAL_BITS: u32 = 64;
+
+// te_inst formats and the subformats of format 3
+const FORMAT_BRANCH: u64 = 1;
+const FORMAT_ADDRESS: u64 = 2;
+const FORMAT_SYNC: u64 = 3;
+const SYNC_START: u64 = 0;
+const SYNC_TRAP: u64 = 1;
+const SYNC_SUPPORT: u64 = 3;
+
+// bits of a branch map holding `branches` branches, 0 for a full map
+fn branch_map_bits(branches: u64) -> u32 {
+    match branches {
+        0 => 31,
+        1 => 1,
+        2..=9 => 9,
+        10..=17 => 17,
+        18..=25 => 25,
+        _ => 31,
+    }
+}
+
+pub struct ETraceSource<R> {
+    stream: R,
+    // translated packets not handed out yet
+    pending: VecDeque<Packet>,
+    // the last address sent, differential addresses are relative to it
+    last_address: u64,
+    // an address whose meaning depends on the packet after it
+    held: Option<u64>,
+}
+
+impl<R: Read> ETraceSource<R> {
+    pub fn new(stream: R) -> Self {
+        Self { stream, pending: VecDeque::new(), last_address: 0, held: None }
+    }
+
+    // the payload of the next non-idle packet
+    fn read_payload(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let mut header = [0u8; 1];
+            self.stream.read_exact(&mut header)?;
+            if header[0] & EXTEND_BIT != 0 {
+                return Err(anyhow!("extended encapsulation headers are not supported"));
+            }
+            let mut payload = vec![0u8; (header[0] & LENGTH_MASK) as usize];
+            if payload.is_empty() {
+                continue;
+            }
+            self.stream.read_exact(&mut payload)?;
+            return Ok(payload);
+        }
+    }
+
+    fn full_address(&mut self, bits: &mut BitReader) -> u64 {
+        self.last_address = bits.read(ADDRESS_BITS) << ADDRESS_LSB;
+        self.last_address
+    }
+
+    fn differential_address(&mut self, bits: &mut BitReader) -> u64 {
+        let diff = sign_extend(bits.read(ADDRESS_BITS), ADDRESS_BITS) << ADDRESS_LSB;
+        self.last_address = self.last_address.wrapping_add(diff as u64);
+        self.last_address
+    }
+
+    // a held address not followed by a trap was an uninferable jump target
+    fn release_held(&mut self) {
+        if let Some(address) = self.held.take() {
+            self.pending.push_back(Packet::new_jump(FHeader::FUj, address));
+        }
+    }
+
+    fn translate(&mut self, payload: &[u8]) {
+        let mut bits = BitReader::new(payload);
+        match bits.read(2) {
+            FORMAT_SYNC => match bits.read(2) {
+                SYNC_START => {
+                    // a periodic resync, the walk already knows where it is
+                    self.release_held();
+                    bits.read(1 + PRIVILEGE_BITS);
+                    self.full_address(&mut bits);
+                }
+                SYNC_TRAP => {
+                    bits.read(1 + PRIVILEGE_BITS);
+                    let ecause = bits.read(ECAUSE_BITS);
+                    let interrupt = bits.read(1) == 1;
+                    bits.read(1);
+                    let handler = self.full_address(&mut bits);
+                    // without a held address the walk takes the trap at the
+                    // end of the basic block
+                    let from = self.held.take().unwrap_or(handler);
+                    self.pending.push_back(Packet::new_value(ValType::VCause, ecause));
+                    let trap_type = if interrupt {
+                        TrapType::TInterrupt
+                    } else {
+                        self.pending.push_back(Packet::new_value(ValType::VTval, bits.read(TVAL_BITS)));
+                        TrapType::TException
+                    };
+                    self.pending.push_back(Packet::new_trap(trap_type, from, handler));
+                }
+                SYNC_SUPPORT => {
+                    bits.read(2);
+                    // qual_status other than 0 (no change) means tracing ended
+                    if bits.read(2) != 0 {
+                        let last = self.held.take().unwrap_or(self.last_address);
+                        self.pending.push_back(Packet::new_jump(FHeader::FSync, last));
+                    }
+                }
+                subformat => trace!("ignoring te_inst format 3 subformat {}", subformat),
+            },
+            FORMAT_ADDRESS => {
+                self.release_held();
+                self.held = Some(self.differential_address(&mut bits));
+            }
+            FORMAT_BRANCH => {
+                self.release_held();
+                let branches = bits.read(5);
+                let map = bits.read(branch_map_bits(branches));
+                // a set bit is a branch not taken
+                let count = if branches == 0 { 31 } else { branches };
+                for i in 0..count {
+                    self.pending.push_back(Packet::new_branch((map >> i) & 1 == 0));
+                }
+                if branches != 0 {
+                    self.held = Some(self.differential_address(&mut bits));
+                }
+            }
+            format => trace!("ignoring te_inst format {}", format),
+        }
+    }
+}
+
+impl<R: Read> PacketSource for ETraceSource<R> {
+    // skips to the first start sync
+    fn read_first_packet(&mut self) -> Result<(Option<TraceHeader>, Packet)> {
+        loop {
+            let payload = self.read_payload()?;
+            let mut bits = BitReader::new(&payload);
+            if bits.read(2) == FORMAT_SYNC && bits.read(2) == SYNC_START {
+                bits.read(1 + PRIVILEGE_BITS);
+                let start = self.full_address(&mut bits);
+                return Ok((None, Packet::new_jump(FHeader::FSync, start)));
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Packet> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(packet);
+            }
+            match self.read_payload() {
+                Ok(payload) => self.translate(&payload),
+                Err(e) => {
+                    if self.held.is_none() {
+                        return Err(e);
+                    }
+                    self.release_held();
+                }
+            }
+        }
+    }
+}
@@ -12,7 +12,7 @@ pub enum FHeader {
     FTrap = 0b100, // trapping happened - could be interrupt or exception
     FSync = 0b101, // a synchronization packet
     FVal = 0b110,   // this packets report a certain value upon request
-    FRes = 0b111,   // reserved for now
+    FCheck = 0b111, // integrity check of the packets since the previous one
 }
 
 impl From<u8> for FHeader {
@@ -25,7 +25,7 @@ impl From<u8> for FHeader {
             0b100 => FHeader::FTrap,
             0b101 => FHeader::FSync,
             0b110 => FHeader::FVal,
-            0b111 => FHeader::FRes,
+            0b111 => FHeader::FCheck,
             _ => panic!("Invalid FHeader value"),
         }
     }
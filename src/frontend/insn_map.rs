@@ -0,0 +1,165 @@
+// Instruction map of the executable sections of an ELF, shared by the
+// packet decoder and the stack unwinder.
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::debug;
+use object::{Object, ObjectSection};
+use object::elf::SHF_EXECINSTR;
+use rvdasm::disassembler::*;
+use rvdasm::insn::*;
+
+pub struct InsnMap {
+    pub insns: HashMap<u64, Insn>,
+    // words rvdasm cannot decode (vendor custom extensions) by address, present
+    // in `insns` as a non control-flow placeholder of the right length
+    pub opaque: HashMap<u64, u64>,
+    // (address, first parcel) of the instructions of an unsupported length,
+    // skipped a parcel at a time
+    pub skipped: Vec<(u64, u16)>,
+}
+
+impl InsnMap {
+    // `.insn` directive spelling of an opaque word
+    pub fn opaque_text(&self, pc: u64) -> Option<String> {
+        let word = self.opaque.get(&pc)?;
+        let digits = self.insns[&pc].len * 2;
+        Some(format!(".insn 0x{:0width$x}", word, width = digits))
+    }
+}
+
+// length of the instruction starting with the 16-bit parcel `low`, from the
+// standard length encoding; None for the 80-bit and longer formats
+pub fn insn_len(low: u16) -> Option<usize> {
+    if low & 0b11 != 0b11 {
+        Some(2)
+    } else if low & 0b11100 != 0b11100 {
+        Some(4)
+    } else if low & 0b111111 == 0b011111 {
+        Some(6)
+    } else if low & 0b1111111 == 0b0111111 {
+        Some(8)
+    } else {
+        None
+    }
+}
+
+// what an opaque word looks like to the stepping logic: an instruction that
+// falls through to the next one, rvdasm only decodes up to 32 bits so the
+// 48 and 64-bit formats always end up here
+pub fn placeholder(dasm: &Disassembler, len: usize, addr: u64) -> Option<Insn> {
+    let nop: &[u8] = if len == 2 { &[0x01, 0x00] } else { &[0x13, 0x00, 0x00, 0x00] };
+    let mut insn = dasm.disassemble_all(nop, addr).remove(&addr)?;
+    insn.len = len;
+    Some(insn)
+}
+
+// walk the section one instruction at a time, trusting the length encoding
+// over rvdasm: words it left out or decoded with the wrong length (the first
+// half of a long instruction) are decoded again on their own or replaced by
+// placeholders, so that stepping over them stays in sync
+fn fill_holes(dasm: &Disassembler, data: &[u8], addr: u64, map: &mut InsnMap) {
+    let mut offset = 0;
+    while offset + 2 <= data.len() {
+        let pc = addr + offset as u64;
+        let low = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let Some(len) = insn_len(low) else {
+            debug!("unsupported instruction length at {:#x} ({:#06x}), skipping a parcel", pc, low);
+            map.skipped.push((pc, low));
+            offset += 2;
+            continue;
+        };
+        if offset + len > data.len() {
+            break;
+        }
+        if map.insns.get(&pc).is_some_and(|insn| insn.len == len) {
+            offset += len;
+            continue;
+        }
+        let word = &data[offset..offset + len];
+        if let Some(insn) = dasm.disassemble_all(word, pc).remove(&pc).filter(|insn| insn.len == len) {
+            map.insns.insert(pc, insn);
+        } else if let Some(insn) = placeholder(dasm, len, pc) {
+            map.insns.insert(pc, insn);
+            map.opaque.insert(pc, word.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64));
+        }
+        offset += len;
+    }
+}
+
+// (address, contents) of the executable sections, for the raw instruction
+// bytes
+pub fn read_code(elf: &object::File) -> Result<Vec<(u64, Vec<u8>)>> {
+    let mut code = Vec::new();
+    for section in elf.sections() {
+        if let object::SectionFlags::Elf { sh_flags } = section.flags() {
+            if sh_flags & (SHF_EXECINSTR as u64) != 0 {
+                code.push((section.address(), section.data()?.to_vec()));
+            }
+        }
+    }
+    Ok(code)
+}
+
+// the `len` bytes at pc, None outside of the code
+pub fn code_bytes(code: &[(u64, Vec<u8>)], pc: u64, len: usize) -> Option<&[u8]> {
+    code.iter().find(|(addr, data)| pc >= *addr && pc + len as u64 <= *addr + data.len() as u64)
+        .map(|(addr, data)| &data[(pc - addr) as usize..(pc - addr) as usize + len])
+}
+
+fn disassembler(elf: &object::File) -> Disassembler {
+    let elf_arch = elf.architecture();
+
+    let xlen = if elf_arch == object::Architecture::Riscv64 {
+        Xlen::XLEN64
+    } else if elf_arch == object::Architecture::Riscv32 {
+        Xlen::XLEN32
+    } else {
+        panic!("Unsupported architecture: {:?}", elf_arch);
+    };
+
+    Disassembler::new(xlen)
+}
+
+// the instructions of a section, at addr onwards
+fn disassemble_section(dasm: &Disassembler, section: &object::Section, addr: u64, map: &mut InsnMap) -> Result<()> {
+    let data = section.data()?;
+    let sec_map = dasm.disassemble_all(data, addr);
+    debug!(
+        "section `{}` @ {:#x}: {} insns",
+        section.name().unwrap_or("<unnamed>"),
+        addr,
+        sec_map.len()
+    );
+    map.insns.extend(sec_map);
+    fill_holes(dasm, data, addr, map);
+    Ok(())
+}
+
+pub fn build_insn_map(elf: &object::File) -> Result<InsnMap> {
+    let dasm = disassembler(elf);
+
+    let mut map = InsnMap { insns: HashMap::new(), opaque: HashMap::new(), skipped: Vec::new() };
+    for section in elf.sections() {
+        if let object::SectionFlags::Elf { sh_flags } = section.flags() {
+            if sh_flags & (SHF_EXECINSTR as u64) != 0 {
+                disassemble_section(&dasm, &section, section.address(), &mut map)?;
+            }
+        }
+    }
+    if map.insns.is_empty() {
+        return Err(anyhow::anyhow!("No executable instructions found in ELF file"));
+    }
+    if !map.opaque.is_empty() {
+        debug!("{} words could not be disassembled, treating them as opaque .insn", map.opaque.len());
+    }
+    Ok(map)
+}
+
+// the instructions of one section placed at addr, for relocatable objects
+// whose sections all start at 0 until they are loaded
+pub fn build_section_insn_map(elf: &object::File, section: &object::Section, addr: u64) -> Result<InsnMap> {
+    let mut map = InsnMap { insns: HashMap::new(), opaque: HashMap::new(), skipped: Vec::new() };
+    disassemble_section(&disassembler(elf), section, addr, &mut map)?;
+    Ok(map)
+}
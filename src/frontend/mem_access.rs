@@ -0,0 +1,102 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AccessClass {
+    Load,
+    Store,
+    FpLoad,
+    FpStore,
+    // lr, sc and amo, which load and store
+    Atomic,
+}
+
+impl AccessClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessClass::Load => "load",
+            AccessClass::Store => "store",
+            AccessClass::FpLoad => "fp load",
+            AccessClass::FpStore => "fp store",
+            AccessClass::Atomic => "atomic",
+        }
+    }
+
+    pub fn loads(&self) -> bool {
+        matches!(self, AccessClass::Load | AccessClass::FpLoad | AccessClass::Atomic)
+    }
+
+    pub fn stores(&self) -> bool {
+        matches!(self, AccessClass::Store | AccessClass::FpStore | AccessClass::Atomic)
+    }
+}
+
+// what the encoding of a load or store says about its access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub class: AccessClass,
+    // bytes
+    pub size: u64,
+    pub base: u8,
+    pub offset: i64,
+}
+
+fn bits(word: u32, hi: u32, lo: u32) -> u32 {
+    (word >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+// the scalar loads and stores, compressed or not, and the atomics; vector
+// accesses are left to the vector receiver
+pub fn decode_mem_access(bytes: &[u8], rv64: bool) -> Option<MemAccess> {
+    use AccessClass::*;
+    if bytes.len() == 2 {
+        let w = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+        let funct3 = bits(w, 15, 13);
+        // the registers x8-x15 of the CL/CS formats
+        let rs1_c = 8 + bits(w, 9, 7) as u8;
+        // offsets of the word and double word CL/CS and stack pointer formats
+        let off_w = (bits(w, 12, 10) << 3 | bits(w, 6, 6) << 2 | bits(w, 5, 5) << 6) as i64;
+        let off_d = (bits(w, 12, 10) << 3 | bits(w, 6, 5) << 6) as i64;
+        let off_lwsp = (bits(w, 12, 12) << 5 | bits(w, 6, 4) << 2 | bits(w, 3, 2) << 6) as i64;
+        let off_ldsp = (bits(w, 12, 12) << 5 | bits(w, 6, 5) << 3 | bits(w, 4, 2) << 6) as i64;
+        let off_swsp = (bits(w, 12, 9) << 2 | bits(w, 8, 7) << 6) as i64;
+        let off_sdsp = (bits(w, 12, 10) << 3 | bits(w, 9, 7) << 6) as i64;
+        let access = |class, size, base, offset| Some(MemAccess { class, size, base, offset });
+        return match (bits(w, 1, 0), funct3) {
+            (0b00, 0b001) => access(FpLoad, 8, rs1_c, off_d),
+            (0b00, 0b010) => access(Load, 4, rs1_c, off_w),
+            (0b00, 0b011) if rv64 => access(Load, 8, rs1_c, off_d),
+            (0b00, 0b011) => access(FpLoad, 4, rs1_c, off_w),
+            (0b00, 0b101) => access(FpStore, 8, rs1_c, off_d),
+            (0b00, 0b110) => access(Store, 4, rs1_c, off_w),
+            (0b00, 0b111) if rv64 => access(Store, 8, rs1_c, off_d),
+            (0b00, 0b111) => access(FpStore, 4, rs1_c, off_w),
+            (0b10, 0b001) => access(FpLoad, 8, 2, off_ldsp),
+            (0b10, 0b010) => access(Load, 4, 2, off_lwsp),
+            (0b10, 0b011) if rv64 => access(Load, 8, 2, off_ldsp),
+            (0b10, 0b011) => access(FpLoad, 4, 2, off_lwsp),
+            (0b10, 0b101) => access(FpStore, 8, 2, off_sdsp),
+            (0b10, 0b110) => access(Store, 4, 2, off_swsp),
+            (0b10, 0b111) if rv64 => access(Store, 8, 2, off_sdsp),
+            (0b10, 0b111) => access(FpStore, 4, 2, off_swsp),
+            _ => None,
+        };
+    }
+    if bytes.len() != 4 {
+        return None;
+    }
+    let w = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let funct3 = bits(w, 14, 12);
+    let base = bits(w, 19, 15) as u8;
+    let load_offset = (w as i32 >> 20) as i64;
+    let store_offset = ((w as i32 >> 25) << 5 | bits(w, 11, 7) as i32) as i64;
+    let (class, size, offset) = match bits(w, 6, 0) {
+        0x03 => (Load, [1, 2, 4, 8, 1, 2, 4, 0][funct3 as usize], load_offset),
+        0x07 => (FpLoad, [0, 2, 4, 8, 16, 0, 0, 0][funct3 as usize], load_offset),
+        0x23 => (Store, [1, 2, 4, 8, 0, 0, 0, 0][funct3 as usize], store_offset),
+        0x27 => (FpStore, [0, 2, 4, 8, 16, 0, 0, 0][funct3 as usize], store_offset),
+        0x2f => (Atomic, [0, 0, 4, 8, 0, 0, 0, 0][funct3 as usize], 0),
+        _ => return None,
+    };
+    if size == 0 {
+        return None;
+    }
+    Some(MemAccess { class, size, base, offset })
+}
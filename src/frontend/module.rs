@@ -0,0 +1,80 @@
+// Kernel modules: relocatable objects the kernel loads at run time, each
+// section of a `.ko` placed at its own address. Their code and symbols are
+// merged into the maps of the traced binary, so that traces through driver
+// code decode and get names like the rest of the kernel.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use anyhow::{anyhow, Result};
+use object::{Object, ObjectSection};
+use rvdasm::insn::Insn;
+
+use crate::frontend::context::parse_u64;
+use crate::frontend::insn_map::build_section_insn_map;
+
+#[derive(Clone, Debug)]
+pub struct ModuleSection {
+    pub module: String,
+    pub address: u64,
+    pub ko: String,
+    pub section: String,
+}
+
+impl ModuleSection {
+    // the section in the module object, its data and symbols start at 0
+    pub fn find<'data, 'file>(&self, ko: &'file object::File<'data>) -> Result<object::Section<'data, 'file>> {
+        ko.section_by_name(&self.section)
+            .ok_or_else(|| anyhow!("module {} ({}) has no section `{}`", self.module, self.ko, self.section))
+    }
+
+    // the addresses the section was loaded at
+    pub fn range(&self) -> Result<(u64, u64)> {
+        let data = std::fs::read(&self.ko)?;
+        let ko = object::File::parse(&*data)?;
+        Ok((self.address, self.address + self.find(&ko)?.size()))
+    }
+
+    // the raw code of the section where it was loaded
+    pub fn load_code(&self) -> Result<(u64, Vec<u8>)> {
+        let data = std::fs::read(&self.ko)?;
+        let ko = object::File::parse(&*data)?;
+        Ok((self.address, self.find(&ko)?.data()?.to_vec()))
+    }
+
+    // the instructions of the section where it was loaded
+    pub fn load_insns(&self) -> Result<HashMap<u64, Insn>> {
+        let data = std::fs::read(&self.ko)?;
+        let ko = object::File::parse(&*data)?;
+        Ok(build_section_insn_map(&ko, &self.find(&ko)?, self.address)?.insns)
+    }
+}
+
+// one `<module> <address> <ko> [<section>]` line per loaded section, as the
+// kernel lists them in /sys/module/<module>/sections, `.text` when the
+// section is left out; `#` starts a comment
+pub fn read_module_map(path: &str) -> Result<Vec<ModuleSection>> {
+    let mut sections = Vec::new();
+    for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 && fields.len() != 4 {
+            return Err(anyhow!("{}:{}: expected `<module> <address> <ko> [<section>]`, got `{}`", path, lineno + 1, line));
+        }
+        sections.push(ModuleSection {
+            module: fields[0].to_string(),
+            address: parse_u64(fields[1])?,
+            ko: fields[2].to_string(),
+            section: fields.get(3).unwrap_or(&".text").to_string(),
+        });
+    }
+    Ok(sections)
+}
+
+pub fn read_module_maps(path: &str) -> Result<Vec<ModuleSection>> {
+    if path.is_empty() { Ok(Vec::new()) } else { read_module_map(path) }
+}
@@ -0,0 +1,204 @@
+// Nexus (IEEE-5001) input, as RISC-V N-Trace encoders emit it in branch
+// history mode. Messages are bytes of 6 MDO data bits above 2 MSEO bits; MSEO
+// 01 ends a variable-size field and 11 ends the message. Fields fill the MDO
+// bits LSB first, fixed-size ones ahead of the first variable-size one. No
+// SRC or timestamp fields are expected.
+//
+// Translated are program trace syncs, indirect branches with their branch
+// history, history overflows and the correlation message ending the trace.
+// ICNT is not needed since the walk stops at every branch anyway, which also
+// means the exact instruction taking a trap is unknown: traps are taken at the
+// end of the basic block they happen in.
+use std::collections::VecDeque;
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use log::trace;
+use tacit_wire::field;
+
+use crate::frontend::f_header::FHeader;
+use crate::frontend::packet::{Packet, TraceHeader};
+use crate::frontend::source::PacketSource;
+use crate::frontend::trap_type::TrapType;
+
+const MSEO_MASK: u8 = 0b11;
+const MSEO_END_FIELD: u8 = 0b01;
+const MSEO_END_MESSAGE: u8 = 0b11;
+const MDO_BITS: u32 = 6;
+
+const TCODE_BITS: u32 = 6;
+const SYNC_BITS: u32 = 4;
+const BTYPE_BITS: u32 = 2;
+const RCODE_BITS: u32 = 4;
+const EVCODE_BITS: u32 = 4;
+const CDF_BITS: u32 = 2;
+
+const TCODE_DIRECT_BRANCH: u64 = 3;
+const TCODE_INDIRECT_BRANCH: u64 = 4;
+const TCODE_PROG_TRACE_SYNC: u64 = 9;
+const TCODE_RESOURCE_FULL: u64 = 27;
+const TCODE_INDIRECT_BRANCH_HIST: u64 = 28;
+const TCODE_INDIRECT_BRANCH_HIST_SYNC: u64 = 29;
+const TCODE_PROG_TRACE_CORRELATION: u64 = 33;
+
+// RCODE of a ResourceFull message carrying a full branch history
+const RCODE_HIST: u64 = 1;
+// B-TYPE of an indirect branch that is an exception or interrupt
+const BTYPE_TRAP: u64 = 1;
+
+// the fields of a message, each with the bits it came in; the first one
+// starts with the fixed-size fields
+struct Message {
+    fields: Vec<u64>,
+    // bits of the first field not consumed yet
+    pos: u32,
+}
+
+impl Message {
+    // the next fixed-size field of the first field
+    fn fixed(&mut self, bits: u32) -> u64 {
+        let value = field(self.fields[0], self.pos, bits);
+        self.pos += bits;
+        value
+    }
+
+    // the `i`th variable-size field, 0 if the message does not have it
+    fn var(&self, i: usize) -> u64 {
+        match i {
+            0 => self.fields[0] >> self.pos,
+            _ => self.fields.get(i).copied().unwrap_or(0),
+        }
+    }
+}
+
+pub struct NexusSource<R> {
+    stream: R,
+    // translated packets not handed out yet
+    pending: VecDeque<Packet>,
+    // the last address sent, U-ADDR fields are XORed with it
+    last_address: u64,
+}
+
+impl<R: Read> NexusSource<R> {
+    pub fn new(stream: R) -> Self {
+        Self { stream, pending: VecDeque::new(), last_address: 0 }
+    }
+
+    fn read_message(&mut self) -> Result<Message> {
+        let mut fields = Vec::new();
+        let mut field = 0u64;
+        let mut bits = 0;
+        loop {
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte)?;
+            if bits < u64::BITS {
+                field |= ((byte[0] >> 2) as u64) << bits;
+            }
+            bits += MDO_BITS;
+            match byte[0] & MSEO_MASK {
+                MSEO_END_FIELD | MSEO_END_MESSAGE => {
+                    fields.push(field);
+                    field = 0;
+                    bits = 0;
+                    if byte[0] & MSEO_MASK == MSEO_END_MESSAGE {
+                        return Ok(Message { fields, pos: 0 });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn full_address(&mut self, f_addr: u64) -> u64 {
+        self.last_address = f_addr << 1;
+        self.last_address
+    }
+
+    fn unique_address(&mut self, u_addr: u64) -> u64 {
+        self.last_address ^= u_addr << 1;
+        self.last_address
+    }
+
+    // HIST holds the oldest branch in its top bit under a stop bit, a set bit
+    // is a taken branch
+    fn push_history(&mut self, hist: u64) {
+        let len = u64::BITS - hist.leading_zeros();
+        for i in (0..len.saturating_sub(1)).rev() {
+            self.pending.push_back(Packet::new_branch((hist >> i) & 1 == 1));
+        }
+    }
+
+    // the branches up to an indirect branch, then its target
+    fn push_indirect(&mut self, btype: u64, target: u64, hist: u64) {
+        self.push_history(hist);
+        if btype == BTYPE_TRAP {
+            // the walk never reaches the target, so it stops at the block end
+            self.pending.push_back(Packet::new_trap(TrapType::TException, target, target));
+        } else {
+            self.pending.push_back(Packet::new_jump(FHeader::FUj, target));
+        }
+    }
+
+    fn translate(&mut self, mut message: Message) -> Result<()> {
+        match message.fixed(TCODE_BITS) {
+            TCODE_INDIRECT_BRANCH_HIST => {
+                let btype = message.fixed(BTYPE_BITS);
+                let target = self.unique_address(message.var(1));
+                self.push_indirect(btype, target, message.var(2));
+            }
+            TCODE_INDIRECT_BRANCH_HIST_SYNC => {
+                message.fixed(SYNC_BITS);
+                let btype = message.fixed(BTYPE_BITS);
+                let target = self.full_address(message.var(1));
+                self.push_indirect(btype, target, message.var(2));
+            }
+            TCODE_RESOURCE_FULL => {
+                if message.fixed(RCODE_BITS) == RCODE_HIST {
+                    self.push_history(message.var(0));
+                }
+            }
+            TCODE_PROG_TRACE_SYNC => {
+                // a periodic resync, the walk already knows where it is
+                message.fixed(SYNC_BITS);
+                self.full_address(message.var(1));
+            }
+            TCODE_PROG_TRACE_CORRELATION => {
+                message.fixed(EVCODE_BITS);
+                // CDF 1: the branches since the last message follow ICNT
+                if message.fixed(CDF_BITS) == 1 {
+                    self.push_history(message.var(1));
+                }
+                self.pending.push_back(Packet::new_jump(FHeader::FSync, self.last_address));
+            }
+            TCODE_DIRECT_BRANCH | TCODE_INDIRECT_BRANCH => {
+                return Err(anyhow!("Nexus messages without branch history are not supported, trace in branch history mode"));
+            }
+            tcode => trace!("ignoring Nexus message with TCODE {}", tcode),
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> PacketSource for NexusSource<R> {
+    // skips to the first program trace sync
+    fn read_first_packet(&mut self) -> Result<(Option<TraceHeader>, Packet)> {
+        loop {
+            let mut message = self.read_message()?;
+            if message.fixed(TCODE_BITS) == TCODE_PROG_TRACE_SYNC {
+                message.fixed(SYNC_BITS);
+                let start = self.full_address(message.var(1));
+                return Ok((None, Packet::new_jump(FHeader::FSync, start)));
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Packet> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(packet);
+            }
+            let message = self.read_message()?;
+            self.translate(message)?;
+        }
+    }
+}
@@ -0,0 +1,342 @@
+// Code overlays: different code copied into the same RAM region at run
+// time. Each overlay names the ELF and section holding its code and when it
+// is the one loaded, so that the instruction map and the symbols of a region
+// follow the trace instead of mixing every overlay linked at that address.
+// The executable mappings of the processes of an OS trace are overlays too,
+// loaded while their address space is the current context of the sideband.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use anyhow::{anyhow, Result};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol};
+use rvdasm::insn::Insn;
+
+use crate::frontend::context::{parse_u64, ContextMap};
+use crate::frontend::insn_map::{build_insn_map, code_bytes, read_code};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Activation {
+    // loaded from the first timestamp, until the second one if any
+    Interval(u64, Option<u64>),
+    // loaded once execution reaches this address, the overlay loader
+    Symbol(u64),
+    // loaded while the sideband context is in this address space, a mapping
+    // of the process
+    Asid(u64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    pub start: u64,
+    pub end: u64,
+    pub elf: String,
+    // None for the code of every executable section
+    pub section: Option<String>,
+    // added to the ELF addresses, for code not loaded where it was linked
+    pub bias: u64,
+    pub activation: Activation,
+}
+
+impl Overlay {
+    // instructions of the overlay section that fall inside the region
+    pub fn load_insns(&self) -> Result<HashMap<u64, Insn>> {
+        let data = std::fs::read(&self.elf)?;
+        let elf = object::File::parse(&*data)?;
+        let (sec_start, sec_end) = self.section_range(&elf)?;
+        Ok(build_insn_map(&elf)?.insns.into_iter()
+            .filter(|&(addr, _)| addr >= sec_start && addr < sec_end)
+            .map(|(addr, insn)| (self.relocate(addr), insn))
+            .filter(|&(addr, _)| self.contains(addr))
+            .collect())
+    }
+
+    // the raw code of the overlay section that falls inside the region, as
+    // (address, contents) where it runs
+    pub fn load_code(&self) -> Result<Vec<(u64, Vec<u8>)>> {
+        let data = std::fs::read(&self.elf)?;
+        let elf = object::File::parse(&*data)?;
+        let (sec_start, sec_end) = self.section_range(&elf)?;
+        // the region in ELF addresses
+        let (start, end) = (self.start.wrapping_sub(self.bias), self.end.wrapping_sub(self.bias));
+        Ok(read_code(&elf)?.into_iter()
+            .filter_map(|(addr, bytes)| {
+                let from = addr.max(sec_start).max(start);
+                let to = (addr + bytes.len() as u64).min(sec_end).min(end);
+                (from < to).then(|| (self.relocate(from), bytes[(from - addr) as usize..(to - addr) as usize].to_vec()))
+            })
+            .collect())
+    }
+
+    // the ELF addresses of the overlay code, before relocation
+    pub fn section_range(&self, elf: &object::File) -> Result<(u64, u64)> {
+        let Some(name) = &self.section else {
+            return Ok((0, u64::MAX));
+        };
+        let section = elf.section_by_name(name)
+            .ok_or_else(|| anyhow!("overlay ELF {} has no section `{}`", self.elf, name))?;
+        Ok((section.address(), section.address() + section.size()))
+    }
+
+    // where an ELF address is at run time
+    pub fn relocate(&self, addr: u64) -> u64 {
+        addr.wrapping_add(self.bias)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+pub struct OverlayMap {
+    pub overlays: Vec<Overlay>,
+    // the context switches the process mappings follow
+    pub contexts: Option<ContextMap>,
+}
+
+impl OverlayMap {
+    // one `<start> <end> <elf> <section> <activation>` line per overlay, the
+    // activation being `<from>..[<to>]` timestamps or `@<symbol>` of the base
+    // ELF, `#` starts a comment
+    pub fn from_file(path: &str, base_elf: &object::File) -> Result<Self> {
+        let mut overlays = Vec::new();
+        for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(anyhow!("{}:{}: expected `<start> <end> <elf> <section> <activation>`, got `{}`",
+                                   path, lineno + 1, line));
+            }
+            let activation = if let Some(symbol) = fields[4].strip_prefix('@') {
+                let addr = base_elf.symbols().find(|sym| sym.name() == Ok(symbol)).map(|sym| sym.address())
+                    .ok_or_else(|| anyhow!("{}:{}: no symbol `{}` in the binary", path, lineno + 1, symbol))?;
+                Activation::Symbol(addr)
+            } else if let Some((from, to)) = fields[4].split_once("..") {
+                Activation::Interval(parse_u64(from)?, if to.is_empty() { None } else { Some(parse_u64(to)?) })
+            } else {
+                return Err(anyhow!("{}:{}: activation `{}` is neither `<from>..[<to>]` nor `@<symbol>`",
+                                   path, lineno + 1, fields[4]));
+            };
+            overlays.push(Overlay {
+                start: parse_u64(fields[0])?,
+                end: parse_u64(fields[1])?,
+                elf: fields[2].to_string(),
+                section: Some(fields[3].to_string()),
+                bias: 0,
+                activation,
+            });
+        }
+        Ok(Self { overlays, contexts: None })
+    }
+
+    // the overlays and the process mappings, None when there are neither;
+    // the mappings follow the context switches of the sideband file
+    pub fn from_paths(overlays: &str, process_map: &str, sideband: &str, base_elf: &object::File) -> Result<Option<Self>> {
+        if overlays.is_empty() && process_map.is_empty() {
+            return Ok(None);
+        }
+        let mut map = if overlays.is_empty() {
+            Self { overlays: Vec::new(), contexts: None }
+        } else {
+            Self::from_file(overlays, base_elf)?
+        };
+        if !process_map.is_empty() {
+            if sideband.is_empty() {
+                return Err(anyhow!("process map {} needs a sideband file of context switches", process_map));
+            }
+            map.overlays.extend(read_process_map(process_map)?);
+            map.contexts = Some(ContextMap::from_file(sideband)?);
+        }
+        Ok(Some(map))
+    }
+}
+
+// one `<asid> <start> <end> <offset> <elf>` line per executable mapping of a
+// process, as perf records mmap events: the ELF contents from file offset
+// `offset` on are mapped at `start` up to `end` in address space `asid`,
+// `#` starts a comment
+pub fn read_process_map(path: &str) -> Result<Vec<Overlay>> {
+    let mut mappings = Vec::new();
+    for (lineno, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("{}:{}: expected `<asid> <start> <end> <offset> <elf>`, got `{}`", path, lineno + 1, line));
+        }
+        let start = parse_u64(fields[1])?;
+        let offset = parse_u64(fields[3])?;
+        let data = std::fs::read(fields[4])?;
+        let elf = object::File::parse(&*data)?;
+        let bias = load_bias(&elf, offset, start)
+            .ok_or_else(|| anyhow!("{}:{}: no segment of {} at file offset {:#x}", path, lineno + 1, fields[4], offset))?;
+        mappings.push(Overlay {
+            start,
+            end: parse_u64(fields[2])?,
+            elf: fields[4].to_string(),
+            section: None,
+            bias,
+            activation: Activation::Asid(parse_u64(fields[0])?),
+        });
+    }
+    Ok(mappings)
+}
+
+// what to add to the ELF addresses for its byte at file offset `offset` to
+// be at `start`, from the segment holding that byte
+fn load_bias(elf: &object::File, offset: u64, start: u64) -> Option<u64> {
+    elf.segments().find_map(|segment| {
+        let (file_start, size) = segment.file_range();
+        (offset >= file_start && offset < file_start + size)
+            .then(|| start.wrapping_sub(segment.address() + (offset - file_start)))
+    })
+}
+
+// which overlay each region holds as the trace goes, regions are the
+// distinct (start, end) ranges of the overlays
+pub struct OverlayTracker {
+    pub regions: Vec<(u64, u64)>,
+    region_of: Vec<usize>,
+    activations: Vec<Activation>,
+    // per region, the loaded overlay or None for the base ELF contents
+    active: Vec<Option<usize>>,
+    // per overlay, whether the last timestamp was inside its interval, or
+    // its address space
+    inside: Vec<bool>,
+    contexts: Option<ContextMap>,
+}
+
+impl OverlayTracker {
+    pub fn new(map: &OverlayMap) -> Self {
+        let mut regions = Vec::new();
+        let mut region_of = Vec::new();
+        for overlay in map.overlays.iter() {
+            let region = (overlay.start, overlay.end);
+            let i = regions.iter().position(|&r| r == region).unwrap_or_else(|| {
+                regions.push(region);
+                regions.len() - 1
+            });
+            region_of.push(i);
+        }
+        let active = vec![None; regions.len()];
+        let inside = vec![false; map.overlays.len()];
+        let activations = map.overlays.iter().map(|overlay| overlay.activation.clone()).collect();
+        Self { regions, region_of, activations, active, inside, contexts: map.contexts.clone() }
+    }
+
+    // note the time and where execution is, returns the (region, overlay)
+    // pairs that changed; the timestamp is None for untimed entries
+    pub fn observe(&mut self, timestamp: Option<u64>, pc: u64) -> Vec<(usize, Option<usize>)> {
+        let mut changes = Vec::new();
+        for (overlay, activation) in self.activations.iter().enumerate() {
+            let region = self.region_of[overlay];
+            let loaded = self.active[region] == Some(overlay);
+            // intervals act on their edges, so a later load in the region wins
+            let load = match (activation, timestamp) {
+                (Activation::Symbol(addr), _) => *addr == pc,
+                (Activation::Interval(..) | Activation::Asid(_), Some(ts)) => {
+                    let inside = match activation {
+                        Activation::Interval(from, to) => ts >= *from && to.is_none_or(|to| ts < to),
+                        Activation::Asid(asid) => self.contexts.as_ref().is_some_and(|contexts| contexts.lookup(ts).asid == *asid),
+                        Activation::Symbol(_) => unreachable!(),
+                    };
+                    let entered = inside && !self.inside[overlay];
+                    if loaded && !inside {
+                        self.active[region] = None;
+                        changes.push((region, None));
+                    }
+                    self.inside[overlay] = inside;
+                    entered
+                }
+                (Activation::Interval(..) | Activation::Asid(_), None) => false,
+            };
+            if load && !loaded {
+                self.active[region] = Some(overlay);
+                changes.push((region, Some(overlay)));
+            }
+        }
+        // unloads first, the base contents they restore must not cover a
+        // mapping of the next process over part of the same range
+        changes.sort_by_key(|&(_, overlay)| overlay.is_some());
+        changes
+    }
+
+    // the overlay loaded in a region, None for the base ELF contents
+    pub fn active(&self, region: usize) -> Option<usize> {
+        self.active[region]
+    }
+}
+
+// replace what a map holds in `region` with `contents`
+pub fn swap_region<T: Clone>(map: &mut HashMap<u64, T>, region: (u64, u64), contents: &HashMap<u64, T>) {
+    map.retain(|&addr, _| addr < region.0 || addr >= region.1);
+    map.extend(contents.iter().map(|(&addr, value)| (addr, value.clone())));
+}
+
+// the instruction map side of the overlays: the region contents of the base
+// ELF and of every overlay, swapped in as the tracker says
+pub struct OverlayInsns {
+    pub tracker: OverlayTracker,
+    base: Vec<HashMap<u64, Insn>>,
+    overlays: Vec<HashMap<u64, Insn>>,
+}
+
+impl OverlayInsns {
+    pub fn load(map: &OverlayMap, insn_map: &HashMap<u64, Insn>) -> Result<Self> {
+        let tracker = OverlayTracker::new(map);
+        let base = tracker.regions.iter()
+            .map(|&(start, end)| insn_map.iter()
+                .filter(|(&addr, _)| addr >= start && addr < end)
+                .map(|(&addr, insn)| (addr, insn.clone()))
+                .collect())
+            .collect();
+        let overlays = map.overlays.iter().map(Overlay::load_insns).collect::<Result<_>>()?;
+        Ok(Self { tracker, base, overlays })
+    }
+
+    // update `insn_map` for the overlays loaded by now, returns the changes
+    pub fn sync(&mut self, timestamp: Option<u64>, pc: u64, insn_map: &mut HashMap<u64, Insn>) -> Vec<(usize, Option<usize>)> {
+        let changes = self.tracker.observe(timestamp, pc);
+        for &(region, overlay) in changes.iter() {
+            let contents = match overlay {
+                Some(overlay) => &self.overlays[overlay],
+                None => &self.base[region],
+            };
+            swap_region(insn_map, self.tracker.regions[region], contents);
+        }
+        changes
+    }
+}
+
+// the raw bytes side of the overlays, for writers that keep the encodings:
+// the code of every overlay, read instead of the base code while loaded
+pub struct OverlayCode {
+    pub tracker: OverlayTracker,
+    overlays: Vec<Vec<(u64, Vec<u8>)>>,
+}
+
+impl OverlayCode {
+    pub fn load(map: &OverlayMap) -> Result<Self> {
+        let overlays = map.overlays.iter().map(Overlay::load_code).collect::<Result<_>>()?;
+        Ok(Self { tracker: OverlayTracker::new(map), overlays })
+    }
+
+    // the `len` bytes at pc in the overlay loaded over it, or in `base` when
+    // none is; None outside of the code
+    pub fn bytes<'a>(&'a self, base: &'a [(u64, Vec<u8>)], pc: u64, len: usize) -> Option<&'a [u8]> {
+        let loaded = self.tracker.regions.iter().enumerate()
+            .filter(|(_, &(start, end))| pc >= start && pc < end)
+            .find_map(|(region, _)| self.tracker.active(region));
+        match loaded {
+            Some(overlay) => code_bytes(&self.overlays[overlay], pc, len),
+            None => code_bytes(base, pc, len),
+        }
+    }
+}
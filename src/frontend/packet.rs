@@ -1,11 +1,14 @@
-use std::fs::File;
-use std::io::{Read, BufReader};
-use anyhow::Result;
+use std::io::Read;
+use anyhow::{anyhow, Result};
 use log::trace;
+use tacit_wire::{read_u32_le, read_u8, read_varint, read_varint_width};
 
+use crate::frontend::address::{AddressScheme, Compression};
 use crate::frontend::c_header::*;
+use crate::frontend::check_type::*;
 use crate::frontend::f_header::*;
 use crate::frontend::trap_type::*;
+use crate::frontend::val_type::*;
 
 #[derive(Debug)]
 pub struct Packet {
@@ -13,10 +16,22 @@ pub struct Packet {
     pub c_header: CHeader,
     pub f_header: FHeader,
     pub trap_type: TrapType,
+    pub val_type: ValType,
+    pub value: u64,
+    // register of a VReg FVal packet
+    pub regno: u16,
     pub target_address: u64,
+    // payload bits of the varint target_address came in
+    pub target_bits: u32,
     pub from_address: u64,
     pub ctx: u64,
     pub timestamp: u64,
+    // FCheck contents
+    pub sequence: Option<u64>,
+    pub crc: Option<u64>,
+    // a sync packet the source resumed at after dropping corrupted packets,
+    // not the end of the trace
+    pub resync: bool,
 }
 
 // Initialize a packet with default values
@@ -25,41 +40,63 @@ impl Packet {
         Packet {
             is_compressed: false,
             c_header: CHeader::CNa,
-            f_header: FHeader::FRes,
+            f_header: FHeader::FCheck,
             trap_type: TrapType::TNone,
+            val_type: ValType::VNone,
+            value: 0,
+            regno: 0,
             target_address: 0,
+            target_bits: 0,
             from_address: 0,
             ctx: 0,
             timestamp: 0,
+            sequence: None,
+            crc: None,
+            resync: false,
         }
     }
-}
 
-fn read_u8(stream: &mut BufReader<File>) -> Result<u8> {
-    let mut buf = [0u8; 1];
-    stream.read_exact(&mut buf)?;
-    Ok(buf[0])
-}
+    // untimed packets for the sources translating other trace formats,
+    // addresses are full ones
+    pub fn new_branch(taken: bool) -> Packet {
+        let mut packet = Packet::new();
+        packet.f_header = if taken { FHeader::FTb } else { FHeader::FNt };
+        packet
+    }
 
-const VAR_MASK: u8 = 0b1000_0000;
-const VAR_LAST: u8 = 0b1000_0000;
-const VAR_OFFSET: u8 = 7;
-const VAR_VAL_MASK: u8 = 0b0111_1111;
-
-fn read_varint(stream: &mut BufReader<File>) -> Result<u64> {
-    let mut result = Vec::new();
-    loop {
-        let byte = read_u8(stream)?;
-        trace!("byte: {:08b}", byte);
-        result.push(byte);
-        if byte & VAR_MASK == VAR_LAST { break; }
+    // an FUj or FSync packet to `target`
+    pub fn new_jump(f_header: FHeader, target: u64) -> Packet {
+        let mut packet = Packet::new();
+        packet.f_header = f_header;
+        packet.target_address = target;
+        packet.target_bits = 64;
+        packet
     }
-    Ok(result.iter().rev().fold(0, |acc, &x| (acc << VAR_OFFSET) | (x & VAR_VAL_MASK) as u64))
-} 
 
-pub fn read_packet(stream: &mut BufReader<File>) -> Result<Packet> {
-    let mut packet = Packet::new();
+    pub fn new_trap(trap_type: TrapType, from: u64, target: u64) -> Packet {
+        let mut packet = Packet::new_jump(FHeader::FTrap, target);
+        packet.trap_type = trap_type;
+        packet.from_address = from;
+        packet
+    }
+
+    pub fn new_value(val_type: ValType, value: u64) -> Packet {
+        let mut packet = Packet::new();
+        packet.f_header = FHeader::FVal;
+        packet.val_type = val_type;
+        packet.value = value;
+        packet
+    }
+}
+
+pub fn read_packet<R: Read>(stream: &mut R) -> Result<Packet> {
     let first_byte = read_u8(stream)?;
+    read_packet_after(first_byte, stream)
+}
+
+// the rest of a packet whose first byte was already read
+fn read_packet_after<R: Read>(first_byte: u8, stream: &mut R) -> Result<Packet> {
+    let mut packet = Packet::new();
     trace!("first_byte: {:08b}", first_byte);
     let c_header = CHeader::from(first_byte & C_HEADER_MASK);
     match c_header {
@@ -80,7 +117,7 @@ pub fn read_packet(stream: &mut BufReader<File>) -> Result<Packet> {
                     packet.c_header = CHeader::CNa;
                 }
                 FHeader::FUj => {
-                    packet.target_address = read_varint(stream)?;
+                    (packet.target_address, packet.target_bits) = read_varint_width(stream)?;
                     packet.timestamp = read_varint(stream)?;
                     packet.f_header = f_header;
                     packet.c_header = CHeader::CNa;
@@ -92,16 +129,37 @@ pub fn read_packet(stream: &mut BufReader<File>) -> Result<Packet> {
                     packet.c_header = CHeader::CNa;
                 }
                 FHeader::FTrap => {
-                    let trap_type = TrapType::from((first_byte & TRAP_TYPE_MASK) >> TRAP_TYPE_OFFSET);
-                    packet.trap_type = trap_type;
+                    // corrupted traces can have any bits here
+                    let trap_bits = (first_byte & TRAP_TYPE_MASK) >> TRAP_TYPE_OFFSET;
+                    if !matches!(trap_bits, 0b000 | 0b001 | 0b010 | 0b100) {
+                        return Err(anyhow!("invalid trap type {:03b}", trap_bits));
+                    }
+                    packet.trap_type = TrapType::from(trap_bits);
                     packet.from_address = read_varint(stream)?;
-                    packet.target_address = read_varint(stream)?;
+                    (packet.target_address, packet.target_bits) = read_varint_width(stream)?;
                     packet.timestamp = read_varint(stream)?;
                     packet.f_header = f_header;
                     packet.c_header = CHeader::CNa;
                 }
-                _ => {
-                    println!("Invalid FHeader value: {}", first_byte);
+                FHeader::FVal => {
+                    packet.val_type = ValType::from((first_byte & VAL_TYPE_MASK) >> VAL_TYPE_OFFSET);
+                    if packet.val_type == ValType::VReg {
+                        packet.regno = read_varint(stream)? as u16;
+                    }
+                    packet.value = read_varint(stream)?;
+                    packet.f_header = f_header;
+                    packet.c_header = CHeader::CNa;
+                }
+                FHeader::FCheck => {
+                    let check_type = (first_byte & CHECK_TYPE_MASK) >> CHECK_TYPE_OFFSET;
+                    if check_type & CHECK_SEQUENCE != 0 {
+                        packet.sequence = Some(read_varint(stream)?);
+                    }
+                    if check_type & CHECK_CRC != 0 {
+                        packet.crc = Some(read_varint(stream)?);
+                    }
+                    packet.f_header = f_header;
+                    packet.c_header = CHeader::CNa;
                 }
             }
         }
@@ -109,10 +167,110 @@ pub fn read_packet(stream: &mut BufReader<File>) -> Result<Packet> {
     Ok(packet)
 }
 
-pub fn read_first_packet(stream: &mut BufReader<File>) -> Result<Packet> {
-    // call read_packet
-    let packet = read_packet(stream)?;
-    assert!(packet.f_header == FHeader::FSync);
-    assert!(packet.c_header == CHeader::CNa);
+// what a varint of a packet carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarintField {
+    Address,
+    Timestamp,
+    // the address of a load or store
+    DataAddress,
+    // the other FVal values and FCheck contents
+    Other,
+}
+
+// the varints following the first byte of a packet, in order, kept in sync
+// with read_packet so packet-framing readers (async, network) know how much
+// to fetch
+pub fn varint_fields(first_byte: u8) -> &'static [VarintField] {
+    use VarintField::*;
+    match CHeader::from(first_byte & C_HEADER_MASK) {
+        CHeader::CNa => match FHeader::from((first_byte & F_HEADER_MASK) >> FHEADER_OFFSET) {
+            FHeader::FTb | FHeader::FNt | FHeader::FIj => &[Timestamp],
+            FHeader::FVal => match ValType::from((first_byte & VAL_TYPE_MASK) >> VAL_TYPE_OFFSET) {
+                ValType::VLoad | ValType::VStore => &[DataAddress],
+                ValType::VReg => &[Other, Other],
+                _ => &[Other],
+            },
+            FHeader::FUj | FHeader::FSync => &[Address, Timestamp],
+            FHeader::FTrap => &[Address, Address, Timestamp],
+            FHeader::FCheck => {
+                let check_type = (first_byte & CHECK_TYPE_MASK) >> CHECK_TYPE_OFFSET;
+                match (check_type & (CHECK_SEQUENCE | CHECK_CRC)).count_ones() {
+                    0 => &[],
+                    1 => &[Other],
+                    _ => &[Other, Other],
+                }
+            }
+        },
+        _ => &[],
+    }
+}
+
+// number of varints following the first byte of a packet
+pub fn varint_count(first_byte: u8) -> usize {
+    varint_fields(first_byte).len()
+}
+
+// Optional header ahead of the first sync packet, so a trace says which
+// encoder settings it was made with:
+//   TRACE_MAGIC, version (u8), length of the parameters (u8), parameters:
+//   address shift (u8), address compression (u8, 0 xor, 1 absolute,
+//   2 delta), br_mode (u8), bp_entries (u32 le)
+// Newer versions may append parameters, readers skip the ones they do not
+// know. A sync packet never starts with the first magic byte (it would be a
+// compressed packet), so headerless traces are told apart by that byte.
+pub const TRACE_MAGIC: &[u8; 8] = b"TACITTRC";
+pub const TRACE_VERSION: u8 = 1;
+const TRACE_PARAMS_LEN: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    pub version: u8,
+    pub address: AddressScheme,
+    pub br_mode: u64,
+    pub bp_entries: u64,
+}
+
+// the header past its first magic byte
+pub fn read_header<R: Read>(stream: &mut R) -> Result<TraceHeader> {
+    let mut magic = [0u8; 7];
+    stream.read_exact(&mut magic)?;
+    if magic != TRACE_MAGIC[1..] {
+        return Err(anyhow!("bad trace header magic"));
+    }
+    let version = read_u8(stream)?;
+    let mut params = vec![0u8; read_u8(stream)? as usize];
+    stream.read_exact(&mut params)?;
+    if params.len() < TRACE_PARAMS_LEN {
+        return Err(anyhow!("trace header version {} has {} bytes of parameters, expected at least {}",
+                           version, params.len(), TRACE_PARAMS_LEN));
+    }
+    Ok(TraceHeader {
+        version,
+        address: AddressScheme { shift: params[0] as u32, compression: Compression::from_code(params[1])? },
+        br_mode: params[2] as u64,
+        bp_entries: read_u32_le(&mut &params[3..7])? as u64,
+    })
+}
+
+// the trace header if there is one, and the sync packet the trace starts with
+pub fn read_first_packet<R: Read>(stream: &mut R) -> Result<(Option<TraceHeader>, Packet)> {
+    let mut first_byte = read_u8(stream)?;
+    let header = if first_byte == TRACE_MAGIC[0] {
+        let header = read_header(stream)?;
+        first_byte = read_u8(stream)?;
+        Some(header)
+    } else {
+        None
+    };
+    let packet = expect_sync(read_packet_after(first_byte, stream)?)?;
+    Ok((header, packet))
+}
+
+// a trace has to start with a sync packet, for the address it starts at
+pub(crate) fn expect_sync(packet: Packet) -> Result<Packet> {
+    if packet.c_header != CHeader::CNa || packet.f_header != FHeader::FSync {
+        return Err(anyhow!("the trace starts with a {:?} packet, not a sync packet", packet.f_header));
+    }
     Ok(packet)
 }
@@ -0,0 +1,143 @@
+// Regions of interest delimited by marker functions or pcs, as a benchmark
+// harness brackets what it measures with e.g. `trace_region_begin()` and
+// `trace_region_end()`. The frontend can restrict the entries it hands to
+// the receivers to the open regions.
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use object::{Object, ObjectSymbol};
+
+use crate::backend::event::{Entry, Event};
+use crate::frontend::context::parse_u64;
+use crate::frontend::decoder::EntrySink;
+
+pub const DEFAULT_REGION: &str = "trace_region_begin:trace_region_end";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Marker {
+    Pc(u64),
+    // the entry of the function
+    Func(String),
+}
+
+impl FromStr for Marker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Err(anyhow!("empty region marker"));
+        }
+        // symbol names never start with a digit
+        match parse_u64(s) {
+            Ok(pc) => Ok(Marker::Pc(pc)),
+            Err(_) => Ok(Marker::Func(s.to_string())),
+        }
+    }
+}
+
+impl Marker {
+    pub fn resolve(&self, elf: &object::File) -> Result<u64> {
+        match self {
+            Marker::Pc(pc) => Ok(*pc),
+            Marker::Func(name) => elf.symbols().find(|sym| sym.name() == Ok(name.as_str())).map(|sym| sym.address())
+                .ok_or_else(|| anyhow!("region marker `{}`: no such symbol in the binary", name)),
+        }
+    }
+}
+
+impl std::fmt::Display for Marker {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Marker::Pc(pc) => write!(f, "{:#x}", pc),
+            Marker::Func(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// a region from the execution of its begin marker to the one of its end marker
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionSpec {
+    pub begin: Marker,
+    pub end: Marker,
+}
+
+impl FromStr for RegionSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (begin, end) = s.split_once(':')
+            .ok_or_else(|| anyhow!("region `{}` is not <begin>:<end>", s))?;
+        Ok(RegionSpec { begin: begin.parse()?, end: end.parse()? })
+    }
+}
+
+impl std::fmt::Display for RegionSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.begin, self.end)
+    }
+}
+
+impl RegionSpec {
+    // (begin pc, end pc)
+    pub fn resolve(&self, elf: &object::File) -> Result<(u64, u64)> {
+        Ok((self.begin.resolve(elf)?, self.end.resolve(elf)?))
+    }
+}
+
+// which entries are inside a region: the instruction at a begin pc opens
+// its region, the one at the end pc is the last entry of it. Start, End and
+// context switches always pass so the receivers keep their bearings.
+#[derive(Clone, Debug, Default)]
+pub struct RegionFilter {
+    // (begin pc, end pc, open)
+    regions: Vec<(u64, u64, bool)>,
+}
+
+impl RegionFilter {
+    pub fn new(regions: &[(u64, u64)]) -> Self {
+        RegionFilter { regions: regions.iter().map(|&(begin, end)| (begin, end, false)).collect() }
+    }
+
+    pub fn from_elf(specs: &[RegionSpec], elf_path: &str) -> Result<Self> {
+        let elf_data = std::fs::read(elf_path)?;
+        let elf = object::File::parse(&*elf_data)?;
+        let regions = specs.iter().map(|spec| spec.resolve(&elf)).collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(&regions))
+    }
+
+    pub fn passes(&mut self, entry: &Entry) -> bool {
+        match entry.event {
+            Event::Start | Event::End | Event::ContextSwitch => true,
+            Event::None => {
+                let pc = entry.arc.0;
+                for region in self.regions.iter_mut().filter(|region| region.0 == pc) {
+                    region.2 = true;
+                }
+                let inside = self.regions.iter().any(|region| region.2);
+                for region in self.regions.iter_mut().filter(|region| region.1 == pc) {
+                    region.2 = false;
+                }
+                inside
+            }
+            _ => self.regions.iter().any(|region| region.2),
+        }
+    }
+
+    pub fn sink<'a, S: EntrySink>(&'a mut self, bus: &'a mut S) -> RegionSink<'a, S> {
+        RegionSink { bus, filter: self }
+    }
+}
+
+// hands on only the entries inside a region
+pub struct RegionSink<'a, S: EntrySink> {
+    bus: &'a mut S,
+    filter: &'a mut RegionFilter,
+}
+
+impl<S: EntrySink> EntrySink for RegionSink<'_, S> {
+    fn broadcast(&mut self, entry: Entry) {
+        if self.filter.passes(&entry) {
+            self.bus.broadcast(entry);
+        }
+    }
+}
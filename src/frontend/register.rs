@@ -0,0 +1,69 @@
+// register numbers of FVal register packets, those of the abstract commands
+// of the debug spec: CSRs at 0x0000-0x0fff, GPRs at 0x1000-0x101f and FPRs
+// at 0x1020-0x103f
+pub const GPR_BASE: u16 = 0x1000;
+pub const FPR_BASE: u16 = 0x1020;
+const CSR_LIMIT: u16 = 0x1000;
+
+const GPR_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+// the CSRs worth knowing by name, the others are `csr0x<number>`
+const CSR_NAMES: &[(u16, &str)] = &[
+    (0x001, "fflags"), (0x002, "frm"), (0x003, "fcsr"),
+    (0x100, "sstatus"), (0x104, "sie"), (0x105, "stvec"), (0x106, "scounteren"),
+    (0x140, "sscratch"), (0x141, "sepc"), (0x142, "scause"), (0x143, "stval"), (0x144, "sip"),
+    (0x180, "satp"),
+    (0x300, "mstatus"), (0x301, "misa"), (0x302, "medeleg"), (0x303, "mideleg"), (0x304, "mie"),
+    (0x305, "mtvec"), (0x306, "mcounteren"), (0x320, "mcountinhibit"),
+    (0x340, "mscratch"), (0x341, "mepc"), (0x342, "mcause"), (0x343, "mtval"), (0x344, "mip"),
+    (0xb00, "mcycle"), (0xb02, "minstret"),
+    (0xc00, "cycle"), (0xc01, "time"), (0xc02, "instret"),
+    (0xf11, "mvendorid"), (0xf12, "marchid"), (0xf13, "mimpid"), (0xf14, "mhartid"),
+];
+
+pub fn register_name(regno: u16) -> String {
+    if regno < CSR_LIMIT {
+        match CSR_NAMES.iter().find(|(number, _)| *number == regno) {
+            Some((_, name)) => name.to_string(),
+            None => format!("csr{:#x}", regno),
+        }
+    } else if regno < FPR_BASE {
+        GPR_NAMES.get((regno - GPR_BASE) as usize).map_or_else(|| format!("reg{:#x}", regno), |name| name.to_string())
+    } else if regno < FPR_BASE + 32 {
+        format!("f{}", regno - FPR_BASE)
+    } else {
+        format!("reg{:#x}", regno)
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// the register number of an ABI or CSR name, `x<n>`, `f<n>`, or a CSR
+// number as `csr<number>` or `<number>`
+pub fn parse_register(name: &str) -> Option<u16> {
+    let name = name.trim();
+    if let Some(index) = GPR_NAMES.iter().position(|gpr| *gpr == name) {
+        return Some(GPR_BASE + index as u16);
+    }
+    if name == "fp" {
+        return Some(GPR_BASE + 8);
+    }
+    if let Some((number, _)) = CSR_NAMES.iter().find(|(_, csr)| *csr == name) {
+        return Some(*number);
+    }
+    if let Some(index) = name.strip_prefix('x').and_then(|n| n.parse::<u16>().ok()).filter(|&n| n < 32) {
+        return Some(GPR_BASE + index);
+    }
+    if let Some(index) = name.strip_prefix('f').and_then(|n| n.parse::<u16>().ok()).filter(|&n| n < 32) {
+        return Some(FPR_BASE + index);
+    }
+    parse_number(name.strip_prefix("csr").unwrap_or(name)).filter(|&n| n < CSR_LIMIT)
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use anyhow::{anyhow, Context as _, Result};
+use rvdasm::disassembler::*;
+use rvdasm::insn::*;
+use tacit_wire::{read_u16_le, read_varint};
+
+use crate::backend::event::{AttrValue, Attributes, DataAccess, Entry, Event, RegisterValue, TrapInfo};
+use crate::backend::replay_receiver::*;
+use crate::backend::schema::check_schema;
+use crate::frontend::context::Context;
+use crate::frontend::insn_map::placeholder;
+
+/// Reads back a `trace.replay.bin` written by the replay receiver.
+pub struct ReplayReader<R: Read = BufReader<File>> {
+    stream: R,
+    pub version: u16,
+    pub schema_version: u16,
+    // build info of the decoder that wrote it, empty before version 2
+    pub writer: String,
+    dasm: Disassembler,
+    insns: HashMap<u64, Insn>,
+}
+
+// cursor over the payload of one record
+struct Payload<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Payload<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.pos).ok_or_else(|| anyhow!("replay record truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut rest = self.rest();
+        let value = read_varint(&mut rest).context("replay record truncated")?;
+        self.pos = self.data.len() - rest.len();
+        Ok(value)
+    }
+
+    fn bytes(&mut self) -> Result<&[u8]> {
+        let len = self.varint()? as usize;
+        let bytes = self.pos.checked_add(len).and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| anyhow!("replay record truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn rest(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+}
+
+impl ReplayReader {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?), path)
+    }
+}
+
+impl<R: Read> ReplayReader<R> {
+    // `path` only names the input in error messages
+    pub fn from_reader(mut stream: R, path: &str) -> Result<Self> {
+        let mut header = [0u8; 11];
+        stream.read_exact(&mut header)?;
+        if &header[0..8] != REPLAY_MAGIC {
+            return Err(anyhow!("{} is not a replay file", path));
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version > REPLAY_VERSION {
+            return Err(anyhow!("{} has replay format version {}, this decoder reads up to {}", path, version, REPLAY_VERSION));
+        }
+        let xlen = match header[10] {
+            32 => Xlen::XLEN32,
+            64 => Xlen::XLEN64,
+            other => return Err(anyhow!("unsupported xlen {} in {}", other, path)),
+        };
+        // the schema of before it was recorded
+        let (mut schema_version, mut writer) = (1, String::new());
+        if version >= 2 {
+            schema_version = read_u16_le(&mut stream)?;
+            let info = read_record(&mut stream)?;
+            writer = String::from_utf8_lossy(&info).into_owned();
+        }
+        check_schema(schema_version, path)?;
+        Ok(ReplayReader { stream, version, schema_version, writer, dasm: Disassembler::new(xlen), insns: HashMap::new() })
+    }
+
+    // next decoded entry, None at the end of the file
+    pub fn read_entry(&mut self) -> Result<Option<Entry>> {
+        loop {
+            let mut tag = [0u8; 1];
+            match self.stream.read_exact(&mut tag) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            let data = read_record(&mut self.stream)?;
+            let mut payload = Payload { data: &data, pos: 0 };
+            match tag[0] {
+                RECORD_INSN => {
+                    let pc = payload.varint()?;
+                    // opaque and long-format words come back as placeholders
+                    let insn = self.dasm.disassemble_all(payload.rest(), pc).remove(&pc)
+                        .filter(|insn| insn.len == payload.rest().len())
+                        .or_else(|| placeholder(&self.dasm, payload.rest().len(), pc))
+                        .ok_or_else(|| anyhow!("replay: cannot disassemble instruction at {:#x}", pc))?;
+                    self.insns.insert(pc, insn);
+                }
+                RECORD_ENTRY => return Ok(Some(parse_entry(&data, &self.insns)?)),
+                // written by a newer decoder, not needed to rebuild the entries
+                _ => {}
+            }
+        }
+    }
+}
+
+// a varint length and as many bytes, the length is only trusted as far as
+// the stream has the bytes
+fn read_record(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_varint(&mut *stream)?;
+    let mut data = Vec::new();
+    stream.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(anyhow!("replay record truncated, {} of {} bytes", data.len(), len));
+    }
+    Ok(data)
+}
+
+// rebuild an entry from a RECORD_ENTRY payload, `insns` holds the
+// instructions defined so far
+pub fn parse_entry(data: &[u8], insns: &HashMap<u64, Insn>) -> Result<Entry> {
+    let mut payload = Payload { data, pos: 0 };
+    let code = payload.u8()?;
+    let event = Event::from_code(code).ok_or_else(|| anyhow!("replay: unknown event code {}", code))?;
+    let flags = payload.u8()?;
+    let arc = (payload.varint()?, payload.varint()?);
+    let timestamp = if flags & FLAG_TIMESTAMP != 0 { Some(payload.varint()?) } else { None };
+    let ctx = if flags & FLAG_CTX != 0 { Some(Context::new(payload.varint()?, payload.varint()?)) } else { None };
+    let trap = if flags & FLAG_TRAP != 0 {
+        let bits = payload.u8()?;
+        let mut field = |bit: u8| -> Result<Option<u64>> { if bits & bit != 0 { Ok(Some(payload.varint()?)) } else { Ok(None) } };
+        let (cause, handler, tval) = (field(TRAP_CAUSE)?, field(TRAP_HANDLER)?, field(TRAP_TVAL)?);
+        Some(TrapInfo { cause, tval, handler })
+    } else {
+        None
+    };
+    let data = if flags & FLAG_DATA != 0 {
+        let size = payload.varint()?;
+        Some(DataAccess { address: arc.1, size, is_store: payload.u8()? != 0 })
+    } else {
+        None
+    };
+    let reg = if flags & FLAG_REG != 0 {
+        Some(RegisterValue { regno: payload.varint()? as u16, value: payload.varint()? })
+    } else {
+        None
+    };
+    let mut attrs = Attributes::default();
+    if flags & FLAG_ATTRS != 0 {
+        for _ in 0..payload.varint()? {
+            let key = String::from_utf8_lossy(payload.bytes()?).into_owned();
+            let kind = payload.u8()?;
+            let value = payload.bytes()?;
+            match kind {
+                ATTR_U64 => attrs.insert(&key, AttrValue::U64(Payload { data: value, pos: 0 }.varint()?)),
+                ATTR_STR => attrs.insert(&key, AttrValue::Str(String::from_utf8_lossy(value).into_owned())),
+                // written by a newer decoder
+                _ => {}
+            }
+        }
+    }
+    let insn = if flags & FLAG_INSN != 0 {
+        Some(insns.get(&arc.0).cloned().ok_or_else(|| anyhow!("replay: instruction at {:#x} used before defined", arc.0))?)
+    } else {
+        None
+    };
+    Ok(Entry { event, arc, insn, timestamp, ctx, trap, data, reg, attrs })
+}
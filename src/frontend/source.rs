@@ -0,0 +1,204 @@
+// Where the decoder gets its packets from. The decoder works on tacit
+// packets, the sources of other trace formats translate their messages into
+// untimed tacit packets with full addresses.
+use std::collections::VecDeque;
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::info;
+
+use crate::frontend::check_type::{crc16, CRC_INIT};
+use crate::frontend::etrace::ETraceSource;
+use crate::frontend::f_header::FHeader;
+use crate::frontend::nexus::NexusSource;
+use crate::frontend::packet::{self, Packet, TraceHeader};
+use crate::diagnostics::{self, Category};
+use crate::metrics;
+
+pub trait PacketSource {
+    // the trace header if there is one, and the sync packet the trace starts with
+    fn read_first_packet(&mut self) -> Result<(Option<TraceHeader>, Packet)>;
+    // the next packet, an error once the stream is exhausted
+    fn read_packet(&mut self) -> Result<Packet>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Tacit,
+    // RISC-V Efficient Trace te_inst packets
+    ETrace,
+    // RISC-V N-Trace / IEEE-5001 branch history messages
+    Nexus,
+}
+
+impl FromStr for TraceFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tacit" => Ok(TraceFormat::Tacit),
+            "etrace" => Ok(TraceFormat::ETrace),
+            "nexus" => Ok(TraceFormat::Nexus),
+            _ => Err(anyhow!("unknown trace format `{}`, expected tacit, etrace or nexus", s)),
+        }
+    }
+}
+
+impl TraceFormat {
+    pub fn source<'a, R: Read + 'a>(&self, stream: R) -> Box<dyn PacketSource + 'a> {
+        match self {
+            TraceFormat::Tacit => Box::new(TacitSource::new(stream)),
+            TraceFormat::ETrace => Box::new(ETraceSource::new(stream)),
+            TraceFormat::Nexus => Box::new(NexusSource::new(stream)),
+        }
+    }
+}
+
+// counts the bytes read and keeps the CRC of those since the last check
+struct CheckedReader<R> {
+    inner: R,
+    offset: u64,
+    crc: u16,
+}
+
+impl<R: Read> Read for CheckedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.offset += n as u64;
+        self.crc = buf[..n].iter().fold(self.crc, |crc, &byte| crc16(crc, byte));
+        Ok(n)
+    }
+}
+
+// Tacit packets, verified against the check packets of the encoder if it
+// inserts any. Once a check packet was seen, packets are held back until the
+// next one vouches for them; a failed check drops them and skips to the next
+// sync packet, where decoding resumes.
+pub struct TacitSource<R> {
+    stream: CheckedReader<R>,
+    // packets read since the last check packet
+    held: VecDeque<Packet>,
+    // packets verified and not handed out yet
+    ready: VecDeque<Packet>,
+    checking: bool,
+    next_sequence: u64,
+    // the check after a resync covers the lost bytes, its CRC cannot match
+    skip_crc: bool,
+    // where the bytes covered by the next check packet start
+    checked_until: u64,
+}
+
+impl<R: Read> TacitSource<R> {
+    pub fn new(stream: R) -> Self {
+        Self {
+            stream: CheckedReader { inner: stream, offset: 0, crc: CRC_INIT },
+            held: VecDeque::new(),
+            ready: VecDeque::new(),
+            checking: false,
+            next_sequence: 0,
+            skip_crc: false,
+            checked_until: 0,
+        }
+    }
+
+    // what is wrong with the packets a check packet covers, if anything;
+    // `crc` is the one of the bytes before it
+    fn verify(&mut self, check: &Packet, crc: u16) -> Option<String> {
+        let mut problem = None;
+        if let Some(sequence) = check.sequence {
+            if sequence != self.next_sequence && self.checking {
+                problem = Some(format!("check packet {} where {} was due", sequence, self.next_sequence));
+            }
+            self.next_sequence = sequence + 1;
+        }
+        if let Some(expected) = check.crc {
+            if expected != crc as u64 && !self.skip_crc {
+                problem = problem.or(Some(format!("CRC {:#06x}, the bytes give {:#06x}", expected, crc)));
+            }
+        }
+        self.skip_crc = false;
+        problem
+    }
+
+    // skip to the next sync packet; it is the end of the trace when nothing
+    // follows it, a place to resume decoding otherwise
+    fn resync(&mut self) -> Result<()> {
+        loop {
+            let packet = match packet::read_packet(&mut self.stream) {
+                Ok(packet) => packet,
+                Err(e) if e.is::<std::io::Error>() => return Err(e),
+                Err(_) => continue,
+            };
+            if packet.f_header != FHeader::FSync {
+                continue;
+            }
+            info!("resynchronized at byte {}", self.stream.offset);
+            self.checked_until = self.stream.offset;
+            let mut sync = packet;
+            match packet::read_packet(&mut self.stream) {
+                Ok(next) => {
+                    sync.resync = true;
+                    self.held.push_back(sync);
+                    self.held.push_back(next);
+                    self.skip_crc = true;
+                }
+                Err(_) => self.ready.push_back(sync),
+            }
+            return Ok(());
+        }
+    }
+}
+
+impl<R: Read> PacketSource for TacitSource<R> {
+    fn read_first_packet(&mut self) -> Result<(Option<TraceHeader>, Packet)> {
+        packet::read_first_packet(&mut self.stream)
+    }
+
+    fn read_packet(&mut self) -> Result<Packet> {
+        loop {
+            if let Some(packet) = self.ready.pop_front() {
+                return Ok(packet);
+            }
+            let start = self.stream.offset;
+            let crc = self.stream.crc;
+            let packet = match packet::read_packet(&mut self.stream) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    if self.held.is_empty() {
+                        return Err(e);
+                    }
+                    // nothing vouches for the packets after the last check
+                    diagnostics::report(Category::TruncatedPacket,
+                                        format!("{} packets after byte {} are not covered by a check packet",
+                                                self.held.len(), self.checked_until));
+                    self.ready.append(&mut self.held);
+                    continue;
+                }
+            };
+            if packet.f_header != FHeader::FCheck {
+                if self.checking {
+                    self.held.push_back(packet);
+                    continue;
+                }
+                return Ok(packet);
+            }
+            self.stream.crc = CRC_INIT;
+            match self.verify(&packet, crc) {
+                None => self.ready.append(&mut self.held),
+                Some(problem) => {
+                    diagnostics::report(Category::CorruptedPacket,
+                                        format!("bytes {}..{}: {}, dropping {} packets", self.checked_until, start, problem, self.held.len()));
+                    metrics::record_corrupted();
+                    self.held.clear();
+                    self.checking = true;
+                    self.resync()?;
+                    continue;
+                }
+            }
+            self.checking = true;
+            self.checked_until = self.stream.offset;
+        }
+    }
+}
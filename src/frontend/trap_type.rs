@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use rvdasm::insn::Insn;
+
 pub const TRAP_TYPE_MASK: u8 = 0b1110_0000;
 pub const TRAP_TYPE_OFFSET: u8 = 5;
 
@@ -19,4 +22,83 @@ impl From<u8> for TrapType {
             _ => panic!("Invalid TrapType value"),
         }
     }
-}
\ No newline at end of file
+}
+
+// the exception code of an mcause value, without the interrupt bit of
+// either xlen
+pub fn cause_code(mcause: u64) -> u64 {
+    mcause & 0x7fff_ffff
+}
+
+// name of a standard exception or interrupt code
+pub fn cause_name(interrupt: bool, code: u64) -> Option<&'static str> {
+    let name = if interrupt {
+        match code {
+            1 => "Supervisor software interrupt",
+            3 => "Machine software interrupt",
+            5 => "Supervisor timer interrupt",
+            7 => "Machine timer interrupt",
+            9 => "Supervisor external interrupt",
+            11 => "Machine external interrupt",
+            13 => "Counter-overflow interrupt",
+            _ => return None,
+        }
+    } else {
+        match code {
+            0 => "Instruction address misaligned",
+            1 => "Instruction access fault",
+            2 => "Illegal instruction",
+            3 => "Breakpoint",
+            4 => "Load address misaligned",
+            5 => "Load access fault",
+            6 => "Store/AMO address misaligned",
+            7 => "Store/AMO access fault",
+            8 => "Environment call from U-mode",
+            9 => "Environment call from S-mode",
+            11 => "Environment call from M-mode",
+            12 => "Instruction page fault",
+            13 => "Load page fault",
+            15 => "Store/AMO page fault",
+            18 => "Software check",
+            19 => "Hardware error",
+            _ => return None,
+        }
+    };
+    Some(name)
+}
+
+// interrupt causes a vectored mtvec has slots for
+const VECTOR_SLOTS: u64 = 64;
+
+// where traps go, from the value of mtvec: BASE in the upper bits, MODE in
+// the low two (0 direct, 1 vectored)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrapVector {
+    pub base: u64,
+    pub vectored: bool,
+}
+
+impl TrapVector {
+    pub fn from_mtvec(mtvec: u64) -> Self {
+        Self { base: mtvec & !0b11, vectored: mtvec & 0b11 == 1 }
+    }
+
+    fn in_table(&self, target: u64) -> bool {
+        self.vectored && target >= self.base && target < self.base + 4 * VECTOR_SLOTS && (target - self.base).is_multiple_of(4)
+    }
+
+    // an interrupt taken in vectored mode lands at BASE + 4 * cause
+    pub fn cause_of(&self, target: u64, interrupt: bool) -> Option<u64> {
+        if interrupt && self.in_table(target) { Some((target - self.base) / 4) } else { None }
+    }
+
+    // entry of the handler code, the vector slots hold a jump to it
+    pub fn handler(&self, target: u64, insn_map: &HashMap<u64, Insn>) -> u64 {
+        match insn_map.get(&target) {
+            Some(insn) if self.in_table(target) && insn.is_direct_jump() => {
+                (target as i64 + insn.get_imm().unwrap().get_val_signed_imm() as i64) as u64
+            }
+            _ => target,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+pub const VAL_TYPE_MASK: u8 = 0b1110_0000;
+pub const VAL_TYPE_OFFSET: u8 = 5;
+
+// what an FVal packet reports, the trap CSRs precede the FTrap packet they
+// belong to; data addresses come one per scalar load or store, in program
+// order, ahead of the packet that steps past the instruction; register
+// values are sampled at the last timed packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValType {
+    VNone  = 0b000,
+    VCause = 0b001, // mcause/scause
+    VTval  = 0b010, // mtval/stval
+    VLoad  = 0b011, // address read by a load, lr or amo
+    VStore = 0b100, // address written by a store or sc
+    VReg   = 0b101, // register number, then its value
+}
+
+impl From<u8> for ValType {
+    fn from(value: u8) -> Self {
+        match value {
+            0b001 => ValType::VCause,
+            0b010 => ValType::VTval,
+            0b011 => ValType::VLoad,
+            0b100 => ValType::VStore,
+            0b101 => ValType::VReg,
+            // values this decoder does not know are read and ignored
+            _ => ValType::VNone,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// decoder library, shared by the command line tool, the C ABI and the wasm build
+pub mod frontend {
+    pub mod packet;
+    pub mod source;
+    pub mod byte_order;
+    pub mod etrace;
+    pub mod nexus;
+    pub mod address;
+    pub mod br_mode;
+    pub mod c_header;
+    pub mod f_header;
+    pub mod trap_type;
+    pub mod val_type;
+    pub mod register;
+    pub mod check_type;
+    pub mod bp_double_saturating_counter;
+    pub mod context;
+    pub mod insn_map;
+    pub mod mem_access;
+    pub mod module;
+    pub mod overlay;
+    pub mod region;
+    pub mod decoder;
+    pub mod correlate;
+    pub mod backpressure;
+    #[cfg(feature = "async")]
+    pub mod async_decoder;
+    pub mod replay_reader;
+}
+pub mod backend {
+    pub mod abstract_receiver;
+    pub mod event;
+    pub mod schema;
+    pub mod stats_receiver;
+    pub mod txt_receiver;
+    pub mod stack_txt_receiver;
+    pub mod atomic_receiver;
+    pub mod afdo_receiver;
+    pub mod gcda_receiver;
+    pub mod stack_unwinder;
+    pub mod timing_model;
+    pub mod speedscope_receiver;
+    pub mod phase;
+    pub mod perfetto_receiver;
+    pub mod vpp_receiver;
+    pub mod foc_receiver;
+    pub mod vbb_receiver;
+    pub mod perf_receiver;
+    pub mod sample_receiver;
+    pub mod gantt_receiver;
+    pub mod top_receiver;
+    pub mod compare;
+    pub mod amdahl_receiver;
+    pub mod branch_receiver;
+    pub mod entropy_receiver;
+    pub mod markov_receiver;
+    pub mod unreached_receiver;
+    pub mod ipc_receiver;
+    pub mod priv_receiver;
+    pub mod spin_receiver;
+    pub mod hotpath_receiver;
+    pub mod mem_receiver;
+    pub mod data_receiver;
+    pub mod sharing_receiver;
+    pub mod reg_receiver;
+    pub mod watch_receiver;
+    pub mod assert_receiver;
+    pub mod gmon_receiver;
+    pub mod layout_receiver;
+    pub mod region_receiver;
+    pub mod combined_receiver;
+    pub mod fxt_receiver;
+    pub mod gdb_receiver;
+    pub mod annotation_receiver;
+    pub mod heatmap_receiver;
+    pub mod test_receiver;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod plugin_receiver;
+    #[cfg(feature = "otf2")]
+    pub mod otf2_receiver;
+    pub mod html_receiver;
+    pub mod hotspots_receiver;
+    pub mod vcd_receiver;
+    pub mod spike_receiver;
+    pub mod replay_receiver;
+    pub mod vector_receiver;
+    #[cfg(feature = "columnar")]
+    pub mod columnar_receiver;
+    #[cfg(feature = "serve")]
+    pub mod serve_receiver;
+    #[cfg(feature = "script")]
+    pub mod script_receiver;
+}
+pub mod metrics;
+pub mod diagnostics;
+pub mod repeat_check;
+pub mod watchdog;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
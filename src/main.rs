@@ -5,85 +5,126 @@ extern crate bus;
 extern crate log;
 extern crate env_logger;
 extern crate gcno_reader;
-mod frontend {
-    pub mod packet;
-    pub mod br_mode;
-    pub mod c_header;
-    pub mod f_header;
-    pub mod trap_type;
-    pub mod bp_double_saturating_counter;
-}
-mod backend {
-    pub mod abstract_receiver;
-    pub mod event;
-    pub mod stats_receiver;
-    pub mod txt_receiver;
-    pub mod stack_txt_receiver;
-    pub mod atomic_receiver;
-    pub mod afdo_receiver;
-    pub mod gcda_receiver;
-    pub mod stack_unwinder;
-    pub mod speedscope_receiver;
-    pub mod perfetto_receiver;
-    pub mod vpp_receiver;
-    pub mod foc_receiver;
-    pub mod vbb_receiver;
-}
-
-use frontend::f_header::FHeader;
 
 // file IO
 use std::fs::File;
-use std::io::{Read, BufReader};
-// collections 
-use std::collections::HashMap;
 // argparse dependency
-use clap::Parser;
-// objdump dependency
-use rvdasm::disassembler::*;
-use rvdasm::insn::*;
-use object::{Object, ObjectSection, ObjectSymbol, SectionFlags};
-use object::elf::SHF_EXECINSTR;
+use clap::{Parser, Subcommand};
 // bus dependency
 use bus::Bus;
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 // frontend dependency
-use frontend::bp_double_saturating_counter::BpDoubleSaturatingCounter;
-use frontend::br_mode::BrMode;
+use ltrace_decoder::frontend::br_mode::BrMode;
+use ltrace_decoder::frontend::decoder::{traces_decoder, replay_decoder, DecoderConfig, BUS_SIZE};
+use ltrace_decoder::frontend::correlate::{correlated_decoder, Alignment};
+use ltrace_decoder::frontend::backpressure::Backpressure;
+use ltrace_decoder::frontend::context::parse_u64;
+use ltrace_decoder::frontend::address::AddressScheme;
+use ltrace_decoder::frontend::source::TraceFormat;
+use ltrace_decoder::frontend::byte_order::ByteOrder;
+use ltrace_decoder::frontend::register::parse_register;
+use ltrace_decoder::frontend::region::{Marker, RegionFilter, RegionSpec, DEFAULT_REGION};
 // backend dependency
-use backend::event::{Entry, Event};
-use backend::stats_receiver::StatsReceiver;
-use backend::txt_receiver::TxtReceiver;
-use backend::stack_txt_receiver::StackTxtReceiver;
-use backend::atomic_receiver::AtomicReceiver;
-use backend::afdo_receiver::AfdoReceiver;
-use backend::abstract_receiver::AbstractReceiver;
-use backend::gcda_receiver::GcdaReceiver;
-use backend::speedscope_receiver::SpeedscopeReceiver;
-use backend::perfetto_receiver::PerfettoReceiver;
-use backend::vpp_receiver::VPPReceiver;
-use backend::foc_receiver::FOCReceiver;
-use backend::vbb_receiver::VBBReceiver;
+use ltrace_decoder::backend::event::Entry;
+use ltrace_decoder::backend::stats_receiver::{PacketBreakdown, StatsReceiver};
+use ltrace_decoder::backend::txt_receiver::{TxtOptions, TxtReceiver};
+use ltrace_decoder::backend::stack_txt_receiver::StackTxtReceiver;
+use ltrace_decoder::backend::atomic_receiver::AtomicReceiver;
+use ltrace_decoder::backend::afdo_receiver::AfdoReceiver;
+use ltrace_decoder::backend::abstract_receiver::{boxed, AbstractReceiver, TraceMetadata};
+use ltrace_decoder::backend::gcda_receiver::GcdaReceiver;
+use ltrace_decoder::backend::stack_unwinder::{self, UnwinderOptions};
+use ltrace_decoder::backend::timing_model::{CostTable, TimingModel, Uniform};
+use ltrace_decoder::backend::speedscope_receiver::SpeedscopeReceiver;
+use ltrace_decoder::backend::phase::PhaseBoundary;
+use ltrace_decoder::backend::perfetto_receiver::PerfettoReceiver;
+use ltrace_decoder::backend::vpp_receiver::VPPReceiver;
+use ltrace_decoder::backend::foc_receiver::{FOCReceiver, FocConfig};
+use ltrace_decoder::backend::vbb_receiver::VBBReceiver;
+use ltrace_decoder::backend::perf_receiver::PerfReceiver;
+use ltrace_decoder::backend::sample_receiver::{SampleReceiver, SampleRate};
+use ltrace_decoder::backend::gantt_receiver::GanttReceiver;
+use ltrace_decoder::backend::top_receiver::TopReceiver;
+use ltrace_decoder::backend::amdahl_receiver::AmdahlReceiver;
+use ltrace_decoder::backend::branch_receiver::BranchReceiver;
+use ltrace_decoder::backend::entropy_receiver::EntropyReceiver;
+use ltrace_decoder::backend::markov_receiver::MarkovReceiver;
+use ltrace_decoder::backend::unreached_receiver::UnreachedReceiver;
+use ltrace_decoder::backend::ipc_receiver::IpcReceiver;
+use ltrace_decoder::backend::priv_receiver::{Level, PrivReceiver};
+use ltrace_decoder::backend::spin_receiver::SpinReceiver;
+use ltrace_decoder::backend::hotpath_receiver::HotPathReceiver;
+use ltrace_decoder::backend::mem_receiver::MemReceiver;
+use ltrace_decoder::backend::data_receiver::DataReceiver;
+use ltrace_decoder::backend::sharing_receiver::SharingReceiver;
+use ltrace_decoder::backend::reg_receiver::RegReceiver;
+use ltrace_decoder::backend::watch_receiver::{WatchReceiver, Watch};
+use ltrace_decoder::backend::assert_receiver::{self, AssertReceiver, AssertSpec};
+use ltrace_decoder::backend::gmon_receiver::GmonReceiver;
+use ltrace_decoder::backend::layout_receiver::LayoutReceiver;
+use ltrace_decoder::backend::region_receiver::RegionReceiver;
+use ltrace_decoder::backend::combined_receiver::CombinedReceiver;
+use ltrace_decoder::backend::fxt_receiver::FxtReceiver;
+use ltrace_decoder::backend::gdb_receiver::GdbReceiver;
+use ltrace_decoder::backend::annotation_receiver::AnnotationReceiver;
+use ltrace_decoder::backend::heatmap_receiver::HeatmapReceiver;
+use ltrace_decoder::backend::test_receiver::TestReceiver;
+use ltrace_decoder::backend::plugin_receiver::{PluginLibrary, PluginReceiver};
+#[cfg(feature = "otf2")]
+use ltrace_decoder::backend::otf2_receiver::Otf2Receiver;
+use ltrace_decoder::backend::html_receiver::HtmlReceiver;
+use ltrace_decoder::backend::hotspots_receiver::{HotspotSchema, HotspotsReceiver};
+use ltrace_decoder::backend::vcd_receiver::VcdReceiver;
+use ltrace_decoder::backend::spike_receiver::SpikeReceiver;
+use ltrace_decoder::backend::replay_receiver::ReplayReceiver;
+use ltrace_decoder::backend::compare::{compare, Run};
+use ltrace_decoder::backend::vector_receiver::VectorReceiver;
+#[cfg(feature = "columnar")]
+use ltrace_decoder::backend::columnar_receiver::{ColumnarReceiver, ColumnarFormat};
+#[cfg(feature = "serve")]
+use ltrace_decoder::backend::serve_receiver::ServeReceiver;
+#[cfg(feature = "script")]
+use ltrace_decoder::backend::script_receiver::ScriptReceiver;
+use ltrace_decoder::diagnostics;
+use ltrace_decoder::metrics;
+use ltrace_decoder::repeat_check;
+use ltrace_decoder::watchdog;
 // error handling
 use anyhow::Result;
-// logging
-use log::{debug, trace};
 
-const BRANCH_OPCODES: &[&str] = &["beq", "bge", "bgeu", "blt", "bltu", "bne", "beqz", "bnez",
-                                "bgez", "blez", "bltz", "bgtz", "bgt", "ble", "bgtu", "bleu",
-                                "c.beqz", "c.bnez", "c.bltz", "c.bgez"];
-const IJ_OPCODES: &[&str] = &["jal", "j", "call", "tail", "c.j", "c.jal"];
-const UJ_OPCODES: &[&str] = &["jalr", "jr", "c.jr", "c.jalr", "ret"];
-const BUS_SIZE: usize = 1024;
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Run the selected receivers over a trace.replay.bin instead of decoding packets
+    Replay {
+        // path to the replay file written by --to-replay
+        replay_trace: String,
+    },
+    /// Decode several runs, each of its own binary, and compare the cycles and calls of their functions
+    Compare {
+        // `[<label>=]<binary>:<trace>[,<trace>...]` per run, the first is the
+        // one the others are compared to
+        #[arg(required = true)]
+        runs: Vec<String>,
+        // print the N hottest functions of the first run, 0 for none
+        #[arg(long, default_value_t = 20)]
+        rows: usize,
+    },
+}
 
 #[derive(Clone, Parser)]
-#[command(name = "trace-decoder", version = "0.1.0", about = "Decode trace files")]
+#[command(name = "trace-decoder", version = "0.1.0", about = "Decode trace files", subcommand_negates_reqs = true)]
 struct Args {
-    // path to the encoded trace file
-    #[arg(short, long)]
-    encoded_trace: String,
-    // path to the binary file
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    // path to the encoded trace file, not needed when replaying; repeat it
+    // for traces of the same binary whose counts are to be summed, timelines
+    // take them only with --correlate
+    #[arg(short, long, required = true)]
+    encoded_trace: Vec<String>,
+    // path to the binary file, each run names its own with compare
+    #[arg(short, long, required = true, default_value_t = String::from(""))]
     binary: String,
     // path to the decoded trace file
     #[arg(short, long, default_value_t = String::from("trace.dump"))]
@@ -94,9 +135,67 @@ struct Args {
     // branch prediction number of entries
     #[arg(long, default_value_t = 1024)]
     bp_entries: u64,
-    // print the timestamp in the decoded trace file
+    // print the timestamp of instructions in the legacy decoded trace file
     #[arg(short, long, default_value_t = false)]
     timestamp: bool,
+    // with --to-txt, annotate instructions with their function
+    #[arg(long, default_value_t = false)]
+    txt_symbols: bool,
+    // with --to-txt, annotate instructions with their source file:line
+    #[arg(long, default_value_t = false)]
+    txt_source: bool,
+    // with --to-txt, write the free-form format of before the columnar one
+    #[arg(long, default_value_t = false)]
+    txt_legacy: bool,
+    // decode the traces side by side as harts 0, 1, ... on one timeline, aligned by
+    // their first sync packets (`sync`) or by cycles added to each (`0,-1200`)
+    #[arg(long, default_value_t = String::from(""))]
+    correlate: String,
+    // path to a sideband file of `<timestamp> <hart> <asid>` context switches
+    #[arg(long, default_value_t = String::from(""))]
+    sideband: String,
+    // comma-separated setjmp-like functions, on top of setjmp/_setjmp/sigsetjmp
+    #[arg(long, default_value_t = String::from(""))]
+    setjmp: String,
+    // comma-separated longjmp-like functions, on top of longjmp/_longjmp/siglongjmp
+    #[arg(long, default_value_t = String::from(""))]
+    longjmp: String,
+    // comma-separated stub symbol patterns (`*` wildcard) to step through without a frame
+    #[arg(long, default_value_t = String::from(""))]
+    stub_symbols: String,
+    // list the other names of aliased functions in the stack outputs
+    #[arg(long, default_value_t = false)]
+    symbol_aliases: bool,
+    // comma-separated perf-style map files naming runtime-generated code
+    #[arg(long, default_value_t = String::from(""))]
+    symbol_map: String,
+    // path to a table of instruction costs, to spread the cycles between timed events by; evenly when empty
+    #[arg(long, default_value_t = String::from(""))]
+    timing_model: String,
+    // path to an overlay description file, for code swapped into the same RAM at runtime
+    #[arg(long, default_value_t = String::from(""))]
+    overlays: String,
+    // path to a process map of `<asid> <start> <end> <offset> <elf>` mappings, needs --sideband
+    #[arg(long, default_value_t = String::from(""))]
+    process_map: String,
+    // path to a map of `<module> <address> <ko> [<section>]` kernel module sections
+    #[arg(long, default_value_t = String::from(""))]
+    modules: String,
+    // mtvec value (base | mode), to resolve the handlers of vectored interrupts
+    #[arg(long, default_value_t = String::from(""))]
+    mtvec: String,
+    // addresses in packets are shifted right by this many bits
+    #[arg(long, default_value_t = 1)]
+    addr_shift: u32,
+    // how jump and trap targets are compressed: xor, absolute or delta
+    #[arg(long, default_value_t = String::from("xor"))]
+    addr_compression: String,
+    // packet format of the encoded trace: tacit, etrace or nexus
+    #[arg(long, default_value_t = String::from("tacit"))]
+    format: String,
+    // byte order of the capture link: little, big16 or big32, optionally with -bitrev
+    #[arg(long, default_value_t = String::from("little"))]
+    byte_order: String,
     // output the decoded trace in stats format
     #[arg(long, default_value_t = false)]
     to_stats: bool,
@@ -106,6 +205,12 @@ struct Args {
     // output the tracked callstack in text format
     #[arg(long, default_value_t = false)]
     to_stack_txt: bool,
+    // with --to-stack-txt, write only the frames entered and left, indented by depth
+    #[arg(long, default_value_t = false)]
+    stack_txt_delta: bool,
+    // with --to-stack-txt, leave out the frames deeper than this, 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    max_depth: usize,
     // output a trace of atomic operations in text format 
     #[arg(long, default_value_t = false)]
     to_atomics: bool,
@@ -118,345 +223,792 @@ struct Args {
     // output the decoded trace in gcda format
     #[arg(long, default_value_t = false)]
     to_gcda: bool,
+    // gcov version to write the gcda for, e.g. 13 or 13.2, by default the one of the gcno
+    #[arg(long, default_value_t = String::new())]
+    gcov_version: String,
     // output the decoded trace in speedscope format
     #[arg(long, default_value_t = false)]
     to_speedscope: bool,
+    // with --to-speedscope, split the profile into warmup and steady state at
+    // cycles=<n>, func=<name> or loop=<name>
+    #[arg(long, default_value_t = String::new())]
+    phase_split: String,
     // output the decoded trace in perfetto format
     #[arg(long, default_value_t = false)]
     to_perfetto: bool,
     // output the decoded trace in vpp format
     #[arg(long, default_value_t = false)]
     to_vpp: bool,
+    // with --to-vpp, summarize the time of each basic block of a path (branch-target mode only)
+    #[arg(long, default_value_t = false)]
+    vpp_bb_summary: bool,
+    // with --to-vpp, also output the statistics of every path as csv
+    #[arg(long, default_value_t = false)]
+    vpp_csv: bool,
     // output the decoded trace in foc format
     #[arg(long, default_value_t = false)]
     to_foc: bool,
+    // with --to-foc, the file of the warmup/sample pattern, parameter sweep and output columns
+    #[arg(long, default_value_t = String::new())]
+    foc_config: String,
     // output the decoded trace in vbb format
     #[arg(long, default_value_t = false)]
     to_vbb: bool,
+    // with --to-vbb, report the block executions slower than this multiple of their median, 0 for none
+    #[arg(long, default_value_t = 0.0)]
+    vbb_anomaly: f64,
+    // with --to-vbb, list the blocks by total cycles rather than by address
+    #[arg(long, default_value_t = false)]
+    vbb_sort_total: bool,
+    // output synthetic samples in linux perf.data format
+    #[arg(long, default_value_t = false)]
+    to_perf: bool,
+    // timestamp units between two perf samples
+    #[arg(long, default_value_t = 1000)]
+    perf_period: u64,
+    // output downsampled call stacks in sampled speedscope and pprof format
+    #[arg(long, default_value_t = false)]
+    to_samples: bool,
+    // retired instructions between two stack samples
+    #[arg(long, default_value_t = 1000)]
+    sample_insns: u64,
+    // timestamp units between two stack samples, instead of counting instructions
+    #[arg(long, default_value_t = 0)]
+    sample_period: u64,
+    // output one csv row per function invocation
+    #[arg(long, default_value_t = false)]
+    to_gantt: bool,
+    // print the N functions with the most self cycles at the end, 0 for none
+    #[arg(long, default_value_t = 0)]
+    top: usize,
+    // comma-separated name=factor what-ifs, e.g. memcpy=2,log_debug=inf; inf removes the calls
+    #[arg(long, default_value_t = String::new())]
+    speedup: String,
+    // output per branch site taken counts and, in predict mode, hit/miss counts
+    #[arg(long, default_value_t = false)]
+    to_branches: bool,
+    // output the branch outcome entropy and the packet cost of each branch mode
+    #[arg(long, default_value_t = false)]
+    to_entropy: bool,
+    // output a markov model of the basic block transitions as json and dot
+    #[arg(long, default_value_t = false)]
+    to_markov: bool,
+    // output the functions and basic blocks of the binary that never ran
+    #[arg(long, default_value_t = false)]
+    to_unreached: bool,
+    // output the instructions per cycle over windows and the cpi per function
+    #[arg(long, default_value_t = false)]
+    to_ipc: bool,
+    // cycles per window of --to-ipc
+    #[arg(long, default_value_t = 10000)]
+    ipc_window: u64,
+    // output the cycles spent in M-mode, S-mode and U-mode over windows and per function
+    #[arg(long, default_value_t = false)]
+    to_priv: bool,
+    // cycles per window of --to-priv
+    #[arg(long, default_value_t = 10000)]
+    priv_window: u64,
+    // privilege level the trace starts in, m, s or u
+    #[arg(long, default_value_t = String::from("m"))]
+    priv_start: String,
+    // output the busy-wait loops and the cycles they spun for
+    #[arg(long, default_value_t = false)]
+    to_spin: bool,
+    // output the K call paths with the most cycles, 0 for none
+    #[arg(long, default_value_t = 0)]
+    hot_paths: usize,
+    // output the loads and stores of every function, from their encodings
+    #[arg(long, default_value_t = false)]
+    to_mem: bool,
+    // with --to-mem, also log every access in order
+    #[arg(long, default_value_t = false)]
+    mem_log: bool,
+    // output a memory access profile from the data addresses in the trace
+    #[arg(long, default_value_t = false)]
+    to_data: bool,
+    // output the cache lines that distinct harts store to distinct bytes of close in time
+    #[arg(long, default_value_t = false)]
+    to_sharing: bool,
+    // with --to-sharing, the bytes of a cache line
+    #[arg(long, default_value_t = 64)]
+    sharing_line: u64,
+    // with --to-sharing, the cycles within which the stores of two harts conflict
+    #[arg(long, default_value_t = 1000)]
+    sharing_window: u64,
+    // output the register and CSR values reported in the trace as csv and vcd
+    #[arg(long, default_value_t = false)]
+    to_regs: bool,
+    // with --to-regs, the comma-separated registers to keep, e.g. sp,mstatus,0x7c0; all when empty
+    #[arg(long, default_value_t = String::new())]
+    regs: String,
+    // report every execution of a location, pc=<address> or func=<name>; repeat it for more
+    #[arg(long)]
+    watch: Vec<String>,
+    // check the assertions of a spec file, exiting non-zero when one fails
+    #[arg(long, default_value_t = String::new())]
+    assert: String,
+    // output a gmon.out with a cycle histogram and call graph arcs for gprof
+    #[arg(long, default_value_t = false)]
+    to_gmon: bool,
+    // output a function order for i-cache locality, for the linker
+    #[arg(long, default_value_t = false)]
+    to_layout: bool,
+    // with --to-layout, the size in bytes of the cache the conflicts are predicted for
+    #[arg(long, default_value_t = 32768)]
+    layout_cache_size: u64,
+    // the <begin>:<end> marker functions or pcs of a region of interest, for
+    // --to-regions and --region-only, trace_region_begin:trace_region_end by default
+    #[arg(long)]
+    region: Vec<String>,
+    // output the cycles, instructions and calls of every region execution
+    #[arg(long, default_value_t = false)]
+    to_regions: bool,
+    // only hand the entries inside a region to the receivers
+    #[arg(long, default_value_t = false)]
+    region_only: bool,
+    // output the traps, atomics, branches and calls in one time-ordered report
+    #[arg(long, default_value_t = false)]
+    to_combined: bool,
+    // cycles per window of --to-combined
+    #[arg(long, default_value_t = 10000)]
+    combined_window: u64,
+    // output function durations and traps in the fuchsia trace format
+    #[arg(long, default_value_t = false)]
+    to_fxt: bool,
+    // output a gdb command file with breakpoints at the hot, anomalous and violating locations
+    #[arg(long, default_value_t = false)]
+    to_gdb: bool,
+    // with --to-gdb, set tracepoints collecting the registers instead of breakpoints
+    #[arg(long, default_value_t = false)]
+    gdb_tracepoints: bool,
+    // output per-address counts, cycles and flags for reverse engineering tools
+    #[arg(long, default_value_t = false)]
+    to_annotations: bool,
+    // output per source line hits and cycles for editor heatmaps
+    #[arg(long, default_value_t = false)]
+    to_heatmap: bool,
+    // output per-test coverage and cycles of a test suite
+    #[arg(long, default_value_t = false)]
+    to_tests: bool,
+    // with --to-tests, the function or pc starting a test
+    #[arg(long, default_value_t = String::from("test_start"))]
+    test_start: String,
+    // with --to-tests, the function or pc ending a test, by default the next start ends it
+    #[arg(long, default_value_t = String::new())]
+    test_end: String,
+    // with --to-tests, a file of `<id> <name>` lines naming the tests
+    #[arg(long, default_value_t = String::new())]
+    test_names: String,
+    // path to a plugin library of receivers, repeat it for several libraries
+    #[arg(long)]
+    plugin: Vec<String>,
+    // comma-separated names of the plugin receivers to run, all of them by default
+    #[arg(long, default_value_t = String::new())]
+    plugin_outputs: String,
+    // output the decoded trace as an otf2 archive, needs the `otf2` feature
+    #[arg(long, default_value_t = false)]
+    to_otf2: bool,
+    // output a self-contained html report
+    #[arg(long, default_value_t = false)]
+    to_html: bool,
+    // output the self and inclusive time and calls of every function as csv
+    #[arg(long, default_value_t = false)]
+    to_hotspots: bool,
+    // path to a schema file naming the columns of the hotspots csv, empty for the default ones
+    #[arg(long, default_value_t = String::from(""))]
+    hotspots_schema: String,
+    // output pc, function, call depth and trap level as a vcd waveform
+    #[arg(long, default_value_t = false)]
+    to_vcd: bool,
+    // output the retired instructions as a spike commit log
+    #[arg(long, default_value_t = false)]
+    to_spike: bool,
+    // output the decoded entries in the replayable binary format
+    #[arg(long, default_value_t = false)]
+    to_replay: bool,
+    // output vector vs scalar instruction counts and the functions using vector code
+    #[arg(long, default_value_t = false)]
+    to_vector: bool,
+    // output the control-flow events as an arrow ipc file, needs the `columnar` feature
+    #[arg(long, default_value_t = false)]
+    to_arrow: bool,
+    // output the control-flow events as a parquet file, needs the `columnar` feature
+    #[arg(long, default_value_t = false)]
+    to_parquet: bool,
+    // stream the events as json over websocket on this address (e.g. 0.0.0.0:9000), needs the `serve` feature
+    #[arg(long, default_value_t = String::from(""))]
+    serve: String,
+    // run a rhai script over the events, needs the `script` feature
+    #[arg(long, default_value_t = String::from(""))]
+    script: String,
+    // expose prometheus metrics over http on this address (e.g. 0.0.0.0:9100)
+    #[arg(long, default_value_t = String::from(""))]
+    metrics: String,
+    // number of entries the bus holds before the frontend has to wait for a receiver
+    #[arg(long, default_value_t = BUS_SIZE)]
+    bus_size: usize,
+    // on a full bus: block (wait), drop (count the lost entries) or spill (queue them on disk)
+    #[arg(long, default_value_t = String::from("block"))]
+    backpressure: String,
+    // abort when a receiver panics or makes no progress for this many seconds, 0 to disable
+    #[arg(long, default_value_t = 60)]
+    watchdog: u64,
+    // print where the time went (frontend, bus, each receiver) at the end of the run
+    #[arg(long, default_value_t = false)]
+    profile: bool,
+    // write the count and first messages of each kind of decode problem to decode.diag.json
+    #[arg(long, default_value_t = false)]
+    diag_json: bool,
+    // fail the run when decode problems of these kinds occurred, comma separated or `all`
+    // (missing-symbol, unknown-opcode, stack-desync, return-mismatch, truncated-packet, corrupted-packet,
+    // unmatched-data, format-version, incomplete-output)
+    #[arg(long, default_value_t = String::from(""))]
+    diag_fatal: String,
+    // decode the trace a second time and fail if any output written to the working directory differs
+    #[arg(long, default_value_t = false)]
+    repeat_check: bool,
 }
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
 
-fn refund_addr(addr: u64) -> u64 {
-    addr << 1
-}
-
-// step until encountering a br/jump
-fn step_bb(pc: u64, insn_map: &HashMap<u64, Insn>, bus: &mut Bus<Entry>, br_mode: &BrMode) -> u64 {
-    let mut pc = pc;
-    let stop_on_ij = *br_mode == BrMode::BrTarget;
-    loop {
-        trace!("stepping bb pc: {:x}", pc);
-        let insn = insn_map.get(&pc).unwrap();
-        bus.broadcast(Entry::new_insn(insn, pc));
-        if stop_on_ij {
-            if insn.is_branch() || insn.is_direct_jump() || insn.is_indirect_jump() {
-                break;
-            } else {
-                pc += insn.len as u64;
-            }
-        } else {
-            if insn.is_branch() || insn.is_indirect_jump() {
-                break;
-            } else if insn.is_direct_jump() {
-                let new_pc = (pc as i64 + insn.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
-                pc = new_pc;
-            } else {
-                pc += insn.len as u64;
-            }
-        }
+    if !args.metrics.is_empty() {
+        metrics::serve(&args.metrics)?;
+    }
+    if args.watchdog > 0 {
+        watchdog::spawn(Duration::from_secs(args.watchdog));
+    }
+    if !args.repeat_check {
+        return run(args);
     }
-    pc
-}
-
-fn step_bb_until(pc: u64, insn_map: &HashMap<u64, Insn>, target_pc: u64, bus: &mut Bus<Entry>) -> u64 {
-    // println!("stepping bb from pc: {:x} until pc: {:x}", pc, target_pc);
-    let mut pc = pc;
 
-    loop {
-        let insn = insn_map.get(&pc).unwrap();
-        bus.broadcast(Entry::new_insn(insn, pc));
-        if insn.is_branch() || insn.is_direct_jump() {
-            break;
-        }
-        if pc == target_pc {
-            break;
-        }
-        pc += insn.len as u64;
+    let first_start = SystemTime::now();
+    run(args.clone())?;
+    let first = repeat_check::output_digests(first_start)?;
+    println!("[Repeat] decoding again to check that the {} outputs come out the same", first.len());
+    metrics::reset();
+    diagnostics::reset();
+    let second_start = SystemTime::now();
+    run(args)?;
+    let second = repeat_check::output_digests(second_start)?;
+    let differences = repeat_check::differences(&first, &second);
+    for difference in &differences {
+        println!("[Repeat] {}", difference);
+    }
+    if !differences.is_empty() {
+        return Err(anyhow::anyhow!("{} outputs differ between two decodes of the same trace", differences.len()));
     }
-    pc
+    println!("[Repeat] {} outputs are identical", second.len());
+    Ok(())
 }
 
-// frontend decoding packets and pushing entries to the bus
-fn trace_decoder(args: &Args, mut bus: Bus<Entry>) -> Result<()> {
-    let mut elf_file = File::open(args.binary.clone())?;
-    let mut elf_buffer = Vec::new();
-    elf_file.read_to_end(&mut elf_buffer)?;
-    let elf = object::File::parse(&*elf_buffer)?;
-    let elf_arch = elf.architecture();
-
+// decode the trace once, with the receivers the arguments select
+fn run(args: Args) -> Result<()> {
+    let names = |list: &str| -> Vec<String> { list.split(',').filter(|s| !s.is_empty()).map(String::from).collect() };
+    let mut dynamic_symbols = Vec::new();
+    for path in names(&args.symbol_map) {
+        dynamic_symbols.extend(stack_unwinder::read_symbol_map(&path)?);
+    }
+    let unwinder_options = UnwinderOptions {
+        setjmp: names(&args.setjmp),
+        longjmp: names(&args.longjmp),
+        stubs: names(&args.stub_symbols),
+        keep_aliases: args.symbol_aliases,
+        dynamic_symbols,
+        overlays: args.overlays.clone(),
+        process_map: args.process_map.clone(),
+        sideband: args.sideband.clone(),
+        modules: args.modules.clone(),
+    };
 
-    let xlen = if elf_arch == object::Architecture::Riscv64 {
-        Xlen::XLEN64
-    } else if elf_arch == object::Architecture::Riscv32 {
-        Xlen::XLEN32
+    let timing_model: Arc<dyn TimingModel> = if args.timing_model.is_empty() {
+        Arc::new(Uniform)
     } else {
-        panic!("Unsupported architecture: {:?}", elf_arch);
+        Arc::new(CostTable::from_file(&args.timing_model)?)
     };
 
-    let dasm = Disassembler::new(xlen);
-
-    let mut insn_map = HashMap::new();
-    for section in elf.sections() {
-        if let object::SectionFlags::Elf { sh_flags } = section.flags() {
-            if sh_flags & (SHF_EXECINSTR as u64) != 0 {
-                let addr = section.address();
-                let data = section.data()?;
-                let sec_map = dasm.disassemble_all(&data, addr);
-                debug!(
-                    "section `{}` @ {:#x}: {} insns",
-                    section.name().unwrap_or("<unnamed>"),
-                    addr,
-                    sec_map.len()
-                );
-                insn_map.extend(sec_map);
-            }
+    let diag_fatal = diagnostics::parse_categories(&args.diag_fatal)?;
+    let backpressure: Backpressure = args.backpressure.parse()?;
+    let address = AddressScheme { shift: args.addr_shift, compression: args.addr_compression.parse()? };
+    let mtvec = if args.mtvec.is_empty() { None } else { Some(parse_u64(&args.mtvec)?) };
+    let format: TraceFormat = args.format.parse()?;
+    let byte_order: ByteOrder = args.byte_order.parse()?;
+    let alignment: Option<Alignment> = if args.correlate.is_empty() { None } else { Some(args.correlate.parse()?) };
+    if alignment.is_some() && !args.sideband.is_empty() {
+        return Err(anyhow::anyhow!("--correlate makes each trace a context of its own, it cannot take a --sideband"));
+    }
+    // traces decoded one after the other restart their timestamps and find
+    // the call stack of the last one, only their counts add up
+    let timelines = [
+        (args.to_speedscope, "--to-speedscope"), (args.to_perfetto, "--to-perfetto"), (args.to_fxt, "--to-fxt"),
+        (args.to_vcd, "--to-vcd"), (args.to_otf2, "--to-otf2"), (args.to_gantt, "--to-gantt"),
+    ];
+    if args.encoded_trace.len() > 1 && alignment.is_none() {
+        if let Some((_, flag)) = timelines.iter().find(|(enabled, _)| *enabled) {
+            return Err(anyhow::anyhow!("{} lays out a single timeline, lay several traces side by side with --correlate", flag));
         }
     }
-    if insn_map.is_empty() {
-        return Err(anyhow::anyhow!("No executable instructions found in ELF file"));
-    }
-    debug!("[main] found {} instructions", insn_map.len());
-
-    let encoded_trace_file = File::open(args.encoded_trace.clone())?;
-    let mut encoded_trace_reader : BufReader<File> = BufReader::new(encoded_trace_file);
-
-    let mut bp_counter = BpDoubleSaturatingCounter::new(args.bp_entries);
-
-    let br_mode = BrMode::from(args.br_mode);
-    let mode_is_predict = br_mode == BrMode::BrPredict || br_mode == BrMode::BrHistory;
-
-    let packet = frontend::packet::read_first_packet(&mut encoded_trace_reader)?;
-    let mut packet_count = 0;
-
-    trace!("packet: {:?}", packet);
-    let mut pc = refund_addr(packet.target_address);
-    let mut timestamp = packet.timestamp;
-    bus.broadcast(Entry::new_timed_event(Event::Start, packet.timestamp, pc, 0));
-
-    while let Ok(packet) = frontend::packet::read_packet(&mut encoded_trace_reader) {
-        packet_count += 1;
-        // special handling for the last packet, should be unlikely hinted
-        trace!("[{}]: packet: {:?}", packet_count, packet);
-        if packet.f_header == FHeader::FSync {
-            pc = step_bb_until(pc, &insn_map, refund_addr(packet.target_address), &mut bus);
-            println!("detected FSync packet, trace ending!");
-            bus.broadcast(Entry::new_timed_event(Event::End, packet.timestamp, pc, 0));
-            break;
-        } else if packet.f_header == FHeader::FTrap {
-            pc = step_bb_until(pc, &insn_map, refund_addr(packet.from_address), &mut bus);
-            pc = refund_addr(packet.target_address ^ (pc >> 1));
-            timestamp += packet.timestamp;
-            bus.broadcast(Entry::new_timed_trap(packet.trap_type, timestamp, refund_addr(packet.from_address), pc));
-        } else if mode_is_predict && packet.f_header == FHeader::FTb { // predicted hit
-            bus.broadcast(Entry::new_timed_event(Event::BPHit, packet.timestamp, pc, pc));
-            // predict for timestamp times
-            for _ in 0..packet.timestamp {
-                pc = step_bb(pc, &insn_map, &mut bus, &br_mode);
-                let insn_to_resolve = insn_map.get(&pc).unwrap();
-                if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                    bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                    panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-                 }
-                let taken = bp_counter.predict(pc, true);
-                if taken {
-                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
-                    bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
-                    pc = new_pc;
-                } else {
-                    let new_pc = pc + insn_to_resolve.len as u64;
-                    bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
-                    pc = new_pc;
-                }
-            }
-        } else if mode_is_predict && packet.f_header == FHeader::FNt { // predicted miss
-            timestamp += packet.timestamp;
-            bus.broadcast(Entry::new_timed_event(Event::BPMiss, timestamp, pc, pc));
-            pc = step_bb(pc, &insn_map, &mut bus, &br_mode);
-            let insn_to_resolve = insn_map.get(&pc).unwrap();
-            if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-             }
-            let taken = bp_counter.predict(pc, false);
-            if !taken { // reverse as we mispredicted
-                let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
-                bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
-                pc = new_pc;
-            } else {
-                let new_pc = pc + insn_to_resolve.len as u64;
-                bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
-                pc = new_pc;
-            }
-        } else  {
-            // trace!("pc before step_bb: {:x}", pc);
-            pc = step_bb(pc, &insn_map, &mut bus, &br_mode);
-            let insn_to_resolve = insn_map.get(&pc).unwrap();
-            // trace!("pc after step_bb: {:x}", pc);
-            timestamp += packet.timestamp;
-            match packet.f_header {
-                FHeader::FTb => {
-                    if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                       bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                       panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-                    }
-                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
-                    bus.broadcast(Entry::new_timed_event(Event::TakenBranch, timestamp, pc, new_pc));
-                    // trace!("pc before br: {:x}, after taken branch: {:x}", pc, new_pc);
-                    pc = new_pc;
-                }
-                FHeader::FNt => {
-                    if !BRANCH_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                        bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                        panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-                    }
-                    let new_pc = pc + insn_to_resolve.len as u64;
-                    bus.broadcast(Entry::new_timed_event(Event::NonTakenBranch, timestamp, pc, new_pc));
-                    // trace!("pc before nt: {:x}, after nt: {:x}", pc, new_pc);
-                    pc = new_pc;
-                }
-                FHeader::FIj => {
-                    if !IJ_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                        bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                        panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-                    }
-                    let new_pc = (pc as i64 + insn_to_resolve.get_imm().unwrap().get_val_signed_imm() as i64) as u64;
-                    bus.broadcast(Entry::new_timed_event(Event::InferrableJump, timestamp, pc, new_pc));
-                    // trace!("pc before ij: {:x}, after ij: {:x}", pc, new_pc);
-                    pc = new_pc;
-                }
-                FHeader::FUj => {
-                    if !UJ_OPCODES.contains(&insn_to_resolve.get_name().as_str()) {
-                        bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                        panic!("pc: {:x}, timestamp: {}, insn: {:?}", pc, timestamp, insn_to_resolve);
-                    }
-                    let new_pc = refund_addr(packet.target_address ^ (pc >> 1));
-                    bus.broadcast(Entry::new_timed_event(Event::UninferableJump, timestamp, pc, new_pc));
-                    // trace!("pc before uj: {:x}, after uj: {:x}", pc, new_pc);
-                    pc = new_pc;
-                }
-                _ => {
-                    bus.broadcast(Entry::new_timed_event(Event::Panic, 0, pc, 0));
-                    panic!("unknown FHeader: {:?}", packet.f_header);
-                }
-            }
-            // log the timestamp
+    let mut speedups = Vec::new();
+    for speedup in names(&args.speedup) {
+        let (name, factor) = speedup.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("--speedup `{}` is not name=factor", speedup))?;
+        let factor: f64 = factor.parse()
+            .map_err(|_| anyhow::anyhow!("--speedup `{}`: `{}` is not a number or inf", speedup, factor))?;
+        if factor <= 0.0 {
+            return Err(anyhow::anyhow!("--speedup `{}`: the factor must be positive", speedup));
         }
+        speedups.push((name.to_string(), factor));
     }
-
-    drop(bus);
-    println!("[Success] Decoded {} packets", packet_count);
-
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-
-    let mut bus: Bus<Entry> = Bus::new(BUS_SIZE);
+    let mut registers = Vec::new();
+    for name in names(&args.regs) {
+        registers.push(parse_register(&name).ok_or_else(|| anyhow::anyhow!("--regs: `{}` is not a register or CSR", name))?);
+    }
+    if let Some(Command::Compare { runs, rows }) = &args.command {
+        if !args.sideband.is_empty() || args.region_only {
+            return Err(anyhow::anyhow!("compare decodes the runs on their own, it cannot take a --sideband or --region-only"));
+        }
+        let runs = runs.iter().map(|run| run.parse()).collect::<Result<Vec<Run>>>()?;
+        let config = DecoderConfig {
+            br_mode: args.br_mode,
+            bp_entries: args.bp_entries,
+            overlays: args.overlays.clone(),
+            process_map: args.process_map.clone(),
+            modules: args.modules.clone(),
+            mtvec,
+            address,
+            format,
+            byte_order,
+            ..Default::default()
+        };
+        return compare(&runs, &config, &unwinder_options, *rows);
+    }
+    let regions = if args.region.is_empty() { vec![DEFAULT_REGION.to_string()] } else { args.region.clone() };
+    let regions = regions.iter().map(|region| region.parse()).collect::<Result<Vec<RegionSpec>>>()?;
+    let region_filter = if args.region_only { Some(RegionFilter::from_elf(&regions, &args.binary)?) } else { None };
+    let mut bus: Bus<Entry> = Bus::new(args.bus_size);
     let mut receivers: Vec<Box<dyn AbstractReceiver>> = vec![];
 
     // add a receiver to the bus for stats output
     if args.to_stats {
-        let encoded_trace_file = File::open(args.encoded_trace.clone())?;
-        // get the file size
-        let file_size = encoded_trace_file.metadata()?.len();
-        // close the file
-        drop(encoded_trace_file);
+        // the payload size is unknown when replaying without the original trace
+        let mut file_size = 0;
+        for encoded_trace in &args.encoded_trace {
+            let encoded_trace_file = File::open(encoded_trace)?;
+            // get the file size
+            file_size += encoded_trace_file.metadata()?.len();
+            // close the file
+            drop(encoded_trace_file);
+        }
+        // the packet framing is only known for tacit traces
+        let breakdown = if format == TraceFormat::Tacit { Some(PacketBreakdown::from_traces(&args.encoded_trace)?) } else { None };
         let stats_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(StatsReceiver::new(stats_bus_endpoint, BrMode::from(args.br_mode), file_size)));
+        receivers.push(boxed(StatsReceiver::new(stats_bus_endpoint, BrMode::from(args.br_mode), file_size, args.binary.clone(), breakdown))?);
     }
     
     // add a receiver to the bus for txt output
     if args.to_txt {
         let txt_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(TxtReceiver::new(txt_bus_endpoint)));
+        let options = TxtOptions { timestamp: args.timestamp, symbols: args.txt_symbols, source: args.txt_source, legacy: args.txt_legacy };
+        receivers.push(boxed(TxtReceiver::new(txt_bus_endpoint, args.binary.clone(), &unwinder_options, options))?);
     }
 
     if args.to_stack_txt {
-        let stack_txt_rx = StackTxtReceiver::new(bus.add_rx(), args.binary.clone());
-        receivers.push(Box::new(stack_txt_rx));
+        let stack_txt_rx = StackTxtReceiver::new(bus.add_rx(), args.binary.clone(), &unwinder_options, args.stack_txt_delta, args.max_depth);
+        receivers.push(boxed(stack_txt_rx)?);
     }
 
     if args.to_atomics {
-        let atomic_rx = AtomicReceiver::new(bus.add_rx(), args.binary.clone());
-        receivers.push(Box::new(atomic_rx));
+        let atomic_rx = AtomicReceiver::new(bus.add_rx(), args.binary.clone(), &unwinder_options);
+        receivers.push(boxed(atomic_rx)?);
     }
 
 
     if args.to_afdo {
         let afdo_bus_endpoint = bus.add_rx();
-        let mut elf_file = File::open(args.binary.clone())?;
-        let mut elf_buffer = Vec::new();
-        elf_file.read_to_end(&mut elf_buffer)?;
-        let elf = object::File::parse(&*elf_buffer)?;
-        receivers.push(Box::new(AfdoReceiver::new(afdo_bus_endpoint, elf.entry().clone())));
-        drop(elf_file);
+        receivers.push(boxed(AfdoReceiver::new(afdo_bus_endpoint, args.binary.clone()))?);
     }
 
     if args.to_gcda {
         let gcda_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(GcdaReceiver::new(gcda_bus_endpoint, args.gcno.clone(), args.binary.clone())));
+        let gcov_version = if args.gcov_version.is_empty() { None } else { Some(args.gcov_version.parse()?) };
+        receivers.push(boxed(GcdaReceiver::new(gcda_bus_endpoint, args.gcno.clone(), args.binary.clone(), gcov_version))?);
     }
 
     if args.to_speedscope {
         let speedscope_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(SpeedscopeReceiver::new(speedscope_bus_endpoint, args.binary.clone())));
+        let boundary = if args.phase_split.is_empty() { None } else { Some(args.phase_split.parse::<PhaseBoundary>()?) };
+        let receiver = SpeedscopeReceiver::new(speedscope_bus_endpoint, args.binary.clone(), &unwinder_options)
+            .and_then(|receiver| match &boundary { Some(boundary) => receiver.with_phase(boundary), None => Ok(receiver) });
+        receivers.push(boxed(receiver)?);
     }
 
     if args.to_perfetto {
         let perfetto_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(PerfettoReceiver::new(perfetto_bus_endpoint, args.binary.clone())));
+        receivers.push(boxed(PerfettoReceiver::new(perfetto_bus_endpoint, args.binary.clone(), &unwinder_options))?);
     }
 
     if args.to_vpp {
         let vpp_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(VPPReceiver::new(vpp_bus_endpoint, args.binary.clone(), args.br_mode == 0)));
+        receivers.push(boxed(VPPReceiver::new(vpp_bus_endpoint, args.binary.clone(), &unwinder_options, args.br_mode == 0, args.vpp_bb_summary, args.vpp_csv))?);
     }
 
     if args.to_foc {
         let foc_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(FOCReceiver::new(foc_bus_endpoint, args.binary.clone())));
+        receivers.push(boxed(FOCReceiver::new(foc_bus_endpoint, args.binary.clone(), &unwinder_options, FocConfig::from_path(&args.foc_config)?))?);
     }
 
     if args.to_vbb {
         let vbb_bus_endpoint = bus.add_rx();
-        receivers.push(Box::new(VBBReceiver::new(vbb_bus_endpoint)));
+        receivers.push(boxed(VBBReceiver::new(vbb_bus_endpoint, args.binary.clone(), args.vbb_anomaly, args.vbb_sort_total))?);
     }
 
-    let frontend_handle = thread::spawn(move || trace_decoder(&args, bus));
-    let receiver_handles: Vec<_> = receivers.into_iter()
-        .map(|mut receiver| thread::spawn(move || receiver.try_receive_loop()))
-        .collect();
+    if args.to_perf {
+        let perf_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(PerfReceiver::new(perf_bus_endpoint, args.binary.clone(), &unwinder_options, args.perf_period))?);
+    }
+
+    if args.to_samples {
+        let rate = if args.sample_period > 0 { SampleRate::Period(args.sample_period) } else { SampleRate::Insns(args.sample_insns.max(1)) };
+        let samples_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(SampleReceiver::new(samples_bus_endpoint, args.binary.clone(), &unwinder_options, rate))?);
+    }
+
+    if args.to_gantt {
+        let gantt_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(GanttReceiver::new(gantt_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.top > 0 {
+        let top_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(TopReceiver::new(top_bus_endpoint, args.binary.clone(), &unwinder_options, args.top))?);
+    }
+
+    if !speedups.is_empty() {
+        let amdahl_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(AmdahlReceiver::new(amdahl_bus_endpoint, args.binary.clone(), &unwinder_options, speedups))?);
+    }
+
+    if args.to_branches {
+        let branches_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(BranchReceiver::new(branches_bus_endpoint, args.binary.clone(), BrMode::from(args.br_mode)))?);
+    }
+
+    if args.to_entropy {
+        let entropy_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(EntropyReceiver::new(entropy_bus_endpoint, args.binary.clone(), args.bp_entries))?);
+    }
+
+    if args.to_markov {
+        let markov_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(MarkovReceiver::new(markov_bus_endpoint, args.binary.clone()))?);
+    }
+
+    if args.to_unreached {
+        let unreached_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(UnreachedReceiver::new(unreached_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_ipc {
+        let ipc_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(IpcReceiver::new(ipc_bus_endpoint, args.binary.clone(), &unwinder_options, args.ipc_window))?);
+    }
+    if args.to_priv {
+        let priv_bus_endpoint = bus.add_rx();
+        let start: Level = args.priv_start.parse()?;
+        receivers.push(boxed(PrivReceiver::new(priv_bus_endpoint, args.binary.clone(), &unwinder_options, args.priv_window, start))?);
+    }
+
+    if args.to_spin {
+        let spin_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(SpinReceiver::new(spin_bus_endpoint, args.binary.clone()))?);
+    }
+
+    if args.hot_paths > 0 {
+        let hotpath_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(HotPathReceiver::new(hotpath_bus_endpoint, args.binary.clone(), &unwinder_options, args.hot_paths))?);
+    }
+
+    if args.to_mem {
+        let mem_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(MemReceiver::new(mem_bus_endpoint, args.binary.clone(), args.mem_log))?);
+    }
+
+    if args.to_data {
+        let data_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(DataReceiver::new(data_bus_endpoint, args.binary.clone()))?);
+    }
+
+    if args.to_sharing {
+        let sharing_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(SharingReceiver::new(sharing_bus_endpoint, args.binary.clone(), &unwinder_options, args.sharing_line, args.sharing_window))?);
+    }
+
+    if args.to_regs {
+        let regs_bus_endpoint = bus.add_rx();
+        let selection = if registers.is_empty() { None } else { Some(registers) };
+        receivers.push(boxed(RegReceiver::new(regs_bus_endpoint, selection))?);
+    }
+
+    if !args.watch.is_empty() {
+        let watches = args.watch.iter().map(|watch| watch.parse()).collect::<Result<Vec<Watch>>>()?;
+        let watch_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(WatchReceiver::new(watch_bus_endpoint, args.binary.clone(), &unwinder_options, &watches))?);
+    }
+
+    if !args.assert.is_empty() {
+        let assert_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(AssertReceiver::new(assert_bus_endpoint, args.binary.clone(), &unwinder_options, AssertSpec::from_file(&args.assert)?))?);
+    }
+
+    if args.to_gmon {
+        let gmon_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(GmonReceiver::new(gmon_bus_endpoint, args.binary.clone(), &unwinder_options, timing_model.clone()))?);
+    }
+
+    if args.to_layout {
+        let layout_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(LayoutReceiver::new(layout_bus_endpoint, args.binary.clone(), &unwinder_options, args.layout_cache_size))?);
+    }
+
+    if args.to_regions {
+        let region_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(RegionReceiver::new(region_bus_endpoint, args.binary.clone(), &regions))?);
+    }
+
+    if args.to_combined {
+        let combined_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(CombinedReceiver::new(combined_bus_endpoint, args.binary.clone(), &unwinder_options, args.combined_window))?);
+    }
+
+    if args.to_fxt {
+        let fxt_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(FxtReceiver::new(fxt_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_gdb {
+        let gdb_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(GdbReceiver::new(gdb_bus_endpoint, args.binary.clone(), &unwinder_options, args.gdb_tracepoints))?);
+    }
+
+    if args.to_annotations {
+        let annotation_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(AnnotationReceiver::new(annotation_bus_endpoint, args.binary.clone(), timing_model.clone()))?);
+    }
+
+    if args.to_heatmap {
+        let heatmap_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(HeatmapReceiver::new(heatmap_bus_endpoint, args.binary.clone(), timing_model.clone()))?);
+    }
+
+    if args.to_tests {
+        let test_bus_endpoint = bus.add_rx();
+        let start: Marker = args.test_start.parse()?;
+        let end: Option<Marker> = if args.test_end.is_empty() { None } else { Some(args.test_end.parse()?) };
+        receivers.push(boxed(TestReceiver::new(test_bus_endpoint, args.binary.clone(), &unwinder_options, &start, end.as_ref(), &args.test_names))?);
+    }
+
+    // add the receivers of the plugin libraries, selected by name
+    let plugin_outputs = names(&args.plugin_outputs);
+    let mut plugin_names = Vec::new();
+    for path in &args.plugin {
+        let library = PluginLibrary::open(path)?;
+        for name in library.names() {
+            if plugin_outputs.is_empty() || plugin_outputs.iter().any(|output| output == name) {
+                let plugin_bus_endpoint = bus.add_rx();
+                receivers.push(boxed(PluginReceiver::new(plugin_bus_endpoint, &library, name))?);
+            }
+            plugin_names.push(name.to_string());
+        }
+    }
+    if let Some(missing) = plugin_outputs.iter().find(|output| !plugin_names.contains(output)) {
+        return Err(anyhow::anyhow!("--plugin-outputs: no plugin has a receiver named `{}`", missing));
+    }
+
+    if args.to_otf2 {
+        #[cfg(feature = "otf2")]
+        {
+            let otf2_bus_endpoint = bus.add_rx();
+            receivers.push(boxed(Otf2Receiver::new(otf2_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+        }
+        #[cfg(not(feature = "otf2"))]
+        return Err(anyhow::anyhow!("--to-otf2 needs the decoder to be built with `--features otf2`"));
+    }
+
+    if args.to_html {
+        let html_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(HtmlReceiver::new(html_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_hotspots {
+        let schema = if args.hotspots_schema.is_empty() { HotspotSchema::default() } else { HotspotSchema::from_file(&args.hotspots_schema)? };
+        let hotspots_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(HotspotsReceiver::new(hotspots_bus_endpoint, args.binary.clone(), &unwinder_options, &args.modules, schema))?);
+    }
+
+    if args.to_vcd {
+        let vcd_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(VcdReceiver::new(vcd_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_spike {
+        let spike_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(SpikeReceiver::new(spike_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_replay {
+        let replay_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(ReplayReceiver::new(replay_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_vector {
+        let vector_bus_endpoint = bus.add_rx();
+        receivers.push(boxed(VectorReceiver::new(vector_bus_endpoint, args.binary.clone(), &unwinder_options))?);
+    }
+
+    if args.to_arrow || args.to_parquet {
+        #[cfg(feature = "columnar")]
+        {
+            if args.to_arrow {
+                let arrow_bus_endpoint = bus.add_rx();
+                receivers.push(boxed(ColumnarReceiver::new(arrow_bus_endpoint, args.binary.clone(), &unwinder_options, ColumnarFormat::ArrowIpc))?);
+            }
+            if args.to_parquet {
+                let parquet_bus_endpoint = bus.add_rx();
+                receivers.push(boxed(ColumnarReceiver::new(parquet_bus_endpoint, args.binary.clone(), &unwinder_options, ColumnarFormat::Parquet))?);
+            }
+        }
+        #[cfg(not(feature = "columnar"))]
+        return Err(anyhow::anyhow!("--to-arrow and --to-parquet need the decoder to be built with `--features columnar`"));
+    }
+
+    if !args.serve.is_empty() {
+        #[cfg(feature = "serve")]
+        {
+            let serve_bus_endpoint = bus.add_rx();
+            receivers.push(boxed(ServeReceiver::new(serve_bus_endpoint, &args.serve))?);
+        }
+        #[cfg(not(feature = "serve"))]
+        return Err(anyhow::anyhow!("--serve needs the decoder to be built with `--features serve`"));
+    }
+
+    if !args.script.is_empty() {
+        #[cfg(feature = "script")]
+        {
+            let script_bus_endpoint = bus.add_rx();
+            receivers.push(boxed(ScriptReceiver::new(script_bus_endpoint, args.binary.clone(), &unwinder_options, &args.script))?);
+        }
+        #[cfg(not(feature = "script"))]
+        return Err(anyhow::anyhow!("--script needs the decoder to be built with `--features script`"));
+    }
+
+    let metadata = TraceMetadata {
+        binary: args.binary.clone(),
+        traces: match &args.command {
+            Some(Command::Replay { replay_trace }) => vec![replay_trace.clone()],
+            _ => args.encoded_trace.clone(),
+        },
+        replay: args.command.is_some(),
+        br_mode: args.br_mode,
+        bp_entries: args.bp_entries,
+    };
+    let profile = args.profile;
+    let diag_json = args.diag_json;
+    let frontend_start = Instant::now();
+    let frontend_handle = thread::spawn(move || match &args.command {
+        Some(Command::Replay { replay_trace }) => replay_decoder(replay_trace, backpressure, region_filter, bus),
+        _ => {
+            let config = DecoderConfig {
+                binary: args.binary.clone(),
+                br_mode: args.br_mode,
+                bp_entries: args.bp_entries,
+                sideband: args.sideband.clone(),
+                backpressure,
+                overlays: args.overlays.clone(),
+                process_map: args.process_map.clone(),
+                modules: args.modules.clone(),
+                mtvec,
+                address,
+                format,
+                byte_order,
+                regions: region_filter,
+                ..Default::default()
+            };
+            match &alignment {
+                Some(alignment) => correlated_decoder(&config, &args.encoded_trace, alignment, bus),
+                None => traces_decoder(&config, &args.encoded_trace, bus),
+            }
+        }
+    });
+    let mut receiver_handles = Vec::new();
+    for mut receiver in receivers {
+        let metadata = metadata.clone();
+        receiver_handles.push(thread::Builder::new().name(receiver.name())
+            .spawn(move || receiver.try_receive_loop(&metadata))?);
+    }
 
     // Handle frontend thread
-    match frontend_handle.join() {
+    let frontend_result = frontend_handle.join();
+    let frontend_time = frontend_start.elapsed();
+    // let the receivers flush what they have, also when the frontend failed
+    let receiver_results = watchdog::join_receivers(receiver_handles);
+    // what went wrong while decoding is worth keeping most when it failed
+    let diag_summary = diagnostics::summary();
+    if !diag_summary.is_empty() {
+        println!("[Diagnostics] {}", diag_summary);
+    }
+    if diag_json {
+        diagnostics::write_json("decode.diag.json", &diag_fatal)?;
+    }
+    match frontend_result {
         Ok(result) => result?,
         Err(e) => {
-            // still join the receivers
-            for handle in receiver_handles {
-                handle.join().unwrap();
-            }
             println!("frontend thread panicked: {:?}", e);
             return Err(anyhow::anyhow!("Frontend thread panicked: {:?}", e));
         }
     }
 
-    // Handle receiver threads
-    for (i, handle) in receiver_handles.into_iter().enumerate() {
-        if let Err(e) = handle.join() {
-            return Err(anyhow::anyhow!("Receiver thread {} panicked: {:?}", i, e));
+    // Handle receiver threads, reporting every failed one
+    let mut failed = 0;
+    for (name, result) in receiver_results {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                println!("[Receiver] {} failed: {:#}", name, e);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("[Receiver] {} panicked: {:?}", name, e);
+                failed += 1;
+            }
         }
     }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} receivers failed, their outputs are incomplete", failed));
+    }
+
+    if profile {
+        print!("{}", metrics::profile_report(frontend_time));
+    }
+
+    let mismatches = metrics::checksum_mismatches();
+    for mismatch in &mismatches {
+        println!("[Checksum] {}", mismatch);
+    }
+    if !mismatches.is_empty() {
+        return Err(anyhow::anyhow!("{} receivers lost or repeated entries", mismatches.len()));
+    }
+
+    let violations = assert_receiver::violations();
+    if violations > 0 {
+        return Err(anyhow::anyhow!("{} trace assertions failed, see trace.assert.json", violations));
+    }
+
+    let fatal: Vec<String> = diagnostics::fatal_occurred(&diag_fatal).iter()
+        .map(|(category, count)| format!("{} {}", count, category))
+        .collect();
+    if !fatal.is_empty() {
+        return Err(anyhow::anyhow!("fatal decode diagnostics: {}", fatal.join(", ")));
+    }
 
     Ok(())
 }
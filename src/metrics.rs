@@ -0,0 +1,302 @@
+// Prometheus metrics of the running decode, served as plain text over http
+// with `--metrics addr:port`. The counters are process-wide and always
+// updated, so a scrape simply reads them; rates (packets/s, insns/s) are
+// left to the prometheus side.
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::backend::event::Event;
+use crate::frontend::f_header::FHeader;
+use crate::frontend::packet::Packet;
+
+const F_HEADERS: [FHeader; 8] = [FHeader::FTb, FHeader::FNt, FHeader::FUj, FHeader::FIj,
+                                 FHeader::FTrap, FHeader::FSync, FHeader::FVal, FHeader::FCheck];
+
+pub struct Metrics {
+    // [f_header][is_compressed]
+    packets: [[AtomicU64; 2]; 8],
+    // entries put on the bus, including the instructions
+    entries: AtomicU64,
+    insns: AtomicU64,
+    // the frontend hit an instruction not matching the packet
+    panics: AtomicU64,
+    // the packet stream ended without a sync packet
+    truncated: AtomicU64,
+    // check packets that failed, each dropping the packets it covers
+    corrupted: AtomicU64,
+    // time the frontend was blocked on a full bus
+    stall_ns: AtomicU64,
+    // entries the backpressure policy dropped or spilled to disk
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+    receivers: Mutex<Vec<Arc<ReceiverMetrics>>>,
+}
+
+// progress and timing of one receiver thread
+pub struct ReceiverMetrics {
+    pub name: String,
+    // entries taken off the bus
+    pub entries: AtomicU64,
+    // time spent processing entries, flushing included
+    pub busy_ns: AtomicU64,
+    // time spent waiting on an empty bus
+    pub idle_ns: AtomicU64,
+    // largest backlog seen, in entries
+    pub max_lag: AtomicU64,
+    // the receiver's own count of the entries it processed, once finished
+    pub checksum: AtomicU64,
+    // the receive loop returned, or unwound if panicked is set too
+    pub finished: AtomicBool,
+    pub panicked: AtomicBool,
+}
+
+impl ReceiverMetrics {
+    pub fn add_busy(&self, elapsed: Duration) {
+        self.busy_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_idle(&self, elapsed: Duration) {
+        self.idle_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn lag(&self) -> u64 {
+        METRICS.entries.load(Ordering::Relaxed).saturating_sub(self.entries.load(Ordering::Relaxed))
+    }
+
+    // cheap enough to call every few thousand entries
+    pub fn sample_lag(&self) {
+        self.max_lag.fetch_max(self.lag(), Ordering::Relaxed);
+    }
+}
+
+pub static METRICS: Metrics = Metrics {
+    packets: [const { [const { AtomicU64::new(0) }; 2] }; 8],
+    entries: AtomicU64::new(0),
+    insns: AtomicU64::new(0),
+    panics: AtomicU64::new(0),
+    truncated: AtomicU64::new(0),
+    corrupted: AtomicU64::new(0),
+    stall_ns: AtomicU64::new(0),
+    dropped: AtomicU64::new(0),
+    spilled: AtomicU64::new(0),
+    receivers: Mutex::new(Vec::new()),
+};
+
+pub fn record_packet(packet: &Packet) {
+    METRICS.packets[packet.f_header.clone() as usize][packet.is_compressed as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_entry(event: &Event) {
+    METRICS.entries.fetch_add(1, Ordering::Relaxed);
+    match event {
+        Event::None => { METRICS.insns.fetch_add(1, Ordering::Relaxed); }
+        Event::Panic => { METRICS.panics.fetch_add(1, Ordering::Relaxed); }
+        _ => {}
+    }
+}
+
+pub fn record_truncated() {
+    METRICS.truncated.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_corrupted() {
+    METRICS.corrupted.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_dropped() {
+    METRICS.dropped.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_spilled() {
+    METRICS.spilled.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_stall(elapsed: Duration) {
+    METRICS.stall_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+// start the counters over and forget the receivers, for a run repeated in
+// the same process (--repeat-check)
+pub fn reset() {
+    for counts in &METRICS.packets {
+        for count in counts {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+    for counter in [&METRICS.entries, &METRICS.insns, &METRICS.panics, &METRICS.truncated, &METRICS.corrupted,
+                    &METRICS.stall_ns, &METRICS.dropped, &METRICS.spilled] {
+        counter.store(0, Ordering::Relaxed);
+    }
+    METRICS.receivers.lock().unwrap().clear();
+}
+
+// counters the receiver loop updates, same-typed receivers get a `#N` suffix
+pub fn register_receiver(name: &str) -> Arc<ReceiverMetrics> {
+    let mut receivers = METRICS.receivers.lock().unwrap();
+    let taken = receivers.iter().filter(|r| r.name.split('#').next() == Some(name)).count();
+    let name = if taken == 0 { name.to_string() } else { format!("{}#{}", name, taken + 1) };
+    let receiver = Arc::new(ReceiverMetrics {
+        name,
+        entries: AtomicU64::new(0),
+        busy_ns: AtomicU64::new(0),
+        idle_ns: AtomicU64::new(0),
+        max_lag: AtomicU64::new(0),
+        checksum: AtomicU64::new(0),
+        finished: AtomicBool::new(false),
+        panicked: AtomicBool::new(false),
+    });
+    receivers.push(receiver.clone());
+    receiver
+}
+
+// reads one receiver's value of a per-receiver metric
+type ReceiverValue = fn(&ReceiverMetrics) -> String;
+
+pub fn receivers() -> Vec<Arc<ReceiverMetrics>> {
+    METRICS.receivers.lock().unwrap().clone()
+}
+
+fn seconds(ns: &AtomicU64) -> f64 {
+    ns.load(Ordering::Relaxed) as f64 / 1e9
+}
+
+// text exposition format, version 0.0.4
+pub fn render() -> String {
+    let mut out = String::new();
+    let entries = METRICS.entries.load(Ordering::Relaxed);
+    out.push_str("# HELP tacit_packets_total Trace packets decoded, by packet type.\n");
+    out.push_str("# TYPE tacit_packets_total counter\n");
+    for (f_header, counts) in F_HEADERS.iter().zip(METRICS.packets.iter()) {
+        for (compressed, count) in counts.iter().enumerate() {
+            let _ = writeln!(out, "tacit_packets_total{{type=\"{:?}\",compressed=\"{}\"}} {}",
+                             f_header, compressed == 1, count.load(Ordering::Relaxed));
+        }
+    }
+    out.push_str("# HELP tacit_instructions_total Instructions retired by the decoded trace.\n");
+    out.push_str("# TYPE tacit_instructions_total counter\n");
+    let _ = writeln!(out, "tacit_instructions_total {}", METRICS.insns.load(Ordering::Relaxed));
+    out.push_str("# HELP tacit_entries_total Entries broadcast to the receivers.\n");
+    out.push_str("# TYPE tacit_entries_total counter\n");
+    let _ = writeln!(out, "tacit_entries_total {}", entries);
+    out.push_str("# HELP tacit_decode_errors_total Decode errors, by kind.\n");
+    out.push_str("# TYPE tacit_decode_errors_total counter\n");
+    let _ = writeln!(out, "tacit_decode_errors_total{{kind=\"panic\"}} {}", METRICS.panics.load(Ordering::Relaxed));
+    let _ = writeln!(out, "tacit_decode_errors_total{{kind=\"truncated\"}} {}", METRICS.truncated.load(Ordering::Relaxed));
+    let _ = writeln!(out, "tacit_decode_errors_total{{kind=\"corrupted\"}} {}", METRICS.corrupted.load(Ordering::Relaxed));
+    out.push_str("# HELP tacit_frontend_stall_seconds_total Time the frontend waited on a full bus.\n");
+    out.push_str("# TYPE tacit_frontend_stall_seconds_total counter\n");
+    let _ = writeln!(out, "tacit_frontend_stall_seconds_total {}", seconds(&METRICS.stall_ns));
+    out.push_str("# HELP tacit_entries_dropped_total Entries dropped on a full bus (--backpressure drop).\n");
+    out.push_str("# TYPE tacit_entries_dropped_total counter\n");
+    let _ = writeln!(out, "tacit_entries_dropped_total {}", METRICS.dropped.load(Ordering::Relaxed));
+    out.push_str("# HELP tacit_entries_spilled_total Entries queued on disk on a full bus (--backpressure spill).\n");
+    out.push_str("# TYPE tacit_entries_spilled_total counter\n");
+    let _ = writeln!(out, "tacit_entries_spilled_total {}", METRICS.spilled.load(Ordering::Relaxed));
+    let receivers = METRICS.receivers.lock().unwrap();
+    let per_receiver: [(&str, &str, &str, ReceiverValue); 5] = [
+        ("tacit_receiver_entries_total", "counter", "Entries processed by each receiver.",
+         |r| r.entries.load(Ordering::Relaxed).to_string()),
+        ("tacit_receiver_lag_entries", "gauge", "Entries broadcast but not yet processed by each receiver.",
+         |r| r.lag().to_string()),
+        ("tacit_receiver_max_lag_entries", "gauge", "Largest backlog seen by each receiver.",
+         |r| r.max_lag.load(Ordering::Relaxed).to_string()),
+        ("tacit_receiver_busy_seconds_total", "counter", "Time each receiver spent processing entries.",
+         |r| seconds(&r.busy_ns).to_string()),
+        ("tacit_receiver_idle_seconds_total", "counter", "Time each receiver spent waiting on an empty bus.",
+         |r| seconds(&r.idle_ns).to_string()),
+    ];
+    for (metric, kind, help, value) in per_receiver {
+        let _ = writeln!(out, "# HELP {} {}", metric, help);
+        let _ = writeln!(out, "# TYPE {} {}", metric, kind);
+        for receiver in receivers.iter() {
+            let _ = writeln!(out, "{}{{receiver=\"{}\"}} {}", metric, receiver.name, value(receiver));
+        }
+    }
+    out
+}
+
+// the receivers that finished having processed a different number of
+// entries than the frontend broadcast, i.e. that lost or repeated some
+pub fn checksum_mismatches() -> Vec<String> {
+    let broadcast = METRICS.entries.load(Ordering::Relaxed);
+    let receivers = METRICS.receivers.lock().unwrap();
+    receivers.iter()
+        .filter(|r| r.finished.load(Ordering::Relaxed) && !r.panicked.load(Ordering::Relaxed))
+        .filter_map(|r| {
+            let checksum = r.checksum.load(Ordering::Relaxed);
+            (checksum != broadcast).then(|| format!("{} processed {} entries, the frontend broadcast {}", r.name, checksum, broadcast))
+        })
+        .collect()
+}
+
+// end of run summary of where the time went, `frontend` is the wall time of
+// the frontend thread
+pub fn profile_report(frontend: Duration) -> String {
+    let mut out = String::new();
+    let stall = seconds(&METRICS.stall_ns);
+    let _ = writeln!(out, "frontend: {:.3}s, {:.3}s ({:.1}%) blocked on a full bus, {} entries",
+                     frontend.as_secs_f64(), stall, stall / frontend.as_secs_f64().max(1e-9) * 100.0,
+                     METRICS.entries.load(Ordering::Relaxed));
+    let dropped = METRICS.dropped.load(Ordering::Relaxed);
+    let spilled = METRICS.spilled.load(Ordering::Relaxed);
+    if dropped > 0 || spilled > 0 {
+        let _ = writeln!(out, "backpressure: {} entries dropped, {} spilled to disk", dropped, spilled);
+    }
+    let receivers = METRICS.receivers.lock().unwrap();
+    let _ = writeln!(out, "{:<24} {:>12} {:>10} {:>10} {:>12} {:>10}", "receiver", "entries", "busy (s)", "idle (s)", "max backlog", "ns/entry");
+    for receiver in receivers.iter() {
+        let entries = receiver.entries.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{:<24} {:>12} {:>10.3} {:>10.3} {:>12} {:>10.1}", receiver.name, entries,
+                         seconds(&receiver.busy_ns), seconds(&receiver.idle_ns), receiver.max_lag.load(Ordering::Relaxed),
+                         receiver.busy_ns.load(Ordering::Relaxed) as f64 / entries.max(1) as f64);
+    }
+    // the frontend only blocks when the slowest receiver lets the bus fill up
+    if let Some(slowest) = receivers.iter().max_by_key(|r| r.busy_ns.load(Ordering::Relaxed)) {
+        if stall > 0.1 * frontend.as_secs_f64() {
+            let _ = writeln!(out, "bottleneck: {} (the frontend spent {:.1}% of its time waiting for it)",
+                             slowest.name, stall / frontend.as_secs_f64() * 100.0);
+        } else {
+            let _ = writeln!(out, "bottleneck: frontend (the receivers kept up with the bus)");
+        }
+    }
+    out
+}
+
+fn respond(stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // drain the headers, the request itself does not matter
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let body = render();
+    let mut stream = stream;
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           body.len(), body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+// serve the metrics on addr for the rest of the process
+pub fn serve(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("serving metrics on http://{}/metrics", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                warn!("metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// `--repeat-check`: the trace is decoded twice in the same process and the
+// files both runs wrote to the working directory must be byte-identical.
+// Threads and hash map iteration order (a new random seed per map) differ
+// between the two runs, so an output that depends on either shows up as a
+// difference.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+// which messages are sampled depends on how the receiver threads interleave
+const IGNORED: [&str; 1] = ["decode.diag.json"];
+// file timestamps come from a coarser clock, they can lag behind by a tick
+const CLOCK_SLACK: Duration = Duration::from_millis(50);
+
+// (size, hash) of an output
+pub type Digest = (u64, u64);
+
+fn digest(path: &std::path::Path) -> Result<Digest> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n]);
+        size += n as u64;
+    }
+    Ok((size, hasher.finish()))
+}
+
+// the files of the working directory modified since `since`, by name
+pub fn output_digests(since: SystemTime) -> Result<BTreeMap<String, Digest>> {
+    let since = since.checked_sub(CLOCK_SLACK).unwrap_or(since);
+    let mut digests = BTreeMap::new();
+    for dir_entry in fs::read_dir(".")? {
+        let dir_entry = dir_entry?;
+        let metadata = dir_entry.metadata()?;
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if !metadata.is_file() || metadata.modified()? < since || IGNORED.contains(&name.as_str()) {
+            continue;
+        }
+        digests.insert(name, digest(&dir_entry.path())?);
+    }
+    Ok(digests)
+}
+
+// what differs between the outputs of the two runs, one line per file
+pub fn differences(first: &BTreeMap<String, Digest>, second: &BTreeMap<String, Digest>) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (name, &(size, hash)) in second {
+        match first.get(name) {
+            None => differences.push(format!("{} was only written by the second run", name)),
+            Some(&(first_size, _)) if first_size != size => {
+                differences.push(format!("{} is {} bytes, {} bytes the first time", name, size, first_size))
+            }
+            Some(&(_, first_hash)) if first_hash != hash => differences.push(format!("{} has different contents", name)),
+            Some(_) => {}
+        }
+    }
+    for name in first.keys().filter(|name| !second.contains_key(*name)) {
+        differences.push(format!("{} was only written by the first run", name));
+    }
+    differences
+}
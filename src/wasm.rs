@@ -0,0 +1,85 @@
+// Browser entry points for decoding a dropped trace + ELF client-side,
+// built with `wasm-pack build --target web`. There are no threads on
+// wasm32, so instead of the bus the decoder feeds its sink directly.
+use wasm_bindgen::prelude::*;
+use bus::Bus;
+use serde_json::{json, Value};
+
+use crate::backend::abstract_receiver::AbstractReceiver;
+use crate::backend::event::{Entry, Event};
+use crate::backend::speedscope_receiver::SpeedscopeReceiver;
+use crate::backend::stack_unwinder::{StackUnwinder, UnwinderOptions};
+use crate::frontend::context::Context;
+use crate::frontend::decoder::{decode, DecoderConfig, EntrySink};
+
+// collects the control-flow events, skipping the per-instruction entries
+struct EventCollector {
+    events: Vec<Value>,
+}
+
+impl EntrySink for EventCollector {
+    fn broadcast(&mut self, entry: Entry) {
+        if entry.event != Event::None {
+            self.events.push(json!({
+                "event": entry.event.to_string(),
+                "from": entry.arc.0,
+                "to": entry.arc.1,
+                "timestamp": entry.timestamp,
+            }));
+        }
+    }
+}
+
+// drives a receiver on the decoding thread, the first error it returns
+// stops it and is kept for the caller
+struct ReceiverSink<'a, R: AbstractReceiver> {
+    receiver: &'a mut R,
+    error: Option<anyhow::Error>,
+}
+
+impl<R: AbstractReceiver> EntrySink for ReceiverSink<'_, R> {
+    fn broadcast(&mut self, entry: Entry) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.receiver._receive_entry(entry) {
+            Ok(()) => self.receiver._bump_checksum(),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+fn config(br_mode: u32, bp_entries: u32) -> DecoderConfig {
+    DecoderConfig { br_mode: br_mode as u64, bp_entries: bp_entries as u64, ..Default::default() }
+}
+
+fn js_error(e: anyhow::Error) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// Decodes `trace` against `elf` and returns the control-flow events as a
+/// json array of `{event, from, to, timestamp}`.
+#[wasm_bindgen]
+pub fn decode_to_json(elf: &[u8], trace: &[u8], br_mode: u32, bp_entries: u32) -> Result<String, JsError> {
+    let mut collector = EventCollector { events: Vec::new() };
+    let mut trace = trace;
+    decode(elf, &mut trace, None, &config(br_mode, bp_entries), &mut collector).map_err(js_error)?;
+    Ok(Value::Array(collector.events).to_string())
+}
+
+/// Decodes `trace` against `elf` and returns a speedscope profile.
+#[wasm_bindgen]
+pub fn decode_to_speedscope(elf: &[u8], trace: &[u8], br_mode: u32, bp_entries: u32) -> Result<String, JsError> {
+    let unwinder = StackUnwinder::from_elf_bytes(elf, &UnwinderOptions::default()).map_err(js_error)?;
+    // the receiver wants a bus endpoint, nothing is ever sent on it
+    let mut bus: Bus<Entry> = Bus::new(1);
+    let mut receiver = SpeedscopeReceiver::with_unwinder(bus.add_rx(), unwinder);
+    let mut trace = trace;
+    let mut sink = ReceiverSink { receiver: &mut receiver, error: None };
+    decode(elf, &mut trace, None, &config(br_mode, bp_entries), &mut sink).map_err(js_error)?;
+    if let Some(e) = sink.error {
+        return Err(js_error(e));
+    }
+    receiver.close_all_frames();
+    Ok(receiver.profile_json(Context::default()))
+}
@@ -0,0 +1,75 @@
+// Detects receivers that panicked or stopped making progress while entries
+// are waiting for them. Instead of letting the frontend block on the bus
+// forever, the run is aborted: the frontend stops, the healthy receivers
+// drain the bus and flush their partial outputs, and the stuck ones are left
+// behind.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use crate::metrics;
+
+static ABORTED: AtomicBool = AtomicBool::new(false);
+// receivers given up on, their threads are never joined
+static STUCK: AtomicUsize = AtomicUsize::new(0);
+
+const POLL: Duration = Duration::from_millis(100);
+
+pub fn aborted() -> bool {
+    ABORTED.load(Ordering::Relaxed)
+}
+
+fn abort(reason: String) {
+    error!("[watchdog] {}, aborting", reason);
+    eprintln!("[Watchdog] {}, aborting with partial outputs", reason);
+    ABORTED.store(true, Ordering::Relaxed);
+}
+
+// watch the registered receivers, a receiver is stuck when it has entries
+// waiting but took none of them for `timeout`
+pub fn spawn(timeout: Duration) {
+    thread::spawn(move || {
+        // entry count and when it last changed, per receiver
+        let mut progress: HashMap<String, (u64, Instant)> = HashMap::new();
+        while !aborted() {
+            thread::sleep(POLL);
+            for receiver in metrics::receivers() {
+                if receiver.finished.load(Ordering::Relaxed) {
+                    if receiver.panicked.load(Ordering::Relaxed) {
+                        abort(format!("receiver {} panicked", receiver.name));
+                    }
+                    continue;
+                }
+                let entries = receiver.entries.load(Ordering::Relaxed);
+                let (last_entries, since) = progress.entry(receiver.name.clone()).or_insert((entries, Instant::now()));
+                if entries != *last_entries || receiver.lag() == 0 {
+                    *last_entries = entries;
+                    *since = Instant::now();
+                } else if since.elapsed() >= timeout {
+                    STUCK.fetch_add(1, Ordering::Relaxed);
+                    abort(format!("receiver {} made no progress for {}s with {} entries waiting",
+                                  receiver.name, timeout.as_secs(), receiver.lag()));
+                }
+            }
+        }
+    });
+}
+
+// join the receiver threads, except the ones the watchdog gave up on, each
+// result with the name of its thread
+pub fn join_receivers<T>(handles: Vec<JoinHandle<T>>) -> Vec<(String, thread::Result<T>)> {
+    loop {
+        let running = handles.iter().filter(|handle| !handle.is_finished()).count();
+        if running == 0 || (aborted() && running <= STUCK.load(Ordering::Relaxed)) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    handles.into_iter()
+        .filter(|handle| handle.is_finished())
+        .map(|handle| (handle.thread().name().unwrap_or("?").to_string(), handle.join()))
+        .collect()
+}
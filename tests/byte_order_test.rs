@@ -0,0 +1,78 @@
+use std::io::Read;
+
+use anyhow::Result;
+
+use ltrace_decoder::diagnostics::{self, Category};
+use ltrace_decoder::frontend::byte_order::{ByteOrder, LinkReader};
+
+const ORDERS: [&str; 6] = ["little", "big16", "big32", "little-bitrev", "big16-bitrev", "big32-bitrev"];
+
+// what a link of the given byte order delivers for a stream
+fn to_link(stream: &[u8], order: ByteOrder) -> Vec<u8> {
+    stream.chunks(order.word).flat_map(|word| {
+        word.iter().rev().map(|&byte| if order.bit_reversed { byte.reverse_bits() } else { byte }).collect::<Vec<_>>()
+    }).collect()
+}
+
+// read a byte at a time, words are handed out across calls
+fn read_bytewise<R: Read>(mut reader: R) -> Result<Vec<u8>> {
+    let mut stream = Vec::new();
+    let mut byte = [0u8; 1];
+    while reader.read(&mut byte)? == 1 {
+        stream.push(byte[0]);
+    }
+    Ok(stream)
+}
+
+#[test]
+fn test_parse_orders() -> Result<()> {
+    assert!(ORDERS[0].parse::<ByteOrder>()?.is_native());
+    assert_eq!("big16".parse::<ByteOrder>()?, ByteOrder { word: 2, bit_reversed: false });
+    assert_eq!("big32-bitrev".parse::<ByteOrder>()?, ByteOrder { word: 4, bit_reversed: true });
+    assert!("big64".parse::<ByteOrder>().is_err());
+    assert!("bitrev".parse::<ByteOrder>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_round_trip() -> Result<()> {
+    let stream: Vec<u8> = (0..24).map(|i| (i * 37 + 1) as u8).collect();
+    for name in ORDERS {
+        let order: ByteOrder = name.parse()?;
+        let link = to_link(&stream, order);
+        if !order.is_native() {
+            assert_ne!(link, stream, "{}", name);
+        }
+        let mut read = Vec::new();
+        LinkReader::new(&link[..], order).read_to_end(&mut read)?;
+        assert_eq!(read, stream, "{}", name);
+        assert_eq!(read_bytewise(LinkReader::new(&link[..], order))?, stream, "{} a byte at a time", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_word_swap() -> Result<()> {
+    let link = [0x11, 0x22, 0x33, 0x44];
+    let mut read = Vec::new();
+    LinkReader::new(&link[..], "big16".parse()?).read_to_end(&mut read)?;
+    assert_eq!(read, [0x22, 0x11, 0x44, 0x33]);
+    read.clear();
+    LinkReader::new(&link[..], "big32".parse()?).read_to_end(&mut read)?;
+    assert_eq!(read, [0x44, 0x33, 0x22, 0x11]);
+    read.clear();
+    LinkReader::new(&link[..], "little-bitrev".parse()?).read_to_end(&mut read)?;
+    assert_eq!(read, [0x88, 0x44, 0xcc, 0x22]);
+    Ok(())
+}
+
+#[test]
+fn test_trailing_partial_word() -> Result<()> {
+    let before = diagnostics::count(Category::TruncatedPacket);
+    let link = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+    let mut read = Vec::new();
+    LinkReader::new(&link[..], "big32".parse()?).read_to_end(&mut read)?;
+    assert_eq!(read, [0x44, 0x33, 0x22, 0x11]);
+    assert!(diagnostics::count(Category::TruncatedPacket) > before);
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Fixtures of the decoding tests: RV64 programs linked into an ELF and the
+// tacit packets to trace them with.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use anyhow::Result;
+use object::write::{Object, Symbol, SymbolSection};
+use object::{Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+
+use ltrace_decoder::backend::event::Entry;
+use ltrace_decoder::frontend::decoder::EntrySink;
+use tacit_wire::write_varint;
+
+pub const ADDI_A0: u32 = 0x0015_0513;
+pub const ADDI_T0: u32 = 0x0012_8293;
+pub const J_SELF: u32 = 0x0000_006f;
+pub const J_PLUS_12: u32 = 0x00c0_006f;
+pub const MRET: u32 = 0x3020_0073;
+pub const SRET: u32 = 0x1020_0073;
+pub const URET: u32 = 0x0020_0073;
+
+// 0x00-0x0c: straight-line code, 0x10: end, 0x14: handler
+pub const STRAIGHT: [u32; 7] = [ADDI_A0, ADDI_A0, ADDI_A0, ADDI_A0, J_SELF, ADDI_T0, MRET];
+
+pub const F_TRAP: u8 = 0b100;
+pub const F_SYNC: u8 = 0b101;
+pub const T_EXCEPTION: u8 = 0b001;
+pub const T_INTERRUPT: u8 = 0b010;
+pub const T_RETURN: u8 = 0b100;
+
+pub struct CollectSink(pub Vec<Entry>);
+
+impl EntrySink for CollectSink {
+    fn broadcast(&mut self, entry: Entry) {
+        self.0.push(entry);
+    }
+}
+
+// the program as the .text of `main`, from address 0
+pub fn build_elf(program: &[u32]) -> Vec<u8> {
+    let mut obj = Object::new(BinaryFormat::Elf, Architecture::Riscv64, Endianness::Little);
+    let text = obj.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+    let code: Vec<u8> = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+    obj.append_section_data(text, &code, 4);
+    obj.add_symbol(Symbol {
+        name: b"main".to_vec(),
+        value: 0,
+        size: code.len() as u64,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(text),
+        flags: SymbolFlags::None,
+    });
+    obj.write().unwrap()
+}
+
+pub fn packet(trace: &mut Vec<u8>, first_byte: u8, varints: &[u64]) {
+    trace.push(first_byte);
+    for &value in varints {
+        write_varint(trace, value);
+    }
+}
+
+// a directory of its own to run the decoder in, removed when dropped
+pub struct Workdir(pub PathBuf);
+
+impl Workdir {
+    pub fn new(name: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Workdir(dir))
+    }
+
+    // returns the path written to
+    pub fn write(&self, file: &str, contents: &[u8]) -> Result<PathBuf> {
+        let path = self.0.join(file);
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    pub fn read(&self, file: &str) -> Result<String> {
+        Ok(std::fs::read_to_string(self.0.join(file))?)
+    }
+
+    // the decoder with `args`, the outputs go to the directory
+    pub fn run(&self, args: &[&str]) -> Result<Output> {
+        Ok(Command::new(env!("CARGO_BIN_EXE_ltrace-decoder")).current_dir(&self.0).args(args).output()?)
+    }
+}
+
+impl Drop for Workdir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
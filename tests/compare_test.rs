@@ -0,0 +1,56 @@
+use anyhow::Result;
+
+use ltrace_decoder::backend::compare::{geomean_ratio, ratio, write_csv, Run, RunProfile};
+
+fn profile(label: &str, functions: &[(&str, u64, u64)]) -> RunProfile {
+    RunProfile {
+        label: label.to_string(),
+        functions: functions.iter().map(|&(name, cycles, calls)| (name.to_string(), (cycles, calls))).collect(),
+        total: functions.iter().map(|&(_, cycles, _)| cycles).sum(),
+    }
+}
+
+#[test]
+fn test_run_spec() -> Result<()> {
+    assert_eq!("O2=build/o2.elf:a.trace,b.trace".parse::<Run>()?, Run {
+        label: "O2".to_string(),
+        binary: "build/o2.elf".to_string(),
+        traces: vec!["a.trace".to_string(), "b.trace".to_string()],
+    });
+    assert_eq!("o3.elf:runs/ss_corner.tacit".parse::<Run>()?.label, "ss_corner");
+    assert!("o3.elf".parse::<Run>().is_err());
+    assert!("o3.elf:".parse::<Run>().is_err());
+    assert!("=o3.elf:a.trace".parse::<Run>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ratios() {
+    let base = profile("base", &[("main", 100, 1), ("memcpy", 400, 20), ("init", 50, 1)]);
+    let run = profile("run", &[("main", 200, 1), ("memcpy", 200, 20), ("memcpy_vec", 30, 20)]);
+    assert_eq!(ratio(&base, &run, "main"), Some(2.0));
+    assert_eq!(ratio(&base, &run, "init"), None);
+    assert_eq!(ratio(&base, &run, "memcpy_vec"), None);
+    // 2.0 and 0.5, the functions in only one run left out
+    assert!((geomean_ratio(&base, &run).unwrap() - 1.0).abs() < 1e-9);
+    assert_eq!(geomean_ratio(&base, &profile("empty", &[])), None);
+}
+
+#[test]
+fn test_write_csv() -> Result<()> {
+    let base = profile("base", &[("main", 100, 1), ("memcpy", 400, 20)]);
+    let run = profile("fast", &[("main", 50, 1), ("memcpy", 100, 20), ("memset", 10, 2)]);
+    let path = std::env::temp_dir().join(format!("tacit-compare-{}.csv", std::process::id()));
+    write_csv(&[base, run], path.to_str().unwrap())?;
+    let csv = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(csv.lines().collect::<Vec<_>>(), vec![
+        "function,base cycles,base calls,fast cycles,fast calls,fast ratio",
+        "memcpy,400,20,100,20,0.2500",
+        "main,100,1,50,1,0.5000",
+        "memset,0,0,10,2,",
+        "total,500,,160,,0.3200",
+        "geomean,,,,,0.3536",
+    ]);
+    Ok(())
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use anyhow::Result;
+
+use common::*;
+use ltrace_decoder::backend::event::{Entry, Event, SOURCE_ATTR};
+use ltrace_decoder::frontend::address::{AddressScheme, Compression};
+use ltrace_decoder::frontend::correlate::{correlate, Alignment};
+use ltrace_decoder::frontend::decoder::DecoderConfig;
+
+// an interrupt at 0x08 taken `taken` cycles after the start at `start`,
+// returning `handled` cycles later, the trace ending `rest` cycles after
+fn interrupted_trace(start: u64, taken: u64, handled: u64, rest: u64) -> Vec<u8> {
+    let mut trace = Vec::new();
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x00, start]);
+    packet(&mut trace, T_INTERRUPT << 5 | F_TRAP << 2 | 0b10, &[0x08, 0x14, taken]);
+    packet(&mut trace, T_RETURN << 5 | F_TRAP << 2 | 0b10, &[0x18, 0x08, handled]);
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x10, start + taken + handled + rest]);
+    trace
+}
+
+// the traces written out, decoded side by side
+fn correlate_traces(name: &str, traces: &[Vec<u8>], alignment: &Alignment) -> Result<Vec<Entry>> {
+    let dir = std::env::temp_dir().join(format!("tacit-correlate-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let mut paths = Vec::new();
+    for (i, trace) in traces.iter().enumerate() {
+        let path = dir.join(format!("hart{}.tacit", i));
+        std::fs::write(&path, trace)?;
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    let config = DecoderConfig {
+        br_mode: 0,
+        address: AddressScheme { shift: 0, compression: Compression::Absolute },
+        ..DecoderConfig::default()
+    };
+    let mut sink = CollectSink(Vec::new());
+    let result = correlate(&build_elf(&STRAIGHT), &config, &paths, alignment, &mut sink);
+    std::fs::remove_dir_all(&dir)?;
+    result?;
+    Ok(sink.0)
+}
+
+// the timed entries and the context switches, as (event, hart switched to
+// or 0, timestamp)
+fn timeline(entries: &[Entry]) -> Vec<(Event, u64, u64)> {
+    entries.iter()
+        .filter(|entry| entry.event != Event::None)
+        .map(|entry| (entry.event.clone(), entry.ctx.map_or(0, |ctx| ctx.hart), entry.timestamp.unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_correlate_sync() -> Result<()> {
+    let traces = [interrupted_trace(100, 10, 10, 10), interrupted_trace(1000, 5, 20, 3)];
+    let entries = correlate_traces("sync", &traces, &Alignment::Sync)?;
+    // the second trace starts at 100 as well, one Start and one End for both
+    assert_eq!(timeline(&entries), vec![
+        (Event::Start, 0, 100),
+        (Event::ContextSwitch, 1, 105),
+        (Event::TrapInterrupt, 0, 105),
+        (Event::ContextSwitch, 0, 110),
+        (Event::TrapInterrupt, 0, 110),
+        (Event::TrapReturn, 0, 120),
+        (Event::ContextSwitch, 1, 125),
+        (Event::TrapReturn, 0, 125),
+        (Event::ContextSwitch, 0, 130),
+        (Event::End, 0, 130),
+    ]);
+    let sources: Vec<String> = entries.iter()
+        .filter_map(|entry| entry.attrs.get(SOURCE_ATTR))
+        .map(|source| source.to_string())
+        .collect();
+    assert!(sources[0].ends_with("hart1.tacit") && sources[1].ends_with("hart0.tacit"));
+    // every instruction of each trace is there, in the order of its trace
+    let hart_insns = |hart: u64| -> Vec<u64> {
+        let mut current = 0;
+        entries.iter().filter_map(|entry| {
+            if let Some(ctx) = entry.ctx {
+                current = ctx.hart;
+            }
+            (entry.event == Event::None && current == hart).then_some(entry.arc.0)
+        }).collect()
+    };
+    let expected = vec![0x00, 0x04, 0x14, 0x18, 0x08, 0x0c, 0x10];
+    assert_eq!(hart_insns(0), expected);
+    assert_eq!(hart_insns(1), expected);
+    Ok(())
+}
+
+#[test]
+fn test_correlate_offsets() -> Result<()> {
+    let traces = [interrupted_trace(100, 10, 10, 10), interrupted_trace(1000, 5, 20, 3)];
+    let entries = correlate_traces("offsets", &traces, &Alignment::Offsets(vec![0, -890]))?;
+    let traps: Vec<(Event, u64, u64)> = timeline(&entries).into_iter()
+        .filter(|(event, _, _)| *event != Event::Start)
+        .collect();
+    // the second trace ends last, its End closes the timeline
+    assert_eq!(traps, vec![
+        (Event::ContextSwitch, 0, 110),
+        (Event::TrapInterrupt, 0, 110),
+        (Event::ContextSwitch, 1, 115),
+        (Event::TrapInterrupt, 0, 115),
+        (Event::ContextSwitch, 0, 120),
+        (Event::TrapReturn, 0, 120),
+        (Event::ContextSwitch, 1, 135),
+        (Event::TrapReturn, 0, 135),
+        (Event::End, 0, 138),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_alignment() -> Result<()> {
+    assert_eq!("sync".parse::<Alignment>()?, Alignment::Sync);
+    assert_eq!("0, -1200".parse::<Alignment>()?, Alignment::Offsets(vec![0, -1200]));
+    assert!("later".parse::<Alignment>().is_err());
+    let traces = [interrupted_trace(100, 10, 10, 10), interrupted_trace(1000, 5, 20, 3)];
+    assert!(correlate_traces("count", &traces, &Alignment::Offsets(vec![0])).is_err());
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+
+use ltrace_decoder::frontend::etrace::ETraceSource;
+use ltrace_decoder::frontend::f_header::FHeader;
+use ltrace_decoder::frontend::packet::Packet;
+use ltrace_decoder::frontend::source::PacketSource;
+
+const FORMAT_BRANCH: u64 = 1;
+const FORMAT_ADDRESS: u64 = 2;
+const FORMAT_SYNC: u64 = 3;
+const SYNC_START: u64 = 0;
+const SYNC_TRAP: u64 = 1;
+const SYNC_SUPPORT: u64 = 3;
+
+// a te_inst payload, fields go in LSB first
+#[derive(Default)]
+struct Payload {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl Payload {
+    fn field(mut self, value: u64, width: u32) -> Self {
+        for i in 0..width {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            self.bytes[self.bits / 8] |= (((value >> i) & 1) as u8) << (self.bits % 8);
+            self.bits += 1;
+        }
+        self
+    }
+
+    // in the encapsulation header, payload length in the low bits
+    fn write(self, trace: &mut Vec<u8>) {
+        trace.push(self.bytes.len() as u8);
+        trace.extend(self.bytes);
+    }
+}
+
+// the 63 address bits above iaddress_lsb
+fn address(addr: u64) -> u64 {
+    addr >> 1
+}
+
+fn start(trace: &mut Vec<u8>, addr: u64) {
+    Payload::default().field(FORMAT_SYNC, 2).field(SYNC_START, 2).field(0, 3).field(address(addr), 63).write(trace);
+}
+
+fn differential(trace: &mut Vec<u8>, diff: i64) {
+    Payload::default().field(FORMAT_ADDRESS, 2).field(address(diff as u64), 63).write(trace);
+}
+
+fn end(trace: &mut Vec<u8>) {
+    Payload::default().field(FORMAT_SYNC, 2).field(SYNC_SUPPORT, 2).field(0, 2).field(1, 2).write(trace);
+}
+
+// the packets after the first one, as text
+fn read_packets(trace: &[u8]) -> Result<(String, Vec<String>)> {
+    let mut source = ETraceSource::new(trace);
+    let (header, first) = source.read_first_packet()?;
+    assert!(header.is_none());
+    let mut packets = Vec::new();
+    while let Ok(packet) = source.read_packet() {
+        packets.push(describe(&packet));
+    }
+    Ok((describe(&first), packets))
+}
+
+fn describe(packet: &Packet) -> String {
+    match packet.f_header {
+        FHeader::FTb => "tb".to_string(),
+        FHeader::FNt => "nt".to_string(),
+        FHeader::FUj => format!("uj {:#x}", packet.target_address),
+        FHeader::FSync => format!("sync {:#x}", packet.target_address),
+        FHeader::FTrap => format!("{:?} {:#x} -> {:#x}", packet.trap_type, packet.from_address, packet.target_address),
+        FHeader::FVal => format!("{:?} {:#x}", packet.val_type, packet.value),
+        _ => format!("{:?}", packet.f_header),
+    }
+}
+
+#[test]
+fn test_first_packet_skips_to_start_sync() -> Result<()> {
+    let mut trace = Vec::new();
+    // idle bytes and an address packet before tracing starts
+    trace.extend([0x00, 0x00]);
+    differential(&mut trace, 0x40);
+    start(&mut trace, 0x8000_1000);
+    end(&mut trace);
+    let (first, packets) = read_packets(&trace)?;
+    assert_eq!(first, "sync 0x80001000");
+    assert_eq!(packets, ["sync 0x80001000"]);
+    Ok(())
+}
+
+#[test]
+fn test_branch_map_widths() -> Result<()> {
+    for (branches, width) in [(1, 1), (2, 9), (9, 9), (10, 17), (17, 17), (18, 25), (25, 25), (26, 31), (31, 31)] {
+        // the first and last branches are not taken, the others taken
+        let map = 1 | 1 << (branches - 1);
+        let mut trace = Vec::new();
+        start(&mut trace, 0x1000);
+        Payload::default().field(FORMAT_BRANCH, 2).field(branches, 5).field(map, width)
+            .field(address(0x20), 63).write(&mut trace);
+        end(&mut trace);
+        let (_, packets) = read_packets(&trace)?;
+        let mut expected: Vec<String> = (0..branches)
+            .map(|i| if i == 0 || i == branches - 1 { "nt" } else { "tb" }.to_string())
+            .collect();
+        // the address after the map is only read right with the map width
+        expected.push("sync 0x1020".to_string());
+        assert_eq!(packets, expected, "{} branches", branches);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_full_branch_map() -> Result<()> {
+    let mut trace = Vec::new();
+    start(&mut trace, 0x1000);
+    // 0 branches: a full map of 31 and no address
+    Payload::default().field(FORMAT_BRANCH, 2).field(0, 5).field(0x7fff_fffe, 31).write(&mut trace);
+    end(&mut trace);
+    let (_, packets) = read_packets(&trace)?;
+    let mut expected = vec!["tb".to_string()];
+    expected.extend((1..31).map(|_| "nt".to_string()));
+    expected.push("sync 0x1000".to_string());
+    assert_eq!(packets, expected);
+    Ok(())
+}
+
+#[test]
+fn test_differential_addresses() -> Result<()> {
+    let mut trace = Vec::new();
+    start(&mut trace, 0x8000_0000);
+    differential(&mut trace, 0x40);
+    differential(&mut trace, -0x10);
+    // upper bits dropped: the last bit sent, 1, fills the difference, -2
+    trace.extend([1, 0b1111_1110]);
+    end(&mut trace);
+    let (_, packets) = read_packets(&trace)?;
+    assert_eq!(packets, ["uj 0x80000040", "uj 0x80000030", "sync 0x8000002e"]);
+    Ok(())
+}
+
+#[test]
+fn test_trap_fields() -> Result<()> {
+    let mut trace = Vec::new();
+    start(&mut trace, 0x1000);
+    // the address held before a trap is the last instruction retired
+    differential(&mut trace, 0x8);
+    Payload::default().field(FORMAT_SYNC, 2).field(SYNC_TRAP, 2).field(0, 3)
+        .field(2, 6).field(0, 1).field(0, 1).field(address(0x100), 63).field(0xdead, 64).write(&mut trace);
+    // an interrupt has no tval, with no held address it is taken at the
+    // end of the block
+    Payload::default().field(FORMAT_SYNC, 2).field(SYNC_TRAP, 2).field(0, 3)
+        .field(7, 6).field(1, 1).field(0, 1).field(address(0x200), 63).write(&mut trace);
+    end(&mut trace);
+    let (_, packets) = read_packets(&trace)?;
+    assert_eq!(packets, [
+        "VCause 0x2", "VTval 0xdead", "TException 0x1008 -> 0x100",
+        "VCause 0x7", "TInterrupt 0x200 -> 0x200",
+        "sync 0x200",
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_held_address_at_end_of_stream() -> Result<()> {
+    let mut trace = Vec::new();
+    start(&mut trace, 0x1000);
+    differential(&mut trace, 0x10);
+    let (_, packets) = read_packets(&trace)?;
+    assert_eq!(packets, ["uj 0x1010"]);
+    Ok(())
+}
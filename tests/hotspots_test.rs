@@ -0,0 +1,44 @@
+use anyhow::Result;
+
+use ltrace_decoder::backend::hotspots_receiver::{Field, HotspotSchema};
+
+#[test]
+fn test_default_schema() -> Result<()> {
+    let schema: HotspotSchema = "# only the settings\ndelimiter = ;\n".parse()?;
+    assert_eq!(schema.delimiter, ';');
+    assert_eq!(schema.frequency, None);
+    assert_eq!(schema.columns, HotspotSchema::default().columns);
+    let headers: Vec<&str> = schema.columns.iter().map(|(_, name)| name.as_str()).collect();
+    assert_eq!(headers, vec!["function", "module", "self", "inclusive", "count", "source"]);
+    Ok(())
+}
+
+#[test]
+fn test_schema_columns() -> Result<()> {
+    let schema: HotspotSchema = "
+        delimiter = tab
+        frequency = 1e9   # 1 GHz
+        function = Name
+        self = Exclusive Total CPU Time
+        count = Calls
+        address = PC
+    ".parse()?;
+    assert_eq!(schema.delimiter, '\t');
+    assert_eq!(schema.frequency, Some(1e9));
+    assert_eq!(schema.columns, vec![
+        (Field::Function, "Name".to_string()),
+        (Field::SelfTime, "Exclusive Total CPU Time".to_string()),
+        (Field::Count, "Calls".to_string()),
+        (Field::Address, "PC".to_string()),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_schema_errors() {
+    assert!("function = Name\nfunction = Symbol".parse::<HotspotSchema>().is_err());
+    assert!("latency = Latency".parse::<HotspotSchema>().is_err());
+    assert!("function =".parse::<HotspotSchema>().is_err());
+    assert!("delimiter = ::".parse::<HotspotSchema>().is_err());
+    assert!("frequency = -5".parse::<HotspotSchema>().is_err());
+}
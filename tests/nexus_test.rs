@@ -0,0 +1,138 @@
+use anyhow::Result;
+
+use ltrace_decoder::frontend::f_header::FHeader;
+use ltrace_decoder::frontend::nexus::NexusSource;
+use ltrace_decoder::frontend::packet::Packet;
+use ltrace_decoder::frontend::source::PacketSource;
+
+const MSEO_END_FIELD: u8 = 0b01;
+const MSEO_END_MESSAGE: u8 = 0b11;
+
+const TCODE_DIRECT_BRANCH: u64 = 3;
+const TCODE_PROG_TRACE_SYNC: u64 = 9;
+const TCODE_RESOURCE_FULL: u64 = 27;
+const TCODE_INDIRECT_BRANCH_HIST: u64 = 28;
+const TCODE_INDIRECT_BRANCH_HIST_SYNC: u64 = 29;
+const TCODE_PROG_TRACE_CORRELATION: u64 = 33;
+
+// a message of the given fields, each as (value, bits) sent 6 MDO bits a
+// byte, LSB first
+fn message(trace: &mut Vec<u8>, fields: &[(u64, u32)]) {
+    for (i, &(value, bits)) in fields.iter().enumerate() {
+        let chunks = bits.div_ceil(6).max(1);
+        for chunk in 0..chunks {
+            let mdo = ((value >> (6 * chunk)) & 0x3f) as u8;
+            let mseo = match (chunk == chunks - 1, i == fields.len() - 1) {
+                (false, _) => 0,
+                (true, false) => MSEO_END_FIELD,
+                (true, true) => MSEO_END_MESSAGE,
+            };
+            trace.push(mdo << 2 | mseo);
+        }
+    }
+}
+
+// F-ADDR and U-ADDR leave out the always clear bit 0
+fn sync(trace: &mut Vec<u8>, addr: u64) {
+    // TCODE, SYNC and ICNT share the first field
+    message(trace, &[(TCODE_PROG_TRACE_SYNC | 5 << 6 | 3 << 10, 16), (addr >> 1, 63)]);
+}
+
+fn indirect(trace: &mut Vec<u8>, btype: u64, u_addr: u64, hist: u64) {
+    message(trace, &[(TCODE_INDIRECT_BRANCH_HIST | btype << 6 | 2 << 8, 12), (u_addr >> 1, 63), (hist, 33)]);
+}
+
+fn read_packets(trace: &[u8]) -> Result<(String, Vec<String>)> {
+    let mut source = NexusSource::new(trace);
+    let (header, first) = source.read_first_packet()?;
+    assert!(header.is_none());
+    let mut packets = Vec::new();
+    while let Ok(packet) = source.read_packet() {
+        packets.push(describe(&packet));
+    }
+    Ok((describe(&first), packets))
+}
+
+fn describe(packet: &Packet) -> String {
+    match packet.f_header {
+        FHeader::FTb => "tb".to_string(),
+        FHeader::FNt => "nt".to_string(),
+        FHeader::FUj => format!("uj {:#x}", packet.target_address),
+        FHeader::FSync => format!("sync {:#x}", packet.target_address),
+        FHeader::FTrap => format!("{:?} {:#x} -> {:#x}", packet.trap_type, packet.from_address, packet.target_address),
+        _ => format!("{:?}", packet.f_header),
+    }
+}
+
+#[test]
+fn test_mseo_framing() -> Result<()> {
+    let mut trace = Vec::new();
+    // a message of another kind first, its fields span several bytes
+    message(&mut trace, &[(TCODE_RESOURCE_FULL | 1 << 6 | 0x3ff << 10, 20)]);
+    sync(&mut trace, 0x8000_1234);
+    // an empty variable-size field still ends with MSEO 01
+    message(&mut trace, &[(TCODE_INDIRECT_BRANCH_HIST, 8), (0, 0), (1, 1)]);
+    let (first, packets) = read_packets(&trace)?;
+    assert_eq!(first, "sync 0x80001234");
+    assert_eq!(packets, ["uj 0x80001234"]);
+    Ok(())
+}
+
+#[test]
+fn test_hist_stop_bit() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x1000);
+    // the stop bit, then taken, not taken, not taken, taken, oldest first
+    indirect(&mut trace, 0, 0, 0b1_1001);
+    // only the stop bit: no branch since the last message
+    indirect(&mut trace, 0, 0, 0b1);
+    // 32 branches fill a field of 33 bits
+    indirect(&mut trace, 0, 0, 1 << 32 | 0xffff_fffe);
+    let (_, packets) = read_packets(&trace)?;
+    let mut expected: Vec<String> = ["tb", "nt", "nt", "tb", "uj 0x1000", "uj 0x1000"].map(String::from).to_vec();
+    expected.extend((0..31).map(|_| "tb".to_string()));
+    expected.extend(["nt", "uj 0x1000"].map(String::from));
+    assert_eq!(packets, expected);
+    Ok(())
+}
+
+#[test]
+fn test_u_addr_xor() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x8000_0000);
+    // U-ADDR holds the bits that differ from the last address
+    indirect(&mut trace, 0, 0x8000_0000 ^ 0x8000_0100, 1);
+    indirect(&mut trace, 0, 0x8000_0100 ^ 0x8000_0040, 1);
+    // a sync sends a full address, the next U-ADDR is relative to it
+    message(&mut trace, &[(TCODE_INDIRECT_BRANCH_HIST_SYNC | 5 << 6 | 2 << 12, 14), (0x2000 >> 1, 63), (1, 1)]);
+    indirect(&mut trace, 0, 0x2000 ^ 0x2010, 1);
+    let (_, packets) = read_packets(&trace)?;
+    assert_eq!(packets, ["uj 0x80000100", "uj 0x80000040", "uj 0x2000", "uj 0x2010"]);
+    Ok(())
+}
+
+#[test]
+fn test_trap_and_correlation() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x1000);
+    // B-TYPE 1, an exception, taken at the end of the block
+    indirect(&mut trace, 1, 0x1000 ^ 0x100, 0b10);
+    // a full history in a ResourceFull message
+    message(&mut trace, &[(TCODE_RESOURCE_FULL | 1 << 6 | 0b101 << 10, 13)]);
+    // CDF 1, the branches since the last message follow ICNT
+    message(&mut trace, &[(TCODE_PROG_TRACE_CORRELATION | 1 << 10 | 4 << 12, 18), (0b11, 2)]);
+    let (_, packets) = read_packets(&trace)?;
+    assert_eq!(packets, ["nt", "TException 0x100 -> 0x100", "nt", "tb", "tb", "sync 0x100"]);
+    Ok(())
+}
+
+#[test]
+fn test_branch_messages_without_history() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x1000);
+    message(&mut trace, &[(TCODE_DIRECT_BRANCH | 2 << 6, 12)]);
+    let mut source = NexusSource::new(&trace[..]);
+    source.read_first_packet()?;
+    assert!(source.read_packet().is_err());
+    Ok(())
+}
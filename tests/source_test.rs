@@ -0,0 +1,114 @@
+use anyhow::Result;
+
+use ltrace_decoder::diagnostics::{self, Category};
+use ltrace_decoder::frontend::check_type::{crc16, CHECK_CRC, CHECK_SEQUENCE, CRC_INIT};
+use ltrace_decoder::frontend::f_header::FHeader;
+use ltrace_decoder::frontend::packet::Packet;
+use ltrace_decoder::frontend::source::{PacketSource, TacitSource, TraceFormat};
+use tacit_wire::write_varint;
+
+const F_TB: u8 = 0b000;
+const F_NT: u8 = 0b001;
+const F_SYNC: u8 = 0b101;
+const F_CHECK: u8 = 0b111;
+
+// a trace and where the bytes of its next check packet start
+#[derive(Default)]
+struct Trace {
+    bytes: Vec<u8>,
+    checked: usize,
+}
+
+impl Trace {
+    fn packet(&mut self, first_byte: u8, varints: &[u64]) -> &mut Self {
+        self.bytes.push(first_byte);
+        for &value in varints {
+            write_varint(&mut self.bytes, value);
+        }
+        self
+    }
+
+    fn sync(&mut self, target: u64) -> &mut Self {
+        self.packet(F_SYNC << 2 | 0b10, &[target, 1])
+    }
+
+    fn branch(&mut self, f_header: u8) -> &mut Self {
+        self.packet(f_header << 2 | 0b10, &[1])
+    }
+
+    // a check packet with the CRC of the bytes since the last one, XORed with `error`
+    fn check(&mut self, sequence: u64, error: u16) -> &mut Self {
+        let crc = self.bytes[self.checked..].iter().fold(CRC_INIT, |crc, &byte| crc16(crc, byte)) ^ error;
+        self.packet((CHECK_SEQUENCE | CHECK_CRC) << 5 | F_CHECK << 2 | 0b10, &[sequence, crc as u64]);
+        self.checked = self.bytes.len();
+        self
+    }
+}
+
+fn read_packets(trace: &[u8]) -> Result<Vec<String>> {
+    let mut source = TacitSource::new(trace);
+    let (_, first) = source.read_first_packet()?;
+    let mut packets = vec![describe(&first)];
+    while let Ok(packet) = source.read_packet() {
+        packets.push(describe(&packet));
+    }
+    Ok(packets)
+}
+
+fn describe(packet: &Packet) -> String {
+    match packet.f_header {
+        FHeader::FTb => "tb".to_string(),
+        FHeader::FNt => "nt".to_string(),
+        FHeader::FSync if packet.resync => format!("resync {:#x}", packet.target_address),
+        FHeader::FSync => format!("sync {:#x}", packet.target_address),
+        _ => format!("{:?}", packet.f_header),
+    }
+}
+
+#[test]
+fn test_trace_format_names() -> Result<()> {
+    assert_eq!("tacit".parse::<TraceFormat>()?, TraceFormat::Tacit);
+    assert_eq!("etrace".parse::<TraceFormat>()?, TraceFormat::ETrace);
+    assert_eq!("nexus".parse::<TraceFormat>()?, TraceFormat::Nexus);
+    assert!("ptm".parse::<TraceFormat>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_without_check_packets() -> Result<()> {
+    let mut trace = Trace::default();
+    trace.sync(0x100).branch(F_TB).branch(F_NT).sync(0x200);
+    assert_eq!(read_packets(&trace.bytes)?, ["sync 0x100", "tb", "nt", "sync 0x200"]);
+    Ok(())
+}
+
+#[test]
+fn test_crc_mismatch_resyncs() -> Result<()> {
+    let corrupted = diagnostics::count(Category::CorruptedPacket);
+    let truncated = diagnostics::count(Category::TruncatedPacket);
+    let mut trace = Trace::default();
+    trace.sync(0x100).branch(F_TB).branch(F_NT).check(0, 0);
+    trace.branch(F_TB).check(1, 0x8000);
+    // skipped up to the next sync, whose check cannot match the lost bytes
+    trace.branch(F_TB).sync(0x200).branch(F_NT).check(2, 0x1234);
+    // nothing vouches for these
+    trace.branch(F_TB);
+    assert_eq!(read_packets(&trace.bytes)?, ["sync 0x100", "tb", "nt", "resync 0x200", "nt", "tb"]);
+    assert!(diagnostics::count(Category::CorruptedPacket) > corrupted);
+    assert!(diagnostics::count(Category::TruncatedPacket) > truncated);
+    Ok(())
+}
+
+#[test]
+fn test_sequence_gap() -> Result<()> {
+    let corrupted = diagnostics::count(Category::CorruptedPacket);
+    let mut trace = Trace::default();
+    trace.sync(0x100).branch(F_TB).check(0, 0);
+    // a check packet went missing with the packets it covered
+    trace.branch(F_NT).check(2, 0);
+    // a sync with nothing after it ends the trace
+    trace.branch(F_TB).sync(0x300);
+    assert_eq!(read_packets(&trace.bytes)?, ["sync 0x100", "tb", "sync 0x300"]);
+    assert!(diagnostics::count(Category::CorruptedPacket) > corrupted);
+    Ok(())
+}
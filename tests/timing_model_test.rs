@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use ltrace_decoder::backend::timing_model::{spread, CostTable, Matcher};
+
+#[test]
+fn test_spread_uniform() {
+    // the remainder goes to the first ones, as the even spread always did
+    assert_eq!(spread(10, &[1, 1, 1, 1]), vec![3, 3, 2, 2]);
+    assert_eq!(spread(2, &[1, 1, 1]), vec![1, 1, 0]);
+    assert_eq!(spread(7, &[]), Vec::<u64>::new());
+}
+
+#[test]
+fn test_spread_costs() {
+    // a load of 3 and a mul of 4 between two single-cycle instructions
+    assert_eq!(spread(18, &[1, 3, 4, 1]), vec![2, 6, 8, 2]);
+    // 1.25, 3.75 and 0, the remainder skipping what costs nothing
+    assert_eq!(spread(5, &[1, 3, 0]), vec![2, 3, 0]);
+    // all free, all the same
+    assert_eq!(spread(4, &[0, 0]), vec![2, 2]);
+    let shares = spread(u64::MAX, &[u64::MAX / 2, u64::MAX / 2]);
+    assert_eq!(shares.iter().map(|&share| share as u128).sum::<u128>(), u64::MAX as u128);
+}
+
+#[test]
+fn test_cost_table() -> Result<()> {
+    let table: CostTable = "
+        # a small in-order core
+        load 3
+        mul* 4
+        fdiv.* 20
+        default 2
+    ".parse()?;
+    assert_eq!(table.default, 2);
+    assert_eq!(table.rules, vec![
+        (Matcher::Load, 3),
+        (Matcher::Mnemonic("mul*".to_string()), 4),
+        (Matcher::Mnemonic("fdiv.*".to_string()), 20),
+    ]);
+    assert_eq!("".parse::<CostTable>()?.default, 1);
+    assert!("load".parse::<CostTable>().is_err());
+    assert!("load three".parse::<CostTable>().is_err());
+    assert!("load 3 4".parse::<CostTable>().is_err());
+    Ok(())
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::process::Output;
+
+use anyhow::Result;
+
+use common::*;
+
+fn sync_trace(start: u64, end: u64) -> Vec<u8> {
+    let mut trace = Vec::new();
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x00, start]);
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x10, end]);
+    trace
+}
+
+// the decoder over the traces of one binary, each given with -e
+fn run_decoder(name: &str, traces: &[Vec<u8>], flags: &[&str]) -> Result<Output> {
+    let dir = Workdir::new(&format!("traces-{}", name))?;
+    dir.write("straight.elf", &build_elf(&STRAIGHT))?;
+    let mut args = vec!["--binary", "straight.elf", "--addr-compression", "absolute"];
+    let names: Vec<String> = (0..traces.len()).map(|i| format!("run{}.tacit", i)).collect();
+    for (trace, name) in traces.iter().zip(&names) {
+        dir.write(name, trace)?;
+        args.extend(["--encoded-trace", name]);
+    }
+    args.extend(flags);
+    dir.run(&args)
+}
+
+#[test]
+fn test_two_traces_reject_timelines() -> Result<()> {
+    let traces = [sync_trace(100, 120), sync_trace(0, 30)];
+    let output = run_decoder("timeline", &traces, &["--to-speedscope"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--to-speedscope lays out a single timeline"));
+    // one trace is fine, and so are the counts of two
+    let output = run_decoder("single", &traces[..1], &["--to-speedscope"])?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run_decoder("counts", &traces, &["--to-stats"])?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Ok(())
+}
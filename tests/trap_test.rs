@@ -0,0 +1,139 @@
+mod common;
+
+use anyhow::Result;
+
+use common::*;
+use ltrace_decoder::backend::event::{Entry, Event, RETURN_ATTR};
+use ltrace_decoder::frontend::address::{AddressScheme, Compression};
+use ltrace_decoder::diagnostics::{self, Category};
+use ltrace_decoder::frontend::decoder::{decode, DecoderConfig};
+
+fn sync(trace: &mut Vec<u8>, target: u64) {
+    packet(trace, F_SYNC << 2 | 0b10, &[target, 1]);
+}
+
+fn trap(trace: &mut Vec<u8>, trap_type: u8, from: u64, target: u64) {
+    packet(trace, trap_type << 5 | F_TRAP << 2 | 0b10, &[from, target, 1]);
+}
+
+fn decode_entries(program: &[u32], trace: &[u8], br_mode: u64) -> Result<Vec<Entry>> {
+    let config = DecoderConfig {
+        br_mode,
+        address: AddressScheme { shift: 0, compression: Compression::Absolute },
+        ..DecoderConfig::default()
+    };
+    let mut sink = CollectSink(Vec::new());
+    decode(&build_elf(program), &mut &trace[..], None, &config, &mut sink)?;
+    Ok(sink.0)
+}
+
+// the instructions and traps decoded, as (event, from, to)
+fn decode_events(program: &[u32], trace: &[u8], br_mode: u64) -> Result<Vec<(Event, u64, u64)>> {
+    Ok(decode_entries(program, trace, br_mode)?.into_iter()
+        .filter(|entry| matches!(entry.event, Event::None | Event::TrapException | Event::TrapInterrupt | Event::TrapReturn))
+        .map(|entry| (entry.event, entry.arc.0, entry.arc.1))
+        .collect())
+}
+
+fn insns(pcs: &[u64]) -> Vec<(Event, u64, u64)> {
+    pcs.iter().map(|&pc| (Event::None, pc, pc + 4)).collect()
+}
+
+#[test]
+fn test_interrupt_mid_block() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    trap(&mut trace, T_INTERRUPT, 0x08, 0x14);
+    trap(&mut trace, T_RETURN, 0x18, 0x08);
+    sync(&mut trace, 0x10);
+    // the interrupted instruction runs once, after the handler
+    let mut expected = insns(&[0x00, 0x04]);
+    expected.push((Event::TrapInterrupt, 0x08, 0x14));
+    expected.extend(insns(&[0x14, 0x18]));
+    expected.push((Event::TrapReturn, 0x18, 0x08));
+    expected.extend(insns(&[0x08, 0x0c, 0x10]));
+    assert_eq!(decode_events(&STRAIGHT, &trace, 0)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_interrupt_at_block_start() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    trap(&mut trace, T_INTERRUPT, 0x00, 0x14);
+    trap(&mut trace, T_RETURN, 0x18, 0x00);
+    sync(&mut trace, 0x10);
+    let mut expected = vec![(Event::TrapInterrupt, 0x00, 0x14)];
+    expected.extend(insns(&[0x14, 0x18]));
+    expected.push((Event::TrapReturn, 0x18, 0x00));
+    expected.extend(insns(&[0x00, 0x04, 0x08, 0x0c, 0x10]));
+    assert_eq!(decode_events(&STRAIGHT, &trace, 0)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_interrupt_past_direct_jump() -> Result<()> {
+    // 0x04 jumps to 0x10 without a packet outside of br_mode 0
+    let program = [ADDI_A0, J_PLUS_12, J_SELF, J_SELF, ADDI_A0, ADDI_A0, J_SELF, ADDI_T0, MRET];
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    trap(&mut trace, T_INTERRUPT, 0x14, 0x1c);
+    trap(&mut trace, T_RETURN, 0x20, 0x14);
+    sync(&mut trace, 0x18);
+    let mut expected = insns(&[0x00, 0x04, 0x10]);
+    expected.push((Event::TrapInterrupt, 0x14, 0x1c));
+    expected.extend(insns(&[0x1c, 0x20]));
+    expected.push((Event::TrapReturn, 0x20, 0x14));
+    expected.extend(insns(&[0x14, 0x18]));
+    assert_eq!(decode_events(&program, &trace, 1)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_exception_keeps_faulting_insn() -> Result<()> {
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    trap(&mut trace, T_EXCEPTION, 0x08, 0x14);
+    trap(&mut trace, T_RETURN, 0x18, 0x0c);
+    sync(&mut trace, 0x10);
+    let mut expected = insns(&[0x00, 0x04, 0x08]);
+    expected.push((Event::TrapException, 0x08, 0x14));
+    expected.extend(insns(&[0x14, 0x18]));
+    expected.push((Event::TrapReturn, 0x18, 0x0c));
+    expected.extend(insns(&[0x0c, 0x10]));
+    assert_eq!(decode_events(&STRAIGHT, &trace, 0)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_return_kinds() -> Result<()> {
+    // handlers of a single return at 0x14, 0x18 and 0x1c
+    let program = [ADDI_A0, ADDI_A0, ADDI_A0, ADDI_A0, J_SELF, MRET, SRET, URET];
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    for (at, handler) in [(0x04, 0x14), (0x08, 0x18), (0x0c, 0x1c)] {
+        trap(&mut trace, T_INTERRUPT, at, handler);
+        trap(&mut trace, T_RETURN, handler, at);
+    }
+    sync(&mut trace, 0x10);
+    let returns: Vec<String> = decode_entries(&program, &trace, 0)?.iter()
+        .filter(|entry| entry.event == Event::TrapReturn)
+        .map(|entry| entry.attrs.get(RETURN_ATTR).map(|xret| xret.to_string()).unwrap_or_default())
+        .collect();
+    assert_eq!(returns, ["mret", "sret", "uret"]);
+    Ok(())
+}
+
+#[test]
+fn test_return_mismatch() -> Result<()> {
+    let before = diagnostics::count(Category::ReturnMismatch);
+    let mut trace = Vec::new();
+    sync(&mut trace, 0x00);
+    trap(&mut trace, T_INTERRUPT, 0x08, 0x14);
+    // neither to the interrupted instruction nor past it
+    trap(&mut trace, T_RETURN, 0x18, 0x00);
+    sync(&mut trace, 0x10);
+    decode_entries(&STRAIGHT, &trace, 0)?;
+    assert!(diagnostics::count(Category::ReturnMismatch) > before);
+    Ok(())
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use anyhow::Result;
+
+use common::*;
+
+const VADD_VV: u32 = 0x0000_0057;
+const VSETVLI: u32 = 0x0000_7057;
+
+#[test]
+fn test_vector_overlay_code() -> Result<()> {
+    let dir = Workdir::new("vector-overlay")?;
+    dir.write("base.elf", &build_elf(&STRAIGHT))?;
+    // the same addresses hold vector code once the overlay is loaded, from
+    // the first timestamp on
+    dir.write("overlay.elf", &build_elf(&[ADDI_A0, VADD_VV, VSETVLI, ADDI_A0, J_SELF]))?;
+    dir.write("overlays.txt", b"0x0 0x10 overlay.elf .text 0..\n")?;
+    let mut trace = Vec::new();
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x00, 100]);
+    packet(&mut trace, F_SYNC << 2 | 0b10, &[0x10, 120]);
+    dir.write("run.tacit", &trace)?;
+    let output = dir.run(&["--binary", "base.elf", "--encoded-trace", "run.tacit", "--addr-compression", "absolute",
+                           "--overlays", "overlays.txt", "--to-vector"])?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let report = dir.read("trace.vector.txt")?;
+    assert!(report.contains("vector instructions: 2 "), "{}", report);
+    assert!(report.contains("config (vset*)"), "{}", report);
+    assert!(report.contains("integer arithmetic"), "{}", report);
+    Ok(())
+}